@@ -0,0 +1,628 @@
+// Runs the jobs defined in a directory, as a small job runner for hosts
+// without cron or systemd timers. See the `agent` subcommand for the format
+// of the job files.
+//
+// Each time a job runs, the wrapper is executed for it as a separate
+// process, with the options in its job file, so that each job has its own
+// pipelines for its output, logs, errors and check-ins, and a problem with
+// one of them does not affect the others or the agent.
+//
+// The directory is watched for changes, so that jobs can be added, changed
+// or removed without restarting the agent. Each change is logged, so that
+// changes to the configuration of the host can be traced.
+//
+// When the time a job was scheduled for passes without it being executed,
+// such as when its previous execution was still running or the host was
+// suspended, it is executed once as soon as possible, unless missed times
+// are skipped. Times that passed while the agent was not running are not
+// executed.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use log::{debug, error, info, trace, warn};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::process::Command;
+use tokio::select;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::cli::Cli;
+use crate::config;
+use crate::exit::exit_with_parent;
+use crate::package::NAME;
+use crate::schedule::CronSchedule;
+use crate::signal::{has_terminating_intent, signal_stream};
+use crate::timestamp::Clock;
+
+// How long to wait before executing a job without a schedule again, after
+// it exits.
+const RESTART_DELAY: Duration = Duration::from_secs(10);
+
+// How long to wait for changes to the jobs directory to settle before
+// loading them.
+const RELOAD_DELAY: Duration = Duration::from_millis(500);
+
+// How the jobs with a schedule are executed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScheduleOptions {
+    // How long to wait after a job is due before executing it. This is the
+    // same for every job run by the agent, so that hosts running the same
+    // schedule do not execute their jobs at the same time.
+    pub splay: Duration,
+    // Whether to skip the times the job was scheduled for that passed
+    // without it being executed, instead of executing it once for them.
+    pub skip_if_missed: bool,
+}
+
+impl ScheduleOptions {
+    // Picks a random splay up to the given maximum, for this host.
+    pub fn new(max_splay: Option<Duration>, skip_if_missed: bool) -> Self {
+        let splay = max_splay
+            .map(|max| max.mul_f64(rand::random::<f64>()))
+            .unwrap_or_default();
+
+        Self {
+            splay,
+            skip_if_missed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    name: String,
+    schedule: Option<CronSchedule>,
+    // The arguments to execute the wrapper with for the job.
+    args: Vec<String>,
+}
+
+impl Job {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let job = config::job(path)?;
+        let mut options = job.args;
+
+        let schedule = options
+            .iter()
+            .find_map(|arg| arg.strip_prefix("--cron-schedule="))
+            .map(CronSchedule::parse)
+            .transpose()?;
+
+        let has_cron = options
+            .iter()
+            .any(|arg| arg == "--cron" || arg.starts_with("--cron="));
+
+        if schedule.is_some() && !has_cron {
+            options.push("--cron".to_string());
+        }
+
+        // The name is given before the options, so that it is not taken as
+        // the value of an option with an optional value, such as `--cron`.
+        let args: Vec<String> = std::iter::once(job.name.clone())
+            .chain(options)
+            .chain(std::iter::once("--".to_string()))
+            .chain(job.command)
+            .collect();
+
+        // Check the options when the job is loaded, rather than every time
+        // it is executed.
+        Cli::try_parse_with_env(std::iter::once(NAME.to_string()).chain(args.iter().cloned()))
+            .map_err(|err| {
+                format!(
+                    "invalid options in job file {}: {}",
+                    path.display(),
+                    clap_error_message(&err)
+                )
+            })?;
+
+        Ok(Self {
+            name: job.name,
+            schedule,
+            args,
+        })
+    }
+}
+
+// Returns the paths of the job files in the directory, in order.
+fn job_paths(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|err| format!("could not read jobs directory {}: {}", dir.display(), err))?;
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "toml")
+        })
+        .collect();
+    paths.sort();
+
+    Ok(paths)
+}
+
+// Supervises the jobs defined in the jobs directory, starting and stopping
+// them as their job files are added, changed or removed.
+struct Supervisor<C: Clock> {
+    dir: PathBuf,
+    executable: PathBuf,
+    options: ScheduleOptions,
+    clock: C,
+    cancel: CancellationToken,
+    tasks: TaskTracker,
+    // The running jobs, by the path of their job file, alongside the token
+    // that stops them.
+    jobs: BTreeMap<PathBuf, (Job, CancellationToken)>,
+}
+
+impl<C: Clock> Supervisor<C> {
+    fn new(dir: &Path, executable: PathBuf, options: ScheduleOptions, clock: C) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            executable,
+            options,
+            clock,
+            cancel: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            jobs: BTreeMap::new(),
+        }
+    }
+
+    // Loads the job files in the directory, starting the jobs that were
+    // added, restarting the ones that were changed, and stopping the ones
+    // that were removed. Each change is logged.
+    //
+    // Job files that cannot be loaded are reported as warnings and ignored.
+    // If the job was already running, it keeps running as it was.
+    fn reload(&mut self) -> Result<(), String> {
+        let paths = job_paths(&self.dir)?;
+
+        let removed: Vec<PathBuf> = self
+            .jobs
+            .keys()
+            .filter(|path| !paths.contains(path))
+            .cloned()
+            .collect();
+
+        for path in removed {
+            if let Some((job, cancel)) = self.jobs.remove(&path) {
+                cancel.cancel();
+                info!(
+                    "removed job {}, as {} was removed",
+                    job.name,
+                    path.display()
+                );
+            }
+        }
+
+        for path in paths {
+            let job = match Job::load(&path) {
+                Ok(job) => job,
+                Err(err) => {
+                    warn!("{}; ignoring it", err);
+                    continue;
+                }
+            };
+
+            match self.jobs.remove(&path) {
+                Some((current, cancel)) if current == job => {
+                    self.jobs.insert(path, (current, cancel));
+                    continue;
+                }
+                Some((_, cancel)) => {
+                    cancel.cancel();
+                    info!("updated job {} from {}", job.name, path.display());
+                }
+                None => info!("added job {} from {}", job.name, path.display()),
+            }
+
+            let cancel = self.cancel.child_token();
+            self.tasks.spawn(job_loop(
+                job.clone(),
+                self.executable.clone(),
+                self.options,
+                self.clock.clone(),
+                cancel.clone(),
+            ));
+            self.jobs.insert(path, (job, cancel));
+        }
+
+        Ok(())
+    }
+
+    // Stops all jobs, and waits for them to exit.
+    async fn stop(self) {
+        self.cancel.cancel();
+        self.tasks.close();
+        self.tasks.wait().await;
+    }
+}
+
+// Watches the directory for changes, which are sent to the sender. If the
+// directory cannot be watched, changes to it are not loaded.
+fn watch(dir: &Path, sender: UnboundedSender<()>) -> Option<RecommendedWatcher> {
+    let result = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok_and(|event| !event.kind.is_access()) {
+            let _ = sender.send(());
+        }
+    })
+    .and_then(|mut watcher| {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map(|_| watcher)
+    });
+
+    match result {
+        Ok(watcher) => Some(watcher),
+        Err(err) => {
+            warn!(
+                "could not watch jobs directory {}: {}; changes to it will not be loaded",
+                dir.display(),
+                err
+            );
+            None
+        }
+    }
+}
+
+// Runs the jobs in the directory until a terminating signal is received,
+// after which the running jobs are terminated. Changes to the directory are
+// loaded as they happen.
+pub async fn run(
+    dir: &Path,
+    options: ScheduleOptions,
+    clock: impl Clock,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut supervisor = Supervisor::new(dir, std::env::current_exe()?, options, clock.clone());
+    supervisor.reload()?;
+
+    if !options.splay.is_zero() {
+        info!("delaying scheduled jobs by {:?}", options.splay);
+    }
+
+    if supervisor.jobs.is_empty() {
+        warn!("no jobs found in jobs directory {}", dir.display());
+    }
+
+    let (sender, mut changes) = unbounded_channel();
+    let watcher = watch(dir, sender);
+
+    let mut signals = signal_stream()?;
+
+    let code = loop {
+        select! {
+            Some(()) = changes.recv() => {
+                // Wait for the changes to settle, as a job file may be
+                // written in several steps.
+                loop {
+                    select! {
+                        Some(()) = changes.recv() => {}
+                        _ = clock.sleep(RELOAD_DELAY) => break,
+                    }
+                }
+
+                if let Err(err) = supervisor.reload() {
+                    warn!("{}", err);
+                }
+            }
+
+            Some(signal) = signals.next() => {
+                if has_terminating_intent(&signal) {
+                    debug!("received terminating signal: {}", signal);
+                    break 128 + signal as i32;
+                }
+
+                trace!("ignoring non-terminating signal: {}", signal);
+            }
+        }
+    };
+
+    drop(watcher);
+    supervisor.stop().await;
+
+    Ok(code)
+}
+
+async fn job_loop(
+    job: Job,
+    executable: PathBuf,
+    options: ScheduleOptions,
+    mut clock: impl Clock,
+    cancel: CancellationToken,
+) {
+    // The times the job was scheduled for before the agent started are not
+    // executed, even if they are not skipped.
+    let mut last = local_time(clock.now());
+
+    loop {
+        if let Some(schedule) = job.schedule.as_ref() {
+            select! {
+                _ = cancel.cancelled() => break,
+                _ = async {
+                    next_scheduled(&job.name, schedule, &mut last, options.skip_if_missed, &mut clock).await;
+                    clock.sleep(options.splay).await;
+                } => {}
+            }
+        }
+
+        execute(&job, &executable, &cancel).await;
+
+        if job.schedule.is_none() {
+            select! {
+                _ = cancel.cancelled() => break,
+                _ = clock.sleep(RESTART_DELAY) => {}
+            }
+        }
+    }
+}
+
+// Executes the wrapper for the job, and waits for it to exit. If cancelled,
+// the wrapper is terminated, which terminates the job's command.
+async fn execute(job: &Job, executable: &Path, cancel: &CancellationToken) {
+    let mut command = Command::new(executable);
+    command.args(&job.args).stdin(Stdio::null());
+
+    unsafe {
+        command.pre_exec(exit_with_parent);
+    }
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            error!("could not execute job {}: {}", job.name, err);
+            return;
+        }
+    };
+
+    debug!("executing job {}", job.name);
+
+    let status = select! {
+        status = child.wait() => status,
+        _ = cancel.cancelled() => {
+            if let Some(pid) = child.id() {
+                let _ = kill(Pid::from_raw(pid as i32), Signal::SIGTERM);
+            }
+
+            if let Err(err) = child.wait().await {
+                error!("could not wait for job {}: {}", job.name, err);
+            }
+
+            debug!("terminated job {}", job.name);
+            return;
+        }
+    };
+
+    match status {
+        Ok(status) if status.success() => debug!("job {} finished", job.name),
+        Ok(status) => warn!("job {} exited with {}", job.name, status),
+        Err(err) => error!("could not wait for job {}: {}", job.name, err),
+    }
+}
+
+// Waits until the start of the next minute, in the host's local time, that
+// matches the schedule, and sets it as the last time the job was due.
+//
+// If the schedule matched since the last time the job was due, such as
+// while it was executing, it is due right away, unless missed times are
+// skipped. However many times were missed, it is only due once for them.
+async fn next_scheduled(
+    name: &str,
+    schedule: &CronSchedule,
+    last: &mut NaiveDateTime,
+    skip_if_missed: bool,
+    clock: &mut impl Clock,
+) {
+    loop {
+        let now = local_time(clock.now());
+
+        if let Some(missed) = schedule.last_before(now).filter(|missed| missed > last) {
+            *last = now;
+
+            if skip_if_missed {
+                warn!(
+                    "job {} missed its scheduled time {}; skipping it",
+                    name, missed
+                );
+            } else {
+                warn!(
+                    "job {} missed its scheduled time {}; executing it now",
+                    name, missed
+                );
+                return;
+            }
+        }
+
+        let wait = until_next_minute(clock.now());
+        clock.sleep(wait).await;
+
+        let now = local_time(clock.now());
+        if schedule.matches(now) {
+            *last = now;
+            return;
+        }
+    }
+}
+
+fn until_next_minute(now: Duration) -> Duration {
+    Duration::from_secs(60 - now.as_secs() % 60) - Duration::from_nanos(now.subsec_nanos().into())
+}
+
+fn local_time(now: Duration) -> NaiveDateTime {
+    DateTime::from_timestamp(now.as_secs() as i64, now.subsec_nanos())
+        .expect("invalid system time")
+        .with_timezone(&Local)
+        .naive_local()
+}
+
+// Returns the message of a command-line parsing error, without the usage
+// instructions that follow it.
+fn clap_error_message(err: &clap::Error) -> String {
+    let message = err.to_string();
+    let message: Vec<&str> = message
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .map(str::trim)
+        .collect();
+
+    let message = message.join(" ");
+    message
+        .strip_prefix("error: ")
+        .unwrap_or(&message)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp::tests::TestClock;
+    use crate::timestamp::{SystemTimestamp, Timestamp};
+
+    #[tokio::test]
+    async fn agent_reload() {
+        let dir = std::env::temp_dir().join(format!("{}-agent-test", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let job = |schedule: &str| {
+            format!(
+                r#"
+                    command = "pg_dump app > backup.sql"
+                    api_key = "some-api-key"
+                    cron_schedule = "{schedule}"
+                "#
+            )
+        };
+
+        std::fs::write(dir.join("backup.toml"), job("15 3 * * *")).unwrap();
+        std::fs::write(dir.join("cleanup.toml"), job("0 4 * * *")).unwrap();
+        std::fs::write(dir.join("README.md"), "Not a job file.").unwrap();
+
+        let mut supervisor = Supervisor::new(
+            &dir,
+            PathBuf::from("/bin/true"),
+            ScheduleOptions::default(),
+            SystemTimestamp,
+        );
+        supervisor.reload().expect("failed to load jobs");
+
+        let names: Vec<&str> = supervisor
+            .jobs
+            .values()
+            .map(|(job, _)| job.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["backup", "cleanup"]);
+
+        let (backup, backup_cancel) = supervisor.jobs[&dir.join("backup.toml")].clone();
+        let (_, cleanup_cancel) = supervisor.jobs[&dir.join("cleanup.toml")].clone();
+
+        assert_eq!(
+            backup.args,
+            vec![
+                "backup",
+                "--api-key=some-api-key",
+                "--cron-schedule=15 3 * * *",
+                "--cron",
+                "--",
+                "sh",
+                "-c",
+                "pg_dump app > backup.sql",
+            ]
+        );
+
+        // A job file is changed, one is removed, and one is added.
+        std::fs::write(dir.join("backup.toml"), job("30 3 * * *")).unwrap();
+        std::fs::remove_file(dir.join("cleanup.toml")).unwrap();
+        std::fs::write(dir.join("rotate.toml"), job("0 5 * * *")).unwrap();
+        supervisor.reload().expect("failed to reload jobs");
+
+        let names: Vec<&str> = supervisor
+            .jobs
+            .values()
+            .map(|(job, _)| job.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["backup", "rotate"]);
+        assert!(backup_cancel.is_cancelled());
+        assert!(cleanup_cancel.is_cancelled());
+        assert_ne!(supervisor.jobs[&dir.join("backup.toml")].0, backup);
+
+        // An invalid job file does not stop the running job.
+        let (_, rotate_cancel) = supervisor.jobs[&dir.join("rotate.toml")].clone();
+        std::fs::write(dir.join("rotate.toml"), "heartbeat = true").unwrap();
+        supervisor.reload().expect("failed to reload jobs");
+
+        assert!(supervisor.jobs.contains_key(&dir.join("rotate.toml")));
+        assert!(!rotate_cancel.is_cancelled());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(supervisor.reload().is_err());
+
+        supervisor.stop().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn agent_next_scheduled() {
+        // 2001-09-09T01:46:40Z. Time zone offsets are multiples of fifteen
+        // minutes, so the next time that matches is the same in all of them.
+        let mut clock = TestClock::new(Duration::from_secs(1_000_000_000));
+        let schedule = CronSchedule::parse("*/15 * * * *").unwrap();
+        let mut last = local_time(clock.now());
+
+        next_scheduled("job", &schedule, &mut last, false, &mut clock).await;
+        assert_eq!(clock.as_secs(), 1_000_000_800);
+
+        next_scheduled("job", &schedule, &mut last, false, &mut clock).await;
+        assert_eq!(clock.as_secs(), 1_000_001_700);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn agent_next_scheduled_missed() {
+        let mut clock = TestClock::new(Duration::from_secs(1_000_000_000));
+        let schedule = CronSchedule::parse("*/15 * * * *").unwrap();
+        let mut last = local_time(clock.now());
+
+        next_scheduled("job", &schedule, &mut last, false, &mut clock).await;
+        assert_eq!(clock.as_secs(), 1_000_000_800);
+
+        // The job executes for longer than its schedule's interval, twice.
+        // It is executed once right away for the times it missed.
+        clock.jump_forward(Duration::from_secs(1_900));
+        next_scheduled("job", &schedule, &mut last, false, &mut clock).await;
+        assert_eq!(clock.as_secs(), 1_000_002_700);
+
+        next_scheduled("job", &schedule, &mut last, false, &mut clock).await;
+        assert_eq!(clock.as_secs(), 1_000_003_500);
+
+        // If missed times are skipped, it waits for the next time instead.
+        clock.jump_forward(Duration::from_secs(1_000));
+        next_scheduled("job", &schedule, &mut last, true, &mut clock).await;
+        assert_eq!(clock.as_secs(), 1_000_005_300);
+    }
+
+    #[test]
+    fn agent_schedule_options() {
+        let options = ScheduleOptions::new(None, true);
+        assert_eq!(options.splay, Duration::ZERO);
+        assert!(options.skip_if_missed);
+
+        let options = ScheduleOptions::new(Some(Duration::from_secs(300)), false);
+        assert!(options.splay <= Duration::from_secs(300));
+    }
+
+    #[test]
+    fn agent_until_next_minute() {
+        assert_eq!(
+            until_next_minute(Duration::from_millis(120_250)),
+            Duration::from_millis(59_750)
+        );
+        assert_eq!(
+            until_next_minute(Duration::from_secs(180)),
+            Duration::from_secs(60)
+        );
+    }
+}
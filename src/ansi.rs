@@ -0,0 +1,56 @@
+// Strips ANSI escape sequences, such as the ones used by commands to colour
+// their output, from the lines sent to AppSignal, where they would show up
+// as noise in the log messages.
+
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+fn escape_sequence() -> &'static Regex {
+    static ESCAPE_SEQUENCE: OnceLock<Regex> = OnceLock::new();
+
+    ESCAPE_SEQUENCE.get_or_init(|| {
+        Regex::new(concat!(
+            // Control Sequence Introducer (CSI) sequences, such as colours
+            // and cursor movements: `ESC [`, parameter and intermediate
+            // bytes, and a final byte.
+            r"\x1b\[[0-?]*[ -/]*[@-~]",
+            // Operating System Command (OSC) sequences, such as window
+            // titles and hyperlinks, terminated by `BEL` or `ESC \`.
+            r"|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)?",
+            // Any other two-byte escape sequence.
+            r"|\x1b[@-Z\\-_]",
+        ))
+        .expect("invalid escape sequence pattern")
+    })
+}
+
+pub fn strip(line: String) -> String {
+    match escape_sequence().replace_all(&line, "") {
+        std::borrow::Cow::Owned(stripped) => stripped,
+        std::borrow::Cow::Borrowed(_) => line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_escape_sequences() {
+        for (line, expected) in [
+            ("\x1b[31merror\x1b[0m: failed", "error: failed"),
+            ("\x1b[1;38;5;208mbold orange\x1b[m", "bold orange"),
+            ("\x1b[2K\x1b[1Gprogress 50%", "progress 50%"),
+            ("\x1b]0;some title\x07done", "done"),
+            (
+                "\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\",
+                "link",
+            ),
+            ("\x1bMreverse index", "reverse index"),
+            ("plain [31m text", "plain [31m text"),
+        ] {
+            assert_eq!(strip(line.to_string()), expected, "{line:?}");
+        }
+    }
+}
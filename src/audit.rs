@@ -0,0 +1,145 @@
+// Checks, when the wrapper exits, that the resources it used while running
+// the command were released: that every task it spawned has finished, that
+// the pipes from the command's output were closed, and that the PID file was
+// removed. Resources that were not released are reported as warnings, and
+// listed in the request summary, so that leaks are noticed before they add
+// up in long-lived wrappers.
+//
+// The wrapper does not allocate a pseudo-terminal for the command, so there
+// is no terminal to check for.
+
+use std::os::fd::RawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+use tokio::time::{sleep, Instant};
+
+// How long to wait for tasks that are finishing, such as the ones that
+// forward lines between channels, before reporting them.
+const TASKS_GRACE_PERIOD: Duration = Duration::from_millis(100);
+
+pub struct Audit {
+    // The pipes from the command's output, by their name, file descriptor,
+    // and the target of their `/proc/self/fd` link, which identifies the
+    // pipe even if its file descriptor number is reused.
+    pipes: Vec<(&'static str, RawFd, PathBuf)>,
+    pid_file: Option<PathBuf>,
+    // Whether to check that every task has finished.
+    tasks: bool,
+}
+
+impl Audit {
+    pub fn new(pid_file: Option<&Path>) -> Self {
+        Self {
+            pipes: Vec::new(),
+            pid_file: pid_file.map(Path::to_path_buf),
+            tasks: true,
+        }
+    }
+
+    // Records a pipe to check that it is closed on exit. Pipes can only be
+    // told apart on Linux, where `/proc/self/fd` is available, and are not
+    // checked elsewhere.
+    pub fn pipe(&mut self, name: &'static str, fd: Option<RawFd>) {
+        if let Some((fd, target)) = fd.and_then(|fd| Some((fd, fd_target(fd)?))) {
+            self.pipes.push((name, fd, target));
+        }
+    }
+
+    // Skips checking that every task has finished, such as when the wrapper
+    // stopped waiting for them on purpose.
+    pub fn ignore_tasks(&mut self) {
+        self.tasks = false;
+    }
+
+    // Returns a description of each resource that was not released.
+    pub async fn check(&self) -> Vec<String> {
+        let mut leaks = Vec::new();
+
+        if self.tasks {
+            let tasks = alive_tasks().await;
+            if tasks > 0 {
+                leaks.push(format!("{} tasks still running", tasks));
+            }
+        }
+
+        for (name, fd, target) in &self.pipes {
+            if fd_target(*fd).as_ref() == Some(target) {
+                leaks.push(format!("pipe from {} (fd {}) not closed", name, fd));
+            }
+        }
+
+        if let Some(path) = self.pid_file.as_ref() {
+            if path.exists() {
+                leaks.push(format!("PID file {} not removed", path.display()));
+            }
+        }
+
+        leaks
+    }
+}
+
+fn fd_target(fd: RawFd) -> Option<PathBuf> {
+    std::fs::read_link(format!("/proc/self/fd/{fd}")).ok()
+}
+
+// Returns the number of tasks in the runtime that are still running, after
+// waiting for a short time for them to finish.
+async fn alive_tasks() -> usize {
+    let metrics = Handle::current().metrics();
+    let deadline = Instant::now() + TASKS_GRACE_PERIOD;
+
+    while metrics.num_alive_tasks() > 0 && Instant::now() < deadline {
+        sleep(Duration::from_millis(5)).await;
+    }
+
+    metrics.num_alive_tasks()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn audit_check() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let pid_file = std::env::temp_dir().join(format!("{}-audit-test.pid", std::process::id()));
+        std::fs::write(&pid_file, "1234\n").unwrap();
+
+        let (stream, _other) = UnixStream::pair().unwrap();
+        let fd = stream.as_raw_fd();
+
+        let mut audit = Audit::new(Some(&pid_file));
+        audit.pipe("standard output", Some(fd));
+        audit.pipe("standard error", None);
+
+        runtime.block_on(async {
+            let task = tokio::spawn(std::future::pending::<()>());
+
+            assert_eq!(
+                audit.check().await,
+                vec![
+                    "1 tasks still running".to_string(),
+                    format!("pipe from standard output (fd {fd}) not closed"),
+                    format!("PID file {} not removed", pid_file.display()),
+                ]
+            );
+
+            audit.ignore_tasks();
+            assert_eq!(audit.check().await.len(), 2);
+
+            task.abort();
+            drop(stream);
+            std::fs::remove_file(&pid_file).unwrap();
+
+            assert!(audit.check().await.is_empty());
+        });
+    }
+}
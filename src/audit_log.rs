@@ -0,0 +1,199 @@
+// Appends a record of each invocation of the wrapper to a local audit log,
+// for a trail on the host of who ran which command, and how it ended, that
+// does not depend on the data sent to AppSignal.
+//
+// Each record is a single line of JSON, appended to a file, or sent to the
+// system log with the `authpriv` facility.
+
+use std::ffi::CString;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use nix::unistd::{getuid, User};
+use serde::Serialize;
+
+use crate::package::NAME;
+use crate::timestamp::Timestamp;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditDestination {
+    File(PathBuf),
+    Syslog,
+}
+
+impl AuditDestination {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "" => Err("expected a file path, or `syslog`".to_string()),
+            "syslog" => Ok(Self::Syslog),
+            path => Ok(Self::File(PathBuf::from(path))),
+        }
+    }
+}
+
+pub struct AuditLog {
+    pub destination: AuditDestination,
+    pub name: String,
+    pub command: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    uid: u32,
+    user: Option<String>,
+    // The user who ran the wrapper through `sudo`, if any.
+    sudo_user: Option<String>,
+    pid: u32,
+    name: &'a str,
+    command: &'a str,
+    digest: Option<&'a str>,
+    #[serde(flatten)]
+    outcome: Outcome<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome<'a> {
+    // The wrapper exited with this code, which is the command's exit code,
+    // or `128` plus the signal that terminated it.
+    Exited { exit_code: i32 },
+    // The wrapper could not run the command.
+    Error { error: &'a str },
+}
+
+impl AuditLog {
+    // Appends a record of the invocation to the audit log. The digest is not
+    // known if the configuration could not be built.
+    pub fn write(
+        &self,
+        timestamp: &mut impl Timestamp,
+        digest: Option<&str>,
+        outcome: Outcome,
+    ) -> Result<(), String> {
+        let line = self.record(timestamp, digest, outcome);
+
+        match &self.destination {
+            AuditDestination::File(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(format!("{line}\n").as_bytes()))
+                .map_err(|err| format!("could not write audit log {}: {}", path.display(), err)),
+            AuditDestination::Syslog => {
+                syslog(libc::LOG_AUTHPRIV | libc::LOG_INFO, &line);
+                Ok(())
+            }
+        }
+    }
+
+    fn record(
+        &self,
+        timestamp: &mut impl Timestamp,
+        digest: Option<&str>,
+        outcome: Outcome,
+    ) -> String {
+        let uid = getuid();
+
+        let record = Record {
+            timestamp: timestamp.as_rfc3339(),
+            uid: uid.as_raw(),
+            user: User::from_uid(uid).ok().flatten().map(|user| user.name),
+            sudo_user: std::env::var("SUDO_USER").ok(),
+            pid: std::process::id(),
+            name: &self.name,
+            command: &self.command,
+            digest,
+            outcome,
+        };
+
+        serde_json::to_string(&record).expect("failed to serialize audit record")
+    }
+}
+
+// Sends the line to the system log, with the given facility and level.
+pub fn syslog(priority: libc::c_int, line: &str) {
+    // `openlog` keeps a pointer to the identifier, so it must be static.
+    static IDENT: OnceLock<CString> = OnceLock::new();
+    let ident = IDENT.get_or_init(|| CString::new(NAME).unwrap());
+
+    let format = CString::new("%s").unwrap();
+    let Ok(message) = CString::new(line) else {
+        return;
+    };
+
+    unsafe {
+        libc::openlog(ident.as_ptr(), libc::LOG_PID, libc::LOG_USER);
+        libc::syslog(priority, format.as_ptr(), message.as_ptr());
+        libc::closelog();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp::tests::timestamp;
+
+    #[test]
+    fn audit_destination_parse() {
+        assert_eq!(
+            AuditDestination::parse("syslog"),
+            Ok(AuditDestination::Syslog)
+        );
+        assert_eq!(
+            AuditDestination::parse("/var/log/appsignal-run.log"),
+            Ok(AuditDestination::File(PathBuf::from(
+                "/var/log/appsignal-run.log"
+            )))
+        );
+        assert!(AuditDestination::parse("").is_err());
+    }
+
+    #[test]
+    fn audit_log_write() {
+        let path = std::env::temp_dir().join(format!("{}-audit-log-test", std::process::id()));
+
+        let log = AuditLog {
+            destination: AuditDestination::File(path.clone()),
+            name: "some-name".to_string(),
+            command: "some-command --some-arg".to_string(),
+        };
+
+        log.write(
+            &mut timestamp(),
+            Some("some-digest"),
+            Outcome::Exited { exit_code: 3 },
+        )
+        .unwrap();
+        log.write(
+            &mut timestamp(),
+            None,
+            Outcome::Error {
+                error: "no command to execute",
+            },
+        )
+        .unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let records: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["name"], "some-name");
+        assert_eq!(records[0]["command"], "some-command --some-arg");
+        assert_eq!(records[0]["digest"], "some-digest");
+        assert_eq!(records[0]["outcome"], "exited");
+        assert_eq!(records[0]["exit_code"], 3);
+        assert_eq!(records[0]["pid"], std::process::id());
+        assert_eq!(records[0]["uid"], getuid().as_raw());
+        assert_eq!(records[1]["digest"], serde_json::Value::Null);
+        assert_eq!(records[1]["outcome"], "error");
+        assert_eq!(records[1]["error"], "no command to execute");
+    }
+}
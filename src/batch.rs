@@ -0,0 +1,162 @@
+// Reads the summary line that a batch job writes to its standard output,
+// with `--batch-summary`, such as:
+//
+//     appsignal_summary: processed=1000 failed=3
+//
+// The values in the last summary line are added as tags to the error report
+// for a failure and, if `--metrics` is set, sent as gauges once the command
+// exits. With `--warn-on-batch-failures`, a command that exits successfully
+// but reports failed items is treated as a partial success: a warning is
+// written and sent as a log line, while the exit is still not reported as
+// an error, and the cron finish check-in is still sent.
+
+use std::collections::BTreeMap;
+
+use crate::metrics::{Metric, MetricType};
+
+// The prefix of the summary line.
+pub const PREFIX: &str = "appsignal_summary:";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub values: BTreeMap<String, u64>,
+}
+
+impl BatchSummary {
+    // Parses the summary line, if the line is one. A line with the prefix
+    // whose values are not all `KEY=NUMBER` pairs is not a summary line.
+    pub fn parse(line: &str) -> Option<Self> {
+        let pairs = line.trim().strip_prefix(PREFIX)?;
+
+        let values = pairs
+            .split_whitespace()
+            .map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                let valid = key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+                if key.is_empty() || !valid {
+                    return None;
+                }
+
+                Some((key.to_string(), value.parse().ok()?))
+            })
+            .collect::<Option<BTreeMap<_, _>>>()?;
+
+        (!values.is_empty()).then_some(Self { values })
+    }
+
+    // The number of items that failed, if the summary reports any.
+    pub fn failed(&self) -> u64 {
+        self.values.get("failed").copied().unwrap_or(0)
+    }
+
+    // The values as tags, prefixed with `batch_`.
+    pub fn tags(&self) -> BTreeMap<String, String> {
+        self.values
+            .iter()
+            .map(|(key, value)| (format!("batch_{key}"), value.to_string()))
+            .collect()
+    }
+
+    // The values as gauges, prefixed with `batch_`.
+    pub fn metrics(&self) -> Vec<Metric> {
+        self.values
+            .iter()
+            .map(|(key, value)| Metric {
+                name: format!("batch_{key}"),
+                metric_type: MetricType::Gauge,
+                value: *value as f64,
+                tags: BTreeMap::new(),
+            })
+            .collect()
+    }
+
+    // Describes the values, sorted by their keys.
+    pub fn describe(&self) -> String {
+        self.values
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+// Keeps the last summary line read from the command's standard output.
+#[derive(Debug, Default)]
+pub struct SummaryReader(Option<BatchSummary>);
+
+impl SummaryReader {
+    pub fn line(&mut self, line: &str) {
+        if let Some(summary) = BatchSummary::parse(line) {
+            self.0 = Some(summary);
+        }
+    }
+
+    pub fn summary(self) -> Option<BatchSummary> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_summary_parse() {
+        let summary = BatchSummary::parse("appsignal_summary: processed=1000 failed=3")
+            .expect("expected a summary");
+
+        assert_eq!(summary.failed(), 3);
+        assert_eq!(summary.describe(), "failed=3 processed=1000");
+        assert_eq!(
+            summary.tags(),
+            BTreeMap::from([
+                ("batch_failed".to_string(), "3".to_string()),
+                ("batch_processed".to_string(), "1000".to_string()),
+            ])
+        );
+
+        let summary = BatchSummary::parse("  appsignal_summary:skipped=2\r").unwrap();
+        assert_eq!(summary.failed(), 0);
+
+        for line in [
+            "processed=1000 failed=3",
+            "appsignal_summary:",
+            "appsignal_summary: processed=many",
+            "appsignal_summary: processed=-1",
+            "appsignal_summary: processed",
+            "appsignal_summary: =3",
+            "appsignal_summary: items.failed=3",
+            "some output appsignal_summary: failed=3",
+        ] {
+            assert_eq!(BatchSummary::parse(line), None, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn batch_summary_metrics() {
+        let summary = BatchSummary::parse("appsignal_summary: failed=3").unwrap();
+        let metrics = summary.metrics();
+
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].name, "batch_failed");
+        assert_eq!(metrics[0].metric_type, MetricType::Gauge);
+        assert_eq!(metrics[0].value, 3.0);
+    }
+
+    #[test]
+    fn batch_summary_reader_keeps_last() {
+        let mut reader = SummaryReader::default();
+        reader.line("appsignal_summary: processed=10");
+        reader.line("some output");
+        reader.line("appsignal_summary: processed=20 failed=1");
+        reader.line("appsignal_summary: processed=oops");
+
+        assert_eq!(
+            reader.summary().map(|summary| summary.describe()),
+            Some("failed=1 processed=20".to_string())
+        );
+    }
+}
@@ -0,0 +1,265 @@
+// Runs two commands for the `canary` subcommand, and compares how they
+// exited and, optionally, what they wrote to standard output, so that a new
+// implementation of a job can be checked against the one it replaces while
+// both are run.
+//
+// The first command is the reference: its output is written to the
+// wrapper's output, and the wrapper exits with its exit code, as if it was
+// run on its own. The second command is the candidate: its output is only
+// read to be compared, and its last lines are included in the error that is
+// reported when the commands diverge.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::process::{ExitStatus, Stdio};
+use std::sync::Mutex;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+
+use crate::error::{exit_context, MessageLines, DEFAULT_MESSAGE_LINES};
+use crate::splitter::{LineSplitter, READ_CHUNK_SIZE};
+
+// Separates the reference command from the candidate command.
+pub const SEPARATOR: &str = "----";
+
+// Splits the arguments into the reference command and the candidate
+// command, at the separator.
+pub fn split_commands(args: &[String]) -> Result<(Vec<String>, Vec<String>), String> {
+    match args.iter().position(|arg| arg == SEPARATOR) {
+        Some(index) if index > 0 && index + 1 < args.len() => {
+            Ok((args[..index].to_vec(), args[index + 1..].to_vec()))
+        }
+        _ => Err(format!("expected two commands, separated by `{SEPARATOR}`")),
+    }
+}
+
+#[derive(Debug)]
+pub struct Run {
+    pub exit: ExitStatus,
+    // The digest of the command's standard output.
+    pub digest: String,
+    // The last lines of the command's standard output and standard error.
+    pub lines: Vec<String>,
+}
+
+// Runs the command until it exits. If `passthrough` is set, its output is
+// written to the wrapper's output as it is read.
+pub async fn run(command: &[String], passthrough: bool) -> io::Result<Run> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child.stdout.take().expect("failed to capture stdout");
+    let stderr = child.stderr.take().expect("failed to capture stderr");
+    let lines = Mutex::new(MessageLines::new(DEFAULT_MESSAGE_LINES, None));
+
+    let (digest, _, exit) = tokio::join!(
+        read(stdout, passthrough.then(io::stdout), &lines),
+        read(stderr, passthrough.then(io::stderr), &lines),
+        child.wait(),
+    );
+
+    let lines = lines.into_inner().unwrap().to_vec();
+
+    Ok(Run {
+        exit: exit?,
+        digest: format!("{digest:016x}"),
+        lines,
+    })
+}
+
+// Reads the stream until it is closed, writing it to the given writer, if
+// any, and keeping its last lines. Returns the digest of its contents.
+async fn read(
+    mut from: impl AsyncRead + Unpin,
+    mut to: Option<impl Write>,
+    lines: &Mutex<MessageLines>,
+) -> u64 {
+    let mut buffer = vec![0; READ_CHUNK_SIZE];
+    let mut splitter = LineSplitter::default();
+    let mut digest = Digest::default();
+
+    loop {
+        let chunk = match from.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => &buffer[..read],
+        };
+
+        digest.update(chunk);
+
+        if let Some(to) = to.as_mut() {
+            let _ = to.write_all(chunk).and_then(|_| to.flush());
+        }
+
+        let mut lines = lines.lock().unwrap();
+        for line in splitter.push(chunk) {
+            lines.push(line.text);
+        }
+    }
+
+    if let Some(line) = splitter.finish() {
+        lines.lock().unwrap().push(line.text);
+    }
+
+    digest.0
+}
+
+// A 64-bit FNV-1a hash, which is stable across releases, unlike the
+// standard library's hasher. The digests are only compared to each other,
+// so a cryptographic hash is not needed.
+struct Digest(u64);
+
+impl Default for Digest {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Digest {
+    fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+// Describes how the candidate diverged from the reference, if it did. The
+// candidate could not be run if it is an error.
+pub fn differences(
+    reference: &Run,
+    candidate: &Result<Run, String>,
+    compare_output: bool,
+) -> Vec<String> {
+    let candidate = match candidate {
+        Ok(candidate) => candidate,
+        Err(err) => return vec![format!("the candidate could not be run: {err}")],
+    };
+
+    let mut differences = Vec::new();
+
+    if reference.exit != candidate.exit {
+        differences.push(format!(
+            "the reference exited with {}, but the candidate exited with {}",
+            exit_context(&reference.exit).1,
+            exit_context(&candidate.exit).1
+        ));
+    }
+
+    if compare_output && reference.digest != candidate.digest {
+        differences.push(format!(
+            "the standard output of the reference (digest {}) differs from the \
+             candidate's (digest {})",
+            reference.digest, candidate.digest
+        ));
+    }
+
+    differences
+}
+
+// The tags of the error reported when the commands diverge, with how each
+// of them exited and the digests of their standard output.
+pub fn tags(reference: &Run, candidate: &Result<Run, String>) -> BTreeMap<String, String> {
+    let mut tags = BTreeMap::from([
+        (
+            "reference_exit".to_string(),
+            exit_context(&reference.exit).1,
+        ),
+        ("reference_digest".to_string(), reference.digest.clone()),
+    ]);
+
+    match candidate {
+        Ok(candidate) => {
+            tags.insert(
+                "candidate_exit".to_string(),
+                exit_context(&candidate.exit).1,
+            );
+            tags.insert("candidate_digest".to_string(), candidate.digest.clone());
+        }
+        Err(_) => {
+            tags.insert("candidate_exit".to_string(), "not run".to_string());
+        }
+    }
+
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn canary_split_commands() {
+        assert_eq!(
+            split_commands(&args(&["./old.sh", "-v", "----", "./new.sh"])),
+            Ok((args(&["./old.sh", "-v"]), args(&["./new.sh"])))
+        );
+
+        for invalid in [
+            vec!["./old.sh"],
+            vec!["----", "./new.sh"],
+            vec!["./old.sh", "----"],
+        ] {
+            assert_eq!(
+                split_commands(&args(&invalid)),
+                Err("expected two commands, separated by `----`".to_string()),
+                "{invalid:?} should fail"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn canary_run() {
+        let run = run(
+            &args(&["sh", "-c", "echo one; echo two >&2; exit 3"]),
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(run.exit.code(), Some(3));
+        assert_eq!(run.lines.len(), 2);
+
+        let mut digest = Digest::default();
+        digest.update(b"one\n");
+        assert_eq!(run.digest, format!("{:016x}", digest.0));
+
+        assert!(super::run(&args(&["/nonexistent"]), false).await.is_err());
+    }
+
+    #[test]
+    fn canary_differences() {
+        let run = |code: i32, digest: &str| Run {
+            exit: ExitStatus::from_raw(code << 8),
+            digest: digest.to_string(),
+            lines: vec![],
+        };
+
+        let reference = run(0, "a");
+        assert!(differences(&reference, &Ok(run(0, "a")), true).is_empty());
+        assert!(differences(&reference, &Ok(run(0, "b")), false).is_empty());
+
+        assert_eq!(
+            differences(&reference, &Ok(run(1, "b")), true),
+            vec![
+                "the reference exited with code 0, but the candidate exited with code 1",
+                "the standard output of the reference (digest a) differs from the \
+                 candidate's (digest b)"
+            ]
+        );
+
+        assert_eq!(
+            differences(&reference, &Err("not found".to_string()), true),
+            vec!["the candidate could not be run: not found"]
+        );
+    }
+}
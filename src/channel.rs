@@ -2,70 +2,53 @@ use std::future::Future;
 
 use ::log::debug;
 
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::{channel, Receiver, UnboundedReceiver};
 
-pub async fn maybe_recv<T>(receiver: &mut Option<UnboundedReceiver<T>>) -> Option<Option<T>> {
-    match receiver {
-        Some(receiver) => Some(receiver.recv().await),
-        None => None,
-    }
+// The receiving half of a channel, whether it is bounded or not.
+pub trait Receive<T> {
+    fn receive(&mut self) -> impl Future<Output = Option<T>> + Send;
 }
 
-pub fn maybe_spawn_tee<T: Clone + Send + 'static>(
-    receiver: Option<UnboundedReceiver<T>>,
-) -> (Option<UnboundedReceiver<T>>, Option<UnboundedReceiver<T>>) {
-    match receiver {
-        Some(receiver) => {
-            let (first_receiver, second_receiver) = spawn_tee(receiver);
-            (Some(first_receiver), Some(second_receiver))
-        }
-        None => (None, None),
+impl<T: Send> Receive<T> for Receiver<T> {
+    fn receive(&mut self) -> impl Future<Output = Option<T>> + Send {
+        self.recv()
     }
 }
 
-// An utility function that takes an unbounded receiver and returns two
-// unbounded receivers that will receive the same items, spawning a task
-// to read from the given receiver and write to the returned receivers.
-// The items must implement the `Clone` trait.
-pub fn spawn_tee<T: Clone + Send + 'static>(
-    receiver: UnboundedReceiver<T>,
-) -> (UnboundedReceiver<T>, UnboundedReceiver<T>) {
-    let (future, first_receiver, second_receiver) = tee(receiver);
-
-    tokio::spawn(future);
-
-    (first_receiver, second_receiver)
+impl<T: Send> Receive<T> for UnboundedReceiver<T> {
+    fn receive(&mut self) -> impl Future<Output = Option<T>> + Send {
+        self.recv()
+    }
 }
 
-fn tee<T: Clone + Send + 'static>(
-    receiver: UnboundedReceiver<T>,
-) -> (
-    impl Future<Output = ()>,
-    UnboundedReceiver<T>,
-    UnboundedReceiver<T>,
-) {
-    let (first_sender, first_receiver) = unbounded_channel();
-    let (second_sender, second_receiver) = unbounded_channel();
-
-    let future = tee_loop(receiver, first_sender, second_sender);
-
-    (future, first_receiver, second_receiver)
+pub async fn maybe_recv<T>(receiver: &mut Option<impl Receive<T>>) -> Option<Option<T>> {
+    match receiver {
+        Some(receiver) => Some(receiver.receive().await),
+        None => None,
+    }
 }
 
-async fn tee_loop<T: Clone + Send + 'static>(
-    mut receiver: UnboundedReceiver<T>,
-    first_sender: UnboundedSender<T>,
-    second_sender: UnboundedSender<T>,
-) {
-    while let Some(item) = receiver.recv().await {
-        if let Err(err) = first_sender.send(item.clone()) {
-            debug!("error sending item to first receiver: {}", err);
-            break;
+// Takes a receiver and returns a receiver that will receive the same items,
+// transformed by the given function, spawning a task to read from the given
+// receiver and write to the returned receiver. The returned receiver holds
+// as many items as the given buffer, after which the task waits for it to
+// be read from.
+pub fn maybe_spawn_map<T: Send + 'static, U: Send + 'static>(
+    receiver: Option<Receiver<T>>,
+    buffer: usize,
+    f: impl Fn(T) -> U + Send + 'static,
+) -> Option<Receiver<U>> {
+    let mut receiver = receiver?;
+    let (sender, mapped_receiver) = channel(buffer);
+
+    tokio::spawn(async move {
+        while let Some(item) = receiver.recv().await {
+            if let Err(err) = sender.send(f(item)).await {
+                debug!("error sending mapped item: {}", err);
+                break;
+            }
         }
+    });
 
-        if let Err(err) = second_sender.send(item) {
-            debug!("error sending item to second receiver: {}", err);
-            break;
-        }
-    }
+    Some(mapped_receiver)
 }
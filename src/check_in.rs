@@ -1,14 +1,37 @@
-use crate::client::client;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::client::{check_in_timeout, client};
+use crate::log::Pattern;
+use crate::schedule::CronSchedule;
 use crate::timestamp::Timestamp;
+use crate::units::deserialize_duration;
+use crate::wrap::{default_endpoint, random_digest, require, validate_endpoint};
+use chrono::{DateTime, Local, TimeZone};
+use clap::ValueEnum;
+use log::{debug, warn};
 use reqwest::Request;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
 
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CheckInConfig {
     pub api_key: String,
+    #[serde(default = "default_endpoint")]
     pub endpoint: String,
     pub identifier: String,
 }
 
+impl CheckInConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        require(&self.api_key, "check-in API key")?;
+        validate_endpoint(&self.endpoint)?;
+        require(&self.identifier, "check-in identifier")
+    }
+}
+
 #[derive(Serialize)]
 struct CheckInQuery {
     api_key: String,
@@ -18,34 +41,32 @@ struct CheckInQuery {
     kind: Option<CronKind>,
     #[serde(skip_serializing_if = "Option::is_none")]
     digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schedule: Option<String>,
 }
 
 impl CheckInQuery {
     pub fn from_cron(config: &CronConfig, timestamp: &mut impl Timestamp, kind: CronKind) -> Self {
-        Self::from_config(
-            &config.check_in,
-            timestamp,
-            Some(kind),
-            Some(config.digest.clone()),
-        )
+        Self {
+            kind: Some(kind),
+            digest: Some(config.digest.clone()),
+            schedule: config.schedule.clone(),
+            ..Self::from_config(&config.check_in, timestamp)
+        }
     }
 
     pub fn from_heartbeat(config: &HeartbeatConfig, timestamp: &mut impl Timestamp) -> Self {
-        Self::from_config(&config.check_in, timestamp, None, None)
+        Self::from_config(&config.check_in, timestamp)
     }
 
-    fn from_config(
-        config: &CheckInConfig,
-        timestamp: &mut impl Timestamp,
-        kind: Option<CronKind>,
-        digest: Option<String>,
-    ) -> Self {
+    fn from_config(config: &CheckInConfig, timestamp: &mut impl Timestamp) -> Self {
         Self {
             api_key: config.api_key.clone(),
             identifier: config.identifier.clone(),
             timestamp: timestamp.as_secs(),
-            kind,
-            digest,
+            kind: None,
+            digest: None,
+            schedule: None,
         }
     }
 }
@@ -57,12 +78,39 @@ pub enum CronKind {
     Finish,
 }
 
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CronConfig {
     pub check_in: CheckInConfig,
+    #[serde(default = "random_digest")]
     pub digest: String,
+    // The schedule the command is expected to run on, as a cron expression.
+    // It is sent alongside the check-ins for display purposes, and used to
+    // tell how late the command started.
+    #[serde(default)]
+    pub schedule: Option<String>,
 }
 
 impl CronConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.check_in.validate()?;
+
+        match &self.schedule {
+            Some(schedule) => parse_schedule(schedule).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    // How long after its last scheduled time, in the host's local time, the
+    // command started at the given time.
+    pub fn schedule_drift(&self, started: DateTime<Local>) -> Option<Duration> {
+        let schedule = CronSchedule::parse(self.schedule.as_ref()?).ok()?;
+        let scheduled = schedule.last_before(started.naive_local())?;
+        let scheduled = Local.from_local_datetime(&scheduled).earliest()?;
+
+        (started - scheduled).to_std().ok()
+    }
+
     pub fn request(
         &self,
         timestamp: &mut impl Timestamp,
@@ -73,25 +121,164 @@ impl CronConfig {
         client()
             .post(url)
             .query(&CheckInQuery::from_cron(self, timestamp, kind))
+            .timeout(check_in_timeout())
             .build()
     }
 }
 
+// Checks that the given value is a cron expression, with five fields
+// separated by whitespace, such as `15 3 * * *`, and normalizes the
+// whitespace between them.
+pub fn parse_schedule(value: &str) -> Result<String, String> {
+    CronSchedule::parse(value)?;
+
+    Ok(value.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct HeartbeatConfig {
     pub check_in: CheckInConfig,
+    // The interval is given in seconds, or with units, when deserialized.
+    #[serde(
+        default = "default_heartbeat_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub interval: Duration,
+    // Whether to send heartbeats while the command is idle. If not, a
+    // heartbeat is only sent when the command's output shows that it has
+    // been active since the previous one.
+    #[serde(default = "default_on_idle")]
+    pub on_idle: bool,
+    // The lines of output that show that the command is active. If empty,
+    // any line does.
+    #[serde(default)]
+    pub activity_patterns: Vec<Pattern>,
+    // A shell command that checks whether the command is healthy. If set,
+    // a heartbeat is only sent when it exits successfully.
+    #[serde(default)]
+    pub health_cmd: Option<String>,
+}
+
+// How long to wait for the health command to exit before considering the
+// command unhealthy.
+const HEALTH_CMD_TIMEOUT: Duration = Duration::from_secs(10);
+
+// What to do when the wrapper receives `SIGUSR2`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Usr2Action {
+    // Forward the signal to the child process, like other signals.
+    #[default]
+    Forward,
+    // Send a heartbeat check-in immediately, without forwarding the signal.
+    Heartbeat,
+}
+
+fn default_on_idle() -> bool {
+    true
+}
+
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+fn default_heartbeat_interval() -> Duration {
+    DEFAULT_HEARTBEAT_INTERVAL
 }
 
 impl HeartbeatConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.check_in.validate()?;
+
+        if self.interval.is_zero() {
+            return Err("the heartbeat interval must be greater than zero".to_string());
+        }
+
+        Ok(())
+    }
+
+    // Tracks the activity of the command, if heartbeats are not sent while
+    // it is idle.
+    pub fn activity(&self) -> Option<Activity> {
+        if self.on_idle {
+            return None;
+        }
+
+        Some(Activity {
+            patterns: self.activity_patterns.clone(),
+            active: AtomicBool::new(false),
+        })
+    }
+
+    // Runs the health command, if there is one, and returns whether it
+    // exited successfully. The command is killed if it does not exit in
+    // time, or if the future is dropped.
+    pub async fn is_healthy(&self) -> bool {
+        let Some(command) = self.health_cmd.as_ref() else {
+            return true;
+        };
+
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status();
+
+        match tokio::time::timeout(HEALTH_CMD_TIMEOUT, status).await {
+            Ok(Ok(status)) if status.success() => true,
+            Ok(Ok(status)) => {
+                debug!("health command failed with {status}, skipping heartbeat");
+                false
+            }
+            Ok(Err(err)) => {
+                warn!("could not run health command `{command}`: {err}");
+                false
+            }
+            Err(_) => {
+                debug!(
+                    "health command did not finish within {} seconds, skipping heartbeat",
+                    HEALTH_CMD_TIMEOUT.as_secs()
+                );
+                false
+            }
+        }
+    }
+
     pub fn request(&self, timestamp: &mut impl Timestamp) -> Result<Request, reqwest::Error> {
         let url = format!("{}/check_ins/heartbeats", self.check_in.endpoint);
 
         client()
             .post(url)
             .query(&CheckInQuery::from_heartbeat(self, timestamp))
+            .timeout(check_in_timeout())
             .build()
     }
 }
 
+// Whether the command has been active since the last heartbeat, as shown by
+// the lines of its output.
+pub struct Activity {
+    patterns: Vec<Pattern>,
+    active: AtomicBool,
+}
+
+impl Activity {
+    pub fn observe(&self, line: &str) {
+        if self.patterns.is_empty() || self.patterns.iter().any(|pattern| pattern.0.is_match(line))
+        {
+            self.active.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Returns whether the command has been active since this was last
+    // called.
+    pub fn take(&self) -> bool {
+        self.active.swap(false, Ordering::Relaxed)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -110,6 +297,7 @@ mod tests {
         let config = CronConfig {
             check_in: check_in_config(),
             digest: "some-digest".to_string(),
+            schedule: None,
         };
 
         let request = config.request(&mut timestamp(), CronKind::Start).unwrap();
@@ -131,10 +319,73 @@ mod tests {
         );
     }
 
+    #[test]
+    fn cron_config_request_with_schedule() {
+        let config = CronConfig {
+            check_in: check_in_config(),
+            digest: "some-digest".to_string(),
+            schedule: Some("15 3 * * *".to_string()),
+        };
+
+        let request = config.request(&mut timestamp(), CronKind::Finish).unwrap();
+
+        assert_eq!(
+            request.url().query(),
+            Some(
+                format!(
+                    concat!(
+                        "api_key=some_api_key",
+                        "&identifier=some-identifier",
+                        "&timestamp={}",
+                        "&kind=finish",
+                        "&digest=some-digest",
+                        "&schedule=15+3+*+*+*"
+                    ),
+                    EXPECTED_SECS
+                )
+                .as_str()
+            )
+        );
+    }
+
+    #[test]
+    fn cron_config_schedule_drift() {
+        let mut config = CronConfig {
+            check_in: check_in_config(),
+            digest: "some-digest".to_string(),
+            schedule: None,
+        };
+        let started = Local.with_ymd_and_hms(2024, 3, 13, 10, 7, 42).unwrap();
+
+        assert_eq!(config.schedule_drift(started), None);
+
+        config.schedule = Some("*/5 * * * *".to_string());
+        assert_eq!(
+            config.schedule_drift(started),
+            Some(Duration::from_secs(2 * 60 + 42))
+        );
+    }
+
+    #[test]
+    fn parse_cron_schedule() {
+        assert_eq!(
+            parse_schedule(" 15  3 * * 1-5 "),
+            Ok("15 3 * * 1-5".to_string())
+        );
+
+        for value in ["", "* * * *", "0 * * * * *", "0 25 * * *"] {
+            assert!(parse_schedule(value).is_err(), "{value:?} should fail");
+        }
+    }
+
     #[test]
     fn heartbeat_config_request() {
         let config = HeartbeatConfig {
             check_in: check_in_config(),
+            interval: DEFAULT_HEARTBEAT_INTERVAL,
+            on_idle: true,
+            activity_patterns: vec![],
+            health_cmd: None,
         };
 
         let request = config.request(&mut timestamp()).unwrap();
@@ -153,4 +404,48 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn heartbeat_config_activity() {
+        let mut config = HeartbeatConfig {
+            check_in: check_in_config(),
+            interval: DEFAULT_HEARTBEAT_INTERVAL,
+            on_idle: true,
+            activity_patterns: vec![],
+            health_cmd: None,
+        };
+        assert!(config.activity().is_none());
+
+        config.on_idle = false;
+        let activity = config.activity().unwrap();
+        assert!(!activity.take());
+        activity.observe("any line");
+        assert!(activity.take());
+        assert!(!activity.take());
+
+        config.activity_patterns = vec![Pattern::parse("^processing job").unwrap()];
+        let activity = config.activity().unwrap();
+        activity.observe("waiting for jobs");
+        assert!(!activity.take());
+        activity.observe("processing job 42");
+        assert!(activity.take());
+    }
+
+    #[tokio::test]
+    async fn heartbeat_config_is_healthy() {
+        let mut config = HeartbeatConfig {
+            check_in: check_in_config(),
+            interval: DEFAULT_HEARTBEAT_INTERVAL,
+            on_idle: true,
+            activity_patterns: vec![],
+            health_cmd: None,
+        };
+        assert!(config.is_healthy().await);
+
+        config.health_cmd = Some("test -d /".to_string());
+        assert!(config.is_healthy().await);
+
+        config.health_cmd = Some("exit 1".to_string());
+        assert!(!config.is_healthy().await);
+    }
 }
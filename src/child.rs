@@ -0,0 +1,100 @@
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use tokio::process::{Child, ChildStdin};
+use tokio::task::JoinHandle;
+
+// The child process whose lifetime is tracked by the wrapper.
+//
+// Usually, this is a child process spawned by this wrapper. After an
+// upgrade (see `upgrade.rs`) the wrapper process is re-executed, and the
+// child process that was spawned before the upgrade is inherited by the
+// new wrapper process. As it was not spawned by `tokio::process`, it needs
+// to be waited on using `waitpid` directly.
+pub enum ChildProcess {
+    Spawned(Child),
+    Inherited {
+        pid: u32,
+        wait: Option<JoinHandle<io::Result<ExitStatus>>>,
+    },
+}
+
+impl ChildProcess {
+    pub fn inherited(pid: u32) -> Self {
+        Self::Inherited { pid, wait: None }
+    }
+
+    pub fn id(&self) -> Option<u32> {
+        match self {
+            Self::Spawned(child) => child.id(),
+            Self::Inherited { pid, wait } => match wait {
+                Some(wait) if wait.is_finished() => None,
+                _ => Some(*pid),
+            },
+        }
+    }
+
+    // Takes the pipe to the child process' standard input, if it was
+    // spawned with one. Inherited processes never have one, as the pipe is
+    // not passed on upgrade.
+    pub fn take_stdin(&mut self) -> Option<ChildStdin> {
+        match self {
+            Self::Spawned(child) => child.stdin.take(),
+            Self::Inherited { .. } => None,
+        }
+    }
+
+    // Whether the child process is stopped, such as by `SIGSTOP`, in which
+    // case signals sent to it are not handled until it is continued. This is
+    // read from `/proc`, so stopped processes are only detected on Linux.
+    pub fn is_stopped(&self) -> bool {
+        let Some(pid) = self.id() else {
+            return false;
+        };
+
+        // The state is the field after the command name, which is enclosed
+        // in parentheses and may contain spaces.
+        std::fs::read_to_string(format!("/proc/{pid}/stat"))
+            .ok()
+            .and_then(|stat| {
+                let (_, fields) = stat.rsplit_once(')')?;
+                fields.split_whitespace().next().map(str::to_string)
+            })
+            .is_some_and(|state| state == "T" || state == "t")
+    }
+
+    // Waits for the child process to exit. This function is cancel-safe:
+    // for inherited processes, the blocking `waitpid` call is only spawned
+    // once, and its result is awaited on successive calls.
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        match self {
+            Self::Spawned(child) => child.wait().await,
+            Self::Inherited { pid, wait } => {
+                let pid = *pid;
+                let handle =
+                    wait.get_or_insert_with(|| tokio::task::spawn_blocking(move || waitpid(pid)));
+
+                handle.await.map_err(io::Error::other)?
+            }
+        }
+    }
+}
+
+fn waitpid(pid: u32) -> io::Result<ExitStatus> {
+    let pid: libc::pid_t = pid.try_into().expect("Invalid PID");
+    let mut status: libc::c_int = 0;
+
+    loop {
+        let result = unsafe { libc::waitpid(pid, &mut status, 0) };
+
+        if result == pid {
+            return Ok(ExitStatus::from_raw(status));
+        }
+
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
@@ -1,11 +1,52 @@
+use std::collections::BTreeMap;
 use std::ffi::OsString;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::Duration;
 
-use crate::check_in::{CheckInConfig, CronConfig, HeartbeatConfig};
+use crate::audit_log::{AuditDestination, AuditLog};
+use crate::check_in::{
+    parse_schedule, CheckInConfig, CronConfig, HeartbeatConfig, Usr2Action,
+    DEFAULT_HEARTBEAT_INTERVAL,
+};
+use crate::client::{
+    self, Timeouts, TlsConfig, TlsRoots, DEFAULT_CHECK_IN_TIMEOUT, DEFAULT_REQUEST_TIMEOUT,
+};
+use crate::correlate::Correlate;
+use crate::dump::QuitAction;
+use crate::environment;
 use crate::error::ErrorConfig;
-use crate::log::{LogConfig, LogOrigin};
+use crate::failure::{ExitCodes, FailureCodes};
+use crate::identifier::{self, IDENTIFIER_CMD_TIMEOUT};
+use crate::log::{
+    LogBatch, LogConfig, LogOrigin, LogSeverity, Pattern, SeverityPattern, SeveritySources,
+    DEFAULT_LOG_BATCH_SIZE, DEFAULT_LOG_CAPACITY, DEFAULT_LOG_FLUSH_INTERVAL,
+};
+use crate::memory::{within_budget, MemoryBudget};
+use crate::metrics::MetricsConfig;
+use crate::package;
+use crate::priority::WrapperPriority;
+use crate::rate_limit::LogRateLimit;
+use crate::redact::Redactor;
+use crate::restart::RestartPolicy;
+use crate::sample;
+use crate::schema::SchemaVersion;
+use crate::signal::{parse_signal, parse_signal_mapping};
+use crate::sink::{SinkConfig, SinkDestination};
+use crate::state::StateDir;
+use crate::tail::LogFile;
+use crate::trace::{random_trace_id, TraceConfig};
+use crate::units;
+use crate::wrap::{
+    hostname, random_digest, PassthroughErrorAction, StdinMode, WrapConfig, DEFAULT_ENDPOINT,
+};
 
-use ::log::warn;
-use clap::Parser;
+use ::log::{warn, LevelFilter};
+use clap::builder::{BoolishValueParser, RangedU64ValueParser, TypedValueParser};
+use clap::parser::ValueSource;
+use clap::{ArgAction, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use nix::sys::signal::Signal;
 
 /// A wrapper to track the execution of arbitrary processes with AppSignal.
 ///
@@ -18,8 +59,19 @@ use clap::Parser;
 /// output and standard error to its own standard output and standard error,
 /// and it exits with the executed process's exit code.
 #[derive(Debug, Parser)]
-#[command(version)]
+#[command(
+    version,
+    disable_version_flag = true,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    subcommand_value_name = "SUBCOMMAND",
+    disable_help_subcommand = true,
+    args_override_self = true
+)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub subcommand: Option<Command>,
+
     /// The AppSignal *app-level* push API key. Required.
     ///
     /// This is the app-level push API key for the AppSignal application
@@ -35,7 +87,7 @@ pub struct Cli {
         long,
         env = "APPSIGNAL_APP_PUSH_API_KEY",
         value_name = "APP_PUSH_API_KEY",
-        required_unless_present = "log_source"
+        required_unless_present_any = ["log_source", "version"]
     )]
     api_key: Option<String>,
 
@@ -52,15 +104,60 @@ pub struct Cli {
     ///
     /// The `--cron`, `--heartbeat`, `--log` and `--error` options can be
     /// used to override this value for each use case.
-    #[arg(index = 1, value_name = "NAME", required = true)]
-    name: String,
+    #[arg(index = 1, value_name = "NAME", required_unless_present = "version")]
+    name: Option<String>,
 
-    /// The command to execute. Required.
+    /// The command to execute. Required, unless the `--shell` option is
+    /// set.
     ///
-    ///
-    #[arg(index = 2, allow_hyphen_values = true, last = true, required = true)]
+    /// The command is given after the name, and after any options for the
+    /// wrapper. All arguments after the first argument of the command are
+    /// passed to it, even if they look like options for the wrapper. To
+    /// pass a command that starts with `-`, or to make the start of the
+    /// command unambiguous, give it after `--`.
+    #[arg(
+        index = 2,
+        value_name = "COMMAND",
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        required_unless_present_any = ["shell", "version", "pipe"]
+    )]
     pub command: Vec<String>,
 
+    /// Read lines from standard input, instead of running a command.
+    ///
+    /// Use this option at the end of a shell pipeline, such as
+    /// `./sync.sh | appsignal-run sync --stdin --cron`. Each line read from
+    /// standard input is written to standard output and sent as a log.
+    /// Once standard input is closed, the wrapper exits as if a command had
+    /// finished successfully, sending a finish cron check-in if `--cron` is
+    /// set.
+    #[arg(
+        long = "stdin",
+        conflicts_with_all = ["command", "shell", "no_stdin", "stdin_log", "restart"]
+    )]
+    pub pipe: bool,
+
+    /// Run this command string with a shell, instead of the command given
+    /// after the name.
+    ///
+    /// The string is run with `/bin/sh -c`, so it can use pipes,
+    /// redirections and other shell features, such as
+    /// `--shell "pg_dump app | gzip > backup.sql.gz"`. The string is sent
+    /// as the `command` tag of logs and errors.
+    #[arg(long, value_name = "COMMAND", conflicts_with = "command")]
+    shell: Option<String>,
+
+    /// The shell to run the `--shell` command string with. Defaults to
+    /// `/bin/sh`.
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "shell",
+        conflicts_with = "command"
+    )]
+    shell_path: Option<PathBuf>,
+
     /// Send heartbeat check-ins.
     ///
     /// If this option is set, a heartbeat check-in will be sent two times
@@ -76,6 +173,83 @@ pub struct Cli {
     )]
     heartbeat: Option<Option<String>>,
 
+    /// The interval at which to send heartbeat check-ins, such as `10m`.
+    ///
+    /// Defaults to 30 seconds. Longer intervals can be used on hosts where
+    /// sending a heartbeat check-in every 30 seconds is too chatty, such as
+    /// battery-powered or bandwidth-constrained devices.
+    ///
+    /// When the wrapper stops sending heartbeat check-ins, and the last
+    /// heartbeat check-in was sent more than 30 seconds ago, a final
+    /// heartbeat check-in is sent. A heartbeat check-in is also sent
+    /// immediately when the system resumes from sleep.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        requires = "heartbeat",
+        value_parser = units::parse_positive_duration
+    )]
+    heartbeat_interval: Option<Duration>,
+
+    /// Only send heartbeat check-ins while the command is active.
+    ///
+    /// By default, heartbeat check-ins are sent for as long as the command
+    /// is running. If this option is set, after the first heartbeat
+    /// check-in, a heartbeat check-in is only sent if the command wrote a
+    /// line to its standard output or standard error since the previous
+    /// one. Use this option for workers that may get stuck without exiting,
+    /// so that AppSignal alerts when they stop processing.
+    ///
+    /// Lines from standard output or standard error are not used if the
+    /// `--no-stdout` or `--no-stderr` options are set.
+    #[arg(long, requires = "heartbeat")]
+    no_heartbeat_on_idle: bool,
+
+    /// Only consider lines matching a pattern as activity.
+    ///
+    /// If this option is set, alongside `--no-heartbeat-on-idle`, only lines
+    /// that match the given regular expression, such as `^Processed job`,
+    /// show that the command is active. Can be given multiple times.
+    #[arg(
+        long,
+        value_name = "REGEX",
+        value_parser = Pattern::parse,
+        requires = "no_heartbeat_on_idle"
+    )]
+    activity_pattern: Vec<Pattern>,
+
+    /// Only send heartbeat check-ins while a health command succeeds.
+    ///
+    /// By default, heartbeat check-ins are sent for as long as the command
+    /// is running. If this option is set, the given shell command, such as
+    /// `curl -sf http://localhost:3000/health`, is run before each
+    /// heartbeat check-in, and the heartbeat check-in is only sent if it
+    /// exits with a success exit code within 10 seconds.
+    #[arg(long, value_name = "COMMAND", requires = "heartbeat")]
+    health_cmd: Option<String>,
+
+    /// Only consider the command started once it writes a line matching a
+    /// pattern.
+    ///
+    /// By default, the cron start check-in and the first heartbeat
+    /// check-in are sent as soon as the command starts. If this option is
+    /// set, they are only sent once the command writes a line to its
+    /// standard output or standard error that matches the given regular
+    /// expression, such as `^Listening on`. Use this option for services
+    /// that take a while to start.
+    #[arg(long, value_name = "REGEX", value_parser = Pattern::parse)]
+    ready_pattern: Option<Pattern>,
+
+    /// Report a `StartTimeoutError` if the command does not write a line
+    /// matching the `--ready-pattern` within this duration, such as `60s`.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = units::parse_positive_duration,
+        requires = "ready_pattern"
+    )]
+    ready_timeout: Option<Duration>,
+
     /// Send cron check-ins.
     ///
     /// If this option is set, a start cron check-in will be sent when the
@@ -93,6 +267,47 @@ pub struct Cli {
     )]
     cron: Option<Option<String>>,
 
+    /// The schedule the command is expected to run on, as a cron expression.
+    ///
+    /// If this option is set, the schedule, such as `15 3 * * *`, is sent
+    /// alongside the cron check-ins, so that it can be displayed next to
+    /// them. It does not change when the check-ins are sent.
+    ///
+    /// The number of seconds between the last scheduled time, in the host's
+    /// local time, and the time the command started, is added as the
+    /// `schedule_drift` tag to logs and errors, and, if `--metrics` is set,
+    /// sent as the `cron_schedule_drift` metric.
+    #[arg(
+        long,
+        value_name = "SCHEDULE",
+        value_parser = parse_schedule,
+        requires = "cron"
+    )]
+    cron_schedule: Option<String>,
+
+    /// A command whose output to use as the check-in identifier.
+    ///
+    /// The command is run with `sh -c` when the wrapper starts, and the
+    /// first line it writes to its standard output is used as the
+    /// identifier for the cron or heartbeat check-ins, unless an identifier
+    /// is given to `--cron` or `--heartbeat`. Use this when the identifier
+    /// is only known when the command runs, such as when it is derived from
+    /// the instance's index.
+    ///
+    /// If the command fails, writes no output, or does not finish within 10
+    /// seconds, the name given as the first argument is used instead.
+    #[arg(long, value_name = "COMMAND")]
+    identifier_cmd: Option<String>,
+
+    // The identifier written by the `--identifier-cmd` command.
+    #[arg(skip)]
+    command_identifier: Option<String>,
+
+    // The long names of the options given in the command-line arguments, or
+    // in their environment variables, to check them against the policy.
+    #[arg(skip)]
+    given_options: Vec<String>,
+
     /// Do not send logs.
     ///
     /// If this option is set, no logs will be sent to AppSignal.
@@ -116,6 +331,76 @@ pub struct Cli {
     #[arg(long)]
     no_error: bool,
 
+    /// Send metrics about the process when it finishes.
+    ///
+    /// If this option is set, a `process_duration` distribution metric,
+    /// with how long the process ran for in milliseconds, and a
+    /// `process_exit` counter metric will be sent to AppSignal when the
+    /// process finishes. Both metrics are tagged with the name, the
+    /// hostname and the kind of exit, either `code` or `signal`.
+    ///
+    /// While the process is running, its CPU time, resident memory and
+    /// number of open file descriptors are also sent as the
+    /// `process_cpu_time`, `process_memory_rss` and `process_open_fds` gauge
+    /// metrics, once every minute. This is only supported on Linux.
+    #[arg(long)]
+    metrics: bool,
+
+    /// Send StatsD lines in standard output as metrics.
+    ///
+    /// If this option is set, lines in standard output in the StatsD
+    /// format, such as `jobs.processed:1|c|#queue:default`, are sent to
+    /// AppSignal as metrics, tagged with the name, the hostname and the tags
+    /// in the line. Counters (`c`), gauges (`g`), and timers and histograms
+    /// (`ms`, `h` and `d`), sent as distributions, are supported.
+    ///
+    /// These lines are still written to standard output, but they are not
+    /// sent as logs or used as part of the error message.
+    #[arg(long, conflicts_with = "no_stdout")]
+    statsd_lines: bool,
+
+    /// Read a summary line, reporting the outcome of a batch job, from
+    /// standard output.
+    ///
+    /// If this option is set, the values of the last line in standard output
+    /// such as `appsignal_summary: processed=1000 failed=3` are added as
+    /// `batch_processed` and `batch_failed` tags to the error sent to
+    /// AppSignal if the process fails and, if `--metrics` is set, sent as
+    /// gauge metrics with the same names when the process finishes.
+    #[arg(long, conflicts_with = "no_stdout")]
+    batch_summary: bool,
+
+    /// Send a warning log line when the batch summary line reports failed
+    /// items, but the process succeeded.
+    ///
+    /// This reports a partial success, in which some of the batch's items
+    /// failed, without reporting an error or holding back the cron finish
+    /// check-in, which are reserved for a process that fails.
+    #[arg(long, requires = "batch_summary", conflicts_with = "no_log")]
+    warn_on_batch_failures: bool,
+
+    /// Send a span covering the process's execution to an OpenTelemetry
+    /// collector.
+    ///
+    /// When the process finishes, a span is sent to the `/v1/traces` path
+    /// of the given URL, such as `http://localhost:4318`, using the OTLP/HTTP
+    /// protocol with a JSON body. The span is named after the name, and has
+    /// the same attributes as the tags of the errors sent to AppSignal,
+    /// including the digest and how the process exited. Its status is an
+    /// error if the process failed.
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// Add a header to the requests sent to the `--otlp-endpoint` collector,
+    /// such as one that authenticates them. Can be given multiple times.
+    #[arg(
+        long = "otlp-header",
+        value_name = "NAME=VALUE",
+        value_parser = parse_header,
+        requires = "otlp_endpoint"
+    )]
+    otlp_headers: Vec<(String, String)>,
+
     /// Override the log group to use to send logs.
     ///
     /// If this option is not set, the name given as the first argument will
@@ -128,6 +413,138 @@ pub struct Cli {
     #[arg(long, value_name = "GROUP")]
     log: Option<String>,
 
+    /// Send logs to a log group for this invocation only.
+    ///
+    /// If this option is set, a short suffix derived from the digest of the
+    /// invocation is appended to the log group, such as `backup-1a2b3c4d`,
+    /// so that the logs of a single invocation can be told apart when
+    /// debugging it. As a new log group is created for each invocation,
+    /// this option should not be used for commands that run often.
+    #[arg(long, conflicts_with = "no_log")]
+    log_group_per_run: bool,
+
+    /// The severity to send lines from standard output with.
+    ///
+    /// One of `trace`, `debug`, `info`, `warn`, `error` or `critical`. Lines
+    /// whose severity is detected with `--detect-severity` or
+    /// `--severity-pattern` are sent with that severity instead.
+    #[arg(
+        long,
+        value_name = "SEVERITY",
+        value_parser = LogSeverity::parse,
+        default_value = "info",
+        conflicts_with = "no_log"
+    )]
+    stdout_severity: LogSeverity,
+
+    /// The severity to send lines from standard error with.
+    ///
+    /// One of `trace`, `debug`, `info`, `warn`, `error` or `critical`. Lines
+    /// whose severity is detected with `--detect-severity` or
+    /// `--severity-pattern` are sent with that severity instead.
+    #[arg(
+        long,
+        value_name = "SEVERITY",
+        value_parser = LogSeverity::parse,
+        default_value = "error",
+        conflicts_with = "no_log"
+    )]
+    stderr_severity: LogSeverity,
+
+    /// Detect the severity of log lines from their prefix.
+    ///
+    /// By default, lines from standard output are sent with the `info`
+    /// severity, and lines from standard error with the `error` severity.
+    /// If this option is set, lines starting with a severity, such as
+    /// `WARN`, `ERROR:` or `[debug]`, regardless of case, are sent with that
+    /// severity instead.
+    #[arg(long, conflicts_with = "no_log")]
+    detect_severity: bool,
+
+    /// Send log lines matching a pattern with the given severity.
+    ///
+    /// The pattern is given as `SEVERITY=REGEX`, such as `warn=^W:`, where
+    /// the severity is one of `trace`, `debug`, `info`, `warn`, `error` or
+    /// `critical`. Can be given multiple times. The first pattern that
+    /// matches a line determines its severity. These patterns take
+    /// precedence over the ones enabled by `--detect-severity`.
+    #[arg(
+        long,
+        value_name = "SEVERITY=REGEX",
+        value_parser = SeverityPattern::parse,
+        conflicts_with = "no_log"
+    )]
+    severity_pattern: Vec<SeverityPattern>,
+
+    /// The maximum number of log messages to send in a single request.
+    ///
+    /// Log messages are sent in batches, once the batch is full, or every
+    /// 10 seconds. Defaults to 100 messages.
+    #[arg(
+        long,
+        value_name = "MESSAGES",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with = "no_log"
+    )]
+    log_batch_size: Option<usize>,
+
+    /// The maximum size of the log messages in a single request, such as
+    /// `1MB`.
+    ///
+    /// If this option is set, a batch of log messages is also sent once the
+    /// size of its lines reaches the given size.
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = units::parse_positive_size,
+        conflicts_with = "no_log"
+    )]
+    log_batch_bytes: Option<usize>,
+
+    /// The interval at which to send log messages, such as `1s`.
+    ///
+    /// Log messages that have not been sent in a full batch are sent at this
+    /// interval. Defaults to 10 seconds. Use a shorter interval for
+    /// interactive commands, whose logs should show up sooner, and a longer
+    /// one for high-volume commands, to send fewer requests.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = units::parse_positive_duration,
+        conflicts_with = "no_log"
+    )]
+    log_flush_interval: Option<Duration>,
+
+    /// The maximum number of log lines to hold in memory.
+    ///
+    /// If the command writes lines faster than they can be sent as logs,
+    /// the oldest lines that have not been sent yet are dropped, so that
+    /// no more than this number of lines are held in memory. A log message
+    /// reporting the number of dropped lines, as the `dropped_lines`
+    /// attribute, is sent in their place. Defaults to 10000 lines.
+    #[arg(
+        long,
+        value_name = "LINES",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with = "no_log"
+    )]
+    log_buffer_lines: Option<usize>,
+
+    /// The maximum rate at which to send lines as logs.
+    ///
+    /// Given as a number of lines, or of bytes, per second, minute or hour,
+    /// such as `100/s` or `1MB/min`. Bursts of up to the full amount are
+    /// allowed. Lines over the limit are dropped, and a log message
+    /// reporting the number of dropped lines, as the `dropped_lines`
+    /// attribute, is sent in their place.
+    #[arg(
+        long,
+        value_name = "RATE",
+        value_parser = LogRateLimit::parse,
+        conflicts_with = "no_log"
+    )]
+    log_rate_limit: Option<LogRateLimit>,
+
     /// Override the action name to use to group errors.
     ///
     /// If this option is not set, the name given as the first argument will
@@ -143,6 +560,82 @@ pub struct Cli {
     #[arg(long, value_name = "ACTION", requires = "api_key")]
     error: Option<String>,
 
+    /// The number of lines of output to include in the error message.
+    ///
+    /// The last lines of standard output and standard error are included
+    /// in the message of the error sent to AppSignal. Defaults to 10 lines.
+    #[arg(long, value_name = "LINES")]
+    error_lines: Option<usize>,
+
+    /// The maximum size of the output in the error message, such as `4KB`.
+    ///
+    /// If this option is set, the oldest lines are left out of the error
+    /// message to keep it within the given size.
+    #[arg(long, value_name = "SIZE", value_parser = units::parse_size)]
+    error_bytes: Option<usize>,
+
+    /// Only consider the given exit codes as failures.
+    ///
+    /// By default, any non-zero exit code is considered a failure. If this
+    /// option is set, only the given exit codes, such as `2-255` or `1,3-5`,
+    /// are considered failures. Exits caused by a signal are always
+    /// considered failures.
+    ///
+    /// An error is reported for failures, and no cron finish check-in is
+    /// sent for them. For other exit codes, a cron finish check-in is sent.
+    #[arg(
+        long,
+        value_name = "CODES",
+        value_parser = ExitCodes::parse,
+        conflicts_with = "no_error_exit_codes"
+    )]
+    error_exit_codes: Option<ExitCodes>,
+
+    /// Do not consider the given exit codes as failures.
+    ///
+    /// Any non-zero exit code, except for the given ones, such as `1` or
+    /// `1,3-5`, is considered a failure. See the `--error-exit-codes`
+    /// option.
+    #[arg(long, value_name = "CODES", value_parser = ExitCodes::parse)]
+    no_error_exit_codes: Option<ExitCodes>,
+
+    /// Send a cron finish check-in for the given exit codes.
+    ///
+    /// By default, a cron finish check-in is sent when the command exits
+    /// with an exit code that is not considered a failure. If this option is
+    /// set, a cron finish check-in is sent when the command exits with a
+    /// zero exit code or with one of the given exit codes, such as `3` or
+    /// `3-5`.
+    ///
+    /// This option does not change whether an error is reported. See the
+    /// `--error-exit-codes` and `--no-error-exit-codes` options.
+    #[arg(
+        long,
+        value_name = "CODES",
+        value_parser = ExitCodes::parse,
+        requires = "cron"
+    )]
+    success_exit_codes: Option<ExitCodes>,
+
+    /// Only report one in every N consecutive errors, given as `1/N`.
+    ///
+    /// For commands that run very frequently, such as every few seconds,
+    /// this prevents a failing command from flooding the error inbox. The
+    /// first failure after a success is always reported. After that, one in
+    /// every N consecutive failures is reported, with the number of errors
+    /// that were suppressed since the last reported one in the
+    /// `suppressed_errors` tag.
+    ///
+    /// The number of consecutive failures is stored in the state directory
+    /// -- see the `--state-dir` option.
+    #[arg(
+        long,
+        value_name = "1/N",
+        value_parser = sample::parse_rate,
+        conflicts_with = "no_error"
+    )]
+    error_sample: Option<u64>,
+
     /// The log source API key to use to send logs.
     ///
     /// If this option is not set, logs will be sent to the default
@@ -155,6 +648,21 @@ pub struct Cli {
     )]
     log_source: Option<String>,
 
+    /// The log source API keys to use to send logs of each severity.
+    ///
+    /// Given as `SEVERITY=KEY` pairs separated by commas, such as
+    /// `error=KEY1,default=KEY2`. Logs are sent to the log source for the
+    /// highest given severity that is not higher than their own, so that,
+    /// in this example, error and critical logs are sent to the first log
+    /// source. Other logs are sent to the `default` log source, if given,
+    /// or to the one given by the `--log-source` option otherwise.
+    #[arg(
+        long,
+        value_name = "SEVERITY=KEY,...",
+        value_parser = SeveritySources::parse
+    )]
+    log_source_by_severity: Option<SeveritySources>,
+
     /// Do not use standard output in logs or error messages.
     ///
     /// Do not send standard output as logs, and do not use the last
@@ -169,16 +677,64 @@ pub struct Cli {
     #[arg(long)]
     no_stderr: bool,
 
+    /// Send the lines written to a file as logs. Can be given multiple
+    /// times.
+    ///
+    /// For commands that write their logs to files instead of to their
+    /// standard output or standard error. Given as `PATH` or `PATH:GROUP`,
+    /// such as `/var/log/app.log:app`, to send its lines to a log group
+    /// other than the one for the command's output. Only the lines written
+    /// after the wrapper starts are sent, with the path of the file as the
+    /// `file` attribute. Files that are rotated, by renaming or truncating
+    /// them, keep being followed.
+    #[arg(
+        long,
+        value_name = "PATH[:GROUP]",
+        value_parser = LogFile::parse,
+        conflicts_with = "no_log"
+    )]
+    log_file: Vec<LogFile>,
+
+    /// Redact matches of a pattern from logs and error messages.
+    ///
+    /// Every match of the given regular expression, such as
+    /// `Bearer [A-Za-z0-9._-]+`, is replaced with `[REDACTED]` in the lines
+    /// of standard output and standard error sent to AppSignal, either as
+    /// logs or as part of the error message. Can be given multiple times.
+    /// The output written by the wrapper itself is not redacted.
+    #[arg(long, value_name = "REGEX", value_parser = Pattern::parse)]
+    redact: Vec<Pattern>,
+
+    /// Do not strip ANSI escape sequences from logs and error messages.
+    ///
+    /// By default, escape sequences, such as the ones used to colour the
+    /// output, are removed from the lines of standard output and standard
+    /// error sent to AppSignal. The output written by the wrapper itself
+    /// always keeps them.
+    #[arg(long)]
+    no_strip_ansi: bool,
+
+    /// Do not compress the logs and errors sent to AppSignal.
+    ///
+    /// By default, the bodies of the requests that send logs and errors are
+    /// compressed with gzip. Disabling compression can help when debugging
+    /// the requests sent to AppSignal.
+    #[arg(long)]
+    no_compress: bool,
+
     /// The AppSignal public endpoint to use.
     #[arg(
         long,
         hide = true,
         env = "APPSIGNAL_PUBLIC_ENDPOINT",
         value_name = "PUBLIC_ENDPOINT",
-        default_value = "https://appsignal-endpoint.net"
+        default_value = DEFAULT_ENDPOINT
     )]
     endpoint: String,
 
+    #[command(flatten)]
+    client: ClientArgs,
+
     /// The hostname to report. Determined automatically.
     ///
     /// This value will be used as the hostname when sending logs, and added
@@ -191,6 +747,38 @@ pub struct Cli {
     )]
     hostname: String,
 
+    /// The revision of the application that the command belongs to.
+    ///
+    /// If this option is set, the revision is added as an attribute to logs
+    /// and as a tag to errors, so that they can be matched with the deploy
+    /// markers for that revision in AppSignal.
+    #[arg(long, env = "APP_REVISION", value_name = "REVISION")]
+    revision: Option<String>,
+
+    /// Add a tag to logs and errors. Can be given multiple times.
+    ///
+    /// Each tag is given as a `KEY=VALUE` pair, such as
+    /// `--tag environment=production`. Tags are sent as attributes in logs
+    /// and as tags in errors, alongside the hostname and digest.
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    tags: Vec<(String, String)>,
+
+    /// Add the version of `appsignal-run` as the `wrap_version` tag to logs
+    /// and errors.
+    ///
+    /// Use this option to tell which version of the wrapper sent which data
+    /// when running several versions of it across a fleet of hosts.
+    #[arg(long)]
+    tag_wrap_version: bool,
+
+    /// Add the hostname of the host `appsignal-run` runs on as the
+    /// `wrap_host` tag to logs and errors.
+    ///
+    /// Unlike the hostname given with `--hostname`, it is always determined
+    /// automatically.
+    #[arg(long)]
+    tag_wrap_host: bool,
+
     /// The digest to uniquely identify this invocation of the process.
     /// Used in cron check-ins as a digest, in logs as an attribute, and in
     /// errors as a tag.
@@ -202,406 +790,3813 @@ pub struct Cli {
       hide_default_value = true
     )]
     digest: String,
-}
 
-pub fn hostname() -> String {
-    use nix::unistd::gethostname;
+    /// Write the PID of the wrapper process to a file.
+    ///
+    /// The file is removed when the wrapper exits. Use the `upgrade`
+    /// subcommand with the same PID file to re-execute a running wrapper
+    /// with a new version of the binary, without restarting the command.
+    #[arg(long, value_name = "PATH")]
+    pid_file: Option<PathBuf>,
 
-    gethostname()
-        .ok()
-        .and_then(|hostname| OsString::into_string(hostname).ok())
-        .unwrap_or_else(|| "unknown".to_string())
-}
+    /// Expose the digest of the invocation to the command, for correlation.
+    ///
+    /// If set to `appsignal`, the `APPSIGNAL_RUN_DIGEST` environment
+    /// variable is set for the command to the digest that its logs, errors
+    /// and cron check-ins are tagged with, and `APPSIGNAL_RUN_DIGEST_TAG` to
+    /// the name of that tag. An application instrumented with AppSignal can
+    /// add the same tag to its own samples, so that they can be
+    /// cross-referenced with the data sent by the wrapper.
+    ///
+    /// If set to `tracecontext`, the `TRACEPARENT` and
+    /// `APPSIGNAL_RUN_TRACEPARENT` environment variables are also set for
+    /// the command to the W3C Trace Context of the invocation's span, which
+    /// is sent with `--otlp-endpoint`. An application instrumented with
+    /// OpenTelemetry can parent its own traces to it.
+    #[arg(long, value_name = "INTEGRATION", value_enum)]
+    correlate: Option<Correlate>,
 
-fn random_digest() -> String {
-    use hex::encode;
-    use rand::random;
+    /// Keep reading output for at most this long after the command exits,
+    /// such as `30s`.
+    ///
+    /// Processes started by the command, such as daemons started by a
+    /// launcher script, may keep writing to its standard output and
+    /// standard error after it exits. By default, the wrapper keeps reading
+    /// them, sending them as logs, until every such process has closed
+    /// them. If this option is set, the wrapper stops reading them after
+    /// the given duration, and exits.
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_duration)]
+    linger: Option<Duration>,
 
-    encode(random::<[u8; 8]>())
-}
+    /// Kill the command if it has not exited this long after it was asked
+    /// to terminate, such as `30s`.
+    ///
+    /// When the wrapper receives `SIGTERM`, `SIGINT` or `SIGQUIT`, it
+    /// forwards the signal to the command and waits for it to exit. If this
+    /// option is set and the command ignores the signal, the wrapper sends
+    /// it `SIGKILL` after the given duration.
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_duration)]
+    kill_after: Option<Duration>,
 
-impl Cli {
-    fn log_and_no_log_warning(&self) -> Option<String> {
-        let using: Option<&str> = if self.no_log {
-            Some("--no-log")
-        } else if self.no_stdout && self.no_stderr {
-            Some("--no-stdout and --no-stderr")
-        } else {
-            None
-        };
+    /// Restart the command when it fails.
+    ///
+    /// The policy is `on-failure`, optionally followed by the maximum number
+    /// of restarts and how long to wait before each restart, such as
+    /// `on-failure:max=3,backoff=30s`. By default, the command is restarted
+    /// at most 5 times, 10 seconds after it fails.
+    ///
+    /// An error is reported for each failure of the command. Heartbeat
+    /// check-ins keep being sent while the command is restarted. The wrapper
+    /// exits with the command's last exit status once it succeeds, once the
+    /// restarts are exhausted, or once the wrapper is asked to terminate.
+    #[arg(long, value_name = "POLICY", value_parser = RestartPolicy::parse)]
+    restart: Option<RestartPolicy>,
 
-        let alongside = if self.log.is_some() {
-            Some("--log")
-        } else if self.log_source.is_some() {
-            Some("--log-source")
-        } else {
-            None
-        };
+    /// Report the command as stalled if it writes no output for this long.
+    ///
+    /// A command that is hung, such as on a network mount that is no longer
+    /// reachable, may never exit, keeping its cron check-in open forever. If
+    /// this option is set and the command writes no lines to its standard
+    /// output or standard error for the given duration, such as `10m`, a
+    /// `StallError` is reported, with the last lines of its output. It is
+    /// reported again only if the command stalls again after writing more
+    /// output.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = units::parse_positive_duration
+    )]
+    stall_timeout: Option<Duration>,
 
-        match (using, alongside) {
-            (Some(using), Some(alongside)) => Some(format!(
-                "using {using} alongside {alongside}; \
-                no logs will be sent to AppSignal"
-            )),
-            _ => None,
-        }
-    }
+    /// Kill the command with `SIGKILL` when it stalls. See the
+    /// `--stall-timeout` option.
+    #[arg(long, requires = "stall_timeout")]
+    stall_kill: bool,
 
-    fn error_and_no_error_warning(&self) -> Option<String> {
-        if self.no_error && self.error.is_some() {
-            return Some(
-                "using --no-error alongside --error; \
-                no errors will be sent to AppSignal"
-                    .to_string(),
-            );
-        };
+    /// Run this command, through the shell, each time the command fails.
+    ///
+    /// Use it to run a script that gathers context about the failure, such
+    /// as the state of the host. The exit code of the command that failed,
+    /// or the signal that terminated it, is set in the
+    /// `APPSIGNAL_RUN_EXIT_CODE` or `APPSIGNAL_RUN_EXIT_SIGNAL`
+    /// environment variable. The output of the on-failure command is
+    /// written to standard error once it finishes.
+    #[arg(long, value_name = "COMMAND")]
+    on_failure: Option<String>,
 
-        None
-    }
+    /// Kill the `--on-failure` command if it does not finish within this
+    /// duration, such as `1m`. Defaults to 30 seconds.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = units::parse_positive_duration,
+        requires = "on_failure"
+    )]
+    on_failure_timeout: Option<Duration>,
 
-    fn no_log_and_no_data_warning(&self) -> Option<String> {
-        if !self.no_error {
-            return None;
-        }
+    /// Include the output of the `--on-failure` command, and how it exited,
+    /// in the error report for the failure.
+    ///
+    /// The error report is then sent once the on-failure command has
+    /// finished, or once it is killed after its timeout. The last lines of
+    /// its output are kept, with the same limits as the command's output.
+    #[arg(long, requires = "on_failure", conflicts_with = "no_error")]
+    on_failure_report: bool,
 
-        let no_checkins: bool = self.cron.is_none() && self.heartbeat.is_none();
+    /// Wait at most this long for data to be sent to AppSignal after the
+    /// command exits, such as `5s`.
+    ///
+    /// By default, the wrapper waits until every request to AppSignal has
+    /// completed, or has timed out, before it exits. If this option is set,
+    /// the wrapper exits after the given duration, even if some requests
+    /// have not completed.
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_duration)]
+    flush_timeout: Option<Duration>,
 
-        if no_checkins {
-            let using: Option<&str> = if self.no_log {
-                Some("--no-log and --no-error")
-            } else if self.no_stdout && self.no_stderr {
-                Some("--no-stdout, --no-stderr and --no-error")
-            } else {
-                None
-            };
+    /// Write the command's output as soon as it is read.
+    ///
+    /// By default, the wrapper writes the standard output and standard
+    /// error of the command a line at a time, so a prompt or a progress
+    /// indicator that does not end a line is not shown until it does. If
+    /// this option is set, the output is written, and flushed, as soon as it
+    /// is read. It is still sent as logs a line at a time.
+    ///
+    /// Note that the command may itself buffer its output, as it is not
+    /// writing to a terminal.
+    #[arg(long)]
+    unbuffered: bool,
 
-            if let Some(using) = using {
-                return Some(format!(
-                    "using {using} without either --cron or --heartbeat; \
-                    no data will be sent to AppSignal"
-                ));
-            }
-        }
+    /// What to do when the command's output cannot be written.
+    ///
+    /// Writing the command's standard output or standard error to the
+    /// wrapper's own can fail, such as when it is a closed pipe or a file on
+    /// a full disk. By default, the output that cannot be written is
+    /// skipped, and the wrapper keeps reading the output to send it as logs,
+    /// warning about the number of skipped writes when it exits. If set to
+    /// `stop`, the wrapper stops reading the output instead.
+    #[arg(
+        long,
+        value_name = "ACTION",
+        value_enum,
+        default_value_t = PassthroughErrorAction::Continue
+    )]
+    pub on_passthrough_error: PassthroughErrorAction,
 
-        None
-    }
+    /// Do not pass standard input to the command.
+    ///
+    /// By default, the command inherits the wrapper's standard input. If
+    /// this option is set, the command's standard input is connected to
+    /// `/dev/null` instead, for commands that misbehave when they inherit
+    /// the standard input of the process that started the wrapper, such as
+    /// a cron daemon.
+    #[arg(long)]
+    no_stdin: bool,
 
-    fn warnings(&self) -> Vec<String> {
-        let mut warnings = Vec::new();
+    /// Send the lines written to the command's standard input as logs.
+    ///
+    /// If this option is set, the wrapper reads its standard input and
+    /// writes it to the command's standard input, sending each line as a
+    /// log with the `debug` severity. The lines are sent after escape
+    /// sequences are stripped and secrets are redacted, like the lines of
+    /// the command's output.
+    #[arg(long, conflicts_with_all = ["no_stdin", "no_log"])]
+    stdin_log: bool,
 
-        if let Some(warning) = self.log_and_no_log_warning() {
-            warnings.push(warning);
-        }
+    /// Run the command as this user, given by name or by ID.
+    ///
+    /// Unless the `--group` option is set, the command also runs as the
+    /// user's primary group, without any supplementary groups. This
+    /// requires the wrapper to run as root, such as when it is started by a
+    /// system service, and allows the command to run unprivileged. The
+    /// command's environment, such as `HOME`, is not changed.
+    #[arg(long, value_name = "USER")]
+    user: Option<String>,
 
-        if let Some(warning) = self.error_and_no_error_warning() {
-            warnings.push(warning);
-        }
+    /// Run the command as this group, given by name or by ID.
+    #[arg(long, value_name = "GROUP")]
+    group: Option<String>,
+
+    /// Run the command in this working directory.
+    ///
+    /// The directory is sent as the `directory` tag of errors.
+    #[arg(long, value_name = "DIR")]
+    chdir: Option<PathBuf>,
+
+    /// Set an environment variable for the command.
+    ///
+    /// Given as `KEY=VALUE`, or as `KEY` to pass the variable from the
+    /// wrapper's environment, if it is set. Can be given multiple times.
+    /// Variables set with this option override the ones in the files given
+    /// with `--env-file`.
+    ///
+    /// The variables that configure the wrapper, such as
+    /// `APPSIGNAL_APP_PUSH_API_KEY` and the ones starting with
+    /// `APPSIGNAL_RUN_`, are removed from the command's environment, unless
+    /// they are set with this option.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = environment::parse_var)]
+    env: Vec<(String, Option<String>)>,
+
+    /// Set the environment variables in this file for the command.
+    ///
+    /// The file has a `KEY=VALUE` variable on each line. Empty lines and
+    /// lines starting with `#` are ignored, and values are used as is,
+    /// including any quotes. Can be given multiple times.
+    #[arg(long, value_name = "FILE")]
+    env_file: Vec<PathBuf>,
+
+    /// Do not pass the wrapper's environment to the command.
+    ///
+    /// If this option is set, the command's environment only contains the
+    /// variables set with `--env` and `--env-file`.
+    #[arg(long)]
+    clear_env: bool,
+
+    /// Append a record of this invocation to an audit log.
+    ///
+    /// Given as the path of a file, or as `syslog` to send it to the system
+    /// log with the `authpriv` facility. The record is a line of JSON with
+    /// the user who ran the wrapper, the name, the command, the digest and
+    /// how the invocation ended, written when the wrapper exits.
+    #[arg(long, value_name = "FILE", value_parser = AuditDestination::parse)]
+    audit: Option<AuditDestination>,
+
+    /// Also send the command's output, and how it exited, to this
+    /// destination. Can be given multiple times.
+    ///
+    /// Given as `file:PATH` to append a line of JSON to the file for each
+    /// line of output, and for the exit, as `syslog` to send them to the
+    /// system log, or as `webhook:URL` to send the exit to the URL as JSON.
+    /// The exit record has the name, the command, the digest, the exit code
+    /// or signal, and whether it is considered a failure.
+    #[arg(long, value_name = "DESTINATION", value_parser = SinkDestination::parse)]
+    sink: Vec<SinkDestination>,
+
+    /// Write a summary of the requests sent to AppSignal when exiting.
+    ///
+    /// If this option is set, the number of requests sent to each AppSignal
+    /// endpoint, and their 50th percentile, 95th percentile and maximum
+    /// latency, are written to standard error once all requests have been
+    /// sent, before the wrapper exits.
+    #[arg(long)]
+    summary: bool,
+
+    /// Only send check-ins, with the lowest possible overhead.
+    ///
+    /// If this option is set, no logs or errors are sent to AppSignal. The
+    /// command's standard output and standard error are not read by the
+    /// wrapper, and the wrapper runs in a single thread. Use this option,
+    /// alongside `--cron` or `--heartbeat`, when wrapping many small
+    /// commands where the overhead of each invocation matters. It cannot be
+    /// used with options that read the command's output, such as
+    /// `--stall-timeout` or `--ready-pattern`.
+    #[arg(
+        long,
+        conflicts_with_all = ["log", "log_source", "log_source_by_severity", "error", "error_sample", "error_lines", "error_bytes", "metrics", "statsd_lines", "log_group_per_run", "detect_severity", "severity_pattern", "redact", "no_heartbeat_on_idle", "log_batch_size", "log_batch_bytes", "log_flush_interval", "log_buffer_lines", "log_rate_limit", "stdout_severity", "stderr_severity", "log_file", "stdin_log", "activity_pattern", "ready_pattern", "ready_timeout", "stall_timeout", "stall_kill", "batch_summary", "warn_on_batch_failures", "on_failure_report", "sink"]
+    )]
+    minimal: bool,
+
+    /// Run the wrapper in a single thread.
+    ///
+    /// By default, the wrapper uses as many threads as CPUs are available
+    /// to it, taking into account the CPU quota of the container it runs in.
+    /// Use this option to reduce the wrapper's footprint when it runs next
+    /// to small workloads.
+    #[arg(long)]
+    single_thread: bool,
+
+    /// Lower the wrapper's own scheduling priority below the command's.
+    ///
+    /// The wrapper's work, such as matching patterns against the command's
+    /// output and compressing requests to AppSignal, runs at the same
+    /// priority as the command by default. If set to `low`, the wrapper runs
+    /// with a niceness of at least 10 and the lowest best-effort I/O
+    /// priority. If set to `idle`, it only uses the CPU and disk when
+    /// nothing else is using them. The priority is lowered once the command
+    /// is started, so that the command keeps its priority.
+    #[arg(long, value_name = "PRIORITY", value_enum)]
+    wrapper_priority: Option<WrapperPriority>,
+
+    /// Limit the memory used by the wrapper's buffers, such as `64MiB`.
+    ///
+    /// The buffers that grow with the command's output are sized from this
+    /// budget: half of it for the log lines that have not been sent yet,
+    /// an eighth of it for the log lines in a single request, a sixteenth
+    /// of it for the output kept for error messages, and an eighth of it
+    /// for the lines being processed, such as to strip escape sequences.
+    /// When the command writes lines faster than they can be sent, the
+    /// oldest lines are dropped to stay within the budget. A line longer
+    /// than 1/1024 of the budget is sent as several log lines. Options that
+    /// size these buffers, such as `--log-batch-bytes` or `--error-bytes`,
+    /// can lower them further. Must be at least 1MiB.
+    #[arg(long, value_name = "SIZE", value_parser = MemoryBudget::parse)]
+    max_wrapper_memory: Option<MemoryBudget>,
+
+    /// Wait for the system clock to be set before starting the command.
+    ///
+    /// Some devices, such as embedded devices without a battery-backed
+    /// clock, boot with their clock set to 1970 until it is synchronised,
+    /// and the timestamps of the check-ins, logs and errors sent while it is
+    /// are wrong. A warning is printed when the clock is set before 2020. If
+    /// this option is set, the command is only started once the clock is
+    /// set, waiting for at most the given duration, such as `2m`.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = units::parse_positive_duration
+    )]
+    wait_for_clock: Option<Duration>,
+
+    /// What to do when the wrapper receives `SIGQUIT`.
+    ///
+    /// By default, `SIGQUIT` is forwarded to the command, like other
+    /// signals. If set to `dump`, the wrapper writes a dump of its internal
+    /// state, such as pending logs and running tasks, to standard error,
+    /// terminates the command and exits. This can be used to debug a wrapper
+    /// that appears to hang.
+    #[arg(long, value_name = "ACTION", value_enum, default_value_t = QuitAction::Forward)]
+    sigquit: QuitAction,
+
+    /// What to do when the wrapper receives `SIGUSR2`.
+    ///
+    /// By default, `SIGUSR2` is forwarded to the command, like other
+    /// signals. If set to `heartbeat`, the wrapper sends a heartbeat
+    /// check-in immediately instead, without changing when the next ones are
+    /// sent. This can be used by a health check script to report that the
+    /// command is alive. Requires the `--heartbeat` option.
+    #[arg(long, value_name = "ACTION", value_enum, default_value_t = Usr2Action::Forward)]
+    sigusr2: Usr2Action,
+
+    /// Forward this signal to the command when the wrapper is asked to
+    /// terminate, such as `SIGQUIT`.
+    ///
+    /// By default, when the wrapper receives `SIGTERM`, `SIGINT` or
+    /// `SIGQUIT`, it forwards the same signal to the command. Use this
+    /// option for commands that shut down gracefully on a different signal,
+    /// such as Unicorn, which does on `SIGQUIT`.
+    #[arg(long, value_name = "SIGNAL", value_parser = parse_signal)]
+    stop_signal: Option<Signal>,
+
+    /// Forward a signal to the command as a different signal, such as
+    /// `SIGHUP=SIGUSR1`. Can be given multiple times.
+    ///
+    /// By default, the wrapper forwards `SIGHUP`, `SIGINT`, `SIGQUIT`,
+    /// `SIGTERM`, `SIGUSR1`, `SIGUSR2` and `SIGWINCH` to the command as the
+    /// same signal. Use this option for commands that expect a different
+    /// signal than the one sent to the wrapper, or to forward other signals.
+    /// It takes precedence over the `--stop-signal` option.
+    #[arg(long, value_name = "FROM=TO", value_parser = parse_signal_mapping)]
+    forward_signal: Vec<(Signal, Signal)>,
+
+    /// Do not forward this signal to the command. Can be given multiple
+    /// times.
+    ///
+    /// The wrapper still handles the signal, so that it does not terminate
+    /// the wrapper, but does not send it to the command. This can be used
+    /// for commands that misbehave on a signal, such as `SIGWINCH`.
+    #[arg(long, value_name = "SIGNAL", value_parser = parse_signal)]
+    no_forward: Vec<Signal>,
+
+    /// Also write the state dump to a file. See the `--sigquit` option.
+    #[arg(long, value_name = "PATH")]
+    dump_file: Option<PathBuf>,
+
+    /// Stop the command when this file exists.
+    ///
+    /// The wrapper checks whether the file exists every second. Once it
+    /// does, the wrapper stops the command as if it had received `SIGTERM`,
+    /// for orchestrators that can create files, but cannot send signals to
+    /// the wrapper. The file is not removed by the wrapper.
+    #[arg(long, value_name = "PATH")]
+    shutdown_file: Option<PathBuf>,
+
+    /// Load options from a TOML configuration file.
+    ///
+    /// Each key in the file is the name of a command-line option, such as
+    /// `api_key` or `log-source`, and its value is the value for that
+    /// option. Use `true` as the value for options that take no value, and
+    /// an array as the value for options that can be repeated.
+    ///
+    /// Options given in the command line take precedence over those in the
+    /// configuration file. Unknown options and invalid values in the
+    /// configuration file are reported as warnings, with their line and
+    /// column, and ignored. See the `--strict-config` option.
+    #[arg(long, env = "APPSIGNAL_RUN_CONFIG", value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Refuse to run if the configuration file has unknown options or
+    /// invalid values.
+    ///
+    /// By default, those options are ignored, so that a typo in an option
+    /// does not stop the command from being monitored. This option can also
+    /// be set in the configuration file itself.
+    #[arg(long)]
+    strict_config: bool,
+
+    /// The directory in which to store state across invocations.
+    ///
+    /// Defaults to `$XDG_STATE_HOME/appsignal-run`, or to
+    /// `~/.local/state/appsignal-run` if `$XDG_STATE_HOME` is not set.
+    #[arg(long, env = "APPSIGNAL_RUN_STATE_DIR", value_name = "PATH")]
+    state_dir: Option<PathBuf>,
+
+    /// Print version.
+    #[arg(short = 'V', long)]
+    version: bool,
+
+    /// Print more of the wrapper's own messages.
+    ///
+    /// Given once, debug messages are printed. Given twice, trace messages
+    /// are printed too. With `--version`, also print how the wrapper was
+    /// built, such as its TLS backend and the root certificates it trusts.
+    ///
+    /// If the `RUST_LOG` environment variable is set, only the messages that
+    /// it allows are printed.
+    #[arg(short = 'v', long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Do not print the wrapper's own messages, other than errors.
+    ///
+    /// The output of the command is still written to the wrapper's output.
+    #[arg(short = 'q', long, conflicts_with = "verbose")]
+    quiet: bool,
+}
+
+// The options for the requests sent to AppSignal, which apply to the wrapper
+// and to its subcommands.
+#[derive(Debug, Args)]
+pub struct ClientArgs {
+    /// Trust the certificates in a PEM file when sending data to AppSignal.
+    ///
+    /// The certificates are trusted in addition to the built-in ones, such
+    /// as when data is sent through a gateway that uses a private
+    /// certificate authority. Can be given multiple times.
+    #[arg(long, value_name = "PEM")]
+    ca_cert: Vec<PathBuf>,
+
+    /// Do not verify TLS certificates when sending data to AppSignal.
+    ///
+    /// This is insecure, and only meant for testing. Use `--ca-cert` to
+    /// trust the certificate authority of a gateway instead.
+    #[arg(long)]
+    insecure: bool,
+
+    /// The root certificates to trust when sending data to AppSignal.
+    ///
+    /// By default, the Mozilla root certificates built into the wrapper are
+    /// trusted, so that data can be sent from minimal containers without a
+    /// system certificate store. If set to `system`, the root certificates
+    /// of the system's certificate store are trusted instead, as read from
+    /// the file given by the `SSL_CERT_FILE` environment variable or from
+    /// its usual location. If set to `all`, both are trusted.
+    #[arg(long, value_name = "ROOTS", value_enum, default_value_t = TlsRoots::Bundled)]
+    tls_roots: TlsRoots,
+
+    /// Authenticate with a client certificate when sending data to AppSignal.
+    ///
+    /// The certificate is read from a PEM file, and is used when data is
+    /// sent through a proxy that requires mutual TLS. The private key for
+    /// the certificate is given with `--client-key`, or, if that option is
+    /// not set, read from the same file.
+    #[arg(long, value_name = "PEM")]
+    client_cert: Option<PathBuf>,
+
+    /// The private key for the client certificate given with
+    /// `--client-cert`, in a PEM file.
+    #[arg(long, value_name = "PEM", requires = "client_cert")]
+    client_key: Option<PathBuf>,
+
+    /// How long to wait for a request to AppSignal to complete, such as
+    /// `30s`.
+    ///
+    /// Requests that take longer, such as requests stuck on an unresponsive
+    /// connection, are cancelled. Applies to requests that send logs,
+    /// errors and metrics. Check-in requests, which are much smaller, use
+    /// the `--check-in-timeout` option instead.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = format!("{}s", DEFAULT_REQUEST_TIMEOUT.as_secs()),
+        value_parser = units::parse_positive_duration
+    )]
+    request_timeout: Duration,
+
+    /// How long to wait for a check-in request to AppSignal to complete.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = format!("{}s", DEFAULT_CHECK_IN_TIMEOUT.as_secs()),
+        value_parser = units::parse_positive_duration
+    )]
+    check_in_timeout: Duration,
+
+    /// Print the requests to AppSignal instead of sending them.
+    ///
+    /// Each request is printed to standard error, with its method, its URL,
+    /// with the API key redacted, and its body, pretty-printed. The command
+    /// is still executed as usual. Use this option to check what a
+    /// configuration sends, without sending it.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Write each request to AppSignal to a file in this directory.
+    ///
+    /// Each request is written as a JSON file, named after the time it was
+    /// sent, with its method, URL, headers and body. The API key is
+    /// redacted, so that the files can be shared, such as in a support
+    /// request. Use the `replay` subcommand to send the recorded requests
+    /// again.
+    #[arg(long, value_name = "DIR")]
+    pub record: Option<PathBuf>,
+
+    /// The version of the schema of the error and log payloads to send.
+    ///
+    /// By default, the latest version is sent. Set an earlier version to
+    /// keep sending payloads that a relay which inspects them on their way
+    /// to AppSignal understands. The version is sent in the
+    /// `X-AppSignal-Run-Payload-Schema` header of each request. The only
+    /// version is `1`.
+    #[arg(long, value_name = "VERSION", value_parser = SchemaVersion::parse)]
+    pub payload_schema_version: Option<SchemaVersion>,
+}
+
+impl ClientArgs {
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            request: self.request_timeout,
+            check_in: self.check_in_timeout,
+        }
+    }
+
+    pub fn tls(&self) -> Result<TlsConfig, String> {
+        let mut ca_certs = Vec::new();
+
+        for path in &self.ca_cert {
+            ca_certs.extend(read_certs(path, "CA certificate")?);
+        }
+
+        if self.tls_roots.includes_system() {
+            let path = client::system_roots_path().ok_or(
+                "could not find the system's root certificates; \
+                set `SSL_CERT_FILE` to the file that contains them",
+            )?;
+
+            ca_certs.extend(read_certs(&path, "system root certificates")?);
+        }
+
+        if self.insecure {
+            warn!("TLS certificate verification is disabled by --insecure");
+        }
+
+        Ok(TlsConfig {
+            roots: self.tls_roots,
+            ca_certs,
+            insecure: self.insecure,
+            identity: self.identity()?,
+        })
+    }
+
+    fn identity(&self) -> Result<Option<reqwest::Identity>, String> {
+        let Some(cert_path) = self.client_cert.as_ref() else {
+            return Ok(None);
+        };
+
+        let read = |path: &PathBuf, kind: &str| {
+            std::fs::read(path).map_err(|err| {
+                format!("could not read client {} {}: {}", kind, path.display(), err)
+            })
+        };
+
+        // The certificate and the key are read as a single PEM bundle.
+        let mut pem = read(cert_path, "certificate")?;
+        if let Some(key_path) = self.client_key.as_ref() {
+            pem.push(b'\n');
+            pem.extend(read(key_path, "key")?);
+        }
+
+        reqwest::Identity::from_pem(&pem).map(Some).map_err(|err| {
+            format!(
+                "could not use client certificate {}: {}",
+                cert_path.display(),
+                err
+            )
+        })
+    }
+}
+
+// Reads the certificates in a PEM bundle, of which there must be at least
+// one.
+fn read_certs(path: &Path, kind: &str) -> Result<Vec<reqwest::Certificate>, String> {
+    let read_error = |err: &dyn std::fmt::Display| {
+        format!("could not read {} {}: {}", kind, path.display(), err)
+    };
+
+    let pem = std::fs::read(path).map_err(|err| read_error(&err))?;
+    let certs = reqwest::Certificate::from_pem_bundle(&pem).map_err(|err| read_error(&err))?;
+
+    if certs.is_empty() {
+        return Err(read_error(&"no certificates found"));
+    }
+
+    Ok(certs)
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Re-execute a running wrapper without restarting its command.
+    ///
+    /// The running wrapper must have been started with the `--pid-file`
+    /// option. It will stop reading the command's output, send any pending
+    /// logs to AppSignal and re-execute itself, using the binary that is
+    /// currently installed at the path it was executed from.
+    ///
+    /// The command keeps running throughout the upgrade, and it is tracked
+    /// by the re-executed wrapper as if it had been started by it.
+    Upgrade(UpgradeArgs),
+
+    /// Send lines from standard input as logs, without executing a command.
+    ///
+    /// Each line read from standard input is sent to AppSignal as a log
+    /// line, until standard input is closed. This can be used to send the
+    /// output of an existing command, such as `journalctl -f`, as logs:
+    ///
+    ///     journalctl -f | appsignal-run log system
+    Log(LogArgs),
+
+    /// Report an error to AppSignal, without executing a command.
+    ///
+    /// This can be used to report failures from shell scripts at arbitrary
+    /// points. The error message is given with the `--message` option, or
+    /// read from standard input if the option is not set:
+    ///
+    ///     appsignal-run error backup --message "Backup disk is full"
+    Error(ErrorArgs),
+
+    /// Simulate a failure of a command, without executing it.
+    ///
+    /// An error is reported to AppSignal as if the command had exited with
+    /// the given exit code or signal, and, if the `--cron` option is set, a
+    /// start cron check-in is sent without a matching finish check-in. This
+    /// can be used to check that AppSignal triggers and notifications are
+    /// set up correctly, without breaking a real command:
+    ///
+    ///     appsignal-run simulate-failure backup --cron --exit-code 2
+    SimulateFailure(SimulateFailureArgs),
+
+    /// Run two commands and report an error if they diverge.
+    ///
+    /// The first command is the reference, such as the current
+    /// implementation of a job, and the second is the candidate, such as
+    /// the implementation that replaces it. They are separated by `----`:
+    ///
+    ///     appsignal-run canary backup -- ./backup.sh ---- ./backup-v2.sh
+    ///
+    /// The output of the reference is written to the wrapper's output, and
+    /// the wrapper exits with its exit code. If the candidate exits with a
+    /// different exit code, or, when `--compare-output` is set, writes a
+    /// different standard output, a `CanaryMismatch` error is reported,
+    /// with the last lines of the candidate's output.
+    Canary(CanaryArgs),
+
+    /// Run the jobs defined in a directory, on their schedules.
+    ///
+    /// Each file with the `.toml` extension in the jobs directory defines a
+    /// job. A job file has the same options as a configuration file, as
+    /// well as the `command` to execute, either as an array of arguments or
+    /// as a string to run with `sh -c`, and, optionally, the `name` of the
+    /// job, which defaults to the name of the file:
+    ///
+    ///     # /etc/appsignal-run/jobs.d/backup.toml
+    ///     command = "pg_dump app | gzip > /backups/app.sql.gz"
+    ///     api_key = "..."
+    ///     cron_schedule = "15 3 * * *"
+    ///
+    /// A job with a `cron_schedule` is executed whenever its schedule
+    /// matches, in the host's local time, and sends cron check-ins. A job
+    /// without one is executed when the agent starts, and executed again
+    /// when it exits.
+    ///
+    /// Each time a job is executed, the wrapper is executed for it with its
+    /// options, so that the output, logs, errors and check-ins of each job
+    /// are handled separately.
+    ///
+    /// The jobs directory is watched for changes. Jobs whose files are
+    /// added are started, jobs whose files are changed are restarted, and
+    /// jobs whose files are removed are stopped, without restarting the
+    /// agent. Each change is logged.
+    Agent(AgentArgs),
+
+    /// Check whether data can be sent to AppSignal from this host.
+    ///
+    /// Resolving the endpoint's host, connecting to it and the TLS
+    /// handshake are checked, as well as whether the routes that check-ins,
+    /// logs and errors are sent to can be reached, and whether the push API
+    /// key is accepted. A report of the checks is printed to standard
+    /// output, and the exit code is 1 if any of them failed:
+    ///
+    ///     appsignal-run diagnose --api-key ...
+    ///
+    /// No check-ins, logs or errors are sent to AppSignal.
+    Diagnose(DiagnoseArgs),
+
+    /// Send the requests recorded with `--record` again.
+    ///
+    /// The requests in the directory are sent in the order they were
+    /// recorded, with the given API key in place of the redacted one. The
+    /// outcome of each request is printed to standard output, and the exit
+    /// code is 1 if any of them failed:
+    ///
+    ///     appsignal-run replay ./recording --api-key ...
+    Replay(ReplayArgs),
+
+    /// Serve a fake AppSignal endpoint, to check the wrapper's configuration.
+    ///
+    /// The fake endpoint listens on the given port on the loopback
+    /// interface, and accepts check-in, log, error and metric requests as
+    /// AppSignal would, without sending them to AppSignal. Each request is
+    /// recorded as a line of JSON, with its route, query parameters, body
+    /// and response status. Requests without an API key, or with a body
+    /// that is not valid JSON, are rejected.
+    ///
+    /// Point a wrapper at it with the `--endpoint` option:
+    ///
+    ///     appsignal-run fake-endpoint --port 8080 --record requests.json
+    ///     appsignal-run backup --endpoint http://127.0.0.1:8080 -- ./backup.sh
+    ///
+    /// This subcommand is only available if the wrapper is built with the
+    /// `fake-endpoint` feature.
+    #[cfg(feature = "fake-endpoint")]
+    FakeEndpoint(FakeEndpointArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct UpgradeArgs {
+    /// The PID file written by the running wrapper. Required.
+    #[arg(long, value_name = "PATH")]
+    pub pid_file: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct AgentArgs {
+    /// The directory containing the job files. Required.
+    #[arg(long, value_name = "DIR")]
+    pub jobs_dir: PathBuf,
+
+    /// Delay jobs with a schedule by a random duration up to this one.
+    ///
+    /// The delay is picked once when the agent starts, and is the same for
+    /// every scheduled execution of every job, so that many hosts running
+    /// the same schedule do not execute their jobs at the same time.
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_duration)]
+    pub splay: Option<Duration>,
+
+    /// Skip the scheduled times that were missed.
+    ///
+    /// By default, when a job's scheduled time passes without it being
+    /// executed, such as when its previous execution is still running or
+    /// the host was suspended, the job is executed once as soon as
+    /// possible. If this option is set, it is executed at the next time
+    /// that matches its schedule instead. Scheduled times that pass while
+    /// the agent is not running are always skipped.
+    #[arg(long)]
+    pub skip_if_missed: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DiagnoseArgs {
+    /// The AppSignal *app-level* push API key. Required.
+    #[arg(
+        long,
+        env = "APPSIGNAL_APP_PUSH_API_KEY",
+        value_name = "APP_PUSH_API_KEY"
+    )]
+    pub api_key: String,
+
+    /// The AppSignal public endpoint to use.
+    #[arg(
+        long,
+        hide = true,
+        env = "APPSIGNAL_PUBLIC_ENDPOINT",
+        value_name = "PUBLIC_ENDPOINT",
+        default_value = DEFAULT_ENDPOINT
+    )]
+    pub endpoint: String,
+
+    #[command(flatten)]
+    client: ClientArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// The directory the requests were recorded to. Required.
+    #[arg(index = 1, value_name = "DIR")]
+    pub dir: PathBuf,
+
+    /// The AppSignal *app-level* push API key to send the requests with.
+    /// Required.
+    #[arg(
+        long,
+        env = "APPSIGNAL_APP_PUSH_API_KEY",
+        value_name = "APP_PUSH_API_KEY"
+    )]
+    pub api_key: String,
+
+    /// Send the requests to this endpoint, instead of the one they were
+    /// recorded for, such as a fake endpoint.
+    #[arg(long, value_name = "URL")]
+    pub endpoint: Option<String>,
+
+    /// Skip the requests recorded longer ago than this, such as `72h`.
+    /// Defaults to 24 hours.
+    ///
+    /// This keeps stale check-ins and data from being sent as if they were
+    /// new. The errors, logs and metrics in the requests that are replayed
+    /// are tagged with `replayed`, so that they are not mistaken for data
+    /// sent live.
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_positive_duration)]
+    pub max_age: Option<Duration>,
+
+    #[command(flatten)]
+    client: ClientArgs,
+}
+
+#[cfg(feature = "fake-endpoint")]
+#[derive(Debug, Args)]
+pub struct FakeEndpointArgs {
+    /// The port to listen on. Required.
+    #[arg(long, value_name = "PORT")]
+    pub port: u16,
+
+    /// The file to append the recorded requests to.
+    ///
+    /// If this option is not set, the recorded requests are written to
+    /// standard output.
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+}
+
+// The options for the data reported to AppSignal by the `log`, `error`,
+// `simulate-failure` and `canary` subcommands.
+#[derive(Debug, Args)]
+pub struct ReportArgs {
+    /// The AppSignal *app-level* push API key. Required.
+    #[arg(
+        long,
+        env = "APPSIGNAL_APP_PUSH_API_KEY",
+        value_name = "APP_PUSH_API_KEY",
+        required = true
+    )]
+    api_key: Option<String>,
+
+    /// The AppSignal public endpoint to use.
+    #[arg(
+        long,
+        hide = true,
+        env = "APPSIGNAL_PUBLIC_ENDPOINT",
+        value_name = "PUBLIC_ENDPOINT",
+        default_value = DEFAULT_ENDPOINT
+    )]
+    endpoint: String,
+
+    #[command(flatten)]
+    client: ClientArgs,
+
+    /// The hostname to report. Determined automatically.
+    #[arg(
+        long,
+        env = "APPSIGNAL_HOSTNAME",
+        default_value = hostname(),
+    )]
+    hostname: String,
+
+    /// The revision of the application to report.
+    #[arg(long, env = "APP_REVISION", value_name = "REVISION")]
+    revision: Option<String>,
+
+    /// Add a tag to the reported logs or error. Can be given multiple times.
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    tags: Vec<(String, String)>,
+
+    /// The digest to uniquely identify this invocation.
+    /// Unless overriden, this value is automatically set to a random value.
+    #[arg(
+      long,
+      hide = true,
+      default_value = random_digest(),
+      hide_default_value = true
+    )]
+    digest: String,
+}
+
+impl ReportArgs {
+    fn error(
+        &self,
+        action: &str,
+        command: Option<String>,
+        tags: impl IntoIterator<Item = (String, String)>,
+    ) -> ErrorConfig {
+        ErrorConfig {
+            api_key: self.api_key.clone().unwrap_or_default(),
+            endpoint: self.endpoint.clone(),
+            action: action.to_string(),
+            hostname: self.hostname.clone(),
+            digest: self.digest.clone(),
+            command,
+            revision: self.revision.clone(),
+            tags: self.tags.iter().cloned().chain(tags).collect(),
+            compress: true,
+        }
+    }
+}
+
+// The API key is not required when a log source API key is given.
+#[derive(Debug, Args)]
+#[command(mut_arg("api_key", |arg| arg
+    .required(false)
+    .required_unless_present("log_source")
+    .help("The AppSignal *app-level* push API key")
+    .long_help(
+        "The AppSignal *app-level* push API key.\n\n\
+        Required unless a log source API key is provided using the \
+        `--log-source` option."
+    )
+))]
+pub struct LogArgs {
+    /// The log group to use to send logs. Required.
+    #[arg(index = 1, value_name = "GROUP")]
+    group: String,
+
+    /// The log source API key to use to send logs.
+    ///
+    /// If this option is not set, logs will be sent to the default
+    /// "application" log source for the application specified by the
+    /// app-level push API key -- see the `--api-key` option.
+    #[arg(
+        long,
+        env = "APPSIGNAL_LOG_SOURCE_API_KEY",
+        value_name = "LOG_SOURCE_API_KEY"
+    )]
+    log_source: Option<String>,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+impl LogArgs {
+    pub fn log(&self) -> LogConfig {
+        LogConfig {
+            api_key: self
+                .log_source
+                .as_ref()
+                .or(self.report.api_key.as_ref())
+                .unwrap()
+                .clone(),
+            endpoint: self.report.endpoint.clone(),
+            origin: LogOrigin::Stdout,
+            hostname: self.report.hostname.clone(),
+            group: self.group.clone(),
+            group_per_run: false,
+            digest: self.report.digest.clone(),
+            command: None,
+            revision: self.report.revision.clone(),
+            tags: self.report.tags.iter().cloned().collect(),
+            severity_patterns: vec![],
+            stdout_severity: LogSeverity::Info,
+            stderr_severity: LogSeverity::Error,
+            batch: LogBatch::default(),
+            rate_limit: None,
+            severity_sources: vec![],
+            compress: true,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ErrorArgs {
+    /// The action name to use to group the error. Required.
+    #[arg(index = 1, value_name = "ACTION")]
+    action: String,
+
+    /// The error message to report.
+    ///
+    /// If this option is not set, the error message is read from standard
+    /// input.
+    #[arg(long)]
+    pub message: Option<String>,
+
+    /// The name of the error to report.
+    #[arg(long, default_value = "Error")]
+    pub name: String,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+impl ErrorArgs {
+    pub fn error(&self) -> ErrorConfig {
+        self.report.error(&self.action, None, [])
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct SimulateFailureArgs {
+    /// The name of the command whose failure to simulate. Required.
+    ///
+    /// This value is used as the action name for the error, and as the
+    /// identifier for the cron check-in, as if given as the name to wrap the
+    /// command with.
+    #[arg(index = 1, value_name = "NAME")]
+    name: String,
+
+    /// The exit code to simulate. Defaults to 1.
+    #[arg(
+        long,
+        value_name = "CODE",
+        value_parser = clap::value_parser!(i32).range(1..=255),
+        conflicts_with = "signal"
+    )]
+    exit_code: Option<i32>,
+
+    /// The signal to simulate the command being terminated by, such as
+    /// `SIGKILL`.
+    #[arg(long, value_name = "SIGNAL", value_parser = parse_signal)]
+    signal: Option<Signal>,
+
+    /// Send a start cron check-in, withholding the finish cron check-in.
+    ///
+    /// Optionally, the identifier for the check-in can be provided. If
+    /// omitted, the name given as the first argument will be used.
+    #[arg(long, value_name = "IDENTIFIER")]
+    cron: Option<Option<String>>,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct CanaryArgs {
+    /// The name of the job whose implementations are compared. Required.
+    ///
+    /// This value is used as the action name for the error reported when
+    /// the commands diverge.
+    #[arg(index = 1, value_name = "NAME")]
+    name: String,
+
+    /// The reference command and the candidate command, separated by
+    /// `----`. Required.
+    #[arg(
+        index = 2,
+        value_name = "COMMANDS",
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        required = true
+    )]
+    pub commands: Vec<String>,
+
+    /// Run both commands at the same time, instead of running the
+    /// candidate after the reference exits.
+    #[arg(long)]
+    pub parallel: bool,
+
+    /// Also compare the standard output of both commands.
+    #[arg(long)]
+    pub compare_output: bool,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+impl CanaryArgs {
+    pub fn error(&self, tags: BTreeMap<String, String>) -> ErrorConfig {
+        self.report
+            .error(&self.name, Some(self.commands.join(" ")), tags)
+    }
+}
+
+impl SimulateFailureArgs {
+    // The error is tagged as simulated, so that it can be told apart from
+    // real failures of the command.
+    pub fn error(&self) -> ErrorConfig {
+        let simulated = ("simulated".to_string(), "true".to_string());
+        self.report.error(&self.name, None, [simulated])
+    }
+
+    pub fn cron(&self) -> Option<CronConfig> {
+        self.cron.as_ref().map(|identifier| CronConfig {
+            check_in: CheckInConfig {
+                api_key: self.report.api_key.clone().unwrap_or_default(),
+                endpoint: self.report.endpoint.clone(),
+                identifier: identifier.as_ref().unwrap_or(&self.name).clone(),
+            },
+            digest: self.report.digest.clone(),
+            schedule: None,
+        })
+    }
+
+    pub fn exit_status(&self) -> ExitStatus {
+        match self.signal {
+            Some(signal) => ExitStatus::from_raw(signal as i32),
+            None => ExitStatus::from_raw(self.exit_code.unwrap_or(1) << 8),
+        }
+    }
+}
+
+fn parse_tag(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err("tags must be given as KEY=VALUE".to_string()),
+    }
+}
+
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err("headers must be given as NAME=VALUE".to_string()),
+    }
+}
+
+// The prefix of the environment variables that set the wrapper's options
+// that do not have an environment variable of their own, such as
+// `APPSIGNAL_RUN_HEARTBEAT` for `--heartbeat`.
+const ENV_PREFIX: &str = "APPSIGNAL_RUN_";
+
+// The options that only make sense in the command line, and so do not have
+// an environment variable.
+const NO_ENV_OPTIONS: &[&str] = &["version", "verbose"];
+
+const DEFAULT_SHELL: &str = "/bin/sh";
+
+// The IDs and environment variables of the options that take an optional
+// value, such as `--cron [IDENTIFIER]`.
+fn optional_value_envs(command: &clap::Command) -> Vec<(String, OsString)> {
+    command
+        .get_arguments()
+        .filter(|arg| {
+            arg.get_num_args()
+                .is_some_and(|range| range.min_values() == 0 && range.max_values() == 1)
+        })
+        .filter_map(|arg| Some((arg.get_id().to_string(), arg.get_env()?.to_os_string())))
+        .collect()
+}
+
+impl Cli {
+    // Parses the command-line arguments, reading the options that are not
+    // given in them from their environment variables.
+    pub fn try_parse_with_env<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let mut command = Self::command_with_env();
+
+        // Options with an optional value, such as `--cron`, are enabled with
+        // the default identifier by a value such as `true` in their
+        // environment variable, and left unset by a value such as `false`,
+        // or by an empty value.
+        let mut enabled_from_env = Vec::new();
+        for (id, env) in optional_value_envs(&command) {
+            let Some(value) = std::env::var_os(&env) else {
+                continue;
+            };
+
+            let enabled = match value.is_empty() {
+                true => Some(false),
+                false => BoolishValueParser::new()
+                    .parse_ref(&command, None, &value)
+                    .ok(),
+            };
+
+            match enabled {
+                Some(true) => enabled_from_env.push(id),
+                Some(false) => command = command.mut_arg(&id, |arg| arg.env(None)),
+                None => {}
+            }
+        }
+
+        let mut matches = command.try_get_matches_from_mut(args)?;
+
+        enabled_from_env.retain(|id| matches.value_source(id) == Some(ValueSource::EnvVariable));
+
+        let given_options = command
+            .get_arguments()
+            .filter(|arg| {
+                matches!(
+                    matches.value_source(arg.get_id().as_str()),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                )
+            })
+            .filter_map(|arg| arg.get_long().map(str::to_string))
+            .collect();
+
+        let mut cli =
+            Self::from_arg_matches_mut(&mut matches).map_err(|err| err.format(&mut command))?;
+        cli.given_options = given_options;
+
+        for id in enabled_from_env {
+            match id.as_str() {
+                "cron" => cli.cron = Some(None),
+                "heartbeat" => cli.heartbeat = Some(None),
+                _ => unreachable!("unexpected option with an optional value: {id}"),
+            }
+        }
+
+        Ok(cli)
+    }
+
+    // The long names of the options given in the command-line arguments, or
+    // in their environment variables.
+    pub fn given_options(&self) -> impl Iterator<Item = &str> {
+        self.given_options.iter().map(String::as_str)
+    }
+
+    // The command strings that the wrapper runs through a shell, with the
+    // shell that runs each of them.
+    pub fn shell_commands(&self) -> Vec<(String, &str)> {
+        let mut commands: Vec<(String, &str)> = [
+            self.identifier_cmd.as_ref(),
+            self.health_cmd.as_ref(),
+            self.on_failure.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|command| ("sh".to_string(), command.as_str()))
+        .collect();
+
+        if let Some(shell) = self.shell.as_ref() {
+            commands.push((self.argv()[0].clone(), shell.as_str()));
+        }
+
+        commands
+    }
+
+    // The options for the requests sent by the wrapper or its subcommand.
+    pub fn client(&self) -> &ClientArgs {
+        match self.subcommand.as_ref() {
+            Some(Command::Log(args)) => &args.report.client,
+            Some(Command::Error(args)) => &args.report.client,
+            Some(Command::SimulateFailure(args)) => &args.report.client,
+            Some(Command::Canary(args)) => &args.report.client,
+            Some(Command::Diagnose(args)) => &args.client,
+            Some(Command::Replay(args)) => &args.client,
+            Some(Command::Upgrade(_)) | Some(Command::Agent(_)) | None => &self.client,
+            #[cfg(feature = "fake-endpoint")]
+            Some(Command::FakeEndpoint(_)) => &self.client,
+        }
+    }
+
+    // The version to print, if the `--version` option is given.
+    pub fn version(&self) -> Option<String> {
+        self.version.then(|| package::version(self.verbose > 0))
+    }
+
+    // The level of the wrapper's own messages to print, if `--quiet` or
+    // `--verbose` is given.
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        match (self.quiet, self.verbose) {
+            (true, _) => Some(LevelFilter::Error),
+            (false, 0) => None,
+            (false, 1) => Some(LevelFilter::Debug),
+            (false, _) => Some(LevelFilter::Trace),
+        }
+    }
+
+    // The command-line interface, with an environment variable for each of
+    // the wrapper's visible options, derived from its long name. Flags can
+    // be set from their environment variable with values such as `true`,
+    // `1` or `yes`.
+    pub fn command_with_env() -> clap::Command {
+        Self::command().mut_args(|arg| {
+            let env = match arg.get_long() {
+                Some(long)
+                    if arg.get_env().is_none()
+                        && !arg.is_hide_set()
+                        && !NO_ENV_OPTIONS.contains(&long) =>
+                {
+                    format!(
+                        "{}{}",
+                        ENV_PREFIX,
+                        long.replace('-', "_").to_ascii_uppercase()
+                    )
+                }
+                _ => return arg,
+            };
+
+            if let ArgAction::SetTrue = arg.get_action() {
+                return arg.env(env).value_parser(BoolishValueParser::new());
+            }
+
+            arg.env(env)
+        })
+    }
+
+    fn log_and_no_log_warning(&self) -> Option<String> {
+        let using: Option<&str> = if self.no_log {
+            Some("--no-log")
+        } else if self.no_stdout && self.no_stderr {
+            Some("--no-stdout and --no-stderr")
+        } else {
+            None
+        };
+
+        let alongside = if self.log.is_some() {
+            Some("--log")
+        } else if self.log_source.is_some() {
+            Some("--log-source")
+        } else {
+            None
+        };
+
+        match (using, alongside) {
+            (Some(using), Some(alongside)) => Some(format!(
+                "using {using} alongside {alongside}; \
+                no logs will be sent to AppSignal"
+            )),
+            _ => None,
+        }
+    }
+
+    fn error_and_no_error_warning(&self) -> Option<String> {
+        if self.no_error && self.error.is_some() {
+            return Some(
+                "using --no-error alongside --error; \
+                no errors will be sent to AppSignal"
+                    .to_string(),
+            );
+        };
+
+        None
+    }
+
+    fn log_group_per_run_warning(&self) -> Option<String> {
+        if self.log_group_per_run {
+            return Some(
+                "using --log-group-per-run; a new log group will be created \
+                for each invocation, which should only be used when debugging"
+                    .to_string(),
+            );
+        }
+
+        None
+    }
+
+    fn no_log_and_no_data_warning(&self) -> Option<String> {
+        let no_checkins: bool = self.cron.is_none() && self.heartbeat.is_none();
+
+        if self.minimal && no_checkins {
+            return Some(
+                "using --minimal without either --cron or --heartbeat; \
+                no data will be sent to AppSignal"
+                    .to_string(),
+            );
+        }
+
+        if !self.no_error || self.metrics || self.statsd_lines {
+            return None;
+        }
+
+        if no_checkins {
+            let using: Option<&str> = if self.no_log {
+                Some("--no-log and --no-error")
+            } else if self.no_stdout && self.no_stderr {
+                Some("--no-stdout, --no-stderr and --no-error")
+            } else {
+                None
+            };
+
+            if let Some(using) = using {
+                return Some(format!(
+                    "using {using} without either --cron or --heartbeat; \
+                    no data will be sent to AppSignal"
+                ));
+            }
+        }
+
+        None
+    }
+
+    fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if let Some(warning) = self.log_and_no_log_warning() {
+            warnings.push(warning);
+        }
+
+        if let Some(warning) = self.error_and_no_error_warning() {
+            warnings.push(warning);
+        }
+
+        if let Some(warning) = self.log_group_per_run_warning() {
+            warnings.push(warning);
+        }
 
         if let Some(warning) = self.no_log_and_no_data_warning() {
             warnings.push(warning);
         }
 
-        warnings
+        if self.identifier_cmd.is_some() && self.cron.is_none() && self.heartbeat.is_none() {
+            warnings.push(
+                "using --identifier-cmd without either --cron or --heartbeat; \
+                the command will not be run"
+                    .to_string(),
+            );
+        }
+
+        warnings
+    }
+
+    pub fn warn(&self) {
+        for warning in self.warnings() {
+            warn!("{}", warning);
+        }
+    }
+
+    pub fn cron(&self) -> Option<CronConfig> {
+        match (self.api_key.as_ref(), self.cron.as_ref()) {
+            (Some(api_key), Some(identifier)) => Some(CronConfig {
+                check_in: CheckInConfig {
+                    api_key: api_key.clone(),
+                    endpoint: self.endpoint.clone(),
+                    identifier: self.identifier(identifier),
+                },
+                digest: self.digest.clone(),
+                schedule: self.cron_schedule.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    // The identifier given to `--cron` or `--heartbeat`, or else the one
+    // written by the `--identifier-cmd` command, or else the name.
+    fn identifier(&self, identifier: &Option<String>) -> String {
+        identifier
+            .as_ref()
+            .or(self.command_identifier.as_ref())
+            .unwrap_or(self.name())
+            .clone()
+    }
+
+    // Runs the `--identifier-cmd` command, if its output would be used as
+    // the check-in identifier. If the command fails, the name is used as
+    // the identifier instead.
+    pub fn run_identifier_cmd(&mut self) {
+        let Some(command) = self.identifier_cmd.as_ref() else {
+            return;
+        };
+
+        if !matches!(
+            (&self.cron, &self.heartbeat),
+            (Some(None), _) | (_, Some(None))
+        ) {
+            return;
+        }
+
+        match identifier::from_command(command, IDENTIFIER_CMD_TIMEOUT) {
+            Ok(identifier) => self.command_identifier = Some(identifier),
+            Err(err) => warn!(
+                "{}; using `{}` as the check-in identifier",
+                err,
+                self.name()
+            ),
+        }
+    }
+
+    pub fn heartbeat(&self) -> Option<HeartbeatConfig> {
+        match (self.api_key.as_ref(), self.heartbeat.as_ref()) {
+            (Some(api_key), Some(identifier)) => Some(HeartbeatConfig {
+                check_in: CheckInConfig {
+                    api_key: api_key.clone(),
+                    endpoint: self.endpoint.clone(),
+                    identifier: self.identifier(identifier),
+                },
+                interval: self
+                    .heartbeat_interval
+                    .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL),
+                on_idle: !self.no_heartbeat_on_idle,
+                activity_patterns: self.activity_pattern.clone(),
+                health_cmd: self.health_cmd.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn log(&self) -> LogConfig {
+        let api_key = self
+            .log_source_by_severity
+            .as_ref()
+            .and_then(|sources| sources.default.as_ref())
+            .or(self.log_source.as_ref())
+            .or(self.api_key.as_ref())
+            .unwrap()
+            .clone();
+        let endpoint = self.endpoint.clone();
+        let origin = self.log_origin();
+        let group = self.log.as_ref().unwrap_or(self.name()).clone();
+        let hostname = self.hostname.clone();
+        let digest = self.digest.clone();
+        let command = Some(self.command_as_str());
+        let revision = self.revision.clone();
+        let tags = self.tags();
+        let severity_patterns = self.severity_patterns();
+
+        LogConfig {
+            api_key,
+            endpoint,
+            origin,
+            hostname,
+            group,
+            group_per_run: self.log_group_per_run,
+            digest,
+            command,
+            revision,
+            tags,
+            severity_patterns,
+            stdout_severity: self.stdout_severity,
+            stderr_severity: self.stderr_severity,
+            batch: LogBatch {
+                size: self.log_batch_size.unwrap_or(DEFAULT_LOG_BATCH_SIZE),
+                bytes: within_budget(
+                    self.log_batch_bytes,
+                    self.max_wrapper_memory,
+                    MemoryBudget::log_batch_bytes,
+                ),
+                flush_interval: self
+                    .log_flush_interval
+                    .unwrap_or(DEFAULT_LOG_FLUSH_INTERVAL),
+                capacity: self.log_buffer_lines.unwrap_or(DEFAULT_LOG_CAPACITY),
+                capacity_bytes: self
+                    .max_wrapper_memory
+                    .as_ref()
+                    .map(MemoryBudget::log_buffer_bytes),
+            },
+            rate_limit: self.log_rate_limit,
+            severity_sources: self
+                .log_source_by_severity
+                .as_ref()
+                .map(|sources| sources.sources.clone())
+                .unwrap_or_default(),
+            compress: !self.no_compress,
+        }
+    }
+
+    // The patterns given with `--severity-pattern` take precedence over the
+    // default ones enabled by `--detect-severity`.
+    fn severity_patterns(&self) -> Vec<SeverityPattern> {
+        let mut patterns = self.severity_pattern.clone();
+
+        if self.detect_severity {
+            patterns.extend(SeverityPattern::defaults());
+        }
+
+        patterns
+    }
+
+    pub fn error(&self) -> Option<ErrorConfig> {
+        if self.no_error || self.minimal {
+            return None;
+        }
+
+        let api_key = self.api_key.as_ref().unwrap().clone();
+        let endpoint = self.endpoint.clone();
+        let action = self.error.as_ref().unwrap_or(self.name()).clone();
+        let hostname = self.hostname.clone();
+        let digest = self.digest.clone();
+        let command = Some(self.command_as_str());
+        let revision = self.revision.clone();
+        let mut tags = self.tags();
+
+        if let Some(dir) = self.chdir.as_ref() {
+            tags.insert("directory".to_string(), dir.display().to_string());
+        }
+
+        Some(ErrorConfig {
+            api_key,
+            endpoint,
+            action,
+            hostname,
+            digest,
+            command,
+            revision,
+            tags,
+            compress: !self.no_compress,
+        })
+    }
+
+    pub fn metrics(&self) -> Option<MetricsConfig> {
+        if !self.metrics {
+            return None;
+        }
+
+        Some(self.metrics_config())
+    }
+
+    pub fn statsd(&self) -> Option<MetricsConfig> {
+        if !self.statsd_lines {
+            return None;
+        }
+
+        Some(self.metrics_config())
+    }
+
+    pub fn trace(&self) -> Option<TraceConfig> {
+        let endpoint = self.otlp_endpoint.clone()?;
+
+        Some(TraceConfig {
+            endpoint,
+            headers: self.otlp_headers.iter().cloned().collect(),
+            name: self.name().clone(),
+            hostname: self.hostname.clone(),
+            digest: self.digest.clone(),
+            command: Some(self.command_as_str()),
+            revision: self.revision.clone(),
+            tags: self.tags(),
+            trace_id: random_trace_id(),
+            span_id: random_digest(),
+        })
+    }
+
+    fn metrics_config(&self) -> MetricsConfig {
+        MetricsConfig {
+            api_key: self.api_key.as_ref().unwrap().clone(),
+            endpoint: self.endpoint.clone(),
+            name: self.name().clone(),
+            hostname: self.hostname.clone(),
+            tags: self.tags(),
+        }
+    }
+
+    // Builds the configuration for the wrapper from the command-line
+    // arguments, validating it.
+    pub fn wrap_config(&self) -> Result<WrapConfig, String> {
+        let log = match self.log_origin() {
+            LogOrigin::None => None,
+            _ => Some(self.log()),
+        };
+
+        WrapConfig::builder(self.argv())
+            .digest(self.digest.clone())
+            .log(log)
+            .error(self.error())
+            .metrics(self.metrics())
+            .statsd(self.statsd())
+            .trace(self.trace())
+            .cron(self.cron())
+            .heartbeat(self.heartbeat())
+            .error_sample(self.error_sample)
+            .error_lines(self.error_lines)
+            .error_bytes(within_budget(
+                self.error_bytes,
+                self.max_wrapper_memory,
+                MemoryBudget::error_bytes,
+            ))
+            .failure_codes(self.failure_codes())
+            .success_codes(self.success_exit_codes.clone())
+            .state_dir(self.state_dir.clone().map(StateDir::new))
+            .pid_file(self.pid_file.clone())
+            .quit_action(self.sigquit)
+            .usr2_action(self.sigusr2)
+            .stop_signal(self.stop_signal)
+            .forward_signals(self.forward_signal.clone())
+            .no_forward(self.no_forward.clone())
+            .dump_file(self.dump_file.clone())
+            .shutdown_file(self.shutdown_file.clone())
+            .correlate(self.correlate)
+            .linger(self.linger)
+            .kill_after(self.kill_after)
+            .restart(self.restart)
+            .stall_timeout(self.stall_timeout)
+            .stall_kill(self.stall_kill)
+            .on_failure(self.on_failure.clone())
+            .on_failure_timeout(self.on_failure_timeout)
+            .on_failure_report(self.on_failure_report)
+            .ready_pattern(self.ready_pattern.clone())
+            .ready_timeout(self.ready_timeout)
+            .flush_timeout(self.flush_timeout)
+            .unbuffered(self.unbuffered)
+            .on_passthrough_error(self.on_passthrough_error)
+            .max_line_bytes(self.max_line_bytes())
+            .lines_buffer(self.lines_buffer())
+            .summary(self.summary)
+            .batch_summary(self.batch_summary)
+            .warn_on_batch_failures(self.warn_on_batch_failures)
+            .redact(Redactor::new(self.redact.clone()))
+            .strip_ansi(!self.no_strip_ansi)
+            .stdout(self.should_pipe_stdout())
+            .stderr(self.should_pipe_stderr())
+            .stdin(self.stdin())
+            .user(self.user.clone())
+            .group(self.group.clone())
+            .chdir(self.chdir.clone())
+            .env(self.env())
+            .env_files(self.env_file.clone())
+            .clear_env(self.clear_env)
+            .sinks(self.sinks())
+            .single_thread(self.single_thread || self.minimal)
+            .wrapper_priority(self.wrapper_priority)
+            .wait_for_clock(self.wait_for_clock)
+            .log_files(self.log_file.clone())
+            .build()
+    }
+
+    // The variables given with `--env`, with the ones given without a value
+    // read from the wrapper's environment. Those that are not set in it are
+    // not passed to the command.
+    fn env(&self) -> Vec<(String, String)> {
+        self.env
+            .iter()
+            .filter_map(|(key, value)| match value {
+                Some(value) => Some((key.clone(), value.clone())),
+                None => Some((key.clone(), std::env::var(key).ok()?)),
+            })
+            .collect()
+    }
+
+    fn failure_codes(&self) -> FailureCodes {
+        match (&self.error_exit_codes, &self.no_error_exit_codes) {
+            (Some(codes), _) => FailureCodes::Only(codes.clone()),
+            (_, Some(codes)) => FailureCodes::Except(codes.clone()),
+            _ => FailureCodes::NonZero,
+        }
+    }
+
+    // The maximum size of a line of the command's output, from the memory
+    // budget.
+    pub fn max_line_bytes(&self) -> Option<usize> {
+        self.max_wrapper_memory
+            .as_ref()
+            .map(MemoryBudget::line_bytes)
+    }
+
+    // How many lines each stage that processes the command's output holds,
+    // from the memory budget.
+    pub fn lines_buffer(&self) -> Option<usize> {
+        self.max_wrapper_memory
+            .as_ref()
+            .map(MemoryBudget::lines_buffer)
+    }
+
+    // The log config for the lines read from standard input with `--stdin`,
+    // if they are sent as logs. There is no command to tag them with.
+    pub fn pipe_log(&self) -> Option<LogConfig> {
+        if !self.log_origin().is_out() {
+            return None;
+        }
+
+        Some(LogConfig {
+            command: None,
+            ..self.log()
+        })
+    }
+
+    fn log_origin(&self) -> LogOrigin {
+        LogOrigin::from_args(self.no_log || self.minimal, self.no_stdout, self.no_stderr)
+    }
+
+    pub fn should_pipe_stderr(&self) -> bool {
+        // If `--no-heartbeat-on-idle` is set, we need to pipe stderr to
+        // detect activity, unless `--no-stderr` is set.
+        if self.no_heartbeat_on_idle {
+            return !self.no_stderr;
+        }
+
+        // If `--error` is set, we need to pipe stderr for the error message,
+        // even if we're not sending logs, unless `--no-stderr` is set.
+        if self.error.is_some() {
+            return !self.no_stderr;
+        }
+
+        // Sinks are passed the lines of stderr, unless `--no-stderr` is set.
+        if !self.sink.is_empty() {
+            return !self.no_stderr;
+        }
+
+        // Stalls and readiness are detected from the lines of stderr,
+        // unless `--no-stderr` is set.
+        if self.stall_timeout.is_some() || self.ready_pattern.is_some() {
+            return !self.no_stderr;
+        }
+
+        self.log_origin().is_err()
+    }
+
+    fn stdin(&self) -> StdinMode {
+        if self.no_stdin {
+            StdinMode::Null
+        } else if self.stdin_log {
+            StdinMode::Log
+        } else {
+            StdinMode::Inherit
+        }
+    }
+
+    pub fn should_pipe_stdout(&self) -> bool {
+        if self.statsd_lines {
+            return true;
+        }
+
+        if self.no_heartbeat_on_idle {
+            return !self.no_stdout;
+        }
+
+        // If `--error` is set, we need to pipe stdout for the error message,
+        // even if we're not sending logs, unless `--no-stdout` is set.
+        if self.error.is_some() {
+            return !self.no_stdout;
+        }
+
+        // Sinks are passed the lines of stdout, unless `--no-stdout` is set.
+        if !self.sink.is_empty() {
+            return !self.no_stdout;
+        }
+
+        // Stalls and readiness are detected from the lines of stdout,
+        // unless `--no-stdout` is set.
+        if self.stall_timeout.is_some() || self.ready_pattern.is_some() {
+            return !self.no_stdout;
+        }
+
+        // The batch summary line is read from stdout.
+        if self.batch_summary {
+            return true;
+        }
+
+        self.log_origin().is_out()
+    }
+
+    fn tags(&self) -> BTreeMap<String, String> {
+        let mut tags: BTreeMap<String, String> = self.tags.iter().cloned().collect();
+
+        if self.tag_wrap_version {
+            tags.insert("wrap_version".to_string(), package::VERSION.to_string());
+        }
+
+        if self.tag_wrap_host {
+            tags.insert("wrap_host".to_string(), hostname());
+        }
+
+        tags
+    }
+
+    fn sinks(&self) -> Vec<SinkConfig> {
+        self.sink
+            .iter()
+            .map(|destination| SinkConfig {
+                destination: destination.clone(),
+                name: self.name().clone(),
+                command: self.command_as_str(),
+            })
+            .collect()
+    }
+
+    pub fn audit_log(&self) -> Option<AuditLog> {
+        Some(AuditLog {
+            destination: self.audit.clone()?,
+            name: self.name().clone(),
+            command: self.command_as_str(),
+        })
+    }
+
+    // The command to execute, either as given after the name or, with the
+    // `--shell` option, as the shell to run the command string with.
+    fn argv(&self) -> Vec<String> {
+        match self.shell.as_ref() {
+            Some(shell) => vec![
+                self.shell_path
+                    .as_ref()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_else(|| DEFAULT_SHELL.to_string()),
+                "-c".to_string(),
+                shell.clone(),
+            ],
+            None => self.command.clone(),
+        }
+    }
+
+    fn command_as_str(&self) -> String {
+        match self.shell.as_ref() {
+            Some(shell) => shell.clone(),
+            None => self.command.join(" "),
+        }
+    }
+
+    // The name is required unless a subcommand is used.
+    fn name(&self) -> &String {
+        self.name.as_ref().expect("name is required")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hook::{FailureHook, DEFAULT_ON_FAILURE_TIMEOUT};
+    use crate::log::SeveritySource;
+    use crate::package::NAME;
+
+    // These arguments are required -- without them, the CLI parser will fail.
+    fn with_required_args(args: Vec<&str>) -> Vec<&str> {
+        let first_args: Vec<&str> = vec![NAME, "some-name", "--api-key", "some-api-key"];
+        let last_args: Vec<&str> = vec!["--", "true"];
+        first_args
+            .into_iter()
+            .chain(args)
+            .chain(last_args)
+            .collect()
+    }
+
+    #[test]
+    fn cli_command_without_separator() {
+        for (args, command) in [
+            (
+                vec!["some-name", "--no-log", "--", "cmd", "--no-log"],
+                vec!["cmd", "--no-log"],
+            ),
+            (
+                vec!["some-name", "--no-log", "cmd", "--no-log", "--", "arg"],
+                vec!["cmd", "--no-log", "--", "arg"],
+            ),
+            (vec!["--no-log", "some-name", "cmd"], vec!["cmd"]),
+        ] {
+            let cli = Cli::try_parse_from(
+                [NAME, "--api-key", "some-api-key"]
+                    .into_iter()
+                    .chain(args.clone()),
+            )
+            .expect("failed to parse CLI arguments");
+
+            assert_eq!(cli.command, command, "{args:?}");
+            assert!(cli.no_log, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn cli_shell() {
+        for (args, command) in [
+            (
+                vec!["--shell", "pg_dump app | gzip > backup.gz"],
+                vec!["/bin/sh", "-c", "pg_dump app | gzip > backup.gz"],
+            ),
+            (
+                vec!["--shell", "echo $HOME", "--shell-path", "/bin/bash"],
+                vec!["/bin/bash", "-c", "echo $HOME"],
+            ),
+        ] {
+            let cli = Cli::try_parse_from(
+                [NAME, "some-name", "--api-key", "some-api-key"]
+                    .into_iter()
+                    .chain(args.clone()),
+            )
+            .expect("failed to parse CLI arguments");
+            let config = cli.wrap_config().expect("failed to build config");
+
+            assert_eq!(config.command, command, "{args:?}");
+            assert_eq!(
+                config.error.unwrap().command.as_deref(),
+                Some(command[2]),
+                "{args:?}"
+            );
+        }
+
+        for args in [
+            vec!["some-name", "--shell", "true", "--", "true"],
+            vec!["some-name", "--shell-path", "/bin/bash", "--", "true"],
+            vec!["some-name"],
+        ] {
+            assert!(
+                Cli::try_parse_from(
+                    [NAME, "--api-key", "some-api-key"]
+                        .into_iter()
+                        .chain(args.clone())
+                )
+                .is_err(),
+                "{args:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn cli_no_warnings() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+
+        let warnings = cli.warnings();
+
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn cli_warnings_log_and_no_log() {
+        for (args, warning) in [(
+                vec!["--log", "some-group", "--no-log"],
+                "using --no-log alongside --log; no logs will be sent to AppSignal"
+            ),
+            (
+                vec!["--log-source", "some-log-source", "--no-log"],
+                "using --no-log alongside --log-source; no logs will be sent to AppSignal"
+            ),
+            (
+                vec!["--log", "some-group", "--no-stdout", "--no-stderr"],
+                "using --no-stdout and --no-stderr alongside --log; no logs will be sent to AppSignal"
+            ),
+            (
+                vec!["--log-source", "some-log-source", "--no-stdout", "--no-stderr"],
+                "using --no-stdout and --no-stderr alongside --log-source; no logs will be sent to AppSignal"
+            )] {
+            let cli = Cli::try_parse_from(
+                with_required_args(args)
+            ).expect("failed to parse CLI arguments");
+
+            let warnings = cli.warnings();
+
+            assert!(!warnings.is_empty());
+            assert!(
+                warnings.contains(&warning.to_string()),
+                "actual: {warnings:?}, expected to contain: {warning:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn cli_warnings_error_and_no_error() {
+        let args = vec!["--error", "some-action", "--no-error"];
+        let cli =
+            Cli::try_parse_from(with_required_args(args)).expect("failed to parse CLI arguments");
+
+        let warnings = cli.warnings();
+
+        assert!(warnings.len() == 1);
+        assert_eq!(
+            warnings[0],
+            "using --no-error alongside --error; no errors will be sent to AppSignal"
+        );
+    }
+
+    #[test]
+    fn cli_severity_patterns() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        assert!(cli.log().severity_patterns.is_empty());
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--detect-severity",
+            "--severity-pattern",
+            "critical=^WARN",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let log_config = cli.log();
+
+        assert_eq!(
+            log_config.severity("WARN: disk full", LogSeverity::Info),
+            LogSeverity::Critical
+        );
+        assert_eq!(
+            log_config.severity("debug: connecting", LogSeverity::Info),
+            LogSeverity::Debug
+        );
+
+        assert!(
+            Cli::try_parse_from(with_required_args(vec!["--severity-pattern", "verbose=^V"]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn cli_stream_severities() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let log_config = cli.log();
+
+        assert_eq!(log_config.stdout_severity, LogSeverity::Info);
+        assert_eq!(log_config.stderr_severity, LogSeverity::Error);
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--stdout-severity",
+            "debug",
+            "--stderr-severity",
+            "WARN",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let log_config = cli.log();
+
+        assert_eq!(log_config.stdout_severity, LogSeverity::Debug);
+        assert_eq!(log_config.stderr_severity, LogSeverity::Warn);
+    }
+
+    #[test]
+    fn cli_options_from_env() {
+        let command = Cli::command_with_env();
+        let env = |long: &str| {
+            command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(long))
+                .and_then(|arg| arg.get_env())
+                .map(|env| env.to_str().unwrap().to_string())
+        };
+
+        assert_eq!(
+            env("error-lines").as_deref(),
+            Some("APPSIGNAL_RUN_ERROR_LINES")
+        );
+        assert_eq!(env("tag").as_deref(), Some("APPSIGNAL_RUN_TAG"));
+        assert_eq!(
+            env("api-key").as_deref(),
+            Some("APPSIGNAL_APP_PUSH_API_KEY")
+        );
+        assert_eq!(env("digest"), None);
+
+        // These environment variables are only read by `try_parse_with_env`,
+        // so they do not affect other tests.
+        std::env::set_var("APPSIGNAL_RUN_SUMMARY", "yes");
+        std::env::set_var("APPSIGNAL_RUN_LINGER", "10");
+
+        let cli = Cli::try_parse_with_env(with_required_args(vec!["--linger", "5"]))
+            .expect("failed to parse CLI arguments");
+
+        std::env::remove_var("APPSIGNAL_RUN_SUMMARY");
+        std::env::remove_var("APPSIGNAL_RUN_LINGER");
+
+        let mut given_options: Vec<&str> = cli.given_options().collect();
+        given_options.sort();
+        assert_eq!(given_options, vec!["api-key", "linger", "summary"]);
+
+        let config = cli.wrap_config().expect("failed to build config");
+        assert!(config.summary);
+        assert_eq!(config.linger, Some(Duration::from_secs(5)));
+
+        // Options with an optional value are enabled with the default
+        // identifier by a boolish value, and are unset by an empty one.
+        for (value, cron) in [
+            ("true", Some("some-name")),
+            ("1", Some("some-name")),
+            ("nightly", Some("nightly")),
+            ("false", None),
+            ("", None),
+        ] {
+            std::env::set_var("APPSIGNAL_RUN_CRON", value);
+            let cli = Cli::try_parse_with_env(with_required_args(vec![]));
+            std::env::remove_var("APPSIGNAL_RUN_CRON");
+
+            let cli = cli.expect("failed to parse CLI arguments");
+            assert_eq!(
+                cli.cron().map(|cron| cron.check_in.identifier).as_deref(),
+                cron,
+                "{value:?}"
+            );
+            assert_eq!(
+                cli.given_options().any(|option| option == "cron"),
+                cron.is_some()
+            );
+        }
+
+        std::env::set_var("APPSIGNAL_RUN_HEARTBEAT", "yes");
+        let cli = Cli::try_parse_with_env(with_required_args(vec!["--cron", "nightly"]));
+        std::env::remove_var("APPSIGNAL_RUN_HEARTBEAT");
+        assert!(cli.is_err());
+
+        std::env::set_var("APPSIGNAL_RUN_HEARTBEAT", "no");
+        let cli = Cli::try_parse_with_env(with_required_args(vec!["--cron", "nightly"]));
+        std::env::remove_var("APPSIGNAL_RUN_HEARTBEAT");
+        assert_eq!(
+            cli.expect("failed to parse CLI arguments")
+                .cron()
+                .map(|cron| cron.check_in.identifier),
+            Some("nightly".to_string())
+        );
+    }
+
+    #[test]
+    fn cli_log_batch() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        assert_eq!(cli.log().batch, LogBatch::default());
+        assert_eq!(cli.log().rate_limit, None);
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--log-batch-size",
+            "500",
+            "--log-batch-bytes",
+            "65536",
+            "--log-flush-interval",
+            "1",
+            "--log-rate-limit",
+            "1MB/min",
+        ]))
+        .expect("failed to parse CLI arguments");
+
+        assert_eq!(cli.log().rate_limit, LogRateLimit::parse("1MB/min").ok());
+
+        assert_eq!(
+            cli.log().batch,
+            LogBatch {
+                size: 500,
+                bytes: Some(65536),
+                flush_interval: Duration::from_secs(1),
+                capacity: DEFAULT_LOG_CAPACITY,
+                capacity_bytes: None,
+            }
+        );
+
+        for args in [
+            vec!["--log-batch-size", "0"],
+            vec!["--log-flush-interval", "0"],
+            vec!["--log-rate-limit", "0/s"],
+            vec!["--no-log", "--log-batch-bytes", "1024"],
+        ] {
+            assert!(Cli::try_parse_from(with_required_args(args)).is_err());
+        }
+    }
+
+    #[test]
+    fn cli_max_wrapper_memory() {
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--max-wrapper-memory",
+            "64MiB",
+            "--error-bytes",
+            "4KB",
+        ]))
+        .expect("failed to parse CLI arguments");
+
+        let batch = cli.log().batch;
+        assert_eq!(batch.capacity_bytes, Some(32 << 20));
+        assert_eq!(batch.bytes, Some(8 << 20));
+
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.error_bytes, Some(4000));
+
+        // Options that size a buffer cannot exceed its share of the budget.
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--max-wrapper-memory",
+            "64MiB",
+            "--log-batch-bytes",
+            "1GB",
+            "--error-bytes",
+            "1GB",
+        ]))
+        .expect("failed to parse CLI arguments");
+        assert_eq!(cli.log().batch.bytes, Some(8 << 20));
+
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.error_bytes, Some(2 << 20));
+
+        assert!(
+            Cli::try_parse_from(with_required_args(vec!["--max-wrapper-memory", "100KB"])).is_err()
+        );
+    }
+
+    #[test]
+    fn cli_redact() {
+        let args = vec!["--redact", "Bearer \\S+", "--redact", "secret"];
+        let cli =
+            Cli::try_parse_from(with_required_args(args)).expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build wrap config");
+
+        assert_eq!(
+            config.redact.redact("secret Bearer abc123".to_string()),
+            "[REDACTED] [REDACTED]"
+        );
+
+        let result = Cli::try_parse_from(with_required_args(vec!["--redact", "("]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn cli_log_file() {
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--log-file",
+            "/var/log/app.log",
+            "--log-file",
+            "/var/log/worker.log:worker",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(
+            config.log_files,
+            vec![
+                LogFile {
+                    path: PathBuf::from("/var/log/app.log"),
+                    group: None,
+                },
+                LogFile {
+                    path: PathBuf::from("/var/log/worker.log"),
+                    group: Some("worker".to_string()),
+                },
+            ]
+        );
+
+        assert!(Cli::try_parse_from(with_required_args(vec![
+            "--log-file",
+            "/var/log/app.log",
+            "--no-log"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn cli_stdin_pipe() {
+        let cli = Cli::try_parse_from([NAME, "some-name", "--api-key", "some-api-key", "--stdin"])
+            .expect("failed to parse CLI arguments");
+        assert!(cli.pipe);
+        assert!(cli.command.is_empty());
+
+        let log = cli.pipe_log().expect("expected a log config");
+        assert_eq!(log.command, None);
+
+        let cli = Cli::try_parse_from([
+            NAME,
+            "some-name",
+            "--api-key",
+            "some-api-key",
+            "--stdin",
+            "--no-log",
+        ])
+        .expect("failed to parse CLI arguments");
+        assert!(cli.pipe_log().is_none());
+
+        assert!(Cli::try_parse_from(with_required_args(vec!["--stdin"])).is_err());
+        assert!(Cli::try_parse_from([NAME, "some-name", "--api-key", "k"]).is_err());
+    }
+
+    #[test]
+    fn cli_log_group_per_run() {
+        let args = vec!["--log-group-per-run", "--digest", "0123456789abcdef"];
+        let cli =
+            Cli::try_parse_from(with_required_args(args)).expect("failed to parse CLI arguments");
+
+        assert_eq!(cli.log().group(), "some-name-01234567");
+        assert_eq!(
+            cli.warnings(),
+            vec!["using --log-group-per-run; a new log group will be created for each invocation, which should only be used when debugging"]
+        );
+
+        assert!(
+            Cli::try_parse_from(with_required_args(vec!["--log-group-per-run", "--no-log"]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn cli_tag_wrap_version_and_host() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let tags = cli.log().tags;
+        assert!(!tags.contains_key("wrap_version"));
+        assert!(!tags.contains_key("wrap_host"));
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--tag-wrap-version",
+            "--tag-wrap-host",
+            "--hostname",
+            "some-hostname",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let tags = cli.log().tags;
+        assert_eq!(tags["wrap_version"], package::VERSION);
+        assert_eq!(tags["wrap_host"], hostname());
+
+        let error = cli.error().expect("expected error config");
+        assert_eq!(error.tags["wrap_version"], package::VERSION);
+    }
+
+    #[test]
+    fn cli_log_source_by_severity() {
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--log-source-by-severity",
+            "error=errors-key,default=verbose-key",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let log = cli.log();
+        assert_eq!(log.api_key, "verbose-key");
+        assert_eq!(
+            log.severity_sources,
+            vec![SeveritySource {
+                severity: LogSeverity::Error,
+                api_key: "errors-key".to_string(),
+            }]
+        );
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--log-source",
+            "log-source-key",
+            "--log-source-by-severity",
+            "warn=warnings-key",
+        ]))
+        .expect("failed to parse CLI arguments");
+        assert_eq!(cli.log().api_key, "log-source-key");
+
+        for value in ["error", "error=", "loud=key", "error=a,error=b"] {
+            assert!(
+                Cli::try_parse_from(with_required_args(vec!["--log-source-by-severity", value]))
+                    .is_err(),
+                "{value:?} should fail"
+            );
+        }
+    }
+
+    #[test]
+    fn cli_stdin() {
+        for (args, expected) in [
+            (vec![], StdinMode::Inherit),
+            (vec!["--no-stdin"], StdinMode::Null),
+            (vec!["--stdin-log"], StdinMode::Log),
+        ] {
+            let cli = Cli::try_parse_from(with_required_args(args))
+                .expect("failed to parse CLI arguments");
+            let config = cli.wrap_config().expect("failed to build config");
+            assert_eq!(config.stdin, expected);
+        }
+
+        for args in [
+            vec!["--stdin-log", "--no-stdin"],
+            vec!["--stdin-log", "--no-log"],
+        ] {
+            assert!(Cli::try_parse_from(with_required_args(args)).is_err());
+        }
+    }
+
+    #[test]
+    fn cli_chdir() {
+        let cli = Cli::try_parse_from(with_required_args(vec!["--chdir", "/tmp"]))
+            .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+
+        assert_eq!(config.chdir, Some(PathBuf::from("/tmp")));
+        assert_eq!(
+            config.error.unwrap().tags.get("directory"),
+            Some(&"/tmp".to_string())
+        );
+    }
+
+    #[test]
+    fn cli_env() {
+        let path = std::env::temp_dir().join(format!("{}-cli-test.env", std::process::id()));
+        std::fs::write(&path, "# comment\nFOO=from-file\nBAR=from-file\n").unwrap();
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--env-file",
+            path.to_str().unwrap(),
+            "--env",
+            "BAR=from-option",
+            "--env",
+            "PATH",
+            "--env",
+            "NOT_SET_IN_THE_WRAPPER_ENVIRONMENT",
+            "--clear-env",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config();
+
+        std::fs::remove_file(&path).unwrap();
+        let config = config.expect("failed to build config");
+
+        assert_eq!(
+            config.env,
+            BTreeMap::from([
+                ("BAR".to_string(), "from-option".to_string()),
+                ("FOO".to_string(), "from-file".to_string()),
+                ("PATH".to_string(), std::env::var("PATH").unwrap()),
+            ])
+        );
+        assert!(config.clear_env);
+
+        let cli = Cli::try_parse_from(with_required_args(vec!["--env-file", "/no/such/file"]))
+            .expect("failed to parse CLI arguments");
+        assert!(cli
+            .wrap_config()
+            .is_err_and(|err| err.starts_with("could not read environment file /no/such/file")));
+
+        assert!(Cli::try_parse_from(with_required_args(vec!["--env", "=value"])).is_err());
+    }
+
+    #[test]
+    fn cli_warnings_no_log_and_no_data() {
+        for (args, warning) in [
+            (
+                vec!["--no-log", "--no-error"],
+                Some("using --no-log and --no-error without either --cron or --heartbeat; no data will be sent to AppSignal")
+            ),
+            (
+                vec!["--no-stdout", "--no-stderr", "--no-error"],
+                Some("using --no-stdout, --no-stderr and --no-error without either --cron or --heartbeat; no data will be sent to AppSignal")
+            ),
+            (
+                vec!["--no-log", "--no-stdout", "--no-stderr", "--no-error"],
+                Some("using --no-log and --no-error without either --cron or --heartbeat; no data will be sent to AppSignal")
+            ),
+            (
+                vec!["--no-log", "--no-error", "--cron"],
+                None
+            ),
+            (
+                vec!["--no-log", "--no-error", "--heartbeat"],
+                None
+            ),
+            (
+                vec!["--no-log", "--no-error", "--metrics"],
+                None
+            ),
+            (
+                vec!["--no-log"],
+                None
+            ),
+            (
+                vec!["--minimal"],
+                Some("using --minimal without either --cron or --heartbeat; no data will be sent to AppSignal")
+            ),
+            (
+                vec!["--minimal", "--cron"],
+                None
+            ),
+        ] {
+            let cli = Cli::try_parse_from(
+                with_required_args(args)
+
+            ).expect("failed to parse CLI arguments");
+
+            let warnings = cli.warnings();
+
+            if let Some(warning) = warning {
+                assert_eq!(warnings.len(), 1);
+                assert_eq!(warnings[0], warning);
+            } else {
+                assert!(warnings.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn cli_log_config() {
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--log",
+            "some-group",
+            "--hostname",
+            "some-hostname",
+            "--digest",
+            "some-digest",
+        ]))
+        .expect("failed to parse CLI arguments");
+
+        let log_config = cli.log();
+
+        assert_eq!(log_config.api_key, "some-api-key");
+        assert_eq!(log_config.endpoint, "https://appsignal-endpoint.net");
+        assert_eq!(log_config.origin, LogOrigin::All);
+        assert_eq!(log_config.group, "some-group");
+        assert_eq!(log_config.hostname, "some-hostname");
+        assert_eq!(log_config.digest, "some-digest");
+    }
+
+    #[test]
+    fn cli_log_config_no_log_options() {
+        for (args, origin) in [
+            (vec!["--no-log"], LogOrigin::None),
+            (vec!["--no-stdout", "--no-stderr"], LogOrigin::None),
+            (vec!["--no-stdout"], LogOrigin::Stderr),
+            (vec!["--no-stderr"], LogOrigin::Stdout),
+        ] {
+            let cli = Cli::try_parse_from(with_required_args(args))
+                .expect("failed to parse CLI arguments");
+
+            let log_config = cli.log();
+
+            assert_eq!(log_config.origin, origin);
+        }
+    }
+
+    #[test]
+    fn cli_wrap_config() {
+        for (args, log, stdout, stderr) in [
+            (vec![], Some(LogOrigin::All), true, true),
+            (vec!["--no-log"], None, false, false),
+            (vec!["--no-log", "--error", "some-action"], None, true, true),
+            (vec!["--no-stdout"], Some(LogOrigin::Stderr), false, true),
+            (vec!["--minimal", "--cron"], None, false, false),
+        ] {
+            let cli = Cli::try_parse_from(with_required_args(
+                args.into_iter()
+                    .chain(["--digest", "some-digest"])
+                    .collect(),
+            ))
+            .expect("failed to parse CLI arguments");
+
+            let config = cli.wrap_config().expect("failed to build config");
+
+            assert_eq!(config.command, vec!["true"]);
+            assert_eq!(config.digest, "some-digest");
+            assert_eq!(config.log.map(|log| log.origin), log);
+            assert_eq!(config.stdout, stdout);
+            assert_eq!(config.stderr, stderr);
+            assert_eq!(config.error.is_none(), cli.minimal);
+            assert_eq!(config.single_thread, cli.minimal);
+            assert_eq!(config.linger, None);
+            assert_eq!(config.kill_after, None);
+            assert_eq!(config.stop_signal, None);
+            assert_eq!(config.flush_timeout, None);
+            assert!(config.strip_ansi);
+        }
+
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        assert!(cli.log().compress);
+        assert!(cli.error().unwrap().compress);
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--linger",
+            "5",
+            "--kill-after",
+            "10",
+            "--stop-signal",
+            "quit",
+            "--flush-timeout",
+            "20",
+            "--no-strip-ansi",
+            "--no-compress",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.linger, Some(Duration::from_secs(5)));
+        assert_eq!(config.kill_after, Some(Duration::from_secs(10)));
+        assert_eq!(config.stop_signal, Some(Signal::SIGQUIT));
+        assert_eq!(config.flush_timeout, Some(Duration::from_secs(20)));
+        assert!(!config.strip_ansi);
+        assert!(!config.log.unwrap().compress);
+        assert!(!config.error.unwrap().compress);
+    }
+
+    #[test]
+    fn cli_forward_signal() {
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--forward-signal",
+            "SIGHUP=SIGUSR1",
+            "--forward-signal",
+            "alrm=term",
+            "--no-forward",
+            "winch",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+
+        assert_eq!(
+            config.forward_signals,
+            BTreeMap::from([
+                (Signal::SIGHUP, Signal::SIGUSR1),
+                (Signal::SIGALRM, Signal::SIGTERM),
+            ])
+        );
+        assert_eq!(config.no_forward, vec![Signal::SIGWINCH]);
+
+        for mapping in ["SIGHUP", "SIGHUP=", "SIGKILL=SIGTERM"] {
+            assert!(
+                Cli::try_parse_from(with_required_args(vec!["--forward-signal", mapping])).is_err(),
+                "{mapping}"
+            );
+        }
+    }
+
+    #[test]
+    fn cli_shutdown_file() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.shutdown_file, None);
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--shutdown-file",
+            "/run/app/shutdown",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(
+            config.shutdown_file,
+            Some(PathBuf::from("/run/app/shutdown"))
+        );
+    }
+
+    #[test]
+    fn cli_sink() {
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--no-log",
+            "--no-error",
+            "--cron",
+            "--sink",
+            "file:/tmp/some-sink.json",
+            "--sink",
+            "syslog",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+
+        assert_eq!(
+            config
+                .sinks
+                .iter()
+                .map(|sink| sink.destination.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                SinkDestination::File(PathBuf::from("/tmp/some-sink.json")),
+                SinkDestination::Syslog
+            ]
+        );
+        assert_eq!(config.sinks[0].name, "some-name");
+        assert!(config.stdout);
+        assert!(config.stderr);
+
+        assert!(Cli::try_parse_from(with_required_args(vec!["--sink", "stdout"])).is_err());
+    }
+
+    #[test]
+    fn cli_error_config() {
+        for (args, error) in [
+            (vec!["--no-error"], None),
+            (vec!["--error", "some-action"], Some("some-action")),
+            (vec![], Some("some-name")),
+        ] {
+            let cli = Cli::try_parse_from(with_required_args(
+                args.into_iter()
+                    .chain(["--hostname", "some-hostname", "--digest", "some-digest"])
+                    .collect(),
+            ))
+            .expect("failed to parse CLI arguments");
+
+            let error_config = cli.error();
+
+            if let Some(action) = error {
+                let error_config = error_config.expect("expected error config");
+                assert_eq!(error_config.action, action);
+                assert_eq!(error_config.api_key, "some-api-key");
+                assert_eq!(error_config.endpoint, "https://appsignal-endpoint.net");
+                assert_eq!(error_config.hostname, "some-hostname");
+                assert_eq!(error_config.digest, "some-digest");
+            } else {
+                assert!(error_config.is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn cli_tags() {
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--tag",
+            "environment=production",
+            "--tag",
+            "customer=some=customer",
+        ]))
+        .expect("failed to parse CLI arguments");
+
+        let expected: BTreeMap<String, String> = [
+            ("environment".to_string(), "production".to_string()),
+            ("customer".to_string(), "some=customer".to_string()),
+        ]
+        .into();
+
+        assert_eq!(cli.log().tags, expected);
+        assert_eq!(cli.error().expect("expected error config").tags, expected);
+
+        for tag in ["environment", "=production"] {
+            assert!(Cli::try_parse_from(with_required_args(vec!["--tag", tag])).is_err());
+        }
+    }
+
+    #[test]
+    fn cli_error_sampler() {
+        for (args, rate) in [(vec!["--error-sample", "1/10"], Some(10)), (vec![], None)] {
+            let cli = Cli::try_parse_from(with_required_args(
+                args.into_iter()
+                    .chain(["--error", "some-action", "--state-dir", "/tmp/some-state"])
+                    .collect(),
+            ))
+            .expect("failed to parse CLI arguments");
+
+            let sampler = cli
+                .wrap_config()
+                .expect("failed to build config")
+                .error_sampler();
+
+            if let Some(rate) = rate {
+                let sampler = sampler.expect("expected error sampler");
+                assert_eq!(sampler.rate, rate);
+                assert_eq!(sampler.action, "some-action");
+                assert_eq!(sampler.state_dir, StateDir::new("/tmp/some-state"));
+            } else {
+                assert!(sampler.is_none());
+            }
+        }
+
+        for args in [
+            vec!["--error-sample", "10"],
+            vec!["--error-sample", "1/10", "--no-error"],
+        ] {
+            assert!(Cli::try_parse_from(with_required_args(args)).is_err());
+        }
+    }
+
+    #[test]
+    fn cli_revision() {
+        let cli = Cli::try_parse_from(with_required_args(vec!["--revision", "abc123"]))
+            .expect("failed to parse CLI arguments");
+
+        assert_eq!(cli.log().revision.as_deref(), Some("abc123"));
+        assert_eq!(
+            cli.error()
+                .expect("expected error config")
+                .revision
+                .as_deref(),
+            Some("abc123")
+        );
+    }
+
+    #[test]
+    fn cli_failure_codes() {
+        for (args, failure_codes) in [
+            (vec![], Some(FailureCodes::NonZero)),
+            (
+                vec!["--error-exit-codes", "2-255"],
+                Some(FailureCodes::Only(ExitCodes::parse("2-255").unwrap())),
+            ),
+            (
+                vec!["--no-error-exit-codes", "1"],
+                Some(FailureCodes::Except(ExitCodes::parse("1").unwrap())),
+            ),
+            (
+                vec!["--error-exit-codes", "2", "--no-error-exit-codes", "1"],
+                None,
+            ),
+            (vec!["--error-exit-codes", "two"], None),
+        ] {
+            let cli = Cli::try_parse_from(with_required_args(args));
+
+            match failure_codes {
+                Some(failure_codes) => assert_eq!(
+                    cli.expect("failed to parse CLI arguments").failure_codes(),
+                    failure_codes
+                ),
+                None => assert!(cli.is_err()),
+            }
+        }
+    }
+
+    #[test]
+    fn cli_upgrade_subcommand() {
+        let cli = Cli::try_parse_from(vec![NAME, "upgrade", "--pid-file", "/tmp/some.pid"])
+            .expect("failed to parse CLI arguments");
+
+        match cli.subcommand {
+            Some(Command::Upgrade(args)) => {
+                assert_eq!(args.pid_file, PathBuf::from("/tmp/some.pid"))
+            }
+            _ => panic!("expected upgrade subcommand"),
+        }
+    }
+
+    #[test]
+    fn cli_agent_subcommand() {
+        let cli = Cli::try_parse_from(vec![NAME, "agent", "--jobs-dir", "/etc/jobs.d"])
+            .expect("failed to parse CLI arguments");
+
+        match cli.subcommand {
+            Some(Command::Agent(args)) => {
+                assert_eq!(args.jobs_dir, PathBuf::from("/etc/jobs.d"));
+                assert_eq!(args.splay, None);
+                assert!(!args.skip_if_missed);
+            }
+            _ => panic!("expected agent subcommand"),
+        }
+
+        let cli = Cli::try_parse_from(vec![
+            NAME,
+            "agent",
+            "--jobs-dir",
+            "/etc/jobs.d",
+            "--splay",
+            "5m",
+            "--skip-if-missed",
+        ])
+        .expect("failed to parse CLI arguments");
+
+        match cli.subcommand {
+            Some(Command::Agent(args)) => {
+                assert_eq!(args.splay, Some(Duration::from_secs(300)));
+                assert!(args.skip_if_missed);
+            }
+            _ => panic!("expected agent subcommand"),
+        }
+
+        assert!(Cli::try_parse_from(vec![NAME, "agent"]).is_err());
+    }
+
+    #[test]
+    fn cli_diagnose_subcommand() {
+        let cli = Cli::try_parse_from(vec![NAME, "diagnose", "--api-key", "some-api-key"])
+            .expect("failed to parse CLI arguments");
+
+        match cli.subcommand {
+            Some(Command::Diagnose(ref args)) => {
+                assert_eq!(args.api_key, "some-api-key");
+                assert_eq!(args.endpoint, DEFAULT_ENDPOINT);
+            }
+            _ => panic!("expected diagnose subcommand"),
+        }
+    }
+
+    #[test]
+    fn cli_canary_subcommand() {
+        let cli = Cli::try_parse_from(vec![
+            NAME,
+            "canary",
+            "backup",
+            "--api-key",
+            "some-api-key",
+            "--compare-output",
+            "--",
+            "./backup.sh",
+            "--full",
+            "----",
+            "./backup-v2.sh",
+        ])
+        .expect("failed to parse CLI arguments");
+
+        match cli.subcommand {
+            Some(Command::Canary(ref args)) => {
+                assert_eq!(
+                    args.commands,
+                    vec!["./backup.sh", "--full", "----", "./backup-v2.sh"]
+                );
+                assert!(args.compare_output);
+                assert!(!args.parallel);
+
+                let error = args.error(BTreeMap::from([(
+                    "candidate_exit".to_string(),
+                    "code 1".to_string(),
+                )]));
+                assert_eq!(error.action, "backup");
+                assert_eq!(error.tags["candidate_exit"], "code 1");
+            }
+            _ => panic!("expected canary subcommand"),
+        }
+
+        assert!(
+            Cli::try_parse_from(vec![NAME, "canary", "backup", "--api-key", "some-api-key"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn cli_replay_subcommand() {
+        let cli = Cli::try_parse_from(vec![
+            NAME,
+            "replay",
+            "/tmp/recording",
+            "--api-key",
+            "some-api-key",
+        ])
+        .expect("failed to parse CLI arguments");
+
+        match cli.subcommand {
+            Some(Command::Replay(ref args)) => {
+                assert_eq!(args.dir, PathBuf::from("/tmp/recording"));
+                assert_eq!(args.api_key, "some-api-key");
+                assert_eq!(args.endpoint, None);
+                assert_eq!(args.max_age, None);
+            }
+            _ => panic!("expected replay subcommand"),
+        }
+
+        let cli = Cli::try_parse_from(vec![
+            NAME,
+            "replay",
+            "/tmp/recording",
+            "--api-key",
+            "some-api-key",
+            "--max-age",
+            "72h",
+        ])
+        .expect("failed to parse CLI arguments");
+
+        match cli.subcommand {
+            Some(Command::Replay(ref args)) => {
+                assert_eq!(args.max_age, Some(Duration::from_secs(72 * 60 * 60)));
+            }
+            _ => panic!("expected replay subcommand"),
+        }
+
+        assert!(Cli::try_parse_from(vec![NAME, "replay", "--api-key", "some-api-key"]).is_err());
     }
 
-    pub fn warn(&self) {
-        for warning in self.warnings() {
-            warn!("{}", warning);
+    #[cfg(feature = "fake-endpoint")]
+    #[test]
+    fn cli_fake_endpoint_subcommand() {
+        let cli = Cli::try_parse_from(vec![NAME, "fake-endpoint", "--port", "8080"])
+            .expect("failed to parse CLI arguments");
+
+        match cli.subcommand {
+            Some(Command::FakeEndpoint(args)) => {
+                assert_eq!(args.port, 8080);
+                assert_eq!(args.record, None);
+            }
+            _ => panic!("expected fake-endpoint subcommand"),
         }
+
+        assert!(Cli::try_parse_from(vec![NAME, "fake-endpoint"]).is_err());
     }
 
-    pub fn cron(&self) -> Option<CronConfig> {
-        match (self.api_key.as_ref(), self.cron.as_ref()) {
-            (Some(api_key), Some(identifier)) => Some(CronConfig {
-                check_in: CheckInConfig {
-                    api_key: api_key.clone(),
-                    endpoint: self.endpoint.clone(),
-                    identifier: identifier.as_ref().unwrap_or(&self.name).clone(),
-                },
-                digest: self.digest.clone(),
-            }),
-            _ => None,
+    #[test]
+    fn cli_heartbeat_interval() {
+        for (args, interval) in [
+            (vec!["--heartbeat"], Some(30)),
+            (
+                vec!["--heartbeat", "--heartbeat-interval", "3600"],
+                Some(3600),
+            ),
+            (vec!["--heartbeat-interval", "3600"], None),
+            (vec!["--heartbeat", "--heartbeat-interval", "0"], None),
+        ] {
+            let cli = Cli::try_parse_from(with_required_args(args));
+
+            match interval {
+                Some(interval) => {
+                    let heartbeat_config = cli
+                        .expect("failed to parse CLI arguments")
+                        .heartbeat()
+                        .expect("expected heartbeat config");
+
+                    assert_eq!(heartbeat_config.interval, Duration::from_secs(interval));
+                }
+                None => assert!(cli.is_err()),
+            }
         }
     }
 
-    pub fn heartbeat(&self) -> Option<HeartbeatConfig> {
-        match (self.api_key.as_ref(), self.heartbeat.as_ref()) {
-            (Some(api_key), Some(identifier)) => Some(HeartbeatConfig {
-                check_in: CheckInConfig {
-                    api_key: api_key.clone(),
-                    endpoint: self.endpoint.clone(),
-                    identifier: identifier.as_ref().unwrap_or(&self.name).clone(),
-                },
-            }),
-            _ => None,
+    #[test]
+    fn cli_health_cmd() {
+        let cli = Cli::try_parse_from(with_required_args(vec!["--heartbeat"]))
+            .expect("failed to parse CLI arguments");
+        let heartbeat_config = cli.heartbeat().expect("expected heartbeat config");
+        assert_eq!(heartbeat_config.health_cmd, None);
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--heartbeat",
+            "--health-cmd",
+            "pg_isready",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let heartbeat_config = cli.heartbeat().expect("expected heartbeat config");
+        assert_eq!(heartbeat_config.health_cmd, Some("pg_isready".to_string()));
+
+        assert!(
+            Cli::try_parse_from(with_required_args(vec!["--health-cmd", "pg_isready"])).is_err()
+        );
+    }
+
+    #[test]
+    fn cli_sigusr2() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.usr2_action, Usr2Action::Forward);
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--heartbeat",
+            "--sigusr2",
+            "heartbeat",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.usr2_action, Usr2Action::Heartbeat);
+
+        let cli = Cli::try_parse_from(with_required_args(vec!["--sigusr2", "heartbeat"]))
+            .expect("failed to parse CLI arguments");
+        assert_eq!(
+            cli.wrap_config().err(),
+            Some("cannot send heartbeats on SIGUSR2 without heartbeat check-ins".to_string())
+        );
+    }
+
+    #[test]
+    fn cli_on_passthrough_error() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(
+            config.on_passthrough_error,
+            PassthroughErrorAction::Continue
+        );
+
+        let cli = Cli::try_parse_from(with_required_args(vec!["--on-passthrough-error", "stop"]))
+            .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.on_passthrough_error, PassthroughErrorAction::Stop);
+
+        assert!(
+            Cli::try_parse_from(with_required_args(vec!["--on-passthrough-error", "ignore"]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn cli_on_failure() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.on_failure, None);
+
+        let cli = Cli::try_parse_from(with_required_args(vec!["--on-failure", "./diagnose.sh"]))
+            .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(
+            config.on_failure,
+            Some(FailureHook {
+                command: "./diagnose.sh".to_string(),
+                timeout: DEFAULT_ON_FAILURE_TIMEOUT,
+                report: false,
+            })
+        );
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--on-failure",
+            "./diagnose.sh",
+            "--on-failure-timeout",
+            "1m",
+            "--on-failure-report",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(
+            config.on_failure,
+            Some(FailureHook {
+                command: "./diagnose.sh".to_string(),
+                timeout: Duration::from_secs(60),
+                report: true,
+            })
+        );
+
+        for args in [
+            vec!["--on-failure-timeout", "1m"],
+            vec!["--on-failure-report"],
+            vec!["--on-failure", "true", "--on-failure-timeout", "0"],
+            vec!["--on-failure", "true", "--on-failure-report", "--no-error"],
+        ] {
+            assert!(
+                Cli::try_parse_from(with_required_args(args.clone())).is_err(),
+                "{args:?} should fail"
+            );
         }
     }
 
-    pub fn log(&self) -> LogConfig {
-        let api_key = self
-            .log_source
-            .as_ref()
-            .or(self.api_key.as_ref())
-            .unwrap()
-            .clone();
-        let endpoint = self.endpoint.clone();
-        let origin = self.log_origin();
-        let group = self.log.as_ref().unwrap_or(&self.name).clone();
-        let hostname = self.hostname.clone();
-        let digest = self.digest.clone();
-        let command = self.command_as_str();
+    #[test]
+    fn cli_batch_summary() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert!(!config.batch_summary);
+        assert!(!config.warn_on_batch_failures);
 
-        LogConfig {
-            api_key,
-            endpoint,
-            origin,
-            hostname,
-            group,
-            digest,
-            command,
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--batch-summary",
+            "--warn-on-batch-failures",
+            "--no-log",
+        ]));
+        assert!(cli.is_err());
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--batch-summary",
+            "--warn-on-batch-failures",
+            "--no-error",
+            "--no-stderr",
+        ]))
+        .expect("failed to parse CLI arguments");
+        assert!(cli.should_pipe_stdout());
+
+        let config = cli.wrap_config().expect("failed to build config");
+        assert!(config.batch_summary);
+        assert!(config.warn_on_batch_failures);
+
+        for args in [
+            vec!["--warn-on-batch-failures"],
+            vec!["--batch-summary", "--no-stdout"],
+        ] {
+            assert!(
+                Cli::try_parse_from(with_required_args(args.clone())).is_err(),
+                "{args:?} should fail"
+            );
         }
     }
 
-    pub fn error(&self) -> Option<ErrorConfig> {
-        if self.no_error {
-            return None;
+    #[test]
+    fn cli_stall_timeout() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.stall_timeout, None);
+        assert!(!config.stall_kill);
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--stall-timeout",
+            "600",
+            "--stall-kill",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.stall_timeout, Some(Duration::from_secs(600)));
+        assert!(config.stall_kill);
+
+        for args in [vec!["--stall-timeout", "0"], vec!["--stall-kill"]] {
+            assert!(
+                Cli::try_parse_from(with_required_args(args.clone())).is_err(),
+                "{args:?} should fail"
+            );
         }
 
-        let api_key = self.api_key.as_ref().unwrap().clone();
-        let endpoint = self.endpoint.clone();
-        let action = self.error.as_ref().unwrap_or(&self.name).clone();
-        let hostname = self.hostname.clone();
-        let digest = self.digest.clone();
-        let command = self.command_as_str();
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--stall-timeout",
+            "600",
+            "--no-stdout",
+            "--no-stderr",
+        ]))
+        .expect("failed to parse CLI arguments");
+        assert_eq!(
+            cli.wrap_config().err(),
+            Some("cannot detect stalls without capturing the output".to_string())
+        );
+    }
 
-        Some(ErrorConfig {
-            api_key,
-            endpoint,
-            action,
-            hostname,
-            digest,
-            command,
-        })
+    #[test]
+    fn cli_ready_pattern() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert!(config.ready_pattern.is_none());
+        assert_eq!(config.ready_timeout, None);
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--ready-pattern",
+            "^Listening on",
+            "--ready-timeout",
+            "30",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        let pattern = config.ready_pattern.expect("no ready pattern");
+        assert!(pattern.0.is_match("Listening on port 3000"));
+        assert_eq!(config.ready_timeout, Some(Duration::from_secs(30)));
+
+        for args in [
+            vec!["--ready-pattern", "("],
+            vec!["--ready-timeout", "30"],
+            vec!["--ready-pattern", "ready", "--ready-timeout", "0"],
+        ] {
+            assert!(
+                Cli::try_parse_from(with_required_args(args.clone())).is_err(),
+                "{args:?} should fail"
+            );
+        }
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--ready-pattern",
+            "ready",
+            "--no-stdout",
+            "--no-stderr",
+        ]))
+        .expect("failed to parse CLI arguments");
+        assert_eq!(
+            cli.wrap_config().err(),
+            Some("cannot detect readiness without capturing the output".to_string())
+        );
     }
 
-    fn log_origin(&self) -> LogOrigin {
-        LogOrigin::from_args(self.no_log, self.no_stdout, self.no_stderr)
+    #[test]
+    fn cli_wait_for_clock() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.wait_for_clock, None);
+
+        let cli = Cli::try_parse_from(with_required_args(vec!["--wait-for-clock", "120"]))
+            .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.wait_for_clock, Some(Duration::from_secs(120)));
+
+        assert!(Cli::try_parse_from(with_required_args(vec!["--wait-for-clock", "0"])).is_err());
     }
 
-    pub fn should_pipe_stderr(&self) -> bool {
-        // If `--error` is set, we need to pipe stderr for the error message,
-        // even if we're not sending logs, unless `--no-stderr` is set.
-        if self.error.is_some() {
-            return !self.no_stderr;
+    #[test]
+    fn cli_wrapper_priority() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.wrapper_priority, None);
+
+        for (value, expected) in [
+            ("low", WrapperPriority::Low),
+            ("idle", WrapperPriority::Idle),
+        ] {
+            let cli = Cli::try_parse_from(with_required_args(vec!["--wrapper-priority", value]))
+                .expect("failed to parse CLI arguments");
+            let config = cli.wrap_config().expect("failed to build config");
+            assert_eq!(config.wrapper_priority, Some(expected));
         }
 
-        self.log_origin().is_err()
-    }
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--wrapper-priority",
+            "low",
+            "--restart",
+            "on-failure",
+        ]))
+        .expect("failed to parse CLI arguments");
+        assert_eq!(
+            cli.wrap_config().err(),
+            Some("cannot lower the wrapper's priority when restarting the command".to_string())
+        );
+    }
+
+    #[test]
+    fn cli_restart() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(config.restart, None);
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--restart",
+            "on-failure:max=3,backoff=1m",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let config = cli.wrap_config().expect("failed to build config");
+        assert_eq!(
+            config.restart,
+            Some(RestartPolicy {
+                max_restarts: 3,
+                backoff: Duration::from_secs(60),
+            })
+        );
+
+        assert!(Cli::try_parse_from(with_required_args(vec!["--restart", "always"])).is_err());
+
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--restart",
+            "on-failure",
+            "--stdin-log",
+        ]))
+        .expect("failed to parse CLI arguments");
+        assert_eq!(
+            cli.wrap_config().err(),
+            Some("cannot restart the command when its standard input is sent as logs".to_string())
+        );
+    }
+
+    #[test]
+    fn cli_log_subcommand() {
+        let cli = Cli::try_parse_from(vec![
+            NAME,
+            "log",
+            "some-group",
+            "--api-key",
+            "some-api-key",
+            "--hostname",
+            "some-hostname",
+            "--digest",
+            "some-digest",
+        ])
+        .expect("failed to parse CLI arguments");
+
+        let Some(Command::Log(args)) = cli.subcommand else {
+            panic!("expected log subcommand");
+        };
+
+        let log_config = args.log();
+
+        assert_eq!(log_config.api_key, "some-api-key");
+        assert_eq!(log_config.endpoint, "https://appsignal-endpoint.net");
+        assert_eq!(log_config.group, "some-group");
+        assert_eq!(log_config.hostname, "some-hostname");
+        assert_eq!(log_config.digest, "some-digest");
+        assert_eq!(log_config.command, None);
+    }
+
+    #[test]
+    fn cli_error_subcommand() {
+        let cli = Cli::try_parse_from(vec![
+            NAME,
+            "error",
+            "some-action",
+            "--message",
+            "some-message",
+            "--api-key",
+            "some-api-key",
+            "--hostname",
+            "some-hostname",
+        ])
+        .expect("failed to parse CLI arguments");
+
+        let Some(Command::Error(args)) = cli.subcommand else {
+            panic!("expected error subcommand");
+        };
+
+        assert_eq!(args.message.as_deref(), Some("some-message"));
+        assert_eq!(args.name, "Error");
+
+        let error_config = args.error();
+
+        assert_eq!(error_config.action, "some-action");
+        assert_eq!(error_config.api_key, "some-api-key");
+        assert_eq!(error_config.hostname, "some-hostname");
+        assert_eq!(error_config.command, None);
+    }
+
+    #[test]
+    fn cli_simulate_failure_subcommand() {
+        let parse = |args: &[&str]| -> Result<SimulateFailureArgs, clap::Error> {
+            let cli = Cli::try_parse_from(
+                [
+                    NAME,
+                    "simulate-failure",
+                    "some-name",
+                    "--api-key",
+                    "some-api-key",
+                ]
+                .iter()
+                .chain(args),
+            )?;
+
+            match cli.subcommand {
+                Some(Command::SimulateFailure(args)) => Ok(args),
+                _ => panic!("expected simulate-failure subcommand"),
+            }
+        };
+
+        let args = parse(&[]).expect("failed to parse CLI arguments");
+        assert_eq!(args.exit_status().code(), Some(1));
+        assert!(args.cron().is_none());
+
+        let error_config = args.error();
+        assert_eq!(error_config.action, "some-name");
+        assert_eq!(
+            error_config.tags.get("simulated").map(String::as_str),
+            Some("true")
+        );
+
+        let args = parse(&["--exit-code", "2", "--cron"]).expect("failed to parse CLI arguments");
+        assert_eq!(args.exit_status().code(), Some(2));
+        assert_eq!(args.cron().unwrap().check_in.identifier, "some-name");
 
-    pub fn should_pipe_stdout(&self) -> bool {
-        // If `--error` is set, we need to pipe stdout for the error message,
-        // even if we're not sending logs, unless `--no-stdout` is set.
-        if self.error.is_some() {
-            return !self.no_stdout;
+        for signal in ["SIGKILL", "kill", "9"] {
+            let args = parse(&["--signal", signal]).expect("failed to parse CLI arguments");
+            assert_eq!(args.exit_status().signal(), Some(libc::SIGKILL));
         }
 
-        self.log_origin().is_out()
+        for args in [
+            &["--exit-code", "0"][..],
+            &["--signal", "SIGNOPE"],
+            &["--exit-code", "2", "--signal", "SIGKILL"],
+        ] {
+            assert!(parse(args).is_err(), "{args:?} should fail");
+        }
     }
 
-    fn command_as_str(&self) -> String {
-        self.command.join(" ")
-    }
-}
+    #[test]
+    fn cli_trace_config() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        assert!(cli.trace().is_none());
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::package::NAME;
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--otlp-endpoint",
+            "http://localhost:4318",
+            "--otlp-header",
+            "authorization=Bearer token",
+            "--digest",
+            "some-digest",
+        ]))
+        .expect("failed to parse CLI arguments");
 
-    // These arguments are required -- without them, the CLI parser will fail.
-    fn with_required_args(args: Vec<&str>) -> Vec<&str> {
-        let first_args: Vec<&str> = vec![NAME, "some-name", "--api-key", "some-api-key"];
-        let last_args: Vec<&str> = vec!["--", "true"];
-        first_args
-            .into_iter()
-            .chain(args)
-            .chain(last_args)
-            .collect()
-    }
+        let config = cli.wrap_config().expect("failed to build config");
+        let trace = config.trace.expect("expected trace config");
+        assert_eq!(trace.endpoint, "http://localhost:4318");
+        assert_eq!(trace.headers["authorization"], "Bearer token");
+        assert_eq!(trace.name, "some-name");
+        assert_eq!(trace.digest, "some-digest");
+        assert_eq!(trace.command.as_deref(), Some("true"));
+        assert_eq!(trace.trace_id.len(), 32);
+        assert_eq!(trace.span_id.len(), 16);
 
-    #[test]
-    fn random_digest() {
-        let digest = super::random_digest();
-        assert!(digest.chars().all(|c| c.is_ascii_hexdigit()));
-        assert_eq!(digest.len(), 16);
+        for args in [
+            vec!["--otlp-header", "authorization=token"],
+            vec![
+                "--otlp-endpoint",
+                "http://localhost:4318",
+                "--otlp-header",
+                "token",
+            ],
+        ] {
+            assert!(
+                Cli::try_parse_from(with_required_args(args.clone())).is_err(),
+                "{args:?} should fail"
+            );
+        }
+
+        for args in [
+            vec!["--otlp-endpoint", "localhost:4318"],
+            vec![
+                "--otlp-endpoint",
+                "http://localhost:4318",
+                "--otlp-header",
+                "bad name=value",
+            ],
+        ] {
+            let cli = Cli::try_parse_from(with_required_args(args.clone()))
+                .expect("failed to parse CLI arguments");
+            assert!(cli.wrap_config().is_err(), "{args:?} should fail");
+        }
     }
 
     #[test]
-    fn cli_no_warnings() {
+    fn cli_metrics_config() {
         let cli =
             Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        assert!(cli.metrics().is_none());
 
-        let warnings = cli.warnings();
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--metrics",
+            "--tag",
+            "team=backend",
+        ]))
+        .expect("failed to parse CLI arguments");
 
-        assert!(warnings.is_empty());
+        let metrics_config = cli.metrics().expect("expected metrics config");
+        assert_eq!(metrics_config.api_key, "some-api-key");
+        assert_eq!(metrics_config.endpoint, "https://appsignal-endpoint.net");
+        assert_eq!(metrics_config.name, "some-name");
+        assert_eq!(
+            metrics_config.tags.get("team").map(String::as_str),
+            Some("backend")
+        );
+
+        assert!(Cli::try_parse_from(with_required_args(vec!["--metrics", "--minimal"])).is_err());
     }
 
     #[test]
-    fn cli_warnings_log_and_no_log() {
-        for (args, warning) in [(
-                vec!["--log", "some-group", "--no-log"],
-                "using --no-log alongside --log; no logs will be sent to AppSignal"
-            ),
-            (
-                vec!["--log-source", "some-log-source", "--no-log"],
-                "using --no-log alongside --log-source; no logs will be sent to AppSignal"
-            ),
-            (
-                vec!["--log", "some-group", "--no-stdout", "--no-stderr"],
-                "using --no-stdout and --no-stderr alongside --log; no logs will be sent to AppSignal"
-            ),
-            (
-                vec!["--log-source", "some-log-source", "--no-stdout", "--no-stderr"],
-                "using --no-stdout and --no-stderr alongside --log-source; no logs will be sent to AppSignal"
-            )] {
-            let cli = Cli::try_parse_from(
-                with_required_args(args)
-            ).expect("failed to parse CLI arguments");
-
-            let warnings = cli.warnings();
-
-            assert!(!warnings.is_empty());
+    fn cli_minimal_conflicts_with_output_options() {
+        for args in [
+            vec!["--stall-timeout", "1s"],
+            vec!["--ready-pattern", "ready"],
+            vec!["--batch-summary"],
+            vec!["--stdout-severity", "debug"],
+            vec!["--log-file", "app.log"],
+            vec!["--stdin-log"],
+            vec!["--sink", "syslog"],
+            vec!["--error", "some-action"],
+            vec!["--log", "some-group"],
+        ] {
             assert!(
-                warnings.contains(&warning.to_string()),
-                "actual: {warnings:?}, expected to contain: {warning:?}"
+                Cli::try_parse_from(with_required_args(args.clone())).is_ok(),
+                "{args:?}"
+            );
+            assert!(
+                Cli::try_parse_from(with_required_args(
+                    ["--minimal"].into_iter().chain(args.clone()).collect()
+                ))
+                .is_err(),
+                "{args:?}"
             );
         }
     }
 
     #[test]
-    fn cli_warnings_error_and_no_error() {
-        let args = vec!["--error", "some-action", "--no-error"];
-        let cli =
-            Cli::try_parse_from(with_required_args(args)).expect("failed to parse CLI arguments");
+    fn cli_cron_schedule() {
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--cron",
+            "--cron-schedule",
+            "15 3 * * *",
+        ]))
+        .expect("failed to parse CLI arguments");
 
-        let warnings = cli.warnings();
+        let cron_config = cli.cron().expect("expected cron config");
+        assert_eq!(cron_config.schedule.as_deref(), Some("15 3 * * *"));
 
-        assert!(warnings.len() == 1);
+        for args in [
+            vec!["--cron-schedule", "15 3 * * *"],
+            vec!["--cron", "--cron-schedule", "15 3 * *"],
+        ] {
+            assert!(Cli::try_parse_from(with_required_args(args)).is_err());
+        }
+    }
+
+    #[test]
+    fn cli_version() {
+        let cli = Cli::try_parse_from([NAME, "--version"]).expect("failed to parse CLI arguments");
         assert_eq!(
-            warnings[0],
-            "using --no-error alongside --error; no errors will be sent to AppSignal"
+            cli.version(),
+            Some(format!("{NAME} {}\n", package::VERSION))
         );
+
+        let cli =
+            Cli::try_parse_from([NAME, "-V", "--verbose"]).expect("failed to parse CLI arguments");
+        let version = cli.version().unwrap();
+        assert!(version.starts_with(&format!("{NAME} {}\n", package::VERSION)));
+        assert!(version.contains("\ntls backend: rustls\n"));
+
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        assert_eq!(cli.version(), None);
+
+        let cli = Cli::try_parse_from([NAME, "-V", "-v"]).expect("failed to parse CLI arguments");
+        assert!(cli.version().unwrap().contains("\ntls backend: rustls\n"));
     }
 
     #[test]
-    fn cli_warnings_no_log_and_no_data() {
-        for (args, warning) in [
-            (
-                vec!["--no-log", "--no-error"],
-                Some("using --no-log and --no-error without either --cron or --heartbeat; no data will be sent to AppSignal")
-            ),
-            (
-                vec!["--no-stdout", "--no-stderr", "--no-error"],
-                Some("using --no-stdout, --no-stderr and --no-error without either --cron or --heartbeat; no data will be sent to AppSignal")
-            ),
-            (
-                vec!["--no-log", "--no-stdout", "--no-stderr", "--no-error"],
-                Some("using --no-log and --no-error without either --cron or --heartbeat; no data will be sent to AppSignal")
-            ),
-            (
-                vec!["--no-log", "--no-error", "--cron"],
-                None
-            ),
-            (
-                vec!["--no-log", "--no-error", "--heartbeat"],
-                None
-            ),
-            (
-                vec!["--no-log"],
-                None
-            ),
+    fn cli_verbosity() {
+        for (args, expected) in [
+            (vec![], None),
+            (vec!["--quiet"], Some(LevelFilter::Error)),
+            (vec!["-q"], Some(LevelFilter::Error)),
+            (vec!["--verbose"], Some(LevelFilter::Debug)),
+            (vec!["-v"], Some(LevelFilter::Debug)),
+            (vec!["-vv"], Some(LevelFilter::Trace)),
+            (vec!["-v", "-v", "-v"], Some(LevelFilter::Trace)),
         ] {
-            let cli = Cli::try_parse_from(
-                with_required_args(args)
+            let cli = Cli::try_parse_from(with_required_args(args.clone()))
+                .expect("failed to parse CLI arguments");
+            assert_eq!(cli.log_level(), expected, "{args:?}");
+            assert_eq!(cli.version(), None);
+        }
 
-            ).expect("failed to parse CLI arguments");
+        assert!(Cli::try_parse_from(with_required_args(vec!["-q", "-v"])).is_err());
+    }
 
-            let warnings = cli.warnings();
+    #[test]
+    fn cli_tls() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let tls = cli.client().tls().expect("failed to load TLS config");
+        assert!(tls.ca_certs.is_empty());
+        assert!(!tls.insecure);
+        assert_eq!(tls.roots, TlsRoots::Bundled);
 
-            if let Some(warning) = warning {
-                assert_eq!(warnings.len(), 1);
-                assert_eq!(warnings[0], warning);
-            } else {
-                assert!(warnings.is_empty());
-            }
+        for (value, roots) in [("system", TlsRoots::System), ("all", TlsRoots::All)] {
+            let cli = Cli::try_parse_from(with_required_args(vec!["--tls-roots", value]))
+                .expect("failed to parse CLI arguments");
+            assert_eq!(cli.client.tls_roots, roots);
         }
-    }
 
-    #[test]
-    fn cli_log_config() {
+        let cli = Cli::try_parse_from(with_required_args(vec!["--insecure"]))
+            .expect("failed to parse CLI arguments");
+        assert!(
+            cli.client()
+                .tls()
+                .expect("failed to load TLS config")
+                .insecure
+        );
+
+        let cli = Cli::try_parse_from([
+            NAME,
+            "error",
+            "some-name",
+            "--api-key",
+            "some-api-key",
+            "--insecure",
+        ])
+        .expect("failed to parse CLI arguments");
+        assert!(
+            cli.client()
+                .tls()
+                .expect("failed to load TLS config")
+                .insecure
+        );
+
+        let cli = Cli::try_parse_from(with_required_args(vec!["--ca-cert", "/nonexistent/ca.pem"]))
+            .expect("failed to parse CLI arguments");
+        assert!(
+            cli.client().tls().is_err_and(
+                |err| err.starts_with("could not read CA certificate /nonexistent/ca.pem")
+            )
+        );
+
         let cli = Cli::try_parse_from(with_required_args(vec![
-            "--log",
-            "some-group",
-            "--hostname",
-            "some-hostname",
-            "--digest",
-            "some-digest",
+            "--client-cert",
+            "/nonexistent/client.pem",
+            "--client-key",
+            "/nonexistent/client.key",
         ]))
         .expect("failed to parse CLI arguments");
+        assert!(cli.client().tls().is_err_and(|err| {
+            err.starts_with("could not read client certificate /nonexistent/client.pem")
+        }));
 
-        let log_config = cli.log();
+        let pem = std::env::temp_dir().join(format!("{}-client.pem", std::process::id()));
+        std::fs::write(&pem, "not a certificate").unwrap();
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--client-cert",
+            pem.to_str().unwrap(),
+        ]))
+        .expect("failed to parse CLI arguments");
+        let result = cli.client().tls();
+        std::fs::remove_file(&pem).unwrap();
+        assert!(result.is_err_and(|err| err.starts_with("could not use client certificate")));
 
-        assert_eq!(log_config.api_key, "some-api-key");
-        assert_eq!(log_config.endpoint, "https://appsignal-endpoint.net");
-        assert_eq!(log_config.origin, LogOrigin::All);
-        assert_eq!(log_config.group, "some-group");
-        assert_eq!(log_config.hostname, "some-hostname");
-        assert_eq!(log_config.digest, "some-digest");
+        assert!(Cli::try_parse_from(with_required_args(vec![
+            "--client-key",
+            "/nonexistent/client.key"
+        ]))
+        .is_err());
     }
 
     #[test]
-    fn cli_log_config_no_log_options() {
-        for (args, origin) in [
-            (vec!["--no-log"], LogOrigin::None),
-            (vec!["--no-stdout", "--no-stderr"], LogOrigin::None),
-            (vec!["--no-stdout"], LogOrigin::Stderr),
-            (vec!["--no-stderr"], LogOrigin::Stdout),
-        ] {
-            let cli = Cli::try_parse_from(with_required_args(args))
-                .expect("failed to parse CLI arguments");
+    fn cli_timeouts() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        let timeouts = cli.client().timeouts();
+        assert_eq!(timeouts.request, DEFAULT_REQUEST_TIMEOUT);
+        assert_eq!(timeouts.check_in, DEFAULT_CHECK_IN_TIMEOUT);
 
-            let log_config = cli.log();
+        let cli = Cli::try_parse_from(with_required_args(vec![
+            "--request-timeout",
+            "60",
+            "--check-in-timeout",
+            "5",
+        ]))
+        .expect("failed to parse CLI arguments");
+        let timeouts = cli.client().timeouts();
+        assert_eq!(timeouts.request, Duration::from_secs(60));
+        assert_eq!(timeouts.check_in, Duration::from_secs(5));
 
-            assert_eq!(log_config.origin, origin);
+        for option in ["--request-timeout", "--check-in-timeout"] {
+            assert!(Cli::try_parse_from(with_required_args(vec![option, "0"])).is_err());
         }
     }
 
     #[test]
-    fn cli_error_config() {
-        for (args, error) in [
-            (vec!["--no-error"], None),
-            (vec!["--error", "some-action"], Some("some-action")),
-            (vec![], Some("some-name")),
-        ] {
-            let cli = Cli::try_parse_from(with_required_args(
-                args.into_iter()
-                    .chain(["--hostname", "some-hostname", "--digest", "some-digest"].into_iter())
-                    .collect(),
-            ))
+    fn cli_dry_run() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        assert!(!cli.client().dry_run);
+
+        let cli = Cli::try_parse_from(with_required_args(vec!["--dry-run"]))
             .expect("failed to parse CLI arguments");
+        assert!(cli.client().dry_run);
 
-            let error_config = cli.error();
+        let cli = Cli::try_parse_from(vec![NAME, "log", "group", "--api-key", "key", "--dry-run"])
+            .expect("failed to parse CLI arguments");
+        assert!(cli.client().dry_run);
+    }
 
-            if let Some(action) = error {
-                let error_config = error_config.expect("expected error config");
-                assert_eq!(error_config.action, action);
-                assert_eq!(error_config.api_key, "some-api-key");
-                assert_eq!(error_config.endpoint, "https://appsignal-endpoint.net");
-                assert_eq!(error_config.hostname, "some-hostname");
-                assert_eq!(error_config.digest, "some-digest");
-            } else {
-                assert!(error_config.is_none());
-            }
+    #[test]
+    fn cli_record() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        assert_eq!(cli.client().record, None);
+
+        let cli = Cli::try_parse_from(with_required_args(vec!["--record", "/tmp/recording"]))
+            .expect("failed to parse CLI arguments");
+        assert_eq!(cli.client().record, Some(PathBuf::from("/tmp/recording")));
+    }
+
+    #[test]
+    fn cli_payload_schema_version() {
+        let cli =
+            Cli::try_parse_from(with_required_args(vec![])).expect("failed to parse CLI arguments");
+        assert_eq!(cli.client().payload_schema_version, None);
+
+        let cli = Cli::try_parse_from(with_required_args(vec!["--payload-schema-version", "1"]))
+            .expect("failed to parse CLI arguments");
+        assert_eq!(cli.client().payload_schema_version, Some(SchemaVersion::V1));
+
+        assert!(
+            Cli::try_parse_from(with_required_args(vec!["--payload-schema-version", "2"])).is_err()
+        );
+    }
+
+    #[test]
+    fn cli_identifier_cmd() {
+        for (args, cron, heartbeat) in [
+            (
+                vec!["--cron", "--identifier-cmd", "echo backup-3"],
+                Some("backup-3"),
+                None,
+            ),
+            (
+                vec!["--heartbeat", "--identifier-cmd", "echo backup-3"],
+                None,
+                Some("backup-3"),
+            ),
+            (
+                vec!["--cron", "some-cron", "--identifier-cmd", "echo backup-3"],
+                Some("some-cron"),
+                None,
+            ),
+            (
+                vec!["--cron", "--identifier-cmd", "exit 1"],
+                Some("some-name"),
+                None,
+            ),
+        ] {
+            let mut cli = Cli::try_parse_from(with_required_args(args))
+                .expect("failed to parse CLI arguments");
+            cli.run_identifier_cmd();
+
+            assert_eq!(
+                cli.cron().map(|cron| cron.check_in.identifier),
+                cron.map(String::from)
+            );
+            assert_eq!(
+                cli.heartbeat()
+                    .map(|heartbeat| heartbeat.check_in.identifier),
+                heartbeat.map(String::from)
+            );
         }
     }
 
@@ -641,6 +4636,7 @@ mod tests {
                     "https://appsignal-endpoint.net"
                 );
                 assert_eq!(cron_config.digest, "some-digest");
+                assert_eq!(cron_config.schedule, None);
             } else {
                 assert!(cron_config.is_none());
             }
@@ -1,35 +1,303 @@
-use reqwest::{Client, ClientBuilder};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
-use ::log::{debug, trace};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use reqwest::{Certificate, Client, ClientBuilder, Identity, RequestBuilder, StatusCode, Url};
 
+use ::log::{debug, trace, warn};
+use clap::ValueEnum;
+
+use crate::dry_run;
+use crate::health::{self, Delivery};
 use crate::package::{NAME, VERSION};
+use crate::summary::{self, Failure};
+
+// The connection timeout for requests to endpoints that failed recently.
+const FAILING_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Whether a warning was written about dropping requests to a failing
+// endpoint, which is only done once.
+static SKIP_WARNED: AtomicBool = AtomicBool::new(false);
+
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_CHECK_IN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long to wait for requests to complete, from connecting to reading the
+// response. Check-in requests are much smaller than other requests, so they
+// are given less time.
+pub struct Timeouts {
+    pub request: Duration,
+    pub check_in: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            request: DEFAULT_REQUEST_TIMEOUT,
+            check_in: DEFAULT_CHECK_IN_TIMEOUT,
+        }
+    }
+}
+
+// The root certificates that are trusted when sending data to AppSignal,
+// besides the ones given with `--ca-cert`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TlsRoots {
+    // The Mozilla root certificates built into the binary, which work in
+    // containers without a system certificate store.
+    #[default]
+    Bundled,
+    // The root certificates of the system's certificate store.
+    System,
+    // Both the built-in and the system's root certificates.
+    All,
+}
+
+impl TlsRoots {
+    pub fn includes_bundled(&self) -> bool {
+        matches!(self, Self::Bundled | Self::All)
+    }
+
+    pub fn includes_system(&self) -> bool {
+        matches!(self, Self::System | Self::All)
+    }
+}
+
+// The files in which the system's certificate store is commonly found, in
+// Debian, Red Hat, SUSE and Alpine based distributions, and in macOS.
+const SYSTEM_ROOTS_PATHS: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+// The file with the system's root certificates: the one given by the
+// `SSL_CERT_FILE` environment variable, like OpenSSL does, or the first one
+// of the common locations that exists.
+pub fn system_roots_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("SSL_CERT_FILE") {
+        return Some(PathBuf::from(path));
+    }
+
+    SYSTEM_ROOTS_PATHS
+        .iter()
+        .map(PathBuf::from)
+        .find(|path| path.is_file())
+}
+
+// The TLS settings for the requests sent to AppSignal, such as the ones sent
+// through a gateway that uses a private certificate authority.
+#[derive(Default)]
+pub struct TlsConfig {
+    // Which root certificates to trust.
+    pub roots: TlsRoots,
+    // Root certificates to trust, in addition to the built-in ones.
+    pub ca_certs: Vec<Certificate>,
+    // Whether to accept invalid certificates. Only meant for testing.
+    pub insecure: bool,
+    // The client certificate and private key to authenticate with, for
+    // proxies that require mutual TLS.
+    pub identity: Option<Identity>,
+}
+
+static TLS: OnceLock<TlsConfig> = OnceLock::new();
+static TIMEOUTS: OnceLock<Timeouts> = OnceLock::new();
+
+// Sets the TLS settings and timeouts for all requests. Until this is called,
+// the default settings are used.
+pub fn init(tls: TlsConfig, timeouts: Timeouts) {
+    let _ = TLS.set(tls);
+    let _ = TIMEOUTS.set(timeouts);
+}
+
+fn timeouts() -> &'static Timeouts {
+    TIMEOUTS.get_or_init(Timeouts::default)
+}
+
+// The timeout for check-in requests, which overrides the client's timeout.
+pub fn check_in_timeout() -> Duration {
+    timeouts().check_in
+}
+
+fn client_builder() -> ClientBuilder {
+    let mut builder = ClientBuilder::new()
+        .user_agent(format!("{NAME}/{VERSION}"))
+        .timeout(timeouts().request);
+
+    if let Some(tls) = TLS.get() {
+        for cert in &tls.ca_certs {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+
+        builder = builder
+            .tls_built_in_root_certs(tls.roots.includes_bundled())
+            .danger_accept_invalid_certs(tls.insecure);
+
+        if let Some(identity) = tls.identity.as_ref() {
+            builder = builder.identity(identity.clone());
+        }
+    }
+
+    builder
+}
 
 pub fn client() -> Client {
+    client_builder().build().unwrap()
+}
+
+// A client for requests to destinations other than AppSignal, such as the
+// webhook sink, which do not use the TLS settings for AppSignal's endpoint.
+pub fn external_client() -> Client {
     ClientBuilder::new()
         .user_agent(format!("{NAME}/{VERSION}"))
+        .timeout(timeouts().request)
         .build()
         .unwrap()
 }
 
-pub async fn send_request(request: Result<reqwest::Request, reqwest::Error>) {
-    let request = match request {
-        Ok(request) => request,
-        Err(err) => {
-            debug!("error creating request: {}", err);
-            return;
+// Sets the body of the request, compressing it with gzip when `compress` is
+// set. The body is sent uncompressed if it cannot be compressed.
+pub fn with_body(builder: RequestBuilder, body: String, compress: bool) -> RequestBuilder {
+    if compress {
+        match gzip(body.as_bytes()) {
+            Ok(compressed) => {
+                return builder.header("Content-Encoding", "gzip").body(compressed);
+            }
+            Err(err) => debug!("error compressing request body: {}", err),
+        }
+    }
+
+    builder.body(body)
+}
+
+fn gzip(body: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+pub fn is_compressed(request: &reqwest::Request) -> bool {
+    request
+        .headers()
+        .get("Content-Encoding")
+        .is_some_and(|encoding| encoding == "gzip")
+}
+
+// Returns the body of the request as text, decompressing it if it was
+// compressed with `with_body`.
+pub fn text_body(request: &reqwest::Request) -> Option<String> {
+    let bytes = request.body()?.as_bytes()?;
+
+    if is_compressed(request) {
+        let mut body = String::new();
+        return match GzDecoder::new(bytes).read_to_string(&mut body) {
+            Ok(_) => Some(body),
+            Err(err) => Some(format!("[invalid gzip body: {err}]")),
+        };
+    }
+
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn failing_client() -> Client {
+    client_builder()
+        .connect_timeout(FAILING_CONNECT_TIMEOUT)
+        .build()
+        .unwrap()
+}
+
+// Whether the request is a check-in, which is sent even to an endpoint that
+// keeps failing.
+fn is_check_in(url: &Url) -> bool {
+    url.path().starts_with("/check_ins/")
+}
+
+// The outcome of sending a request, which tells whether sending it again
+// may succeed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure(Failure),
+    // The request failed to connect, timed out, or was rejected by an
+    // overloaded or failing server.
+    Retryable(Failure),
+}
+
+pub async fn send_request(request: reqwest::Request) -> Outcome {
+    if dry_run::is_enabled() {
+        dry_run::print(&request);
+        return Outcome::Success;
+    }
+
+    let client = match health::delivery(request.url()) {
+        Delivery::Healthy => client(),
+        Delivery::Failing => failing_client(),
+        Delivery::Skip if is_check_in(request.url()) => failing_client(),
+        Delivery::Skip => {
+            // The first request that is dropped is reported right away, and
+            // the rest of them when the wrapper exits.
+            if !SKIP_WARNED.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "dropping requests to {}, as it failed to connect repeatedly; \
+                    sending requests to it again {} seconds after the last failure",
+                    request.url().host_str().unwrap_or_default(),
+                    health::RETRY_AFTER.as_secs()
+                );
+            }
+
+            debug!("skipping request to failing endpoint: {}", request.url());
+            summary::skipped(request.url().path());
+            return Outcome::Failure(Failure::Skipped);
         }
     };
 
-    match client().execute(request.try_clone().unwrap()).await {
+    let started = Instant::now();
+    let result = client.execute(request.try_clone().unwrap()).await;
+    let success = result
+        .as_ref()
+        .is_ok_and(|response| response.status().is_success());
+    summary::request(request.url().path(), started.elapsed(), success);
+
+    match result {
         Ok(response) => {
-            if !response.status().is_success() {
-                debug!("request failed with status: {}", response.status());
-            } else {
+            health::success(request.url());
+
+            let status = response.status();
+
+            if status.is_success() {
                 trace!("request successful: {}", request.url());
+                return Outcome::Success;
+            }
+
+            debug!("request failed with status: {}", status);
+            let failure = Failure::Status(status.as_u16());
+
+            if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+                Outcome::Retryable(failure)
+            } else {
+                Outcome::Failure(failure)
             }
         }
         Err(err) => {
             debug!("error sending request: {:?}", err);
+
+            if err.is_connect() || err.is_timeout() {
+                health::failure(request.url());
+            }
+
+            if err.is_timeout() {
+                Outcome::Retryable(Failure::Timeout)
+            } else if err.is_connect() {
+                Outcome::Retryable(Failure::Connect)
+            } else {
+                Outcome::Failure(Failure::Other)
+            }
         }
-    };
+    }
 }
@@ -0,0 +1,604 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use clap::builder::{BoolishValueParser, TypedValueParser};
+use clap::{Arg, CommandFactory};
+use log::warn;
+use toml::{Spanned, Value};
+
+use crate::cli::Cli;
+use crate::package::NAME;
+
+const CONFIG_ENV: &str = "APPSIGNAL_RUN_CONFIG";
+const STRICT_CONFIG_ENV: &str = "APPSIGNAL_RUN_STRICT_CONFIG";
+const STRICT_CONFIG_OPTION: &str = "strict-config";
+
+// Returns the command-line arguments the wrapper was invoked with, with the
+// options in the configuration file, if any, inserted before them.
+//
+// The configuration file is given with the `--config` option or the
+// `APPSIGNAL_RUN_CONFIG` environment variable. Each key in the file is the
+// name of a long command-line option, and its value is the option's value.
+// As the `Cli` parser is configured to allow options to override themselves,
+// options given in the command line take precedence over those in the file.
+//
+// Unknown options and invalid values in the file are reported as warnings
+// and ignored, unless strict mode is enabled, in which case they are errors.
+pub fn args() -> Result<Vec<OsString>, String> {
+    with_config(std::env::args_os().collect())
+}
+
+fn with_config(args: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    if is_subcommand(&args) {
+        return Ok(args);
+    }
+
+    let options = options(&args);
+
+    let path = match config_path(&options) {
+        Some(path) => path,
+        None => return Ok(args),
+    };
+
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|err| format!("could not read config file {}: {}", path.display(), err))?;
+
+    let config = parse(&path, &contents, is_strict(&options))?;
+
+    for warning in config.warnings {
+        warn!("{}; ignoring it", warning);
+    }
+
+    let mut args = args.into_iter();
+    Ok(args
+        .next()
+        .into_iter()
+        .chain(config.args.into_iter().map(OsString::from))
+        .chain(args)
+        .collect())
+}
+
+// Configuration files only apply when executing a command.
+fn is_subcommand(args: &[OsString]) -> bool {
+    match args.get(1).and_then(|arg| arg.to_str()) {
+        Some(arg) => Cli::command()
+            .get_subcommands()
+            .any(|subcommand| subcommand.get_name() == arg),
+        None => false,
+    }
+}
+
+fn config_path(options: &[(String, Option<OsString>)]) -> Option<PathBuf> {
+    match options.iter().find(|(name, _)| name == "config") {
+        Some((_, value)) => value.as_ref().map(PathBuf::from),
+        None => std::env::var_os(CONFIG_ENV).map(PathBuf::from),
+    }
+}
+
+fn is_strict(options: &[(String, Option<OsString>)]) -> bool {
+    if options.iter().any(|(name, _)| name == STRICT_CONFIG_OPTION) {
+        return true;
+    }
+
+    std::env::var_os(STRICT_CONFIG_ENV).is_some_and(|value| {
+        BoolishValueParser::new()
+            .parse_ref(&Cli::command(), None, &value)
+            .unwrap_or(false)
+    })
+}
+
+// Returns the long options given in the command-line arguments before the
+// name and the start of the command, with their values, if any.
+fn options(args: &[OsString]) -> Vec<(String, Option<OsString>)> {
+    let mut command = Cli::command();
+    command.build();
+
+    let mut args = args.iter().skip(1).peekable();
+    let mut positionals = 0;
+    let mut options = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+
+        let Some(option) = arg.to_str().and_then(|arg| arg.strip_prefix("--")) else {
+            // The first positional argument is the name, and the second one
+            // is the start of the command, whose arguments are not options.
+            positionals += 1;
+            if positionals == 2 {
+                break;
+            }
+
+            continue;
+        };
+
+        let (name, mut value) = match option.split_once('=') {
+            Some((name, value)) => (name, Some(OsString::from(value))),
+            None => (option, None),
+        };
+
+        // Take the value of an option given as a separate argument, so that
+        // it is not mistaken for a positional argument.
+        if value.is_none() && takes_value(&command, name, args.peek()) {
+            value = args.next().cloned();
+        }
+
+        options.push((name.to_string(), value));
+    }
+
+    options
+}
+
+fn takes_value(command: &clap::Command, name: &str, next: Option<&&OsString>) -> bool {
+    let Some(num_args) = command
+        .get_arguments()
+        .find(|arg| arg.get_long() == Some(name))
+        .and_then(|arg| arg.get_num_args())
+    else {
+        return false;
+    };
+
+    if num_args.min_values() > 0 {
+        return true;
+    }
+
+    // An option with an optional value, such as `--cron`, only takes the
+    // next argument as its value if it does not look like an option.
+    num_args.takes_values()
+        && next
+            .and_then(|next| next.to_str())
+            .is_some_and(|next| !next.starts_with('-'))
+}
+
+#[derive(Debug, PartialEq)]
+struct Config {
+    args: Vec<String>,
+    // The problems with the options that were ignored.
+    warnings: Vec<String>,
+}
+
+// Parses the contents of a configuration file into command-line options.
+//
+// Unknown options, and options with invalid values, are left out and
+// reported as warnings, or as an error in strict mode. Strict mode can also
+// be enabled from the configuration file itself.
+fn parse(path: &Path, contents: &str, strict: bool) -> Result<Config, String> {
+    parse_options(path, contents, strict, &[])
+}
+
+// Like `parse`, but leaves out the given keys, which are not options.
+fn parse_options(
+    path: &Path,
+    contents: &str,
+    strict: bool,
+    skip: &[&str],
+) -> Result<Config, String> {
+    let table: BTreeMap<Spanned<String>, Spanned<Value>> = toml::from_str(contents)
+        .map_err(|err| format!("could not parse config file {}: {}", path.display(), err))?;
+
+    let strict = strict
+        || table.iter().any(|(key, value)| {
+            key.get_ref().replace('_', "-") == STRICT_CONFIG_OPTION
+                && value.get_ref() == &Value::Boolean(true)
+        });
+
+    let mut command = Cli::command();
+    command.build();
+
+    let mut args = Vec::new();
+    let mut warnings = Vec::new();
+
+    for (key, value) in table {
+        if skip.contains(&key.get_ref().as_str()) {
+            continue;
+        }
+
+        let option = key.get_ref().replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(option.as_str()) && option != "config");
+
+        let (problem, span) = match arg {
+            None => ("unknown option", key.span()),
+            Some(arg) => {
+                let span = value.span();
+
+                match option_args(arg, &option, value.into_inner()) {
+                    Some(option_args) => {
+                        args.extend(option_args);
+                        continue;
+                    }
+                    None => ("invalid value for option", span),
+                }
+            }
+        };
+
+        let (line, column) = line_and_column(contents, span.start);
+        let message = format!(
+            "{} `{}` in config file {}, at line {}, column {}",
+            problem,
+            key.get_ref(),
+            path.display(),
+            line,
+            column
+        );
+
+        warnings.push((span.start, message));
+    }
+
+    // Report the problems in the order in which they appear in the file.
+    warnings.sort();
+
+    if let (true, Some((_, error))) = (strict, warnings.first()) {
+        return Err(error.clone());
+    }
+
+    Ok(Config {
+        args,
+        warnings: warnings.into_iter().map(|(_, warning)| warning).collect(),
+    })
+}
+
+// The keys in a job file that are not options: the name and the command of
+// the job.
+const JOB_KEYS: [&str; 2] = ["name", "command"];
+
+// A job in the jobs directory of the `agent` subcommand.
+#[derive(Debug, PartialEq)]
+pub struct Job {
+    pub name: String,
+    pub command: Vec<String>,
+    pub args: Vec<String>,
+}
+
+// Reads a job file, which has the same options as a configuration file,
+// alongside the name of the job, which defaults to the name of the file
+// without its extension, and its command. The command is either an array
+// of arguments or a string, which is run with `sh -c`.
+pub fn job(path: &Path) -> Result<Job, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("could not read job file {}: {}", path.display(), err))?;
+
+    parse_job(path, &contents)
+}
+
+fn parse_job(path: &Path, contents: &str) -> Result<Job, String> {
+    let config = parse_options(path, contents, false, &JOB_KEYS)?;
+
+    for warning in config.warnings {
+        warn!("{}; ignoring it", warning);
+    }
+
+    let mut table: BTreeMap<String, Value> = toml::from_str(contents)
+        .map_err(|err| format!("could not parse job file {}: {}", path.display(), err))?;
+
+    let invalid = |key: &str| format!("invalid `{}` in job file {}", key, path.display());
+
+    let name = match table.remove("name") {
+        Some(Value::String(name)) if !name.is_empty() => name,
+        Some(_) => return Err(invalid("name")),
+        None => path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .ok_or_else(|| invalid("name"))?,
+    };
+
+    let command = match table.remove("command") {
+        Some(Value::String(command)) if !command.is_empty() => {
+            vec!["sh".to_string(), "-c".to_string(), command]
+        }
+        Some(Value::Array(values)) if !values.is_empty() => values
+            .into_iter()
+            .map(|value| match value {
+                Value::String(value) => Some(value),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("command"))?,
+        Some(_) => return Err(invalid("command")),
+        None => return Err(format!("missing `command` in job file {}", path.display())),
+    };
+
+    Ok(Job {
+        name,
+        command,
+        args: config.args,
+    })
+}
+
+// Returns the command-line arguments for a value of an option in the
+// configuration file, or `None` if the value is not valid for the option.
+fn option_args(arg: &Arg, option: &str, value: Value) -> Option<Vec<String>> {
+    let values = match value {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+
+    let takes_values = arg.get_action().takes_values();
+    let requires_value = arg
+        .get_num_args()
+        .is_some_and(|num_args| num_args.min_values() > 0);
+
+    let mut args = Vec::new();
+
+    for value in values {
+        let value = match value {
+            Value::Boolean(true) if !requires_value => {
+                args.push(format!("--{option}"));
+                continue;
+            }
+            Value::Boolean(false) => continue,
+            Value::String(value) => value,
+            Value::Integer(value) => value.to_string(),
+            Value::Float(value) => value.to_string(),
+            _ => return None,
+        };
+
+        if !takes_values {
+            return None;
+        }
+
+        if !is_valid(arg, &value) {
+            return None;
+        }
+
+        args.push(format!("--{option}={value}"));
+    }
+
+    Some(args)
+}
+
+// Whether the value is accepted by the value parser of the option, by parsing
+// it with a command that only has that option's value as its argument.
+fn is_valid(arg: &Arg, value: &str) -> bool {
+    clap::Command::new(NAME)
+        .no_binary_name(true)
+        .arg(
+            Arg::new("value")
+                .value_parser(arg.get_value_parser().clone())
+                .allow_hyphen_values(true),
+        )
+        .try_get_matches_from([value])
+        .is_ok()
+}
+
+// Returns the one-based line and column of the given byte offset.
+fn line_and_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn os_args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn parse_config() {
+        let config = parse(
+            Path::new("config.toml"),
+            r#"
+                api_key = "some-api-key"
+                log-source = "some-log-source"
+                heartbeat = true
+                no_log = false
+                heartbeat_interval = 60
+                tag = ["team=backend", "role=primary"]
+            "#,
+            true,
+        )
+        .expect("failed to parse config");
+
+        assert_eq!(
+            config.args,
+            vec![
+                "--api-key=some-api-key",
+                "--heartbeat",
+                "--heartbeat-interval=60",
+                "--log-source=some-log-source",
+                "--tag=team=backend",
+                "--tag=role=primary",
+            ]
+        );
+        assert!(config.warnings.is_empty());
+    }
+
+    #[test]
+    fn parse_job_file() {
+        let path = Path::new("jobs.d/backup.toml");
+
+        assert_eq!(
+            parse_job(
+                path,
+                r#"
+                    command = ["pg_dump", "--file", "backup.sql"]
+                    cron = true
+                    cron_schedule = "15 3 * * *"
+                "#,
+            ),
+            Ok(Job {
+                name: "backup".to_string(),
+                command: vec!["pg_dump", "--file", "backup.sql"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                args: vec![
+                    "--cron".to_string(),
+                    "--cron-schedule=15 3 * * *".to_string()
+                ],
+            })
+        );
+
+        assert_eq!(
+            parse_job(
+                path,
+                r#"
+                    name = "nightly-backup"
+                    command = "pg_dump | gzip > backup.sql.gz"
+                "#,
+            ),
+            Ok(Job {
+                name: "nightly-backup".to_string(),
+                command: vec!["sh", "-c", "pg_dump | gzip > backup.sql.gz"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                args: vec![],
+            })
+        );
+
+        assert_eq!(
+            parse_job(path, "cron = true"),
+            Err("missing `command` in job file jobs.d/backup.toml".to_string())
+        );
+
+        assert_eq!(
+            parse_job(path, "command = [1, 2]"),
+            Err("invalid `command` in job file jobs.d/backup.toml".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_config_errors() {
+        for (contents, error) in [
+            (
+                "unknown = true",
+                "unknown option `unknown` in config file config.toml, at line 1, column 1",
+            ),
+            (
+                "config = \"other.toml\"",
+                "unknown option `config` in config file config.toml, at line 1, column 1",
+            ),
+            (
+                "[hostname]\nvalue = 1",
+                "invalid value for option `hostname` in config file config.toml, at line 1, column 1",
+            ),
+            (
+                "api_key = \"key\"\ntag = [\"team=backend\", \"invalid\"]",
+                "invalid value for option `tag` in config file config.toml, at line 2, column 7",
+            ),
+            (
+                "heartbeat_interval = \"soon\"",
+                "invalid value for option `heartbeat_interval` in config file config.toml, at line 1, column 22",
+            ),
+            (
+                "api_key = true",
+                "invalid value for option `api_key` in config file config.toml, at line 1, column 11",
+            ),
+            (
+                "no_log = \"yes\"",
+                "invalid value for option `no_log` in config file config.toml, at line 1, column 10",
+            ),
+        ] {
+            assert_eq!(
+                parse(Path::new("config.toml"), contents, true),
+                Err(error.to_string())
+            );
+        }
+
+        assert!(parse(Path::new("config.toml"), "api_key = ", false).is_err());
+    }
+
+    #[test]
+    fn parse_config_warnings() {
+        let contents =
+            "api_key = \"some-api-key\"\ntgas = [\"team=backend\"]\nheartbeat_interval = -1\n";
+
+        assert_eq!(
+            parse(Path::new("config.toml"), contents, false),
+            Ok(Config {
+                args: vec!["--api-key=some-api-key".to_string()],
+                warnings: vec![
+                    "unknown option `tgas` in config file config.toml, at line 2, column 1".to_string(),
+                    "invalid value for option `heartbeat_interval` in config file config.toml, at line 3, column 22".to_string(),
+                ],
+            })
+        );
+
+        let contents = format!("strict_config = true\n{contents}");
+
+        assert_eq!(
+            parse(Path::new("config.toml"), &contents, false),
+            Err(
+                "unknown option `tgas` in config file config.toml, at line 3, column 1".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn config_path_from_args() {
+        for (args, path) in [
+            (vec!["run", "--config", "a.toml", "name"], Some("a.toml")),
+            (vec!["run", "--config=b.toml", "name"], Some("b.toml")),
+            (vec!["run", "name", "--", "cmd", "--config", "c.toml"], None),
+            (vec!["run", "name", "cmd", "--config", "c.toml"], None),
+            (
+                vec!["run", "--hostname", "host", "name", "--config", "d.toml"],
+                Some("d.toml"),
+            ),
+            (
+                vec!["run", "--cron", "id", "name", "--config=e.toml", "cmd"],
+                Some("e.toml"),
+            ),
+        ] {
+            assert_eq!(
+                config_path(&options(&os_args(&args))),
+                path.map(PathBuf::from)
+            );
+        }
+
+        assert!(is_strict(&options(&os_args(&[
+            "run",
+            "--strict-config",
+            "name",
+            "cmd"
+        ]))));
+        assert!(!is_strict(&options(&os_args(&[
+            "run",
+            "name",
+            "cmd",
+            "--strict-config"
+        ]))));
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let path = std::env::temp_dir().join(format!("{}-config-test.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "api_key = \"config-api-key\"\nhostname = \"config-hostname\"\n",
+        )
+        .unwrap();
+
+        let args = with_config(os_args(&[
+            "appsignal-run",
+            "--config",
+            path.to_str().unwrap(),
+            "--hostname",
+            "cli-hostname",
+            "some-name",
+            "--",
+            "true",
+        ]))
+        .expect("failed to load config");
+
+        std::fs::remove_file(&path).unwrap();
+
+        let cli = Cli::try_parse_from(args).expect("failed to parse CLI arguments");
+        let log = cli.log();
+
+        assert_eq!(log.api_key, "config-api-key");
+        assert_eq!(log.hostname, "cli-hostname");
+    }
+}
@@ -0,0 +1,56 @@
+// Correlation between the data sent by the wrapper and the data sent by the
+// command itself, when it is instrumented with an AppSignal integration.
+//
+// The wrapper tags its logs, errors and cron check-ins with the digest of
+// the invocation. Exposing the digest to the command allows it to add the
+// same tag to its own samples, so that they can be cross-referenced in
+// AppSignal.
+//
+// The command can also be given the W3C Trace Context of the invocation's
+// span, which is sent to an OpenTelemetry collector with `--otlp-endpoint`,
+// so that a command instrumented with OpenTelemetry can parent its own
+// traces to it.
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::package::NAME;
+use crate::trace::TraceContext;
+
+// The environment variable containing the digest of the invocation.
+pub const DIGEST_ENV: &str = "APPSIGNAL_RUN_DIGEST";
+// The environment variable containing the name of the tag that the digest is
+// sent as by the wrapper.
+pub const DIGEST_TAG_ENV: &str = "APPSIGNAL_RUN_DIGEST_TAG";
+// The environment variable containing the `traceparent` of the invocation's
+// span, as read by OpenTelemetry SDKs, and the wrapper's own variable with
+// the same value, which a nested command cannot have overridden.
+pub const TRACEPARENT_ENV: &str = "TRACEPARENT";
+pub const RUN_TRACEPARENT_ENV: &str = "APPSIGNAL_RUN_TRACEPARENT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Correlate {
+    // An AppSignal integration running inside the command.
+    Appsignal,
+    // An OpenTelemetry SDK running inside the command, which reads the W3C
+    // Trace Context from the environment.
+    Tracecontext,
+}
+
+impl Correlate {
+    // The environment variables to set for the command.
+    pub fn env(&self, digest: &str, trace: &TraceContext) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            (DIGEST_ENV, digest.to_string()),
+            (DIGEST_TAG_ENV, format!("{}-digest", NAME)),
+        ];
+
+        if let Self::Tracecontext = self {
+            env.push((TRACEPARENT_ENV, trace.traceparent()));
+            env.push((RUN_TRACEPARENT_ENV, trace.traceparent()));
+        }
+
+        env
+    }
+}
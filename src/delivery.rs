@@ -0,0 +1,297 @@
+// Delivers the requests to AppSignal one at a time for each type of data, in
+// the order in which they were queued, so that a cron check-in's finish
+// event is not received before its start event, and batches of logs are
+// received in sequence. Requests of different types are delivered
+// independently of each other.
+//
+// Requests that fail to connect, time out, or are rejected by an overloaded
+// server are retried a few times, with an increasing delay, before the next
+// request of the same type is sent. Requests to an endpoint that keeps
+// failing are skipped by the client, so they are not retried for long.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use ::log::debug;
+use tokio::sync::oneshot;
+
+use crate::client::{send_request, Outcome};
+use crate::record;
+use crate::summary::{self, Failure};
+use crate::timestamp::{Clock, SystemTimestamp};
+
+// How many times to send a request before giving up on it.
+const MAX_ATTEMPTS: u32 = 3;
+// How long to wait before the first retry. The delay doubles after each one.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    CheckIn,
+    Log,
+    Error,
+    Metrics,
+    // Spans sent to an OpenTelemetry collector.
+    Trace,
+}
+
+impl DataType {
+    fn queue(&self) -> &'static Queue {
+        static CHECK_INS: Queue = Queue::new();
+        static LOGS: Queue = Queue::new();
+        static ERRORS: Queue = Queue::new();
+        static METRICS: Queue = Queue::new();
+        static TRACES: Queue = Queue::new();
+
+        match self {
+            Self::CheckIn => &CHECK_INS,
+            Self::Log => &LOGS,
+            Self::Error => &ERRORS,
+            Self::Metrics => &METRICS,
+            Self::Trace => &TRACES,
+        }
+    }
+}
+
+// A queue of requests, as a chain in which each request waits for its turn
+// from the one queued before it.
+struct Queue {
+    last: Mutex<Option<oneshot::Receiver<Turn>>>,
+    depth: AtomicUsize,
+}
+
+impl Queue {
+    const fn new() -> Self {
+        Self {
+            last: Mutex::new(None),
+            depth: AtomicUsize::new(0),
+        }
+    }
+
+    // Queues the given future, returning a future that waits for the ones
+    // queued before it to complete before running it. The position in the
+    // queue is taken when this is called, not when the returned future is
+    // first polled, and it is kept if the returned future is dropped.
+    fn push<F: Future>(&'static self, future: F) -> impl Future<Output = F::Output> {
+        let (done, next) = oneshot::channel();
+
+        let previous = self
+            .last
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .replace(next);
+
+        let mut position = Position::new(&self.depth, previous, done);
+
+        async move {
+            position.wait().await;
+            future.await
+        }
+    }
+}
+
+// What a position in the queue passes on to the next one when it is
+// dropped: nothing once it has had its turn, or the turn it was still
+// waiting for, so that the next one waits for it instead.
+struct Turn(Option<oneshot::Receiver<Turn>>);
+
+struct Position {
+    depth: &'static AtomicUsize,
+    previous: Option<oneshot::Receiver<Turn>>,
+    done: Option<oneshot::Sender<Turn>>,
+}
+
+impl Position {
+    fn new(
+        depth: &'static AtomicUsize,
+        previous: Option<oneshot::Receiver<Turn>>,
+        done: oneshot::Sender<Turn>,
+    ) -> Self {
+        depth.fetch_add(1, Ordering::Relaxed);
+
+        Self {
+            depth,
+            previous,
+            done: Some(done),
+        }
+    }
+
+    // Waits for the positions before this one to have had their turn. This
+    // is cancel-safe: the turn being waited for is kept until it is received.
+    async fn wait(&mut self) {
+        while let Some(previous) = self.previous.as_mut() {
+            self.previous = previous.await.ok().and_then(|Turn(previous)| previous);
+        }
+    }
+}
+
+impl Drop for Position {
+    fn drop(&mut self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+
+        if let Some(done) = self.done.take() {
+            let _ = done.send(Turn(self.previous.take()));
+        }
+    }
+}
+
+// Queues the request, returning a future that delivers it after the
+// requests of the same type queued before it. The future must be awaited,
+// or spawned, for the request to be sent.
+pub fn send(
+    data_type: DataType,
+    request: Result<reqwest::Request, reqwest::Error>,
+) -> impl Future<Output = ()> {
+    data_type
+        .queue()
+        .push(deliver(request, send_request, SystemTimestamp))
+}
+
+// The number of requests of the given type that have been queued and not
+// delivered or given up on yet, including the one being sent.
+pub fn depth(data_type: DataType) -> usize {
+    data_type.queue().depth.load(Ordering::Relaxed)
+}
+
+// Sends the request with the given function, retrying it after the delays
+// measured by the given clock.
+async fn deliver<F: Future<Output = Outcome>>(
+    request: Result<reqwest::Request, reqwest::Error>,
+    send: impl Fn(reqwest::Request) -> F,
+    clock: impl Clock,
+) {
+    let request = match request {
+        Ok(request) => request,
+        Err(err) => {
+            debug!("error creating request: {}", err);
+            return;
+        }
+    };
+
+    // The request is recorded once, not for every attempt to send it, so
+    // that it is not sent more than once when it is replayed.
+    record::record(&request);
+
+    let mut delay = RETRY_DELAY;
+    let mut previous = None;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        // The bodies of the requests sent are never streams, so they can
+        // always be cloned.
+        let failure = match send(request.try_clone().unwrap()).await {
+            Outcome::Success => return,
+            Outcome::Failure(failure) => failure,
+            Outcome::Retryable(failure) if attempt == MAX_ATTEMPTS => failure,
+            Outcome::Retryable(failure) => {
+                debug!(
+                    "retrying request to {} in {:?} (attempt {} of {})",
+                    request.url(),
+                    delay,
+                    attempt + 1,
+                    MAX_ATTEMPTS
+                );
+
+                previous = Some(failure);
+                clock.sleep(delay).await;
+                delay *= 2;
+                continue;
+            }
+        };
+
+        // Only the last attempt to send the request is reported, unless it
+        // was skipped because of the failures of the previous attempts.
+        summary::failure(match (failure, previous) {
+            (Failure::Skipped, Some(previous)) => previous,
+            _ => failure,
+        });
+        return;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp::tests::TestClock;
+    use std::sync::Arc;
+    use tokio::task::JoinSet;
+    use tokio::time::{sleep, Instant};
+
+    #[tokio::test(start_paused = true)]
+    async fn queue_push() {
+        static QUEUE: Queue = Queue::new();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut tasks = JoinSet::new();
+
+        // The first request takes longer to send than the ones queued after
+        // it, which still wait for it.
+        for (index, millis) in [(1, 300), (2, 100), (3, 200)] {
+            let order = order.clone();
+            tasks.spawn(QUEUE.push(async move {
+                sleep(Duration::from_millis(millis)).await;
+                order.lock().unwrap().push(index);
+            }));
+        }
+
+        assert_eq!(QUEUE.depth.load(Ordering::Relaxed), 3);
+
+        // A request that is given up on before it is sent does not hold up
+        // the ones queued after it, which still wait for the ones before it.
+        let dropped = QUEUE.push(std::future::pending::<()>());
+        let order_after = order.clone();
+        tasks.spawn(QUEUE.push(async move {
+            order_after.lock().unwrap().push(4);
+        }));
+        drop(dropped);
+
+        while tasks.join_next().await.is_some() {}
+
+        assert_eq!(*order.lock().unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(QUEUE.depth.load(Ordering::Relaxed), 0);
+    }
+
+    // Sends the request, returning the given outcomes in turn, and returns
+    // how long after the first attempt each attempt was made.
+    async fn attempts(outcomes: Vec<Outcome>) -> Vec<Duration> {
+        let clock = TestClock::new(Duration::from_secs(1_000_000_000));
+        let start = Instant::now();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+
+        let request = reqwest::Client::new()
+            .post("https://some-endpoint.com/check_ins/heartbeats")
+            .build();
+        let send = |_| {
+            let mut sent = sent.lock().unwrap();
+            sent.push(start.elapsed());
+            std::future::ready(outcomes[sent.len() - 1])
+        };
+        deliver(request, send, clock).await;
+
+        let sent = sent.lock().unwrap();
+        sent.clone()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deliver_retry_backoff() {
+        let retryable = Outcome::Retryable(Failure::Connect);
+
+        // The delay before each retry doubles, up to the maximum attempts.
+        assert_eq!(
+            attempts(vec![retryable; 3]).await,
+            [0, 1, 3].map(Duration::from_secs)
+        );
+
+        // A request that succeeds, or fails in a way that is not retryable,
+        // is not sent again.
+        assert_eq!(
+            attempts(vec![retryable, Outcome::Success]).await,
+            [0, 1].map(Duration::from_secs)
+        );
+        assert_eq!(
+            attempts(vec![Outcome::Failure(Failure::Status(400))]).await,
+            [Duration::ZERO]
+        );
+    }
+}
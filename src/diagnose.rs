@@ -0,0 +1,343 @@
+// Checks whether data can be sent to AppSignal from this host, for the
+// `diagnose` subcommand. Each step of sending a request is checked in
+// order -- resolving the endpoint's host, connecting to it, the TLS
+// handshake, reaching each of the routes data is sent to, and the push API
+// key being accepted -- so that a failure points at the step that causes
+// it. Once a check fails, the checks that depend on it are skipped.
+//
+// The requests sent to check the routes and the push API key do not contain
+// any data, so no check-ins, logs or errors are created in AppSignal.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use reqwest::{StatusCode, Url};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::timeout;
+
+use crate::client;
+
+// How long to wait for the connection to the endpoint.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The routes that the wrapper sends data to.
+const ROUTES: [&str; 3] = ["/check_ins/cron", "/logs/json", "/errors"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Ok(String),
+    Failed(String),
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub endpoint: String,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            checks: Vec::new(),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| !matches!(check.status, Status::Failed(_)))
+    }
+
+    // Adds the check, returning whether the checks that depend on it can
+    // be run.
+    fn push(&mut self, name: impl Into<String>, status: Status) -> bool {
+        let passed = matches!(status, Status::Ok(_));
+        self.checks.push(Check {
+            name: name.into(),
+            status,
+        });
+        passed
+    }
+
+    // Skips the checks that were not run yet.
+    fn skip_rest(&mut self) {
+        for name in check_names().skip(self.checks.len()) {
+            self.push(name, Status::Skipped);
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Endpoint: {}", self.endpoint)?;
+
+        let width = self
+            .checks
+            .iter()
+            .map(|check| check.name.len())
+            .max()
+            .unwrap_or_default();
+
+        for check in &self.checks {
+            let status = match &check.status {
+                Status::Ok(details) => format!("ok ({details})"),
+                Status::Failed(reason) => format!("FAILED: {reason}"),
+                Status::Skipped => "skipped".to_string(),
+            };
+            writeln!(f, "  {:width$}  {}", check.name, status)?;
+        }
+
+        match self.is_ok() {
+            true => write!(f, "Result: data can be sent to AppSignal"),
+            false => write!(f, "Result: data cannot be sent to AppSignal"),
+        }
+    }
+}
+
+// The names of the checks, in the order they are run.
+fn check_names() -> impl Iterator<Item = String> {
+    ["DNS resolution", "TCP connection", "TLS handshake"]
+        .into_iter()
+        .map(str::to_string)
+        .chain(ROUTES.map(route_check_name))
+        .chain(["Push API key".to_string()])
+}
+
+fn route_check_name(route: &str) -> String {
+    format!("Route {route}")
+}
+
+pub async fn run(endpoint: &str, api_key: &str) -> Report {
+    let mut report = Report::new(endpoint);
+
+    let url = match Url::parse(endpoint) {
+        Ok(url) if url.host_str().is_some() => url,
+        _ => {
+            report.push("DNS resolution", Status::Failed("invalid URL".to_string()));
+            report.skip_rest();
+            return report;
+        }
+    };
+
+    let addrs = match resolve(&url).await {
+        Ok(addrs) => {
+            report.push("DNS resolution", Status::Ok(describe_addrs(&addrs)));
+            addrs
+        }
+        Err(reason) => {
+            report.push("DNS resolution", Status::Failed(reason));
+            report.skip_rest();
+            return report;
+        }
+    };
+
+    if !report.push("TCP connection", connect(&addrs).await.into()) {
+        report.skip_rest();
+        return report;
+    }
+
+    let tls = match url.scheme() {
+        "https" => handshake(&url).await.into(),
+        _ => Status::Ok("the endpoint does not use HTTPS".to_string()),
+    };
+    if !report.push("TLS handshake", tls) {
+        report.skip_rest();
+        return report;
+    }
+
+    for route in ROUTES {
+        let status = match post(endpoint, route, api_key).await {
+            Ok(status) => route_status(status),
+            Err(err) => Status::Failed(err),
+        };
+        report.push(route_check_name(route), status);
+    }
+
+    let key = match post(endpoint, "/logs/json", api_key).await {
+        Ok(status) => key_status(status),
+        Err(err) => Status::Failed(err),
+    };
+    report.push("Push API key", key);
+
+    report
+}
+
+impl From<Result<String, String>> for Status {
+    fn from(result: Result<String, String>) -> Self {
+        match result {
+            Ok(details) => Status::Ok(details),
+            Err(reason) => Status::Failed(reason),
+        }
+    }
+}
+
+async fn resolve(url: &Url) -> Result<Vec<SocketAddr>, String> {
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    match lookup_host((host, port)).await {
+        Ok(addrs) => {
+            let addrs: Vec<SocketAddr> = addrs.collect();
+            match addrs.is_empty() {
+                true => Err(format!("{host} has no addresses")),
+                false => Ok(addrs),
+            }
+        }
+        Err(err) => Err(format!("could not resolve {host}: {err}")),
+    }
+}
+
+fn describe_addrs(addrs: &[SocketAddr]) -> String {
+    addrs
+        .iter()
+        .map(|addr| addr.ip().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Connects to the first address that accepts a connection.
+async fn connect(addrs: &[SocketAddr]) -> Result<String, String> {
+    let mut errors = Vec::new();
+
+    for addr in addrs {
+        let started = Instant::now();
+        match timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => return Ok(format!("{} in {:?}", addr, started.elapsed())),
+            Ok(Err(err)) => errors.push(format!("{addr}: {err}")),
+            Err(_) => errors.push(format!("{addr}: timed out after {CONNECT_TIMEOUT:?}")),
+        }
+    }
+
+    Err(errors.join("; "))
+}
+
+// Sends a request to the endpoint itself, which completes a TLS handshake
+// with it, whatever its response is.
+async fn handshake(url: &Url) -> Result<String, String> {
+    client::client()
+        .get(url.clone())
+        .send()
+        .await
+        .map(|response| format!("{:?}", response.version()))
+        .map_err(|err| error_chain(&err))
+}
+
+// Sends an empty request to the route, returning the response's status.
+async fn post(endpoint: &str, route: &str, api_key: &str) -> Result<StatusCode, String> {
+    client::client()
+        .post(format!("{endpoint}{route}"))
+        .query(&[("api_key", api_key)])
+        .send()
+        .await
+        .map(|response| response.status())
+        .map_err(|err| error_chain(&err))
+}
+
+// An empty request is expected to be rejected as invalid by some routes,
+// which shows that they are reachable all the same.
+fn route_status(status: StatusCode) -> Status {
+    match status {
+        StatusCode::NOT_FOUND => Status::Failed(format!("HTTP {status}; route not found")),
+        status if status.is_server_error() => Status::Failed(format!("HTTP {status}")),
+        status => Status::Ok(format!("HTTP {status}")),
+    }
+}
+
+// An empty batch of logs is accepted if the push API key is valid.
+fn key_status(status: StatusCode) -> Status {
+    match status {
+        status if status.is_success() => Status::Ok("accepted".to_string()),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            Status::Failed(format!("HTTP {status}; the key is not valid"))
+        }
+        status => Status::Failed(format!("HTTP {status}")),
+    }
+}
+
+// The error's message, followed by the messages of its sources, as the
+// message of a request error does not tell why it failed.
+fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+
+    while let Some(err) = source {
+        message.push_str(&format!(": {err}"));
+        source = err.source();
+    }
+
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnose_statuses() {
+        assert_eq!(
+            route_status(StatusCode::BAD_REQUEST),
+            Status::Ok("HTTP 400 Bad Request".to_string())
+        );
+        assert_eq!(
+            route_status(StatusCode::NOT_FOUND),
+            Status::Failed("HTTP 404 Not Found; route not found".to_string())
+        );
+        assert!(matches!(
+            route_status(StatusCode::BAD_GATEWAY),
+            Status::Failed(_)
+        ));
+
+        assert_eq!(
+            key_status(StatusCode::OK),
+            Status::Ok("accepted".to_string())
+        );
+        assert_eq!(
+            key_status(StatusCode::UNAUTHORIZED),
+            Status::Failed("HTTP 401 Unauthorized; the key is not valid".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn diagnose_unreachable_endpoint() {
+        let report = run("not a url", "some-api-key").await;
+        assert!(!report.is_ok());
+        assert_eq!(
+            report.checks[0].status,
+            Status::Failed("invalid URL".to_string())
+        );
+        assert!(report.checks[1..]
+            .iter()
+            .all(|check| check.status == Status::Skipped));
+        assert_eq!(report.checks.len(), 7);
+
+        // Nothing listens on the port of a listener that was just closed.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let report = run(&format!("http://127.0.0.1:{port}"), "some-api-key").await;
+        let statuses: Vec<&Status> = report.checks.iter().map(|check| &check.status).collect();
+        assert_eq!(
+            statuses[0],
+            &Status::Ok("127.0.0.1".to_string()),
+            "{report}"
+        );
+        assert!(matches!(statuses[1], Status::Failed(_)), "{report}");
+        assert!(statuses[2..]
+            .iter()
+            .all(|status| **status == Status::Skipped));
+        assert!(report
+            .to_string()
+            .ends_with("Result: data cannot be sent to AppSignal"));
+    }
+}
@@ -0,0 +1,114 @@
+// Prints the requests that would be sent to AppSignal, instead of sending
+// them, when `--dry-run` is set, so that the data sent for a configuration
+// can be checked without sending it. The command is still executed as
+// usual.
+//
+// Requests are printed to standard error, so that they are not mixed with
+// the command's standard output. The API key in the URL is redacted, and
+// compressed bodies are decompressed and pretty-printed.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use reqwest::Request;
+use serde_json::Value;
+
+use crate::client::text_body;
+use crate::package::NAME;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn print(request: &Request) {
+    // Written at once, so that requests printed concurrently do not mix.
+    eprintln!("{}", format(request));
+}
+
+fn format(request: &Request) -> String {
+    let mut url = request.url().clone();
+    let query: Vec<String> = url
+        .query_pairs()
+        .map(|(key, value)| match key.as_ref() {
+            "api_key" => format!("{key}=[REDACTED]"),
+            _ => format!("{key}={value}"),
+        })
+        .collect();
+    url.set_query(None);
+
+    let mut output = format!("{NAME}: dry run: {} {}", request.method(), url);
+    if !query.is_empty() {
+        output.push('?');
+        output.push_str(&query.join("&"));
+    }
+
+    if let Some(body) = text_body(request) {
+        output.push('\n');
+        output.push_str(&pretty(&body));
+    }
+
+    output
+}
+
+// Pretty-prints a JSON body, or each line of a newline-delimited JSON body.
+// Lines that are not JSON are printed as they are.
+fn pretty(body: &str) -> String {
+    if let Ok(value) = serde_json::from_str::<Value>(body) {
+        return to_pretty(&value);
+    }
+
+    body.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| match serde_json::from_str::<Value>(line) {
+            Ok(value) => to_pretty(&value),
+            Err(_) => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn to_pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{client, with_body};
+
+    #[test]
+    fn dry_run_format() {
+        let request = with_body(
+            client().post("https://appsignal-endpoint.net/logs/json?api_key=secret&kind=start"),
+            "{\"message\":\"one\"}\n{\"message\":\"two\"}\n".to_string(),
+            true,
+        )
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            format(&request),
+            format!(
+                "{NAME}: dry run: POST https://appsignal-endpoint.net/logs/json\
+                 ?api_key=[REDACTED]&kind=start\n\
+                 {{\n  \"message\": \"one\"\n}}\n\
+                 {{\n  \"message\": \"two\"\n}}"
+            )
+        );
+
+        let request = client()
+            .post("https://appsignal-endpoint.net/check_ins/cron?api_key=secret")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            format(&request),
+            format!("{NAME}: dry run: POST https://appsignal-endpoint.net/check_ins/cron?api_key=[REDACTED]")
+        );
+    }
+}
@@ -0,0 +1,186 @@
+// A dump of the wrapper's internal state, written when it receives
+// `SIGQUIT` and is configured not to forward it to the child process. This
+// is a debugging aid for wrappers that appear to hang in the field.
+
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use ::log::debug;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::delivery::{self, DataType};
+use crate::log::LogOrigin;
+use crate::package::NAME;
+
+// What to do when the wrapper receives `SIGQUIT`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuitAction {
+    // Forward the signal to the child process, like other signals.
+    #[default]
+    Forward,
+    // Dump the wrapper's internal state and exit, terminating the child.
+    Dump,
+}
+
+// Counters updated by the wrapper's tasks as they process the command's
+// output, so that they can be read when dumping the wrapper's state.
+pub struct Stats {
+    pub stdout_lines: AtomicUsize,
+    pub stderr_lines: AtomicUsize,
+    pub logged_stdout_lines: AtomicUsize,
+    pub logged_stderr_lines: AtomicUsize,
+    pub pending_log_messages: AtomicUsize,
+    pub dropped_log_messages: AtomicUsize,
+    // Writes of the command's output to the wrapper's output that failed,
+    // and were skipped.
+    pub skipped_output_writes: AtomicUsize,
+}
+
+pub static STATS: Stats = Stats {
+    stdout_lines: AtomicUsize::new(0),
+    stderr_lines: AtomicUsize::new(0),
+    logged_stdout_lines: AtomicUsize::new(0),
+    logged_stderr_lines: AtomicUsize::new(0),
+    pending_log_messages: AtomicUsize::new(0),
+    dropped_log_messages: AtomicUsize::new(0),
+    skipped_output_writes: AtomicUsize::new(0),
+};
+
+pub fn increment(counter: &AtomicUsize) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn add(counter: &AtomicUsize, value: usize) {
+    counter.fetch_add(value, Ordering::Relaxed);
+}
+
+pub fn set(counter: &AtomicUsize, value: usize) {
+    counter.store(value, Ordering::Relaxed);
+}
+
+pub fn get(counter: &AtomicUsize) -> usize {
+    counter.load(Ordering::Relaxed)
+}
+
+pub struct StateDump {
+    pub started: Instant,
+    pub child_pid: Option<u32>,
+    pub tasks: usize,
+    pub log_origin: LogOrigin,
+}
+
+impl StateDump {
+    pub fn render(&self) -> String {
+        let mut dump = String::new();
+
+        let stream = |lines: &AtomicUsize, logged: &AtomicUsize, is_logged: bool| {
+            let (lines, logged) = (get(lines), get(logged));
+
+            if is_logged {
+                format!(
+                    "{} lines read, {} queued for logs",
+                    lines,
+                    lines.saturating_sub(logged)
+                )
+            } else {
+                format!("{} lines read, not logged", lines)
+            }
+        };
+
+        let child_pid = self
+            .child_pid
+            .map_or_else(|| "none".to_string(), |pid| pid.to_string());
+
+        // Writing to a `String` cannot fail.
+        let _ = writeln!(dump, "{} state dump (pid {})", NAME, std::process::id());
+        let _ = writeln!(dump, "  uptime: {:?}", self.started.elapsed());
+        let _ = writeln!(dump, "  child pid: {}", child_pid);
+        let _ = writeln!(dump, "  tasks: {}", self.tasks);
+        let _ = writeln!(
+            dump,
+            "  stdout: {}",
+            stream(
+                &STATS.stdout_lines,
+                &STATS.logged_stdout_lines,
+                self.log_origin.is_out()
+            )
+        );
+        let _ = writeln!(
+            dump,
+            "  stderr: {}",
+            stream(
+                &STATS.stderr_lines,
+                &STATS.logged_stderr_lines,
+                self.log_origin.is_err()
+            )
+        );
+        let _ = writeln!(
+            dump,
+            "  logs: {} messages in pending batch, {} dropped",
+            get(&STATS.pending_log_messages),
+            get(&STATS.dropped_log_messages)
+        );
+        let _ = writeln!(
+            dump,
+            "  queued requests: {} check-ins, {} logs, {} errors, {} metrics",
+            delivery::depth(DataType::CheckIn),
+            delivery::depth(DataType::Log),
+            delivery::depth(DataType::Error),
+            delivery::depth(DataType::Metrics)
+        );
+        let _ = writeln!(
+            dump,
+            "  skipped output writes: {}",
+            get(&STATS.skipped_output_writes)
+        );
+
+        dump
+    }
+
+    // Writes the dump to standard error and, if given, to a file.
+    pub fn write(&self, path: Option<&Path>) {
+        let dump = self.render();
+        eprint!("{}", dump);
+
+        if let Some(path) = path {
+            if let Err(err) = std::fs::write(path, &dump) {
+                debug!("error writing state dump to {}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_dump_render() {
+        let dump = StateDump {
+            started: Instant::now(),
+            child_pid: Some(1234),
+            tasks: 3,
+            log_origin: LogOrigin::Stderr,
+        }
+        .render();
+
+        let lines: Vec<&str> = dump.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            format!("{} state dump (pid {})", NAME, std::process::id())
+        );
+        assert_eq!(lines[2], "  child pid: 1234");
+        assert_eq!(lines[3], "  tasks: 3");
+        assert!(lines[4].ends_with("lines read, not logged"));
+        assert!(lines[5].ends_with("queued for logs"));
+        assert_eq!(
+            lines[7],
+            "  queued requests: 0 check-ins, 0 logs, 0 errors, 0 metrics"
+        );
+    }
+}
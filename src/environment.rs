@@ -0,0 +1,135 @@
+// Builds the environment of the command from the wrapper's own, and from
+// the variables given with `--env` and `--env-file`.
+//
+// The variables that configure the wrapper, such as the push API key, are
+// removed from the command's environment, so that secrets meant for the
+// wrapper are not exposed to the command. They can be passed to the command
+// explicitly with `--env`.
+
+use std::path::Path;
+
+// The variables that configure the wrapper, besides the ones starting with
+// `APPSIGNAL_RUN_`. Variables that are shared with the AppSignal
+// integrations, such as `APP_REVISION`, are not removed.
+const WRAPPER_VARS: [&str; 2] = ["APPSIGNAL_APP_PUSH_API_KEY", "APPSIGNAL_LOG_SOURCE_API_KEY"];
+const WRAPPER_PREFIX: &str = "APPSIGNAL_RUN_";
+
+// Whether the variable configures the wrapper, and should be removed from
+// the command's environment.
+pub fn is_wrapper_var(key: &str) -> bool {
+    WRAPPER_VARS.contains(&key) || key.starts_with(WRAPPER_PREFIX)
+}
+
+// Parses a variable given with `--env`, either as `KEY=VALUE`, or as `KEY`
+// to pass the variable from the wrapper's environment.
+pub fn parse_var(value: &str) -> Result<(String, Option<String>), String> {
+    let (key, value) = match value.split_once('=') {
+        Some((key, value)) => (key, Some(value.to_string())),
+        None => (value, None),
+    };
+
+    if key.is_empty() {
+        return Err("expected KEY=VALUE or KEY".to_string());
+    }
+
+    Ok((key.to_string(), value))
+}
+
+// Reads the variables in an environment file, with a `KEY=VALUE` variable
+// on each line. As in Docker's environment files, values are used as is,
+// without removing quotes, and empty lines and lines starting with `#` are
+// ignored.
+pub fn read_file(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|err| {
+        format!(
+            "could not read environment file {}: {}",
+            path.display(),
+            err
+        )
+    })?;
+
+    parse_file(path, &contents)
+}
+
+fn parse_file(path: &Path, contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim_start();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                vars.push((key.trim().to_string(), value.to_string()));
+            }
+            _ => {
+                return Err(format!(
+                    "invalid line {} in environment file {}; expected KEY=VALUE",
+                    index + 1,
+                    path.display()
+                ))
+            }
+        }
+    }
+
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_is_wrapper_var() {
+        for key in [
+            "APPSIGNAL_APP_PUSH_API_KEY",
+            "APPSIGNAL_LOG_SOURCE_API_KEY",
+            "APPSIGNAL_RUN_CONFIG",
+        ] {
+            assert!(is_wrapper_var(key), "{key:?}");
+        }
+
+        for key in ["APPSIGNAL_PUSH_API_KEY", "APP_REVISION", "PATH"] {
+            assert!(!is_wrapper_var(key), "{key:?}");
+        }
+    }
+
+    #[test]
+    fn environment_parse_var() {
+        assert_eq!(
+            parse_var("KEY=some=value"),
+            Ok(("KEY".to_string(), Some("some=value".to_string())))
+        );
+        assert_eq!(
+            parse_var("KEY="),
+            Ok(("KEY".to_string(), Some("".to_string())))
+        );
+        assert_eq!(parse_var("KEY"), Ok(("KEY".to_string(), None)));
+        assert!(parse_var("=value").is_err());
+    }
+
+    #[test]
+    fn environment_parse_file() {
+        let path = Path::new("app.env");
+
+        assert_eq!(
+            parse_file(
+                path,
+                "# comment\n\nFOO=bar\n  BAZ=\"quoted value\"\nEMPTY=\n"
+            ),
+            Ok(vec![
+                ("FOO".to_string(), "bar".to_string()),
+                ("BAZ".to_string(), "\"quoted value\"".to_string()),
+                ("EMPTY".to_string(), "".to_string()),
+            ])
+        );
+
+        assert_eq!(
+            parse_file(path, "FOO=bar\nexport BAZ\n"),
+            Err("invalid line 2 in environment file app.env; expected KEY=VALUE".to_string())
+        );
+    }
+}
@@ -1,34 +1,58 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::os::unix::process::ExitStatusExt;
 use std::process::ExitStatus;
+use std::time::Duration;
 
-use reqwest::Body;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::client::client;
+use crate::client::{client, with_body};
 use crate::package::NAME;
+use crate::schema;
 use crate::signal::signal_name;
 use crate::timestamp::Timestamp;
+use crate::wrap::{
+    default_compress, default_endpoint, hostname, random_digest, require, validate_endpoint,
+};
 
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ErrorConfig {
     pub api_key: String,
+    #[serde(default = "default_endpoint")]
     pub endpoint: String,
     pub action: String,
+    #[serde(default = "hostname")]
     pub hostname: String,
+    #[serde(default = "random_digest")]
     pub digest: String,
-    pub command: String,
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub revision: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    // Whether to compress the request body with gzip.
+    #[serde(default = "default_compress")]
+    pub compress: bool,
 }
 
 impl ErrorConfig {
-    pub fn request(&self, body: impl Into<Body>) -> Result<reqwest::Request, reqwest::Error> {
-        let url = format!("{}/errors", self.endpoint);
+    pub fn validate(&self) -> Result<(), String> {
+        require(&self.api_key, "error API key")?;
+        validate_endpoint(&self.endpoint)?;
+        require(&self.action, "error action")
+    }
 
-        client()
+    pub fn request(&self, body: ErrorBody) -> Result<reqwest::Request, reqwest::Error> {
+        let url = format!("{}/errors", self.endpoint);
+        let body = schema::error_body(&body).expect("failed to serialize error");
+        let builder = client()
             .post(url)
             .query(&[("api_key", &self.api_key)])
             .header("Content-Type", "application/json")
-            .body(body)
-            .build()
+            .header(schema::HEADER, schema::version().as_str());
+
+        with_body(builder, body, self.compress).build()
     }
 
     pub fn request_from_spawn(
@@ -48,13 +72,50 @@ impl ErrorConfig {
         self.request(ErrorBody::from_exit(self, timestamp, exit, lines))
     }
 
+    pub fn request_from_stopped_at_shutdown(
+        &self,
+        timestamp: &mut impl Timestamp,
+        signal: i32,
+        exit: &ExitStatus,
+        lines: impl IntoIterator<Item = String>,
+    ) -> Result<reqwest::Request, reqwest::Error> {
+        self.request(ErrorBody::from_stopped_at_shutdown(
+            self, timestamp, signal, exit, lines,
+        ))
+    }
+
+    pub fn request_from_stall(
+        &self,
+        timestamp: &mut impl Timestamp,
+        timeout: Duration,
+        lines: impl IntoIterator<Item = String>,
+    ) -> Result<reqwest::Request, reqwest::Error> {
+        self.request(ErrorBody::from_stall(self, timestamp, timeout, lines))
+    }
+
+    pub fn request_from_message(
+        &self,
+        timestamp: &mut impl Timestamp,
+        name: &str,
+        message: &str,
+    ) -> Result<reqwest::Request, reqwest::Error> {
+        self.request(ErrorBody::from_message(self, timestamp, name, message))
+    }
+
     fn tags(&self) -> BTreeMap<String, String> {
-        [
-            ("hostname".to_string(), self.hostname.clone()),
-            (format!("{}-digest", NAME), self.digest.clone()),
-            ("command".to_string(), self.command.clone()),
-        ]
-        .into()
+        let mut tags = self.tags.clone();
+        tags.insert("hostname".to_string(), self.hostname.clone());
+        tags.insert(format!("{}-digest", NAME), self.digest.clone());
+
+        if let Some(command) = self.command.as_ref() {
+            tags.insert("command".to_string(), command.clone());
+        }
+
+        if let Some(revision) = self.revision.as_ref() {
+            tags.insert("revision".to_string(), revision.clone());
+        }
+
+        tags
     }
 }
 
@@ -106,11 +167,51 @@ impl ErrorBody {
             exit_tags(exit),
         )
     }
-}
 
-impl From<ErrorBody> for Body {
-    fn from(body: ErrorBody) -> Self {
-        Body::from(serde_json::to_string(&body).unwrap())
+    pub fn from_stopped_at_shutdown(
+        config: &ErrorConfig,
+        timestamp: &mut impl Timestamp,
+        signal: i32,
+        exit: &ExitStatus,
+        lines: impl IntoIterator<Item = String>,
+    ) -> Self {
+        Self::new(
+            config,
+            timestamp,
+            ErrorBodyError::from_stopped_at_shutdown(signal, exit, lines),
+            exit_tags(exit),
+        )
+    }
+
+    pub fn from_stall(
+        config: &ErrorConfig,
+        timestamp: &mut impl Timestamp,
+        timeout: Duration,
+        lines: impl IntoIterator<Item = String>,
+    ) -> Self {
+        Self::new(
+            config,
+            timestamp,
+            ErrorBodyError::from_stall(timeout, lines),
+            vec![],
+        )
+    }
+
+    pub fn from_message(
+        config: &ErrorConfig,
+        timestamp: &mut impl Timestamp,
+        name: &str,
+        message: &str,
+    ) -> Self {
+        Self::new(
+            config,
+            timestamp,
+            ErrorBodyError {
+                name: name.to_string(),
+                message: message.to_string(),
+            },
+            vec![],
+        )
     }
 }
 
@@ -129,27 +230,144 @@ impl ErrorBodyError {
     }
 
     pub fn from_exit(exit: &ExitStatus, lines: impl IntoIterator<Item = String>) -> Self {
-        let (name, exit_context) = if let Some(code) = exit.code() {
-            ("NonZeroExit".to_string(), format!("code {}", code))
-        } else if let Some(signal) = exit.signal() {
-            (
-                "SignalExit".to_string(),
-                format!("signal {}", signal_name(signal)),
-            )
-        } else {
-            ("UnknownExit".to_string(), "unknown status".to_string())
-        };
+        let (name, exit_context) = exit_context(exit);
 
         let mut lines = lines.into_iter().collect::<Vec<String>>();
         lines.push(format!("[Process exited with {}]", exit_context));
 
         let message = lines.join("\n");
 
-        ErrorBodyError { name, message }
+        ErrorBodyError {
+            name: name.to_string(),
+            message,
+        }
+    }
+
+    // The command was stopped, such as by `SIGSTOP`, when the wrapper was
+    // asked to terminate by the given signal, and it was continued so that
+    // it could be terminated by it.
+    pub fn from_stopped_at_shutdown(
+        signal: i32,
+        exit: &ExitStatus,
+        lines: impl IntoIterator<Item = String>,
+    ) -> Self {
+        let (_, exit_context) = exit_context(exit);
+
+        let mut lines = lines.into_iter().collect::<Vec<String>>();
+        lines.push(format!(
+            "[Process was stopped when {} was received, and exited with {} after it was continued]",
+            signal_name(signal),
+            exit_context
+        ));
+
+        ErrorBodyError {
+            name: "StoppedAtShutdown".to_string(),
+            message: lines.join("\n"),
+        }
+    }
+
+    // The command wrote no output for the given duration, and may be hung.
+    pub fn from_stall(timeout: Duration, lines: impl IntoIterator<Item = String>) -> Self {
+        let mut lines = lines.into_iter().collect::<Vec<String>>();
+        lines.push(format!(
+            "[Process wrote no output for {}s]",
+            timeout.as_secs()
+        ));
+
+        ErrorBodyError {
+            name: "StallError".to_string(),
+            message: lines.join("\n"),
+        }
     }
 }
 
-fn exit_tags(exit: &ExitStatus) -> BTreeMap<String, String> {
+pub fn exit_context(exit: &ExitStatus) -> (&'static str, String) {
+    if let Some(code) = exit.code() {
+        ("NonZeroExit", format!("code {}", code))
+    } else if let Some(signal) = exit.signal() {
+        ("SignalExit", format!("signal {}", signal_name(signal)))
+    } else {
+        ("UnknownExit", "unknown status".to_string())
+    }
+}
+
+pub const DEFAULT_MESSAGE_LINES: usize = 10;
+
+// The last lines of the command's output, to be used as part of the error
+// message. At most `max_lines` lines are kept and, if `max_bytes` is set,
+// the oldest lines are dropped to keep their total size within it.
+pub struct MessageLines {
+    lines: VecDeque<String>,
+    bytes: usize,
+    max_lines: usize,
+    max_bytes: Option<usize>,
+}
+
+impl MessageLines {
+    pub fn new(max_lines: usize, max_bytes: Option<usize>) -> Self {
+        Self {
+            lines: VecDeque::with_capacity(max_lines),
+            bytes: 0,
+            max_lines,
+            max_bytes,
+        }
+    }
+
+    pub fn push(&mut self, mut line: String) {
+        if self.max_lines == 0 {
+            return;
+        }
+
+        if let Some(max_bytes) = self.max_bytes {
+            // Keep the end of a line that does not fit on its own, as the
+            // end of the output is usually the most relevant part.
+            if line.len() > max_bytes {
+                let mut start = line.len() - max_bytes;
+                while !line.is_char_boundary(start) {
+                    start += 1;
+                }
+                line.drain(..start);
+            }
+
+            while self.bytes + line.len() > max_bytes {
+                self.pop_front();
+            }
+        }
+
+        if self.lines.len() >= self.max_lines {
+            self.pop_front();
+        }
+
+        self.bytes += line.len();
+        self.lines.push_back(line);
+    }
+
+    pub fn to_vec(&self) -> Vec<String> {
+        self.lines.iter().cloned().collect()
+    }
+
+    // An empty set of lines, with the same limits as these.
+    pub fn with_same_limits(&self) -> Self {
+        Self::new(self.max_lines, self.max_bytes)
+    }
+
+    fn pop_front(&mut self) {
+        if let Some(line) = self.lines.pop_front() {
+            self.bytes -= line.len();
+        }
+    }
+}
+
+impl IntoIterator for MessageLines {
+    type Item = String;
+    type IntoIter = std::collections::vec_deque::IntoIter<String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.lines.into_iter()
+    }
+}
+
+pub fn exit_tags(exit: &ExitStatus) -> BTreeMap<String, String> {
     if let Some(code) = exit.code() {
         [
             ("exit_code".to_string(), format!("{}", code)),
@@ -179,7 +397,29 @@ mod tests {
             hostname: "some-hostname".to_string(),
             digest: "some-digest".to_string(),
             action: "some-action".to_string(),
-            command: "some-command".to_string(),
+            command: Some("some-command".to_string()),
+            revision: None,
+            tags: BTreeMap::new(),
+            compress: false,
+        }
+    }
+
+    #[test]
+    fn message_lines_limits() {
+        for (max_lines, max_bytes, expected) in [
+            (10, None, vec!["first", "second", "third"]),
+            (2, None, vec!["second", "third"]),
+            (0, None, vec![]),
+            (10, Some(11), vec!["second", "third"]),
+            (10, Some(4), vec!["hird"]),
+        ] {
+            let mut lines = MessageLines::new(max_lines, max_bytes);
+
+            for line in ["first", "second", "third"] {
+                lines.push(line.to_string());
+            }
+
+            assert_eq!(lines.into_iter().collect::<Vec<_>>(), expected);
         }
     }
 
@@ -202,6 +442,13 @@ mod tests {
             request.headers().get("Content-Type").unwrap(),
             "application/json"
         );
+        assert_eq!(
+            request
+                .headers()
+                .get("X-AppSignal-Run-Payload-Schema")
+                .unwrap(),
+            "1"
+        );
         assert_eq!(
             String::from_utf8_lossy(request.body().unwrap().as_bytes().unwrap()),
             format!(
@@ -226,6 +473,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn error_config_request_from_message() {
+        let config = ErrorConfig {
+            command: None,
+            revision: Some("some-revision".to_string()),
+            tags: [("environment".to_string(), "production".to_string())].into(),
+            ..error_config()
+        };
+
+        let request = config
+            .request_from_message(&mut timestamp(), "BackupError", "disk full")
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(request.body().unwrap().as_bytes().unwrap()),
+            format!(
+                concat!(
+                    "{{",
+                    r#""timestamp":{},"#,
+                    r#""action":"some-action","#,
+                    r#""namespace":"process","#,
+                    r#""error":{{"#,
+                    r#""name":"BackupError","#,
+                    r#""message":"disk full""#,
+                    r#"}},"#,
+                    r#""tags":{{"#,
+                    r#""{}-digest":"some-digest","#,
+                    r#""environment":"production","#,
+                    r#""hostname":"some-hostname","#,
+                    r#""revision":"some-revision""#,
+                    r#"}}"#,
+                    "}}"
+                ),
+                EXPECTED_SECS, NAME
+            )
+        );
+    }
+
     #[test]
     fn error_config_request_from_exit() {
         let config = error_config();
@@ -273,4 +558,72 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn error_config_request_from_stopped_at_shutdown() {
+        let config = error_config();
+        let exit = ExitStatus::from_raw(libc::SIGTERM);
+        let lines = vec!["line 1".to_string()];
+
+        let request = config
+            .request_from_stopped_at_shutdown(&mut timestamp(), libc::SIGTERM, &exit, lines)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(request.body().unwrap().as_bytes().unwrap()),
+            format!(
+                concat!(
+                    "{{",
+                    r#""timestamp":{},"#,
+                    r#""action":"some-action","#,
+                    r#""namespace":"process","#,
+                    r#""error":{{"#,
+                    r#""name":"StoppedAtShutdown","#,
+                    r#""message":"line 1\n[Process was stopped when SIGTERM was received, and exited with signal SIGTERM after it was continued]""#,
+                    r#"}},"#,
+                    r#""tags":{{"#,
+                    r#""{}-digest":"some-digest","#,
+                    r#""command":"some-command","#,
+                    r#""exit_kind":"signal","#,
+                    r#""exit_signal":"SIGTERM","#,
+                    r#""hostname":"some-hostname""#,
+                    r#"}}"#,
+                    "}}"
+                ),
+                EXPECTED_SECS, NAME
+            )
+        );
+    }
+    #[test]
+    fn error_config_request_from_stall() {
+        let config = error_config();
+        let lines = vec!["line 1".to_string()];
+
+        let request = config
+            .request_from_stall(&mut timestamp(), Duration::from_secs(60), lines)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(request.body().unwrap().as_bytes().unwrap()),
+            format!(
+                concat!(
+                    "{{",
+                    r#""timestamp":{},"#,
+                    r#""action":"some-action","#,
+                    r#""namespace":"process","#,
+                    r#""error":{{"#,
+                    r#""name":"StallError","#,
+                    r#""message":"line 1\n[Process wrote no output for 60s]""#,
+                    r#"}},"#,
+                    r#""tags":{{"#,
+                    r#""{}-digest":"some-digest","#,
+                    r#""command":"some-command","#,
+                    r#""hostname":"some-hostname""#,
+                    r#"}}"#,
+                    "}}"
+                ),
+                EXPECTED_SECS, NAME
+            )
+        );
+    }
 }
@@ -0,0 +1,134 @@
+use std::ops::RangeInclusive;
+use std::process::ExitStatus;
+
+use serde::Deserialize;
+
+// Which exit codes of the command are considered failures. A failure is
+// reported as an error, and no cron finish check-in is sent for it.
+//
+// Exits caused by a signal are always considered failures.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCodes {
+    // Any non-zero exit code is a failure.
+    #[default]
+    NonZero,
+    // Only the given exit codes are failures.
+    Only(ExitCodes),
+    // Any non-zero exit code, except for the given ones, is a failure.
+    Except(ExitCodes),
+}
+
+impl FailureCodes {
+    pub fn is_failure(&self, exit: &ExitStatus) -> bool {
+        let Some(code) = exit.code() else {
+            return true;
+        };
+
+        match self {
+            Self::NonZero => code != 0,
+            Self::Only(codes) => codes.contains(code),
+            Self::Except(codes) => code != 0 && !codes.contains(code),
+        }
+    }
+}
+
+// A list of exit codes and exit code ranges, such as `1,3-5`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ExitCodes(Vec<RangeInclusive<i32>>);
+
+impl ExitCodes {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid exit codes `{value}`; expected a list such as `1,3-5`");
+
+        let parse_code = |code: &str| {
+            code.trim()
+                .parse::<i32>()
+                .ok()
+                .filter(|code| (0..=255).contains(code))
+                .ok_or_else(invalid)
+        };
+
+        value
+            .split(',')
+            .map(|range| {
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => (parse_code(start)?, parse_code(end)?),
+                    None => {
+                        let code = parse_code(range)?;
+                        (code, code)
+                    }
+                };
+
+                if start > end {
+                    return Err(invalid());
+                }
+
+                Ok(start..=end)
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    pub fn contains(&self, code: i32) -> bool {
+        self.0.iter().any(|range| range.contains(&code))
+    }
+}
+
+impl TryFrom<String> for ExitCodes {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn exit_code(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[test]
+    fn parse_exit_codes() {
+        assert_eq!(ExitCodes::parse("1"), Ok(ExitCodes(vec![1..=1])));
+        assert_eq!(
+            ExitCodes::parse("1, 3-5"),
+            Ok(ExitCodes(vec![1..=1, 3..=5]))
+        );
+
+        for value in ["", "a", "5-3", "1-256", "-1", "1,"] {
+            assert!(ExitCodes::parse(value).is_err(), "{value:?} should fail");
+        }
+    }
+
+    #[test]
+    fn failure_codes_is_failure() {
+        let codes = ExitCodes::parse("1,3-5").unwrap();
+        let signal = ExitStatus::from_raw(9);
+
+        for (failure_codes, failures) in [
+            (FailureCodes::NonZero, [false, true, true, true, true]),
+            (
+                FailureCodes::Only(codes.clone()),
+                [false, true, false, true, false],
+            ),
+            (
+                FailureCodes::Except(codes),
+                [false, false, true, false, true],
+            ),
+        ] {
+            let actual: Vec<bool> = [0, 1, 2, 4, 6]
+                .into_iter()
+                .map(|code| failure_codes.is_failure(&exit_code(code)))
+                .collect();
+
+            assert_eq!(actual, failures, "{failure_codes:?}");
+            assert!(failure_codes.is_failure(&signal));
+        }
+    }
+}
@@ -0,0 +1,443 @@
+// A fake AppSignal endpoint, for checking the wrapper's configuration end to
+// end without sending data to AppSignal. It accepts requests to the same
+// routes as the AppSignal public endpoint, checks that they have an API key
+// and a well-formed body, and records each request as a line of JSON.
+//
+// It only speaks enough HTTP/1.1 to serve the requests sent by the wrapper:
+// bodies must have a `Content-Length`, and are decompressed if they are sent
+// with `Content-Encoding: gzip`. It is only built with the `fake-endpoint`
+// feature.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use ::log::{debug, info};
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::select;
+use tokio_stream::StreamExt;
+
+use crate::signal::{has_terminating_intent, signal_stream};
+use crate::timestamp::{SystemTimestamp, Timestamp};
+
+// The largest request body that is accepted.
+const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+struct Request {
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Record {
+    timestamp: String,
+    method: String,
+    path: String,
+    query: BTreeMap<String, String>,
+    status: u16,
+    // The body, as JSON, or as an array of JSON values for NDJSON bodies.
+    body: Value,
+    // Why the request was rejected, if it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Where the records are written: a file, or standard output.
+struct Recorder(Mutex<Box<dyn std::io::Write + Send>>);
+
+impl Recorder {
+    fn new(path: Option<&PathBuf>) -> Result<Self, String> {
+        let writer: Box<dyn std::io::Write + Send> = match path {
+            Some(path) => Box::new(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|err| {
+                        format!("could not open record file {}: {}", path.display(), err)
+                    })?,
+            ),
+            None => Box::new(std::io::stdout()),
+        };
+
+        Ok(Self(Mutex::new(writer)))
+    }
+
+    fn record(&self, record: &Record) {
+        let line = serde_json::to_string(record).expect("failed to serialize record");
+        let mut writer = self.0.lock().unwrap_or_else(|err| err.into_inner());
+
+        if let Err(err) = writeln!(writer, "{line}").and_then(|_| writer.flush()) {
+            debug!("error writing record: {}", err);
+        }
+    }
+}
+
+// Serves requests on the given port, on the loopback interface, until a
+// terminating signal is received.
+pub async fn run(port: u16, record: Option<PathBuf>) -> Result<i32, Box<dyn std::error::Error>> {
+    let recorder = Arc::new(Recorder::new(record.as_ref())?);
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .await
+        .map_err(|err| format!("could not listen on port {port}: {err}"))?;
+
+    info!(
+        "listening on http://{}; use it as the --endpoint option",
+        listener.local_addr()?
+    );
+
+    let mut signals = signal_stream()?;
+
+    loop {
+        select! {
+            accepted = listener.accept() => {
+                match accepted {
+                    Ok((stream, _)) => {
+                        tokio::spawn(serve(stream, recorder.clone()));
+                    }
+                    Err(err) => debug!("error accepting connection: {}", err),
+                }
+            }
+
+            Some(signal) = signals.next() => {
+                if has_terminating_intent(&signal) {
+                    return Ok(0);
+                }
+            }
+        }
+    }
+}
+
+async fn serve(stream: TcpStream, recorder: Arc<Recorder>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let request = match read_request(&mut reader).await {
+            Ok(Some(request)) => request,
+            Ok(None) => return,
+            Err(err) => {
+                debug!("error reading request: {}", err);
+                let _ = write_response(&mut writer, 400).await;
+                return;
+            }
+        };
+
+        let record = handle(&request, &mut SystemTimestamp);
+        recorder.record(&record);
+
+        if let Err(err) = write_response(&mut writer, record.status).await {
+            debug!("error writing response: {}", err);
+            return;
+        }
+    }
+}
+
+// Reads a request from the connection, or `None` if it was closed before
+// another request was sent.
+async fn read_request(
+    reader: &mut (impl AsyncBufReadExt + Unpin),
+) -> Result<Option<Request>, String> {
+    let mut line = String::new();
+
+    if reader
+        .read_line(&mut line)
+        .await
+        .map_err(|err| err.to_string())?
+        == 0
+    {
+        return Ok(None);
+    }
+
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(format!("invalid request line `{}`", line.trim_end()));
+    };
+    let (method, target) = (method.to_string(), target.to_string());
+
+    let mut headers = Vec::new();
+
+    loop {
+        line.clear();
+        reader
+            .read_line(&mut line)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| format!("invalid header `{header}`"))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut request = Request {
+        method,
+        target,
+        headers,
+        body: Vec::new(),
+    };
+
+    if request.header("Transfer-Encoding").is_some() {
+        return Err("chunked request bodies are not supported".to_string());
+    }
+
+    let length = match request.header("Content-Length") {
+        Some(length) => length
+            .parse::<usize>()
+            .map_err(|_| format!("invalid content length `{length}`"))?,
+        None => 0,
+    };
+
+    if length > MAX_BODY_BYTES {
+        return Err(format!("request body of {length} bytes is too large"));
+    }
+
+    request.body.resize(length, 0);
+    reader
+        .read_exact(&mut request.body)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    Ok(Some(request))
+}
+
+async fn write_response(
+    writer: &mut (impl AsyncWrite + Unpin),
+    status: u16,
+) -> std::io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        _ => "Error",
+    };
+
+    let body = "{}";
+    let response = format!(
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+
+    writer.write_all(response.as_bytes()).await?;
+    writer.flush().await
+}
+
+// The format of the body expected by each route.
+enum BodyFormat {
+    None,
+    Json,
+    Ndjson,
+}
+
+fn handle(request: &Request, timestamp: &mut impl Timestamp) -> Record {
+    let url = reqwest::Url::parse(&format!("http://localhost{}", request.target)).ok();
+    let path = url
+        .as_ref()
+        .map(|url| url.path().to_string())
+        .unwrap_or_else(|| request.target.clone());
+    let query: BTreeMap<String, String> = url
+        .as_ref()
+        .map(|url| url.query_pairs().into_owned().collect())
+        .unwrap_or_default();
+
+    let mut record = Record {
+        timestamp: timestamp.as_rfc3339(),
+        method: request.method.clone(),
+        path,
+        query,
+        status: 200,
+        body: Value::Null,
+        error: None,
+    };
+
+    let result = check(request, &record.path, &record.query);
+
+    match result {
+        Ok(body) => record.body = body,
+        Err((status, error)) => {
+            record.status = status;
+            record.error = Some(error);
+        }
+    }
+
+    record
+}
+
+// Checks the request against its route, returning its parsed body, or the
+// status and reason to reject it with.
+fn check(
+    request: &Request,
+    path: &str,
+    query: &BTreeMap<String, String>,
+) -> Result<Value, (u16, String)> {
+    let format = match path {
+        "/check_ins/cron" | "/check_ins/heartbeats" => BodyFormat::None,
+        "/errors" | "/metrics/json" => BodyFormat::Json,
+        "/logs/json" => BodyFormat::Ndjson,
+        _ => return Err((404, format!("unknown route {path}"))),
+    };
+
+    if request.method != "POST" {
+        return Err((404, format!("unknown route {} {}", request.method, path)));
+    }
+
+    let has_api_key = match query.get("api_key") {
+        Some(key) => !key.is_empty(),
+        None => false,
+    };
+
+    if !has_api_key {
+        return Err((401, "missing API key".to_string()));
+    }
+
+    if path.starts_with("/check_ins/") {
+        for param in ["identifier", "timestamp"] {
+            if !query.contains_key(param) {
+                return Err((400, format!("missing `{param}` parameter")));
+            }
+        }
+
+        if path == "/check_ins/cron"
+            && !matches!(
+                query.get("kind").map(String::as_str),
+                Some("start" | "finish")
+            )
+        {
+            return Err((400, "missing or invalid `kind` parameter".to_string()));
+        }
+    }
+
+    let body = match request.header("Content-Encoding") {
+        Some("gzip") => {
+            let mut body = Vec::new();
+            GzDecoder::new(&request.body[..])
+                .read_to_end(&mut body)
+                .map_err(|err| (400, format!("invalid gzip body: {err}")))?;
+            body
+        }
+        Some(encoding) => return Err((400, format!("unsupported encoding `{encoding}`"))),
+        None => request.body.clone(),
+    };
+
+    let body = String::from_utf8(body).map_err(|_| (400, "body is not UTF-8".to_string()))?;
+    let invalid = |err: serde_json::Error| (400, format!("invalid JSON body: {err}"));
+
+    match format {
+        BodyFormat::None => Ok(Value::Null),
+        BodyFormat::Json => serde_json::from_str(&body).map_err(invalid),
+        BodyFormat::Ndjson => body
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_str(line).map_err(invalid))
+            .collect::<Result<Vec<Value>, _>>()
+            .map(Value::Array),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp::tests::timestamp;
+
+    fn request(target: &str, headers: Vec<(&str, &str)>, body: &[u8]) -> Request {
+        Request {
+            method: "POST".to_string(),
+            target: target.to_string(),
+            headers: headers
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn fake_endpoint_handle() {
+        let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        std::io::Write::write_all(
+            &mut gzipped,
+            b"{\"message\":\"one\"}\n{\"message\":\"two\"}\n",
+        )
+        .unwrap();
+        let gzipped = gzipped.finish().unwrap();
+
+        let record = handle(
+            &request(
+                "/logs/json?api_key=some-api-key",
+                vec![("content-encoding", "gzip")],
+                &gzipped,
+            ),
+            &mut timestamp(),
+        );
+        assert_eq!(record.status, 200);
+        assert_eq!(record.path, "/logs/json");
+        assert_eq!(record.query.get("api_key").unwrap(), "some-api-key");
+        assert_eq!(
+            record.body,
+            serde_json::json!([{"message": "one"}, {"message": "two"}])
+        );
+
+        for (target, body, status) in [
+            ("/errors?api_key=key", &b"{\"action\":\"backup\"}"[..], 200),
+            ("/errors?api_key=key", b"not json", 400),
+            ("/errors", b"{}", 401),
+            ("/metrics/json?api_key=key", b"[]", 200),
+            (
+                "/check_ins/cron?api_key=key&identifier=backup&timestamp=1&kind=start",
+                b"",
+                200,
+            ),
+            (
+                "/check_ins/cron?api_key=key&identifier=backup&timestamp=1",
+                b"",
+                400,
+            ),
+            (
+                "/check_ins/heartbeats?api_key=key&identifier=backup&timestamp=1",
+                b"",
+                200,
+            ),
+            ("/logs?api_key=key", b"", 404),
+        ] {
+            let record = handle(&request(target, vec![], body), &mut timestamp());
+            assert_eq!(record.status, status, "{target}");
+            assert_eq!(record.error.is_some(), status != 200, "{target}");
+        }
+    }
+
+    #[tokio::test]
+    async fn fake_endpoint_read_request() {
+        let mut input: &[u8] =
+            b"POST /errors?api_key=key HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\n{}";
+
+        let request = read_request(&mut input).await.unwrap().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.target, "/errors?api_key=key");
+        assert_eq!(request.header("content-length"), Some("2"));
+        assert_eq!(request.body, b"{}");
+
+        assert!(read_request(&mut input).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,169 @@
+// Tracks the health of the AppSignal endpoints across invocations.
+//
+// When requests to an endpoint fail to connect several times in a row,
+// further requests to it are skipped for a while, instead of waiting for
+// them to time out. This matters for very short commands on flaky
+// networks, whose runtime would otherwise be dominated by connection
+// timeouts. Check-in requests are never skipped, as a missing check-in
+// would report the job as not having run.
+//
+// The health of each endpoint is persisted in the state directory, so that
+// it is shared by successive invocations of the wrapper. The endpoint is
+// resolved again for every request, so that it recovers once its address
+// changes.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ::log::debug;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::state::StateDir;
+
+// The number of consecutive failures after which requests are skipped.
+const FAILURE_THRESHOLD: u32 = 3;
+// How long to skip requests for after the last failure.
+pub const RETRY_AFTER: Duration = Duration::from_secs(60);
+
+static HEALTH: OnceLock<EndpointHealth> = OnceLock::new();
+
+struct EndpointHealth {
+    state_dir: StateDir,
+    hosts: Mutex<HashMap<String, HostHealth>>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct HostHealth {
+    failures: u32,
+    last_failure: Option<u64>,
+}
+
+impl HostHealth {
+    fn delivery(&self, now: u64) -> Delivery {
+        if self.failures == 0 {
+            return Delivery::Healthy;
+        }
+
+        let recent = self
+            .last_failure
+            .is_some_and(|last| now.saturating_sub(last) < RETRY_AFTER.as_secs());
+
+        if self.failures >= FAILURE_THRESHOLD && recent {
+            Delivery::Skip
+        } else {
+            Delivery::Failing
+        }
+    }
+
+    fn success(&mut self) {
+        self.failures = 0;
+        self.last_failure = None;
+    }
+
+    fn failure(&mut self, now: u64) {
+        self.failures += 1;
+        self.last_failure = Some(now);
+    }
+}
+
+// How to deliver a request to an endpoint, given its health.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Delivery {
+    Healthy,
+    // The endpoint failed recently. The request should be sent with a
+    // shorter connection timeout.
+    Failing,
+    // The endpoint keeps failing. The request should be skipped, unless it
+    // is a check-in.
+    Skip,
+}
+
+// Enables endpoint health tracking, persisting it in the given state
+// directory. Until this is called, all requests are delivered.
+pub fn init(state_dir: StateDir) {
+    let _ = HEALTH.set(EndpointHealth {
+        state_dir,
+        hosts: Mutex::new(HashMap::new()),
+    });
+}
+
+pub fn delivery(url: &Url) -> Delivery {
+    with_host(url, |health| health.delivery(now())).unwrap_or(Delivery::Healthy)
+}
+
+pub fn success(url: &Url) {
+    with_host(url, |health| health.success());
+}
+
+pub fn failure(url: &Url) {
+    with_host(url, |health| health.failure(now()));
+}
+
+// Calls the given function with the health of the URL's host, persisting it
+// if the function changed it.
+fn with_host<T>(url: &Url, f: impl FnOnce(&mut HostHealth) -> T) -> Option<T> {
+    let endpoint = HEALTH.get()?;
+    let key = format!("{}:{}", url.host_str()?, url.port_or_known_default()?);
+    let file_name = format!("endpoint-health-{}.json", hex::encode(&key));
+
+    let mut hosts = endpoint.hosts.lock().unwrap_or_else(|err| err.into_inner());
+    let health = hosts
+        .entry(key)
+        .or_insert_with(|| endpoint.state_dir.read(&file_name));
+
+    let previous = health.clone();
+    let result = f(health);
+
+    if *health != previous {
+        if let Err(err) = endpoint.state_dir.write(&file_name, health) {
+            debug!("error writing endpoint health: {}", err);
+        }
+    }
+
+    Some(result)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_health_delivery() {
+        let mut health = HostHealth::default();
+
+        health.success();
+        assert_eq!(health.delivery(1000), Delivery::Healthy);
+
+        health.failure(1000);
+        health.failure(1001);
+        assert_eq!(health.delivery(1002), Delivery::Failing);
+
+        health.failure(1002);
+        assert_eq!(health.delivery(1003), Delivery::Skip);
+        assert_eq!(
+            health.delivery(1002 + RETRY_AFTER.as_secs()),
+            Delivery::Failing
+        );
+
+        health.success();
+        assert_eq!(health.delivery(1003), Delivery::Healthy);
+    }
+
+    #[test]
+    fn host_health_ignores_cached_addrs() {
+        let health: HostHealth =
+            serde_json::from_str(r#"{"failures":1,"last_failure":1000,"addrs":["10.0.0.1:443"]}"#)
+                .expect("failed to parse health");
+
+        assert_eq!(health.delivery(1001), Delivery::Failing);
+    }
+}
@@ -0,0 +1,210 @@
+// Runs the command given with `--on-failure` each time the command fails,
+// such as a script that gathers diagnostics about the host. The hook is run
+// through the shell, with the failed command's exit code, or the signal
+// that terminated it, in its environment. Its output is captured, and
+// written to the wrapper's standard error once it finishes.
+//
+// With `--on-failure-report`, the error report for the failure is sent once
+// the hook has finished, with the hook's output and how it exited. The hook
+// is killed if it does not finish within its timeout, so that it cannot
+// hold back the error report forever.
+
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+
+use ::log::warn;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use crate::error::{ErrorBody, MessageLines};
+use crate::signal::signal_name;
+
+// How long to wait for the hook to finish, before killing it.
+pub const DEFAULT_ON_FAILURE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailureHook {
+    pub command: String,
+    pub timeout: Duration,
+    // Whether to include the hook's output and how it exited in the error
+    // report for the failure.
+    pub report: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookExit {
+    Exited(ExitStatus),
+    TimedOut(Duration),
+    Failed(String),
+}
+
+#[derive(Debug)]
+pub struct HookResult {
+    pub exit: HookExit,
+    // The hook's standard output, followed by its standard error.
+    pub output: String,
+}
+
+impl FailureHook {
+    pub async fn run(&self, exit: &ExitStatus) -> HookResult {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        if let Some(code) = exit.code() {
+            command.env("APPSIGNAL_RUN_EXIT_CODE", code.to_string());
+        }
+
+        if let Some(signal) = exit.signal() {
+            command.env("APPSIGNAL_RUN_EXIT_SIGNAL", signal_name(signal));
+        }
+
+        let result = match command.spawn() {
+            Ok(child) => match timeout(self.timeout, child.wait_with_output()).await {
+                Ok(Ok(output)) => {
+                    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+                    text.push_str(&String::from_utf8_lossy(&output.stderr));
+
+                    HookResult {
+                        exit: HookExit::Exited(output.status),
+                        output: text,
+                    }
+                }
+                Ok(Err(err)) => HookResult::failed(err),
+                // The hook is killed when it is dropped.
+                Err(_) => HookResult {
+                    exit: HookExit::TimedOut(self.timeout),
+                    output: String::new(),
+                },
+            },
+            Err(err) => HookResult::failed(err),
+        };
+
+        if let Err(err) = std::io::stderr().write_all(result.output.as_bytes()) {
+            warn!("could not write the on-failure command's output: {}", err);
+        }
+
+        match &result.exit {
+            HookExit::Exited(status) if status.success() => {}
+            exit => warn!("on-failure command `{}` {}", self.command, exit.describe()),
+        }
+
+        result
+    }
+}
+
+impl HookExit {
+    fn describe(&self) -> String {
+        match self {
+            Self::Exited(status) => match (status.code(), status.signal()) {
+                (Some(code), _) => format!("exited with code {code}"),
+                (_, Some(signal)) => format!("exited with signal {}", signal_name(signal)),
+                _ => "exited with an unknown status".to_string(),
+            },
+            Self::TimedOut(timeout) => format!("did not finish within {timeout:?}"),
+            Self::Failed(reason) => format!("could not run: {reason}"),
+        }
+    }
+}
+
+impl HookResult {
+    fn failed(err: std::io::Error) -> Self {
+        Self {
+            exit: HookExit::Failed(err.to_string()),
+            output: String::new(),
+        }
+    }
+
+    // Adds the hook's output, keeping its last lines within the given
+    // limits, and how it exited to the error report, as an `on_failure` tag.
+    pub fn add_to(self, body: &mut ErrorBody, mut lines: MessageLines) {
+        for line in self.output.lines() {
+            lines.push(line.to_string());
+        }
+
+        let mut message = lines.to_vec();
+        message.push(format!("[On-failure command {}]", self.exit.describe()));
+
+        body.error.message.push('\n');
+        body.error.message.push_str(&message.join("\n"));
+        body.tags
+            .insert("on_failure".to_string(), self.exit.describe());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorBodyError;
+
+    fn hook(command: &str) -> FailureHook {
+        FailureHook {
+            command: command.to_string(),
+            timeout: DEFAULT_ON_FAILURE_TIMEOUT,
+            report: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn hook_run() {
+        let result = hook("echo code $APPSIGNAL_RUN_EXIT_CODE; echo oops >&2; exit 3")
+            .run(&ExitStatus::from_raw(2 << 8))
+            .await;
+
+        assert_eq!(result.exit, HookExit::Exited(ExitStatus::from_raw(3 << 8)));
+        assert_eq!(result.output, "code 2\noops\n");
+
+        let result = hook("echo $APPSIGNAL_RUN_EXIT_SIGNAL")
+            .run(&ExitStatus::from_raw(9))
+            .await;
+        assert_eq!(result.output, "SIGKILL\n");
+    }
+
+    #[tokio::test]
+    async fn hook_timeout() {
+        let hook = FailureHook {
+            timeout: Duration::from_millis(100),
+            ..hook("echo started; sleep 10")
+        };
+
+        let result = hook.run(&ExitStatus::from_raw(1 << 8)).await;
+        assert_eq!(result.exit, HookExit::TimedOut(Duration::from_millis(100)));
+        assert_eq!(
+            result.exit.describe(),
+            "did not finish within 100ms".to_string()
+        );
+    }
+
+    #[test]
+    fn hook_add_to_error_body() {
+        let mut body = ErrorBody {
+            timestamp: 0,
+            action: "some-action".to_string(),
+            namespace: "process".to_string(),
+            error: ErrorBodyError {
+                name: "NonZeroExit".to_string(),
+                message: "some output\n[Process exited with code 1]".to_string(),
+            },
+            tags: Default::default(),
+        };
+
+        HookResult {
+            exit: HookExit::Exited(ExitStatus::from_raw(0)),
+            output: "one\ntwo\nthree\n".to_string(),
+        }
+        .add_to(&mut body, MessageLines::new(2, None));
+
+        assert_eq!(
+            body.error.message,
+            "some output\n[Process exited with code 1]\ntwo\nthree\n[On-failure command exited with code 0]"
+        );
+        assert_eq!(body.tags["on_failure"], "exited with code 0");
+    }
+}
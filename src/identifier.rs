@@ -0,0 +1,97 @@
+// Runs the command given with `--identifier-cmd` to determine the check-in
+// identifier at startup, for setups in which it is only known dynamically,
+// such as when it is derived from the instance's index. The first line that
+// the command writes to its standard output is used as the identifier.
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// How long to wait for the command to write its output and exit.
+pub const IDENTIFIER_CMD_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn from_command(command: &str, timeout: Duration) -> Result<String, String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("could not run identifier command `{command}`: {err}"))?;
+
+    let mut stdout = child.stdout.take().expect("failed to capture stdout");
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut output = String::new();
+        let result = stdout.read_to_string(&mut output).map(|_| output);
+        let _ = sender.send(result);
+    });
+
+    let output = match receiver.recv_timeout(timeout) {
+        Ok(output) => output,
+        Err(_) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!(
+                "identifier command `{command}` did not finish within {} seconds",
+                timeout.as_secs_f64()
+            ));
+        }
+    };
+
+    let status = child
+        .wait()
+        .map_err(|err| format!("could not run identifier command `{command}`: {err}"))?;
+
+    if !status.success() {
+        return Err(format!(
+            "identifier command `{command}` failed with {status}"
+        ));
+    }
+
+    let output = output
+        .map_err(|err| format!("could not read output of identifier command `{command}`: {err}"))?;
+
+    match output.lines().next().map(str::trim) {
+        Some(identifier) if !identifier.is_empty() => Ok(identifier.to_string()),
+        _ => Err(format!(
+            "identifier command `{command}` did not write an identifier"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifier_from_command() {
+        assert_eq!(
+            from_command("printf ' backup-3 \\nother\\n'", IDENTIFIER_CMD_TIMEOUT),
+            Ok("backup-3".to_string())
+        );
+
+        for (command, error) in [
+            (
+                "echo backup; exit 3",
+                "identifier command `echo backup; exit 3` failed with exit status: 3",
+            ),
+            (
+                "echo",
+                "identifier command `echo` did not write an identifier",
+            ),
+            (
+                "sleep 5",
+                "identifier command `sleep 5` did not finish within 0.2 seconds",
+            ),
+        ] {
+            assert_eq!(
+                from_command(command, Duration::from_millis(200)),
+                Err(error.to_string())
+            );
+        }
+    }
+}
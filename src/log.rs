@@ -1,48 +1,368 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
-use serde::Serialize;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-use crate::client::client;
-use crate::ndjson;
+use crate::batch::BatchSummary;
+use crate::client::{client, with_body};
 use crate::package::NAME;
+use crate::rate_limit::LogRateLimit;
+use crate::schema;
+use crate::tail::FileLine;
 use crate::timestamp::Timestamp;
+use crate::units::deserialize_duration;
+use crate::wrap::{
+    default_compress, default_endpoint, hostname, random_digest, require, validate_endpoint,
+};
 
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct LogConfig {
     pub api_key: String,
+    #[serde(default = "default_endpoint")]
     pub endpoint: String,
+    #[serde(default = "hostname")]
     pub hostname: String,
     pub group: String,
+    // Whether to append a suffix, derived from the digest, to the group, so
+    // that the logs of each invocation are sent to a different group.
+    #[serde(default)]
+    pub group_per_run: bool,
+    #[serde(default)]
     pub origin: LogOrigin,
+    #[serde(default = "random_digest")]
     pub digest: String,
-    pub command: String,
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub revision: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    // Patterns to detect the severity of each line with. The first pattern
+    // that matches a line determines its severity.
+    #[serde(default)]
+    pub severity_patterns: Vec<SeverityPattern>,
+    // The severity of lines from each stream that match no pattern.
+    #[serde(default = "default_stdout_severity")]
+    pub stdout_severity: LogSeverity,
+    #[serde(default = "default_stderr_severity")]
+    pub stderr_severity: LogSeverity,
+    #[serde(default)]
+    pub batch: LogBatch,
+    #[serde(default)]
+    pub rate_limit: Option<LogRateLimit>,
+    // The log sources to send messages of each severity, or higher, to,
+    // instead of the one for the API key.
+    #[serde(default)]
+    pub severity_sources: Vec<SeveritySource>,
+    // Whether to compress the request body with gzip.
+    #[serde(default = "default_compress")]
+    pub compress: bool,
+}
+
+fn default_stdout_severity() -> LogSeverity {
+    LogSeverity::Info
+}
+
+fn default_stderr_severity() -> LogSeverity {
+    LogSeverity::Error
 }
 
 impl LogConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        require(&self.api_key, "log API key")?;
+        validate_endpoint(&self.endpoint)?;
+        require(&self.group, "log group")?;
+
+        for source in &self.severity_sources {
+            require(&source.api_key, "log source API key")?;
+        }
+
+        self.batch.validate()
+    }
+
     pub fn request(&self, messages: Vec<LogMessage>) -> Result<reqwest::Request, reqwest::Error> {
+        self.request_to(&self.api_key, messages)
+    }
+
+    // One request for each log source that the messages are sent to.
+    pub fn requests(
+        &self,
+        messages: Vec<LogMessage>,
+    ) -> Vec<Result<reqwest::Request, reqwest::Error>> {
+        if self.severity_sources.is_empty() {
+            return vec![self.request(messages)];
+        }
+
+        let mut by_api_key: BTreeMap<&str, Vec<LogMessage>> = BTreeMap::new();
+        for message in messages {
+            by_api_key
+                .entry(self.api_key_for(message.severity))
+                .or_default()
+                .push(message);
+        }
+
+        by_api_key
+            .into_iter()
+            .map(|(api_key, messages)| self.request_to(api_key, messages))
+            .collect()
+    }
+
+    // The API key of the log source for the source with the highest
+    // severity that is not higher than the given severity, if any.
+    fn api_key_for(&self, severity: LogSeverity) -> &str {
+        self.severity_sources
+            .iter()
+            .filter(|source| source.severity <= severity)
+            .max_by_key(|source| source.severity)
+            .map_or(&self.api_key, |source| &source.api_key)
+    }
+
+    fn request_to(
+        &self,
+        api_key: &str,
+        messages: Vec<LogMessage>,
+    ) -> Result<reqwest::Request, reqwest::Error> {
         let url = format!("{}/logs/json", self.endpoint);
 
-        client()
+        let body = schema::log_body(messages).expect("failed to serialize log messages");
+        let builder = client()
             .post(url)
-            .query(&[("api_key", &self.api_key)])
+            .query(&[("api_key", api_key)])
             .header("Content-Type", "application/x-ndjson")
-            .body(ndjson::to_string(messages).expect("failed to serialize log messages"))
-            .build()
+            .header(schema::HEADER, schema::version().as_str());
+
+        with_body(builder, body, self.compress).build()
+    }
+
+    // Returns the severity of the first pattern that matches the line, or the
+    // given default severity if none do.
+    pub fn severity(&self, line: &str, default: LogSeverity) -> LogSeverity {
+        self.severity_patterns
+            .iter()
+            .find(|pattern| pattern.pattern.0.is_match(line))
+            .map_or(default, |pattern| pattern.severity)
+    }
+
+    pub fn group(&self) -> String {
+        if !self.group_per_run {
+            return self.group.clone();
+        }
+
+        let suffix: String = self.digest.chars().take(8).collect();
+        format!("{}-{}", self.group, suffix)
     }
 
     fn tags(&self) -> BTreeMap<String, String> {
-        [
-            (format!("{}-digest", NAME), self.digest.clone()),
-            ("command".to_string(), self.command.clone()),
-        ]
-        .into()
+        let mut tags = self.tags.clone();
+        tags.insert(format!("{}-digest", NAME), self.digest.clone());
+
+        if let Some(command) = self.command.as_ref() {
+            tags.insert("command".to_string(), command.clone());
+        }
+
+        if let Some(revision) = self.revision.as_ref() {
+            tags.insert("revision".to_string(), revision.clone());
+        }
+
+        tags
+    }
+}
+
+// When to send the log messages that have been read so far in a request.
+// A request is sent when either limit is reached, or when the flush
+// interval elapses, whichever happens first.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogBatch {
+    // The maximum number of messages in a request.
+    pub size: usize,
+    // The maximum size, in bytes, of the messages in a request. The size of
+    // a message is the size of its line, without its attributes.
+    pub bytes: Option<usize>,
+    // The interval is given in seconds, or with units, when deserialized.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub flush_interval: Duration,
+    // The maximum number of messages held in memory, including the ones in
+    // requests that are being sent.
+    pub capacity: usize,
+    // The maximum size, in bytes, of the messages held in memory, including
+    // the ones in requests that are being sent.
+    pub capacity_bytes: Option<usize>,
+}
+
+pub const DEFAULT_LOG_BATCH_SIZE: usize = 100;
+pub const DEFAULT_LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+pub const DEFAULT_LOG_CAPACITY: usize = 10_000;
+
+impl Default for LogBatch {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_LOG_BATCH_SIZE,
+            bytes: None,
+            flush_interval: DEFAULT_LOG_FLUSH_INTERVAL,
+            capacity: DEFAULT_LOG_CAPACITY,
+            capacity_bytes: None,
+        }
+    }
+}
+
+impl LogBatch {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.size == 0 {
+            return Err("the log batch size must be greater than zero".to_string());
+        }
+
+        if self.bytes == Some(0) {
+            return Err("the log batch bytes must be greater than zero".to_string());
+        }
+
+        if self.flush_interval.is_zero() {
+            return Err("the log flush interval must be greater than zero".to_string());
+        }
+
+        if self.capacity < self.size {
+            return Err("the log buffer must be able to hold a full batch".to_string());
+        }
+
+        Ok(())
+    }
+
+    // Whether a batch with the given number of messages, and size in bytes,
+    // should be sent without waiting for the flush interval.
+    pub fn is_full(&self, messages: usize, bytes: usize) -> bool {
+        messages >= self.size || self.bytes.is_some_and(|max| bytes >= max)
+    }
+}
+
+// The log messages that have not been sent yet. When the command writes
+// lines faster than they can be sent, at most `capacity` messages, and, if
+// set, `capacity_bytes` bytes of them, are held in memory, including the
+// ones in requests that are being sent, and the oldest messages that have
+// not been sent are dropped.
+pub struct LogBuffer {
+    messages: VecDeque<LogMessage>,
+    bytes: usize,
+    dropped: usize,
+    capacity: usize,
+    capacity_bytes: Option<usize>,
+    in_flight: Arc<InFlightCounts>,
+}
+
+#[derive(Default)]
+struct InFlightCounts {
+    messages: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize, capacity_bytes: Option<usize>) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            bytes: 0,
+            dropped: 0,
+            capacity,
+            capacity_bytes,
+            in_flight: Arc::new(InFlightCounts::default()),
+        }
+    }
+
+    pub fn push(&mut self, message: LogMessage) {
+        let size = message.message.len();
+
+        // A message that would not fit on its own is dropped, rather than
+        // every message held to make room for it.
+        if self.capacity_bytes.is_some_and(|max| size > max) {
+            self.dropped += 1;
+            return;
+        }
+
+        while self.is_full(size) {
+            self.dropped += 1;
+
+            // If every message held is being sent, the new message is the
+            // oldest one that has not been sent.
+            match self.messages.pop_front() {
+                Some(dropped) => self.bytes -= dropped.message.len(),
+                None => return,
+            }
+        }
+
+        self.bytes += size;
+        self.messages.push_back(message);
+    }
+
+    // Whether a message of the given size cannot be held without dropping
+    // another one.
+    fn is_full(&self, size: usize) -> bool {
+        let messages = self.messages.len() + self.in_flight.messages.load(Ordering::Relaxed);
+        let bytes = self.bytes + self.in_flight.bytes.load(Ordering::Relaxed) + size;
+
+        messages >= self.capacity || self.capacity_bytes.is_some_and(|max| bytes > max)
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // Returns the number of messages dropped since this was last called.
+    pub fn take_dropped(&mut self) -> usize {
+        std::mem::take(&mut self.dropped)
+    }
+
+    // Takes the messages to send in a request. They count towards the
+    // capacity of the buffer until the returned guard is dropped.
+    pub fn take(&mut self) -> (Vec<LogMessage>, InFlight) {
+        let messages: Vec<LogMessage> = std::mem::take(&mut self.messages).into();
+        let bytes = std::mem::take(&mut self.bytes);
+
+        self.in_flight
+            .messages
+            .fetch_add(messages.len(), Ordering::Relaxed);
+        self.in_flight.bytes.fetch_add(bytes, Ordering::Relaxed);
+        let in_flight = InFlight {
+            messages: messages.len(),
+            bytes,
+            counts: self.in_flight.clone(),
+        };
+
+        (messages, in_flight)
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InFlight {
+    messages: usize,
+    bytes: usize,
+    counts: Arc<InFlightCounts>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.counts
+            .messages
+            .fetch_sub(self.messages, Ordering::Relaxed);
+        self.counts.bytes.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum LogOrigin {
     None,
     Stdout,
     Stderr,
+    #[default]
     All,
 }
 
@@ -88,7 +408,7 @@ impl LogMessage {
         message: String,
     ) -> Self {
         Self {
-            group: config.group.clone(),
+            group: config.group(),
             timestamp: timestamp.as_rfc3339(),
             severity,
             message,
@@ -96,18 +416,245 @@ impl LogMessage {
             attributes: config.tags(),
         }
     }
+
+    // A message for a line read from a file given with `--log-file`, with
+    // the file's path as the `file` attribute.
+    pub fn from_file(
+        config: &LogConfig,
+        timestamp: &mut impl Timestamp,
+        severity: LogSeverity,
+        line: FileLine,
+    ) -> Self {
+        let mut message = Self::new(config, timestamp, severity, line.line);
+
+        if let Some(group) = line.file.group.as_ref() {
+            message.group = group.clone();
+        }
+
+        message
+            .attributes
+            .insert("file".to_string(), line.file.path.display().to_string());
+        message
+    }
+
+    // A message reporting that lines of output were dropped, instead of
+    // being sent as logs, with their number as the `dropped_lines`
+    // attribute.
+    pub fn dropped(config: &LogConfig, timestamp: &mut impl Timestamp, dropped: usize) -> Self {
+        let mut message = Self::new(
+            config,
+            timestamp,
+            LogSeverity::Warn,
+            format!(
+                "[{} log lines dropped, as they were written faster than they could be sent]",
+                dropped
+            ),
+        );
+
+        message
+            .attributes
+            .insert("dropped_lines".to_string(), dropped.to_string());
+        message
+    }
+
+    // A message reporting that a batch job succeeded, but that some of its
+    // items failed, with the values of its summary line as `batch_`
+    // attributes.
+    pub fn partial_failure(
+        config: &LogConfig,
+        timestamp: &mut impl Timestamp,
+        summary: &BatchSummary,
+    ) -> Self {
+        let mut message = Self::new(
+            config,
+            timestamp,
+            LogSeverity::Warn,
+            format!(
+                "[Process succeeded, but {} batch items failed: {}]",
+                summary.failed(),
+                summary.describe()
+            ),
+        );
+
+        message.attributes.extend(summary.tags());
+        message
+    }
+
+    // A message reporting that lines of output were dropped, as they were
+    // over the log rate limit, with their number as the `dropped_lines`
+    // attribute.
+    pub fn rate_limited(
+        config: &LogConfig,
+        timestamp: &mut impl Timestamp,
+        dropped: usize,
+        limit: LogRateLimit,
+    ) -> Self {
+        let mut message = Self::new(
+            config,
+            timestamp,
+            LogSeverity::Warn,
+            format!(
+                "[{} log lines dropped, as they exceeded the log rate limit of {}]",
+                dropped, limit
+            ),
+        );
+
+        message
+            .attributes
+            .insert("dropped_lines".to_string(), dropped.to_string());
+        message
+    }
 }
 
-#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+// Declared from lowest to highest severity, which is the order in which
+// they are compared.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "lowercase")]
 pub enum LogSeverity {
+    Trace,
+    Debug,
     Info,
+    Warn,
     Error,
+    Critical,
+}
+
+impl LogSeverity {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "trace" => Ok(Self::Trace),
+            "debug" => Ok(Self::Debug),
+            "info" => Ok(Self::Info),
+            "warn" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            "critical" => Ok(Self::Critical),
+            _ => Err(format!(
+                "invalid severity `{value}`; expected one of \
+                trace, debug, info, warn, error or critical"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeverityPattern {
+    pub severity: LogSeverity,
+    pub pattern: Pattern,
+}
+
+impl SeverityPattern {
+    // Parses a pattern given as `SEVERITY=REGEX`, such as `warn=^W:`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (severity, pattern) = value
+            .split_once('=')
+            .ok_or("severity patterns must be given as SEVERITY=REGEX")?;
+
+        Ok(Self {
+            severity: LogSeverity::parse(severity)?,
+            pattern: Pattern::try_from(pattern.to_string())?,
+        })
+    }
+
+    // Patterns that detect the severity from a prefix of the line, such as
+    // `WARN`, `ERROR:` or `[debug]`, regardless of case.
+    pub fn defaults() -> Vec<Self> {
+        [
+            (LogSeverity::Trace, "trace"),
+            (LogSeverity::Debug, "debug"),
+            (LogSeverity::Info, "info"),
+            (LogSeverity::Warn, "warn(ing)?"),
+            (LogSeverity::Error, "err(or)?"),
+            (LogSeverity::Critical, "crit(ical)?|fatal"),
+        ]
+        .into_iter()
+        .map(|(severity, prefix)| Self {
+            severity,
+            pattern: Pattern(
+                Regex::new(&format!(r"(?i)^\W*({prefix})\b")).expect("invalid default pattern"),
+            ),
+        })
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeveritySource {
+    pub severity: LogSeverity,
+    pub api_key: String,
+}
+
+// The log sources given as `SEVERITY=KEY` pairs separated by commas, such
+// as `error=KEY1,default=KEY2`. The `default` log source, if given, is used
+// for messages below the lowest given severity.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SeveritySources {
+    pub sources: Vec<SeveritySource>,
+    pub default: Option<String>,
+}
+
+impl SeveritySources {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut sources = Self::default();
+
+        for pair in value.split(',') {
+            let (severity, api_key) = pair
+                .trim()
+                .split_once('=')
+                .filter(|(_, api_key)| !api_key.is_empty())
+                .ok_or("log sources by severity must be given as SEVERITY=KEY")?;
+
+            if severity.eq_ignore_ascii_case("default") {
+                sources.default = Some(api_key.to_string());
+                continue;
+            }
+
+            let parsed = LogSeverity::parse(severity)?;
+            if sources
+                .sources
+                .iter()
+                .any(|source| source.severity == parsed)
+            {
+                return Err(format!(
+                    "the log source for the `{severity}` severity is given more than once"
+                ));
+            }
+
+            sources.sources.push(SeveritySource {
+                severity: parsed,
+                api_key: api_key.to_string(),
+            });
+        }
+
+        Ok(sources)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Pattern(pub Regex);
+
+impl Pattern {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        Self::try_from(value.to_string())
+    }
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Regex::new(&value)
+            .map(Self)
+            .map_err(|err| format!("invalid pattern `{value}`: {err}"))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ndjson;
     use crate::timestamp::tests::{timestamp, EXPECTED_RFC3339};
 
     fn log_config() -> LogConfig {
@@ -116,12 +663,182 @@ mod tests {
             endpoint: "https://some-endpoint.com".to_string(),
             hostname: "some-hostname".to_string(),
             group: "some-group".to_string(),
+            group_per_run: false,
             origin: LogOrigin::All,
             digest: "some-digest".to_string(),
-            command: "some-command".to_string(),
+            command: Some("some-command".to_string()),
+            revision: Some("some-revision".to_string()),
+            tags: [("environment".to_string(), "production".to_string())].into(),
+            severity_patterns: vec![],
+            stdout_severity: LogSeverity::Info,
+            stderr_severity: LogSeverity::Error,
+            batch: LogBatch::default(),
+            rate_limit: None,
+            severity_sources: vec![],
+            compress: false,
         }
     }
 
+    #[test]
+    fn log_config_severity() {
+        let mut config = log_config();
+        assert_eq!(
+            config.severity("ERROR: failed", LogSeverity::Info),
+            LogSeverity::Info
+        );
+
+        config.severity_patterns = SeverityPattern::defaults();
+
+        for (line, severity) in [
+            ("TRACE entering loop", LogSeverity::Trace),
+            ("[debug] connecting", LogSeverity::Debug),
+            ("Info: started", LogSeverity::Info),
+            ("WARN disk almost full", LogSeverity::Warn),
+            ("  warning: deprecated", LogSeverity::Warn),
+            ("ERROR: failed", LogSeverity::Error),
+            ("[FATAL] out of memory", LogSeverity::Critical),
+            ("errors: 0", LogSeverity::Info),
+            ("processed 3 jobs", LogSeverity::Info),
+        ] {
+            assert_eq!(
+                config.severity(line, LogSeverity::Info),
+                severity,
+                "{line:?}"
+            );
+        }
+
+        config
+            .severity_patterns
+            .insert(0, SeverityPattern::parse("debug=^processed").unwrap());
+        assert_eq!(
+            config.severity("processed 3 jobs", LogSeverity::Info),
+            LogSeverity::Debug
+        );
+
+        for value in ["debug", "verbose=^V", "warn=("] {
+            assert!(
+                SeverityPattern::parse(value).is_err(),
+                "{value:?} should fail"
+            );
+        }
+    }
+
+    #[test]
+    fn log_batch_is_full() {
+        let mut batch = LogBatch::default();
+        assert!(!batch.is_full(99, 1_000_000));
+        assert!(batch.is_full(100, 0));
+
+        batch.bytes = Some(1000);
+        assert!(!batch.is_full(1, 999));
+        assert!(batch.is_full(1, 1000));
+
+        batch.size = 0;
+        assert_eq!(
+            batch.validate(),
+            Err("the log batch size must be greater than zero".to_string())
+        );
+    }
+
+    #[test]
+    fn log_buffer_drops_oldest() {
+        let config = log_config();
+        let message = |text: &str| {
+            LogMessage::new(
+                &config,
+                &mut timestamp(),
+                LogSeverity::Info,
+                text.to_string(),
+            )
+        };
+
+        let mut buffer = LogBuffer::new(3, None);
+        buffer.push(message("one"));
+        buffer.push(message("two"));
+
+        let (sent, in_flight) = buffer.take();
+        assert_eq!(sent.len(), 2);
+        assert!(buffer.is_empty());
+
+        buffer.push(message("three"));
+        buffer.push(message("four"));
+        buffer.push(message("five"));
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.bytes(), 4);
+        assert_eq!(buffer.take_dropped(), 2);
+
+        drop(in_flight);
+        buffer.push(message("six"));
+        buffer.push(message("seven"));
+        buffer.push(message("eight"));
+
+        let (sent, _) = buffer.take();
+        let sent: Vec<&str> = sent
+            .iter()
+            .map(|message| message.message.as_str())
+            .collect();
+        assert_eq!(sent, vec!["six", "seven", "eight"]);
+        assert_eq!(buffer.take_dropped(), 1);
+
+        let dropped = LogMessage::dropped(&config, &mut timestamp(), 3);
+        assert_eq!(dropped.severity, LogSeverity::Warn);
+        assert_eq!(dropped.attributes["dropped_lines"], "3");
+    }
+
+    #[test]
+    fn log_buffer_capacity_bytes() {
+        let config = log_config();
+        let message = |text: &str| {
+            LogMessage::new(
+                &config,
+                &mut timestamp(),
+                LogSeverity::Info,
+                text.to_string(),
+            )
+        };
+
+        let mut buffer = LogBuffer::new(100, Some(10));
+        buffer.push(message("a"));
+        buffer.push(message("b"));
+
+        // The messages being sent count towards the capacity.
+        let (_, in_flight) = buffer.take();
+        buffer.push(message("three"));
+        assert_eq!(buffer.take_dropped(), 0);
+        buffer.push(message("four"));
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.bytes(), 4);
+        assert_eq!(buffer.take_dropped(), 1);
+
+        // A message larger than the capacity is dropped on its own.
+        drop(in_flight);
+        buffer.push(message("eleven bytes"));
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.take_dropped(), 1);
+
+        buffer.push(message("five"));
+        buffer.push(message("six"));
+        let (sent, _) = buffer.take();
+        let sent: Vec<&str> = sent
+            .iter()
+            .map(|message| message.message.as_str())
+            .collect();
+        assert_eq!(sent, vec!["five", "six"]);
+        assert_eq!(buffer.take_dropped(), 1);
+    }
+
+    #[test]
+    fn log_config_group() {
+        let mut config = LogConfig {
+            digest: "0123456789abcdef".to_string(),
+            ..log_config()
+        };
+        assert_eq!(config.group(), "some-group");
+
+        config.group_per_run = true;
+        assert_eq!(config.group(), "some-group-01234567");
+    }
+
     #[test]
     fn log_config_request() {
         let config = log_config();
@@ -161,7 +878,9 @@ mod tests {
                     r#""hostname":"some-hostname","#,
                     r#""attributes":{{"#,
                     r#""{}-digest":"some-digest","#,
-                    r#""command":"some-command""#,
+                    r#""command":"some-command","#,
+                    r#""environment":"production","#,
+                    r#""revision":"some-revision""#,
                     r#"}}"#,
                     "}}\n",
                     "{{",
@@ -172,7 +891,9 @@ mod tests {
                     r#""hostname":"some-hostname","#,
                     r#""attributes":{{"#,
                     r#""{}-digest":"some-digest","#,
-                    r#""command":"some-command""#,
+                    r#""command":"some-command","#,
+                    r#""environment":"production","#,
+                    r#""revision":"some-revision""#,
                     r#"}}"#,
                     "}}\n"
                 ),
@@ -180,4 +901,108 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn log_config_requests_by_severity() {
+        let config = LogConfig {
+            severity_sources: vec![
+                SeveritySource {
+                    severity: LogSeverity::Error,
+                    api_key: "errors_api_key".to_string(),
+                },
+                SeveritySource {
+                    severity: LogSeverity::Warn,
+                    api_key: "warnings_api_key".to_string(),
+                },
+            ],
+            ..log_config()
+        };
+        let message = |severity| {
+            LogMessage::new(
+                &config,
+                &mut timestamp(),
+                severity,
+                "some-message".to_string(),
+            )
+        };
+
+        let requests = config.requests(vec![
+            message(LogSeverity::Debug),
+            message(LogSeverity::Critical),
+            message(LogSeverity::Warn),
+            message(LogSeverity::Error),
+        ]);
+
+        let urls: Vec<String> = requests
+            .iter()
+            .map(|request| request.as_ref().unwrap().url().to_string())
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "https://some-endpoint.com/logs/json?api_key=errors_api_key",
+                "https://some-endpoint.com/logs/json?api_key=some_api_key",
+                "https://some-endpoint.com/logs/json?api_key=warnings_api_key",
+            ]
+        );
+
+        let lines = |index: usize| {
+            let body = requests[index].as_ref().unwrap().body().unwrap();
+            String::from_utf8_lossy(body.as_bytes().unwrap())
+                .lines()
+                .count()
+        };
+        assert_eq!((lines(0), lines(1), lines(2)), (2, 1, 1));
+    }
+
+    #[test]
+    fn parse_severity_sources() {
+        assert_eq!(
+            SeveritySources::parse("error=key1, default=key2"),
+            Ok(SeveritySources {
+                sources: vec![SeveritySource {
+                    severity: LogSeverity::Error,
+                    api_key: "key1".to_string(),
+                }],
+                default: Some("key2".to_string()),
+            })
+        );
+
+        for value in ["", "error", "error=", "loud=key", "error=a,ERROR=b"] {
+            assert!(
+                SeveritySources::parse(value).is_err(),
+                "{value:?} should fail"
+            );
+        }
+    }
+
+    #[test]
+    fn log_config_request_compressed() {
+        use std::io::Read;
+
+        let config = LogConfig {
+            compress: true,
+            ..log_config()
+        };
+        let messages = || {
+            vec![LogMessage::new(
+                &config,
+                &mut timestamp(),
+                LogSeverity::Info,
+                "some-message".to_string(),
+            )]
+        };
+
+        let expected = ndjson::to_string(messages()).unwrap();
+        let request = config.request(messages()).unwrap();
+
+        assert_eq!(request.headers().get("Content-Encoding").unwrap(), "gzip");
+
+        let mut body = String::new();
+        flate2::read::GzDecoder::new(request.body().unwrap().as_bytes().unwrap())
+            .read_to_string(&mut body)
+            .unwrap();
+
+        assert_eq!(body, expected);
+    }
 }
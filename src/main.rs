@@ -1,60 +1,258 @@
 mod check_in;
 mod cli;
+mod config;
+mod correlate;
 mod error;
 mod log;
-
+mod metrics;
+
+mod agent;
+mod ansi;
+mod audit;
+mod audit_log;
+mod batch;
+mod canary;
 mod channel;
+mod child;
 mod client;
+mod delivery;
+mod diagnose;
+mod dry_run;
+mod dump;
+mod environment;
 mod exit;
+mod failure;
+#[cfg(feature = "fake-endpoint")]
+mod fake_endpoint;
+mod health;
+mod hook;
+mod identifier;
+mod memory;
 mod ndjson;
 mod package;
+mod policy;
+mod priority;
+mod rate_limit;
+mod record;
+mod redact;
+mod resources;
+mod restart;
+mod runtime;
+mod sample;
+mod schedule;
+mod schema;
 mod signal;
+mod sink;
+mod splitter;
+mod state;
+mod statsd;
+mod summary;
+mod tail;
 mod timestamp;
-
-use crate::channel::{maybe_recv, maybe_spawn_tee};
-use crate::check_in::{CronKind, HeartbeatConfig};
+mod trace;
+mod units;
+mod upgrade;
+mod wrap;
+
+use crate::audit::Audit;
+use crate::audit_log::Outcome;
+use crate::batch::SummaryReader;
+use crate::channel::{maybe_recv, maybe_spawn_map, Receive};
+use crate::check_in::{
+    Activity, CronKind, HeartbeatConfig, Usr2Action, DEFAULT_HEARTBEAT_INTERVAL,
+};
+use crate::child::ChildProcess;
 use crate::cli::Cli;
-use crate::client::send_request;
-use crate::log::{LogConfig, LogMessage, LogSeverity};
+use crate::delivery::DataType;
+use crate::dump::{QuitAction, StateDump, STATS};
+use crate::log::{LogBuffer, LogConfig, LogMessage, LogOrigin, LogSeverity, Pattern};
+use crate::metrics::MetricsConfig;
 use crate::package::NAME;
-use crate::signal::{has_terminating_intent, signal_stream};
-use crate::timestamp::SystemTimestamp;
-
-use ::log::{debug, error, trace};
-use error::ErrorConfig;
-use std::collections::VecDeque;
+use crate::policy::{Policy, POLICY_PATH};
+use crate::rate_limit::RateLimiter;
+use crate::redact::Redactor;
+use crate::resources::{ResourceUsage, RESOURCE_USAGE_INTERVAL};
+use crate::signal::{forwarding_signal_stream, has_terminating_intent, signal_stream};
+use crate::sink::{sample_error, sink_loop, BatchSink, ErrorSink, Exit, HookSink, LogSink, Sink};
+use crate::splitter::{LineSplitter, READ_CHUNK_SIZE};
+use crate::tail::FileLine;
+use crate::timestamp::{Clock, ClockJump, ClockJumpDetector, SystemTimestamp, Timestamp};
+use crate::upgrade::{PidFile, UpgradeState};
+use crate::wrap::{PassthroughErrorAction, StdinMode, WrapConfig};
+
+use ::log::{debug, error, trace, warn, LevelFilter};
+use error::{ErrorConfig, MessageLines};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::os::fd::{AsRawFd, RawFd};
 use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::pin::Pin;
 use std::process::{exit, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::{
     io,
-    io::{stderr, stdout, Write},
+    io::{stderr, stdout, BufRead, Cursor, Write},
 };
 use timestamp::MonotonicTimestamp;
-use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
-use tokio::process::{Child, Command};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::unix::pipe;
+use tokio::process::{ChildStdin, Command};
 use tokio::select;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
-use tokio::sync::oneshot;
-use tokio::time::{interval, Duration, MissedTickBehavior};
-use tokio_stream::StreamExt;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::{oneshot, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration, MissedTickBehavior, Sleep};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::io::StreamReader;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
-use clap::Parser;
+use chrono::Local;
 use env_logger::Env;
 
 fn main() {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info"))
-        .format(|buf, record| {
-            let level = record.level().to_string().to_ascii_lowercase();
-            writeln!(buf, "{}: {}: {}", NAME, level, record.args())
-        })
-        .init();
+    // Unless `RUST_LOG` is set, the logger allows all of the wrapper's own
+    // messages, and none of those of its dependencies. The maximum level is
+    // lowered to `warn` until the `--quiet` and `--verbose` options are
+    // parsed, so that those options can raise or lower it without
+    // rebuilding the logger.
+    env_logger::Builder::from_env(
+        Env::default().default_filter_or(concat!(env!("CARGO_CRATE_NAME"), "=trace")),
+    )
+    .format(|buf, record| {
+        let level = record.level().to_string().to_ascii_lowercase();
+        writeln!(buf, "{}: {}: {}", NAME, level, record.args())
+    })
+    .init();
+
+    if std::env::var_os("RUST_LOG").is_none() {
+        ::log::set_max_level(LevelFilter::Warn);
+    }
+
+    // This must happen before the Tokio runtime spawns any threads.
+    let inherited = UpgradeState::inherited();
+
+    let args = match config::args() {
+        Ok(args) => args,
+        Err(err) => {
+            error!("{}", err);
+            exit(1);
+        }
+    };
+
+    let mut cli = Cli::try_parse_with_env(args).unwrap_or_else(|err| err.exit());
+
+    if let Some(level) = cli.log_level() {
+        ::log::set_max_level(level);
+    }
+
+    if let Some(version) = cli.version() {
+        print!("{}", version);
+        exit(0);
+    }
+
+    if cli.client().dry_run {
+        dry_run::enable();
+    }
+
+    if let Some(dir) = cli.client().record.clone() {
+        record::init(dir);
+    }
+
+    if let Some(version) = cli.client().payload_schema_version {
+        schema::init(version);
+    }
+
+    match cli.client().tls() {
+        Ok(tls) => client::init(tls, cli.client().timeouts()),
+        Err(err) => {
+            error!("{}", err);
+            exit(1);
+        }
+    }
+
+    let result = match cli.subcommand.take() {
+        Some(cli::Command::Upgrade(args)) => upgrade::request(&args).map(|_| 0),
+        Some(cli::Command::Log(args)) => log_stdin(args.log()),
+        Some(cli::Command::Error(args)) => report_error(args),
+        Some(cli::Command::SimulateFailure(args)) => simulate_failure(args),
+        Some(cli::Command::Canary(args)) => canary(args),
+        Some(cli::Command::Agent(args)) => run_agent(args),
+        Some(cli::Command::Diagnose(args)) => diagnose(args),
+        Some(cli::Command::Replay(args)) => replay(args),
+        #[cfg(feature = "fake-endpoint")]
+        Some(cli::Command::FakeEndpoint(args)) => run_fake_endpoint(args),
+        None if cli.pipe => match load_policy(&cli) {
+            Ok(_) => {
+                cli.run_identifier_cmd();
+                cli.warn();
+
+                runtime::runtime(true)
+                    .map_err(Into::into)
+                    .and_then(|runtime| runtime.block_on(read_pipe(&cli)))
+            }
+            Err(err) => Err(err.into()),
+        },
+        None => {
+            let mut digest = None;
 
-    let cli = Cli::parse();
-    cli.warn();
+            let config = load_policy(&cli).and_then(|policy| {
+                cli.run_identifier_cmd();
 
-    match start(cli) {
+                let mut config = cli.wrap_config()?;
+                if let Some(policy) = policy {
+                    policy.check_command(&mut config)?;
+                }
+
+                Ok(config)
+            });
+
+            let result = match config {
+                Ok(mut config) => {
+                    if let Some(state) = inherited.as_ref() {
+                        config.set_digest(state.digest.clone());
+                    } else {
+                        cli.warn();
+                    }
+
+                    digest = Some(config.digest.clone());
+
+                    runtime::runtime(config.single_thread)
+                        .map_err(Into::into)
+                        .and_then(|runtime| runtime.block_on(start(config, inherited)))
+                }
+                Err(err) => Err(err.into()),
+            };
+
+            if let Some(audit_log) = cli.audit_log() {
+                let error = result.as_ref().err().map(ToString::to_string);
+                let outcome = match (&result, error.as_deref()) {
+                    (Ok(code), _) => Outcome::Exited { exit_code: *code },
+                    (_, error) => Outcome::Error {
+                        error: error.unwrap_or_default(),
+                    },
+                };
+
+                if let Err(err) = audit_log.write(&mut SystemTimestamp, digest.as_deref(), outcome)
+                {
+                    warn!("{}", err);
+                }
+            }
+
+            result
+        }
+    };
+
+    // Requests that failed are reported once for each reason, instead of
+    // once for each request.
+    for failure in summary::failures() {
+        warn!("{}", failure);
+    }
+
+    match result {
         Ok(code) => exit(code),
         Err(err) => {
             error!("{}", err);
@@ -63,76 +261,615 @@ fn main() {
     }
 }
 
-#[tokio::main]
-async fn start(cli: Cli) -> Result<i32, Box<dyn std::error::Error>> {
-    let cron = cli.cron();
-    let log = cli.log();
-    let error = cli.error();
+// Checks the options and the command strings against the policy file, if
+// there is one, before any command is run. The command itself is checked
+// against the returned policy once it is configured.
+fn load_policy(cli: &Cli) -> Result<Option<Policy>, String> {
+    let policy = Policy::load(Path::new(POLICY_PATH))?;
+
+    if let Some(policy) = policy.as_ref() {
+        policy.check_cli(cli)?;
+    }
+
+    Ok(policy)
+}
+
+async fn start(
+    mut config: WrapConfig,
+    inherited: Option<UpgradeState>,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let started = std::time::Instant::now();
+    let lines_buffer = config.lines_buffer.unwrap_or(LINES_BUFFER);
+
+    health::init(config.state_dir.clone());
+
+    // A child process inherited from an upgrade was already started, so
+    // there is nothing to wait for.
+    if inherited.is_none() && !timestamp::is_sane(&mut SystemTimestamp) {
+        warn!(
+            "the system clock is set to {}; the timestamps of the data sent to AppSignal will be wrong until it is set",
+            SystemTimestamp.as_rfc3339()
+        );
+
+        if let Some(timeout) = config.wait_for_clock {
+            debug!("waiting for the system clock to be set before starting the command");
+
+            if timestamp::wait_for_sane_clock(SystemTimestamp, timeout).await {
+                debug!(
+                    "the system clock was set to {}",
+                    SystemTimestamp.as_rfc3339()
+                );
+            } else {
+                warn!(
+                    "the system clock was not set within {:?}; starting the command anyway",
+                    timeout
+                );
+            }
+        }
+    }
+
+    let sampler = config.error_sampler();
+    let cron = config.cron.take();
+    let mut log = config.log.take();
+    let mut error = config.error.take();
+    let metrics = config.metrics.take();
+    let trace = config.trace.take();
+
+    // How late the command started, compared to its cron schedule. This is
+    // not known for a child process inherited from an upgrade.
+    let schedule_drift = match (inherited.as_ref(), cron.as_ref()) {
+        (None, Some(cron)) => cron.schedule_drift(Local::now()),
+        _ => None,
+    };
+
+    if let Some(drift) = schedule_drift {
+        debug!(
+            "command started {}s after its scheduled time",
+            drift.as_secs()
+        );
+        let drift = drift.as_secs().to_string();
+
+        if let Some(log) = log.as_mut() {
+            log.tags.insert("schedule_drift".to_string(), drift.clone());
+        }
+
+        if let Some(error) = error.as_mut() {
+            error.tags.insert("schedule_drift".to_string(), drift);
+        }
+    }
+
+    let pid_file = match config.pid_file.as_ref() {
+        Some(path) => Some(
+            PidFile::create(path)
+                .map_err(|err| format!("could not write PID file {}: {}", path.display(), err))?,
+        ),
+        None => None,
+    };
 
     let tasks = TaskTracker::new();
+    let mut audit = Audit::new(config.pid_file.as_deref());
+
+    // If heartbeats are not sent while the command is idle, its output is
+    // observed to tell whether it is active.
+    let activity = config
+        .heartbeat
+        .as_ref()
+        .and_then(HeartbeatConfig::activity)
+        .map(Arc::new);
+
+    let log_origin = log.as_ref().map_or(LogOrigin::None, |log| log.origin);
+
+    // Notified when a heartbeat is requested by sending `SIGUSR2` to the
+    // wrapper, if the `--sigusr2` option is set to `heartbeat`.
+    let heartbeat_request = Arc::new(Notify::new());
+    let mut heartbeat: Option<CancellationToken> = None;
+
+    // Resolves to whether the cron start check-in was queued, so that the
+    // finish check-in is queued after it, and not sent at all without it.
+    // The start check-in of a child process inherited from an upgrade was
+    // already sent by the process it was inherited from.
+    let mut cron_start: Option<JoinHandle<bool>> = None;
+
+    // The tasks that stop reading the output of each run of the command
+    // after lingering, aborted once the wrapper exits.
+    let mut lingers = Vec::new();
+
+    // The number of times the command was restarted after it failed.
+    let mut restarts = 0;
+
+    // Cancelled once the command writes a line matching the ready pattern.
+    // A child process inherited from an upgrade is already ready.
+    let ready = config
+        .ready_pattern
+        .as_ref()
+        .filter(|_| inherited.is_none())
+        .map(|_| CancellationToken::new());
+
+    // Cancelled once the command has exited for the last time, so that the
+    // tasks waiting for it to be ready stop waiting.
+    let exited = CancellationToken::new();
+
+    // The files given with `--log-file` are tailed for as long as the
+    // command runs, however many times it is restarted.
+    if let Some(log) = log.clone().filter(|_| !config.log_files.is_empty()) {
+        let (sender, receiver) = channel(lines_buffer);
+        for file in &config.log_files {
+            tasks.spawn(tail::tail(
+                Arc::new(file.clone()),
+                sender.clone(),
+                SystemTimestamp,
+                exited.clone(),
+                config.max_line_bytes,
+            ));
+        }
+        tasks.spawn(log_loop::<Receiver<String>>(
+            log,
+            None,
+            None,
+            None,
+            Some(receiver),
+            SystemTimestamp,
+        ));
+    }
 
-    let (child, stdout, stderr) = match spawn_child(&cli, &tasks) {
-        Ok(spawned_child) => spawned_child,
-        Err(err) => {
-            if let Some(config) = error {
-                tasks.spawn(send_request(
-                    config.request_from_spawn(&mut SystemTimestamp, &err),
+    let exit_status = loop {
+        // The error reports of a command that is restarted are tagged with
+        // the attempt that failed.
+        let error = error.clone().map(|mut error| {
+            if config.restart.is_some() {
+                error
+                    .tags
+                    .insert("attempt".to_string(), (restarts + 1).to_string());
+            }
+            error
+        });
+
+        let pipes = CancellationToken::new();
+
+        // Only the first run of the command can be inherited from an upgrade.
+        let (mut child, stdout, stderr) = match inherited.as_ref().filter(|_| restarts == 0) {
+            Some(state) => {
+                debug!("inherited child process from upgrade: {}", state.pid);
+                inherit_child(state, &config, &tasks, &pipes)?
+            }
+            None => match spawn_child(&config, &tasks, &pipes) {
+                Ok(spawned_child) => spawned_child,
+                Err(err) => {
+                    if let Some(heartbeat) = heartbeat {
+                        heartbeat.cancel();
+                    }
+
+                    exited.cancel();
+
+                    if let Some(config) = error.and_then(|error| sample_error(error, &sampler)) {
+                        tasks.spawn(delivery::send(
+                            DataType::Error,
+                            config.request_from_spawn(&mut SystemTimestamp, &err),
+                        ));
+                    }
+
+                    tasks.close();
+                    tasks.wait().await;
+
+                    return Err(format!("could not spawn child process: {err}").into());
+                }
+            },
+        };
+
+        audit.pipe("standard output", stdout.fd);
+        audit.pipe("standard error", stderr.fd);
+
+        // The priority is lowered once the command is started, so that the
+        // command does not inherit it.
+        if let Some(priority) = config.wrapper_priority {
+            if let Err(err) = priority.apply() {
+                warn!("could not lower the wrapper's priority: {}", err);
+            }
+        }
+
+        // The lines written to the command's standard input, if it is piped, to
+        // send as logs. The pipe is closed once the command exits.
+        let stdin_pipe = CancellationToken::new();
+        let stdin_lines = child.take_stdin().map(|to| {
+            let (sender, lines) = channel(lines_buffer);
+            tasks.spawn(pipe_stdin(read_stdin(), to, sender, stdin_pipe.clone()));
+            lines
+        });
+
+        // StatsD lines are removed from standard output before it is used for
+        // logs and error messages.
+        let stdout_lines = match config.statsd.clone() {
+            Some(statsd) => stdout.receiver.map(|receiver| {
+                let (sender, lines) = channel(lines_buffer);
+                tasks.spawn(statsd_loop(statsd, receiver, sender));
+                lines
+            }),
+            None => stdout.receiver,
+        };
+
+        // Escape sequences are stripped from, and secrets are redacted in, the
+        // lines used for logs and error messages, after they have been written
+        // to the wrapper's output.
+        let (stdout_lines, stderr_lines, stdin_lines) = if config.strip_ansi
+            || !config.redact.is_empty()
+        {
+            (
+                maybe_spawn_sanitize(
+                    stdout_lines,
+                    config.strip_ansi,
+                    &config.redact,
+                    lines_buffer,
+                ),
+                maybe_spawn_sanitize(
+                    stderr.receiver,
+                    config.strip_ansi,
+                    &config.redact,
+                    lines_buffer,
+                ),
+                maybe_spawn_sanitize(stdin_lines, config.strip_ansi, &config.redact, lines_buffer),
+            )
+        } else {
+            (stdout_lines, stderr.receiver, stdin_lines)
+        };
+
+        let (stdout_lines, stderr_lines) = match activity.as_ref() {
+            Some(activity) => (
+                maybe_spawn_activity(stdout_lines, activity, lines_buffer, &tasks),
+                maybe_spawn_activity(stderr_lines, activity, lines_buffer, &tasks),
+            ),
+            None => (stdout_lines, stderr_lines),
+        };
+
+        let (stdout_lines, stderr_lines) = match config.stall_timeout {
+            Some(timeout) => spawn_stall(
+                Stall {
+                    timeout,
+                    error: error.clone(),
+                    kill: child.id().filter(|_| config.stall_kill),
+                    lines: MessageLines::new(config.error_lines, config.error_bytes),
+                },
+                stdout_lines,
+                stderr_lines,
+                lines_buffer,
+                &tasks,
+                SystemTimestamp,
+            ),
+            None => (stdout_lines, stderr_lines),
+        };
+
+        let (stdout_lines, stderr_lines) = match (config.ready_pattern.as_ref(), ready.as_ref()) {
+            (Some(pattern), Some(ready)) => (
+                maybe_spawn_ready(stdout_lines, pattern, ready, lines_buffer, &tasks),
+                maybe_spawn_ready(stderr_lines, pattern, ready, lines_buffer, &tasks),
+            ),
+            _ => (stdout_lines, stderr_lines),
+        };
+
+        // Check-ins and heartbeats are sent once, however many times the
+        // command is restarted.
+        if restarts == 0 {
+            if let Some(cron) = cron.clone().filter(|_| inherited.is_none()) {
+                cron_start = Some(match ready.clone() {
+                    // Without a ready pattern, the start check-in is queued
+                    // right away, before the command can exit.
+                    None => {
+                        let start = delivery::send(
+                            DataType::CheckIn,
+                            cron.request(&mut SystemTimestamp, CronKind::Start),
+                        );
+                        tasks.spawn(async move {
+                            start.await;
+                            true
+                        })
+                    }
+                    Some(ready) => {
+                        let exited = exited.clone();
+                        tasks.spawn(async move {
+                            if !wait_until_ready(Some(ready), &exited).await {
+                                return false;
+                            }
+
+                            delivery::send(
+                                DataType::CheckIn,
+                                cron.request(&mut SystemTimestamp, CronKind::Start),
+                            )
+                            .await;
+                            true
+                        })
+                    }
+                });
+            }
+
+            if let (Some(pattern), Some(ready), Some(timeout)) = (
+                config.ready_pattern.clone(),
+                ready.clone(),
+                config.ready_timeout,
+            ) {
+                tasks.spawn(ready_timeout(
+                    pattern,
+                    ready,
+                    exited.clone(),
+                    timeout,
+                    error.clone(),
+                ));
+            }
+
+            if let Some((metrics, drift)) = metrics.as_ref().zip(schedule_drift) {
+                tasks.spawn(delivery::send(
+                    DataType::Metrics,
+                    metrics.request_from_schedule_drift(drift),
                 ));
-                tasks.close();
-                tasks.wait().await;
             }
 
-            return Err(format!("could not spawn child process: {err}").into());
+            heartbeat = config.heartbeat.take().map(|config| {
+                let token = CancellationToken::new();
+                let heartbeat_loop = heartbeat_loop(
+                    config,
+                    activity.clone(),
+                    heartbeat_request.clone(),
+                    token.clone(),
+                    SystemTimestamp,
+                );
+
+                let (ready, cancel) = (ready.clone(), token.clone());
+                tasks.spawn(async move {
+                    if wait_until_ready(ready, &cancel).await {
+                        heartbeat_loop.await;
+                    }
+                });
+                token
+            });
         }
-    };
 
-    let (log_stdout, error_stdout) = maybe_spawn_tee(stdout);
-    let (log_stderr, error_stderr) = maybe_spawn_tee(stderr);
+        let usage = metrics.as_ref().zip(child.id()).map(|(config, pid)| {
+            let token = CancellationToken::new();
+            tasks.spawn(resource_usage_loop(
+                config.clone(),
+                pid,
+                token.clone(),
+                SystemTimestamp,
+            ));
+            token
+        });
 
-    if let Some(cron) = cron.as_ref() {
-        tasks.spawn(send_request(
-            cron.request(&mut SystemTimestamp, CronKind::Start),
+        // The lines of the command's output, and how it exited, are passed to
+        // each sink, including the ones that send logs and errors to AppSignal.
+        let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+
+        if let Some(log) = log.clone() {
+            let (sink, (stdout, stderr, stdin)) = LogSink::new(log.origin, stdin_lines.is_some());
+            tasks.spawn(log_loop(log, stdout, stderr, stdin, None, SystemTimestamp));
+            sinks.push(Box::new(sink));
+        }
+
+        if let Some(error) = error {
+            sinks.push(Box::new(ErrorSink {
+                error,
+                sampler: sampler.clone(),
+                lines: MessageLines::new(config.error_lines, config.error_bytes),
+                hook: config.on_failure.clone().filter(|hook| hook.report),
+                batch_summary: config.batch_summary.then(SummaryReader::default),
+            }));
+        }
+
+        if config.batch_summary {
+            sinks.push(Box::new(BatchSink {
+                reader: SummaryReader::default(),
+                metrics: metrics.clone(),
+                warn: log.clone().filter(|_| config.warn_on_batch_failures),
+            }));
+        }
+
+        if let Some(hook) = config.on_failure.clone().filter(|hook| !hook.report) {
+            sinks.push(Box::new(HookSink { hook }));
+        }
+
+        for sink in &config.sinks {
+            sinks.push(sink.sink(&config.digest)?);
+        }
+
+        let (exit_sender, exit_receiver) = oneshot::channel();
+        tasks.spawn(sink_loop(
+            sinks,
+            stdout_lines,
+            stderr_lines,
+            stdin_lines,
+            exit_receiver,
         ));
-    }
 
-    let heartbeat = cli.heartbeat().map(|config| {
-        let token = CancellationToken::new();
-        tasks.spawn(heartbeat_loop(config, token.clone()));
-        token
-    });
+        let upgrade_signal = match config.pid_file {
+            Some(_) => Some(upgrade::signal_stream()?),
+            None => None,
+        };
+
+        // If the child was stopped when a terminating signal was received, the
+        // wrapper exits as if the child had been terminated by that signal,
+        // whatever its actual exit status.
+        let mut stopped_at_shutdown = None;
+        // Whether the child exited after the wrapper was asked to terminate,
+        // in which case it is not restarted.
+        let mut terminated = false;
+
+        let exit_status =
+            match forward_signals_and_wait(child, upgrade_signal, &config, &heartbeat_request)
+                .await?
+            {
+                ChildExit::Exited(exit_status) => exit_status,
+                ChildExit::Terminated(exit_status) => {
+                    terminated = true;
+                    exit_status
+                }
+                ChildExit::StoppedAtShutdown(exit_status, signal) => {
+                    terminated = true;
+                    stopped_at_shutdown = Some((signal, exit_status));
+                    ExitStatus::from_raw(signal as i32)
+                }
+                ChildExit::Upgrade(child) => {
+                    let state = UpgradeState {
+                        pid: child.id().ok_or("child process has no PID")?,
+                        stdout: upgrade::inheritable(stdout.fd)?,
+                        stderr: upgrade::inheritable(stderr.fd)?,
+                        digest: config.digest.clone(),
+                    };
 
-    tasks.spawn(log_loop(log, log_stdout, log_stderr));
+                    debug!("upgrading wrapper process");
 
-    let error_message = if error.is_some() {
-        let (sender, receiver) = oneshot::channel();
-        tasks.spawn(error_message_loop(sender, error_stdout, error_stderr));
-        Some(receiver)
-    } else {
-        None
+                    // Stop reading from the child's pipes. Any lines that were already
+                    // read are sent as logs before the wrapper is re-executed.
+                    pipes.cancel();
+
+                    if let Some(heartbeat) = heartbeat {
+                        heartbeat.cancel();
+                    }
+
+                    exited.cancel();
+
+                    if let Some(usage) = usage {
+                        usage.cancel();
+                    }
+
+                    // The pipe to the child's standard input cannot be passed
+                    // to the re-executed wrapper process, so it is closed.
+                    stdin_pipe.cancel();
+
+                    // The child does not exit, so the sinks are dropped once its
+                    // pipes are closed.
+                    drop(exit_sender);
+
+                    tasks.close();
+                    tasks.wait().await;
+
+                    let err = state.exec();
+                    return Err(format!("could not re-execute wrapper process: {err}").into());
+                }
+                ChildExit::Quit(child) => {
+                    StateDump {
+                        started,
+                        child_pid: child.id(),
+                        tasks: tasks.len(),
+                        log_origin,
+                    }
+                    .write(config.dump_file.as_deref());
+
+                    // Exit without waiting for the pending tasks, as they may be the
+                    // reason for the dump. The child process is terminated, as it
+                    // would be if the wrapper was terminated by the signal, and
+                    // continued if it is stopped, so that it handles the signal.
+                    if let Some(id) = child.id() {
+                        let pid = Pid::from_raw(id.try_into().expect("Invalid PID"));
+                        let stopped = child.is_stopped();
+
+                        if let Err(err) = kill(pid, Signal::SIGTERM) {
+                            debug!("error terminating child: {}", err);
+                        }
+
+                        if stopped {
+                            if let Err(err) = kill(pid, Signal::SIGCONT) {
+                                debug!("error continuing child: {}", err);
+                            }
+                        }
+                    }
+
+                    return Ok(128 + Signal::SIGQUIT as i32);
+                }
+            };
+
+        debug!("command exited with: {}", exit_status);
+
+        lingers.extend(config.linger.map(|linger| {
+            // This task is not tracked, so that waiting for the tracked tasks
+            // below does not wait for the deadline when the output is closed
+            // before it.
+            let pipes = pipes.clone();
+            tokio::spawn(async move {
+                SystemTimestamp.sleep(linger).await;
+                debug!("stopped reading output after lingering for {:?}", linger);
+                pipes.cancel();
+            })
+        }));
+
+        if let Some(usage) = usage {
+            usage.cancel();
+        }
+
+        let failure = config.failure_codes.is_failure(&exit_status);
+
+        let exit = Exit {
+            status: exit_status,
+            failure,
+            stopped_at_shutdown,
+        };
+
+        if exit_sender.send(exit).is_err() {
+            debug!("error sending exit to sinks");
+        }
+
+        stdin_pipe.cancel();
+
+        let policy = match config.restart {
+            Some(policy) if failure && !terminated && restarts < policy.max_restarts => policy,
+            _ => break exit_status,
+        };
+
+        restarts += 1;
+        warn!(
+            "command failed; restarting it in {:?} (restart {} of {})",
+            policy.backoff, restarts, policy.max_restarts
+        );
+
+        if let Some(signal) =
+            wait_before_restart(policy.backoff, config.shutdown_file.as_deref()).await?
+        {
+            debug!("received terminating signal before restart: {}", signal);
+            break exit_status;
+        }
     };
 
-    let exit_status = forward_signals_and_wait(child).await?;
+    if let Some(metrics) = metrics.as_ref() {
+        // For a child process inherited from an upgrade, this is the
+        // duration since the upgrade.
+        tasks.spawn(delivery::send(
+            DataType::Metrics,
+            metrics.request_from_exit(started.elapsed(), &exit_status),
+        ));
+    }
 
-    debug!("command exited with: {}", exit_status);
+    if let Some(trace) = trace.as_ref() {
+        tasks.spawn(delivery::send(
+            DataType::Trace,
+            trace.request_from_exit(
+                SystemTimestamp.now(),
+                started.elapsed(),
+                &exit_status,
+                config.failure_codes.is_failure(&exit_status),
+            ),
+        ));
+    }
 
-    if exit_status.success() {
+    if config.is_cron_success(&exit_status) {
         if let Some(cron) = cron.as_ref() {
-            tasks.spawn(send_request(
-                cron.request(&mut SystemTimestamp, CronKind::Finish),
-            ));
+            let finish = cron.request(&mut SystemTimestamp, CronKind::Finish);
+            let start = cron_start.take();
+
+            tasks.spawn(async move {
+                if let Some(start) = start {
+                    if !start.await.unwrap_or(false) {
+                        debug!("not sending cron finish check-in without a start check-in");
+                        return;
+                    }
+                }
+
+                delivery::send(DataType::CheckIn, finish).await;
+            });
         }
-    } else if let Some(error) = error {
-        tasks.spawn(send_error_exit_request(
-            error,
-            exit_status,
-            error_message.unwrap(),
-        ));
     }
 
     if let Some(heartbeat) = heartbeat {
         heartbeat.cancel();
     }
 
+    exited.cancel();
+
     tasks.close();
 
     if !tasks.is_empty() {
@@ -152,6 +889,14 @@ async fn start(cli: Cli) -> Result<i32, Box<dyn std::error::Error>> {
         // for reference.
         let mut signals = signal_stream()?;
 
+        let flush_timeout = async {
+            match config.flush_timeout {
+                Some(timeout) => SystemTimestamp.sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(flush_timeout);
+
         loop {
             select! {
                 biased;
@@ -160,6 +905,16 @@ async fn start(cli: Cli) -> Result<i32, Box<dyn std::error::Error>> {
                     break;
                 }
 
+                _ = &mut flush_timeout => {
+                    warn!(
+                        "stopped waiting for {} tasks to complete after {:?}",
+                        tasks.len(),
+                        config.flush_timeout.unwrap_or_default()
+                    );
+                    audit.ignore_tasks();
+                    break;
+                }
+
                 Some(signal) = signals.next() => {
                     if has_terminating_intent(&signal) {
                         debug!("received terminating signal after child: {}", signal);
@@ -169,138 +924,1205 @@ async fn start(cli: Cli) -> Result<i32, Box<dyn std::error::Error>> {
                     }
                 }
             }
-        }
-    }
+        }
+    }
+
+    for linger in lingers {
+        linger.abort();
+    }
+
+    drop(pid_file);
+
+    let skipped_writes = dump::get(&STATS.skipped_output_writes);
+    if skipped_writes > 0 {
+        warn!(
+            "skipped {} writes of the command's output that could not be written",
+            skipped_writes
+        );
+    }
+
+    for leak in audit.check().await {
+        warn!("resource not released on exit: {}", leak);
+        summary::leak(leak);
+    }
+
+    if config.summary {
+        eprint!("{}", summary::render());
+    }
+
+    if let Some(code) = exit_status.code() {
+        Ok(code)
+    } else {
+        match exit_status.signal() {
+            Some(signal) => Ok(128 + signal),
+            None => Err("command exited without code or signal".into()),
+        }
+    }
+}
+
+// How many lines of the command's output each stage that the lines pass
+// through holds before the stage that sends lines to it waits for it, unless
+// it is sized from the memory budget with `--max-wrapper-memory`. When
+// the lines are not processed as fast as the command writes them, reading
+// its output waits too, instead of holding an ever-growing number of lines
+// in memory. No lines are dropped: the stages only do CPU-bound work, and
+// the lines sent as logs are dropped by the log loop if it falls behind.
+const LINES_BUFFER: usize = 1024;
+
+// One of the child's output streams. The file descriptor is kept in order
+// to pass the pipe to the re-executed wrapper process on upgrade.
+#[derive(Default)]
+struct ChildPipe {
+    fd: Option<RawFd>,
+    receiver: Option<Receiver<String>>,
+}
+
+impl ChildPipe {
+    fn spawn(
+        tasks: &TaskTracker,
+        cancel: &CancellationToken,
+        from: impl AsyncRead + AsRawFd + Unpin + Send + 'static,
+        to: impl Write + Send + 'static,
+        lines: &'static AtomicUsize,
+        config: &WrapConfig,
+    ) -> Self {
+        let fd = from.as_raw_fd();
+        let (sender, receiver) = channel(config.lines_buffer.unwrap_or(LINES_BUFFER));
+
+        if config.unbuffered {
+            tasks.spawn(pipe_chunks(
+                from,
+                to,
+                sender,
+                cancel.clone(),
+                lines,
+                config.on_passthrough_error,
+                config.max_line_bytes,
+            ));
+        } else {
+            tasks.spawn(pipe_lines(
+                from,
+                to,
+                sender,
+                cancel.clone(),
+                lines,
+                config.on_passthrough_error,
+                config.max_line_bytes,
+            ));
+        }
+
+        Self {
+            fd: Some(fd),
+            receiver: Some(receiver),
+        }
+    }
+}
+
+type SpawnedChild = (ChildProcess, ChildPipe, ChildPipe);
+
+// Reports an error with the given message, or with the contents of
+// standard input if no message is given.
+#[tokio::main]
+async fn report_error(args: cli::ErrorArgs) -> Result<i32, Box<dyn std::error::Error>> {
+    let message = match args.message.as_ref() {
+        Some(message) => message.clone(),
+        None => {
+            let mut message = String::new();
+            tokio::io::stdin().read_to_string(&mut message).await?;
+            message.trim_end().to_string()
+        }
+    };
+
+    let error = args.error();
+    delivery::send(
+        DataType::Error,
+        error.request_from_message(&mut SystemTimestamp, &args.name, &message),
+    )
+    .await;
+
+    Ok(0)
+}
+
+// Reports an error as if the command had failed with the given exit status,
+// sending a start cron check-in without a finish cron check-in.
+#[tokio::main]
+async fn simulate_failure(
+    args: cli::SimulateFailureArgs,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    if let Some(cron) = args.cron() {
+        delivery::send(
+            DataType::CheckIn,
+            cron.request(&mut SystemTimestamp, CronKind::Start),
+        )
+        .await;
+    }
+
+    let lines = vec!["[Simulated failure]".to_string()];
+    let error = args.error();
+    delivery::send(
+        DataType::Error,
+        error.request_from_exit(&mut SystemTimestamp, &args.exit_status(), lines),
+    )
+    .await;
+
+    Ok(0)
+}
+
+// Runs the reference and candidate commands, reporting an error if they
+// diverge, and exits with the reference's exit code.
+#[tokio::main]
+async fn canary(args: cli::CanaryArgs) -> Result<i32, Box<dyn std::error::Error>> {
+    let (mut reference, mut candidate) = canary::split_commands(&args.commands)?;
+
+    if let Some(policy) = Policy::load(Path::new(POLICY_PATH))? {
+        policy.check_argv(&mut reference, None)?;
+        policy.check_argv(&mut candidate, None)?;
+    }
+
+    let (reference_run, candidate_run) = if args.parallel {
+        tokio::join!(
+            canary::run(&reference, true),
+            canary::run(&candidate, false)
+        )
+    } else {
+        let reference_run = canary::run(&reference, true).await;
+        (reference_run, canary::run(&candidate, false).await)
+    };
+
+    let reference_run =
+        reference_run.map_err(|err| format!("could not run the reference command: {err}"))?;
+    let candidate_run = candidate_run.map_err(|err| err.to_string());
+
+    let mut message = canary::differences(&reference_run, &candidate_run, args.compare_output);
+    if !message.is_empty() {
+        warn!(
+            "the candidate command diverged from the reference: {}",
+            message.join("; ")
+        );
+
+        if let Some(candidate) = candidate_run
+            .as_ref()
+            .ok()
+            .filter(|candidate| !candidate.lines.is_empty())
+        {
+            message.push("[Last lines of the candidate's output]".to_string());
+            message.extend(candidate.lines.iter().cloned());
+        }
+
+        let error = args.error(canary::tags(&reference_run, &candidate_run));
+        delivery::send(
+            DataType::Error,
+            error.request_from_message(&mut SystemTimestamp, "CanaryMismatch", &message.join("\n")),
+        )
+        .await;
+    }
+
+    match (reference_run.exit.code(), reference_run.exit.signal()) {
+        (Some(code), _) => Ok(code),
+        (_, Some(signal)) => Ok(128 + signal),
+        _ => Err("reference command exited without code or signal".into()),
+    }
+}
+
+// Runs the jobs in the jobs directory until a terminating signal is
+// received.
+#[tokio::main]
+async fn run_agent(args: cli::AgentArgs) -> Result<i32, Box<dyn std::error::Error>> {
+    let options = agent::ScheduleOptions::new(args.splay, args.skip_if_missed);
+    agent::run(&args.jobs_dir, options, SystemTimestamp).await
+}
+
+// Checks whether data can be sent to AppSignal, printing a report of the
+// checks.
+#[tokio::main]
+async fn diagnose(args: cli::DiagnoseArgs) -> Result<i32, Box<dyn std::error::Error>> {
+    let report = diagnose::run(&args.endpoint, &args.api_key).await;
+    println!("{report}");
+
+    Ok(if report.is_ok() { 0 } else { 1 })
+}
+
+// Sends the recorded requests again, one at a time, in the order they were
+// recorded, printing the outcome of each.
+#[tokio::main]
+async fn replay(args: cli::ReplayArgs) -> Result<i32, Box<dyn std::error::Error>> {
+    let requests = record::read(&args.dir)?;
+    let max_age = args.max_age.unwrap_or(record::DEFAULT_REPLAY_MAX_AGE);
+    let now = chrono::Utc::now();
+    let mut failed = 0;
+    let mut skipped = 0;
+
+    for (path, recorded) in &requests {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        match recorded.age(now) {
+            Ok(age) if age > max_age => {
+                println!(
+                    "{name}: skipped, as it was recorded {}s ago, longer ago than {}s",
+                    age.as_secs(),
+                    max_age.as_secs()
+                );
+                skipped += 1;
+                continue;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                println!("{name}: {err}");
+                failed += 1;
+                continue;
+            }
+        }
+
+        let mut recorded = recorded.clone();
+        recorded.mark_replayed();
+
+        let request = match recorded.request(&args.api_key, args.endpoint.as_deref()) {
+            Ok(request) => request,
+            Err(err) => {
+                println!("{name}: {err}");
+                failed += 1;
+                continue;
+            }
+        };
+
+        let target = format!("{} {}", request.method(), request.url().path());
+        match client::send_request(request).await {
+            client::Outcome::Success => println!("{name}: {target}: sent"),
+            client::Outcome::Failure(failure) | client::Outcome::Retryable(failure) => {
+                println!("{name}: {target}: {}", failure.describe());
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "Replayed {} requests, of which {} failed, and skipped {} requests",
+        requests.len() - skipped,
+        failed,
+        skipped
+    );
+    Ok(if failed == 0 { 0 } else { 1 })
+}
+
+// Serves a fake AppSignal endpoint until a terminating signal is received.
+#[cfg(feature = "fake-endpoint")]
+#[tokio::main]
+async fn run_fake_endpoint(args: cli::FakeEndpointArgs) -> Result<i32, Box<dyn std::error::Error>> {
+    fake_endpoint::run(args.port, args.record).await
+}
+
+// Sends the lines read from standard input as logs, until standard input
+// is closed or a terminating signal is received.
+#[tokio::main]
+async fn log_stdin(log: LogConfig) -> Result<i32, Box<dyn std::error::Error>> {
+    let cancel = CancellationToken::new();
+    let (sender, receiver) = channel(LINES_BUFFER);
+
+    tokio::spawn(pipe_lines(
+        read_stdin_chunks(),
+        io::sink(),
+        sender,
+        cancel.clone(),
+        &STATS.stdout_lines,
+        PassthroughErrorAction::Stop,
+        None,
+    ));
+
+    let mut signals = signal_stream()?;
+    let log_loop = log_loop(log, Some(receiver), None, None, None, SystemTimestamp);
+    tokio::pin!(log_loop);
+
+    let mut code = 0;
+
+    loop {
+        select! {
+            _ = &mut log_loop => break,
+
+            Some(signal) = signals.next() => {
+                if has_terminating_intent(&signal) {
+                    // Stop reading from standard input, and send the lines
+                    // that were already read before exiting.
+                    debug!("received terminating signal: {}", signal);
+                    cancel.cancel();
+                    code = 128 + signal as i32;
+                } else {
+                    trace!("ignoring non-terminating signal: {}", signal);
+                }
+            }
+        }
+    }
+
+    Ok(code)
+}
+
+// Reads lines from standard input, with `--stdin`, as if they were the
+// output of a command, for use at the end of a shell pipeline. Once
+// standard input is closed, the input is treated as a command that finished
+// successfully.
+async fn read_pipe(cli: &Cli) -> Result<i32, Box<dyn std::error::Error>> {
+    let tasks = TaskTracker::new();
+    let cron = cli.cron();
+
+    if let Some(cron) = cron.as_ref() {
+        tasks.spawn(delivery::send(
+            DataType::CheckIn,
+            cron.request(&mut SystemTimestamp, CronKind::Start),
+        ));
+    }
+
+    let heartbeat = cli.heartbeat().map(|config| {
+        let token = CancellationToken::new();
+        tasks.spawn(heartbeat_loop(
+            config,
+            None,
+            Arc::new(Notify::new()),
+            token.clone(),
+            SystemTimestamp,
+        ));
+        token
+    });
+
+    let cancel = CancellationToken::new();
+    let (sender, mut receiver) = channel(cli.lines_buffer().unwrap_or(LINES_BUFFER));
+
+    tasks.spawn(pipe_lines(
+        read_stdin_chunks(),
+        stdout(),
+        sender,
+        cancel.clone(),
+        &STATS.stdout_lines,
+        cli.on_passthrough_error,
+        cli.max_line_bytes(),
+    ));
+
+    let lines = async move {
+        match cli.pipe_log() {
+            Some(log) => log_loop(log, Some(receiver), None, None, None, SystemTimestamp).await,
+            None => while receiver.recv().await.is_some() {},
+        }
+    };
+    tokio::pin!(lines);
+
+    let mut signals = signal_stream()?;
+    let mut code = 0;
+
+    loop {
+        select! {
+            _ = &mut lines => break,
+
+            Some(signal) = signals.next() => {
+                if has_terminating_intent(&signal) {
+                    // Stop reading from standard input, and send the lines
+                    // that were already read before exiting.
+                    debug!("received terminating signal: {}", signal);
+                    cancel.cancel();
+                    code = 128 + signal as i32;
+                } else {
+                    trace!("ignoring non-terminating signal: {}", signal);
+                }
+            }
+        }
+    }
+
+    if let Some(heartbeat) = heartbeat {
+        heartbeat.cancel();
+    }
+
+    // The input was only read in full if it was not interrupted.
+    if code == 0 {
+        if let Some(cron) = cron.as_ref() {
+            tasks.spawn(delivery::send(
+                DataType::CheckIn,
+                cron.request(&mut SystemTimestamp, CronKind::Finish),
+            ));
+        }
+    }
+
+    tasks.close();
+    tasks.wait().await;
+
+    Ok(code)
+}
+
+fn spawn_child(
+    config: &WrapConfig,
+    tasks: &TaskTracker,
+    cancel: &CancellationToken,
+) -> io::Result<SpawnedChild> {
+    let mut child = command(config).spawn()?;
+
+    let stdout = if config.stdout {
+        ChildPipe::spawn(
+            tasks,
+            cancel,
+            child.stdout.take().unwrap(),
+            stdout(),
+            &STATS.stdout_lines,
+            config,
+        )
+    } else {
+        ChildPipe::default()
+    };
+
+    let stderr = if config.stderr {
+        ChildPipe::spawn(
+            tasks,
+            cancel,
+            child.stderr.take().unwrap(),
+            stderr(),
+            &STATS.stderr_lines,
+            config,
+        )
+    } else {
+        ChildPipe::default()
+    };
+
+    Ok((ChildProcess::Spawned(child), stdout, stderr))
+}
+
+fn inherit_child(
+    state: &UpgradeState,
+    config: &WrapConfig,
+    tasks: &TaskTracker,
+    cancel: &CancellationToken,
+) -> io::Result<SpawnedChild> {
+    let stdout = match state.stdout() {
+        Some(fd) => ChildPipe::spawn(
+            tasks,
+            cancel,
+            pipe::Receiver::from_owned_fd(fd)?,
+            stdout(),
+            &STATS.stdout_lines,
+            config,
+        ),
+        None => ChildPipe::default(),
+    };
+
+    let stderr = match state.stderr() {
+        Some(fd) => ChildPipe::spawn(
+            tasks,
+            cancel,
+            pipe::Receiver::from_owned_fd(fd)?,
+            stderr(),
+            &STATS.stderr_lines,
+            config,
+        ),
+        None => ChildPipe::default(),
+    };
+
+    Ok((ChildProcess::inherited(state.pid), stdout, stderr))
+}
+
+// Pipes lines from an asynchronous reader to a synchronous writer, sending
+// each line to the given channel sender as it is written, until the reader
+// is closed or the cancellation token is cancelled. Unless told to stop, a
+// line that cannot be written is skipped, and still sent.
+//
+// The reader is read in fixed-size chunks, so that cancellation is not held
+// up while a very long line is being read. A line longer than the maximum
+// size is written and sent in parts, without adding line endings to the
+// output.
+async fn pipe_lines(
+    mut from: impl AsyncRead + Unpin + Send + 'static,
+    mut to: impl Write + Send + 'static,
+    sender: Sender<String>,
+    cancel: CancellationToken,
+    lines: &'static AtomicUsize,
+    on_error: PassthroughErrorAction,
+    max_line_bytes: Option<usize>,
+) {
+    let mut buffer = vec![0; READ_CHUNK_SIZE];
+    let mut splitter = LineSplitter::new(max_line_bytes);
+
+    'read: loop {
+        let read = select! {
+            _ = cancel.cancelled() => None,
+            read = from.read(&mut buffer) => Some(read),
+        };
+
+        let (complete, closed) = match read {
+            // The start of the line being read is not read again, such as by
+            // the wrapper process that the pipe is passed to on upgrade, so
+            // it is written and sent without waiting for its end.
+            None => (splitter.flush().into_iter().collect(), true),
+            Some(Ok(0)) => (splitter.finish().into_iter().collect(), true),
+            Some(Ok(read)) => (splitter.push(&buffer[..read]), false),
+            Some(Err(err)) => {
+                debug!("error reading line: {}", err);
+                break;
+            }
+        };
+
+        for line in complete {
+            let written = match line.end {
+                true => writeln!(to, "{}", line.text),
+                false => write!(to, "{}", line.text),
+            };
+
+            if let Err(err) = written {
+                if on_error == PassthroughErrorAction::Stop {
+                    debug!("error writing line: {}", err);
+                    break 'read;
+                }
+
+                skip_write(err);
+            }
+
+            if let Err(err) = sender.send(line.text).await {
+                debug!("error sending line: {}", err);
+                break 'read;
+            };
+
+            dump::increment(lines);
+        }
+
+        if closed {
+            // The output is flushed so that the start of a line is not lost
+            // if the wrapper is re-executed.
+            if let Err(err) = to.flush() {
+                debug!("error flushing output: {}", err);
+            }
+
+            break;
+        }
+    }
+}
+
+// Counts a write of the command's output that failed and was skipped,
+// warning about the first one, as the rest are likely to fail the same way.
+fn skip_write(err: io::Error) {
+    if STATS.skipped_output_writes.fetch_add(1, Ordering::Relaxed) == 0 {
+        warn!("could not write the command's output: {}; skipping it", err);
+    }
+}
+
+// How many lines, or chunks, read from the wrapper's standard input to hold
+// while they are not being written, before reading more.
+const STDIN_LINES_BUFFER: usize = 16;
+
+// Reads lines from the wrapper's standard input in a separate thread, as
+// reads from it cannot be cancelled. This way, a wrapper whose standard
+// input is never closed, such as a terminal, can still exit when the
+// command does.
+fn read_stdin() -> Receiver<Vec<u8>> {
+    let (sender, receiver) = channel(STDIN_LINES_BUFFER);
+
+    std::thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+
+        loop {
+            let mut line = Vec::new();
+
+            match stdin.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    if sender.blocking_send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    debug!("error reading standard input: {}", err);
+                    break;
+                }
+            }
+        }
+    });
+
+    receiver
+}
+
+// Reads the wrapper's standard input in chunks in a separate thread, like
+// `read_stdin`, for the lines to be read by `pipe_lines`. This way, the
+// wrapper can exit on a terminating signal while its standard input is
+// still open, such as a pipe whose writer has not exited yet.
+fn read_stdin_chunks() -> impl AsyncRead + Unpin + Send + 'static {
+    let (sender, receiver) = channel(STDIN_LINES_BUFFER);
+
+    std::thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        let mut buffer = vec![0; READ_CHUNK_SIZE];
+
+        loop {
+            let chunk = match io::Read::read(&mut stdin, &mut buffer) {
+                Ok(0) => break,
+                Ok(read) => Ok(Cursor::new(buffer[..read].to_vec())),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => Err(err),
+            };
+
+            let failed = chunk.is_err();
+            if sender.blocking_send(chunk).is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    StreamReader::new(ReceiverStream::new(receiver))
+}
+
+// Writes the lines read from the wrapper's standard input to the command's
+// standard input, sending each line to the given channel sender as it is
+// written, until either is closed or the cancellation token is cancelled.
+async fn pipe_stdin(
+    mut from: Receiver<Vec<u8>>,
+    mut to: ChildStdin,
+    sender: Sender<String>,
+    cancel: CancellationToken,
+) {
+    loop {
+        let line = select! {
+            _ = cancel.cancelled() => break,
+            line = from.recv() => line,
+        };
+
+        let Some(line) = line else {
+            break;
+        };
+
+        select! {
+            _ = cancel.cancelled() => break,
+            result = to.write_all(&line) => {
+                if let Err(err) = result {
+                    debug!("error writing to standard input: {}", err);
+                    break;
+                }
+            }
+        }
+
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches(['\n', '\r']);
+
+        // Lines are only sent as logs when logs are enabled, but they are
+        // written to the command either way.
+        let _ = sender.send(line.to_string()).await;
+    }
+}
+
+// Escape sequences are stripped before redacting, so that they cannot split
+// a secret in a way that the redaction patterns do not match.
+fn maybe_spawn_sanitize(
+    lines: Option<Receiver<String>>,
+    strip_ansi: bool,
+    redactor: &Redactor,
+    buffer: usize,
+) -> Option<Receiver<String>> {
+    let redactor = redactor.clone();
+
+    maybe_spawn_map(lines, buffer, move |line| {
+        let line = if strip_ansi { ansi::strip(line) } else { line };
+        redactor.redact(line)
+    })
+}
+
+// How often to check for jumps in the system clock, in order to send a
+// heartbeat check-in immediately after the system resumes from sleep.
+const CLOCK_JUMP_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// Sends the resource usage of the command as metrics, once every
+// `RESOURCE_USAGE_INTERVAL`, until cancelled.
+async fn resource_usage_loop(
+    config: MetricsConfig,
+    pid: u32,
+    cancel: CancellationToken,
+    clock: impl Clock,
+) {
+    let mut interval = clock.interval_at(
+        clock.instant() + RESOURCE_USAGE_INTERVAL,
+        RESOURCE_USAGE_INTERVAL,
+    );
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        select!(
+            _ = cancel.cancelled() => break,
+            _ = interval.tick() => {
+                match ResourceUsage::read(pid) {
+                    Ok(usage) => delivery::send(DataType::Metrics, config.request_from_usage(&usage)).await,
+                    Err(err) => {
+                        debug!("error reading resource usage: {}", err);
+                        break;
+                    }
+                }
+            }
+        );
+    }
+}
+
+async fn heartbeat_loop(
+    config: HeartbeatConfig,
+    activity: Option<Arc<Activity>>,
+    requested: Arc<Notify>,
+    cancel: CancellationToken,
+    mut clock: impl Clock,
+) {
+    let mut interval = clock.interval(config.interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut clock_jumps = ClockJumpDetector::new(clock.clone());
+    let mut clock_jump_interval = clock.interval_at(
+        clock.instant() + CLOCK_JUMP_CHECK_INTERVAL,
+        CLOCK_JUMP_CHECK_INTERVAL,
+    );
+    clock_jump_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    clock_jumps.check();
+
+    // Ensure at least one heartbeat is sent, if the command is healthy.
+    if config.is_healthy().await {
+        delivery::send(DataType::CheckIn, config.request(&mut clock)).await;
+    }
+    let mut last_sent = clock.instant();
+    interval.tick().await;
+
+    // After the first heartbeat, a heartbeat is only sent while the command
+    // is idle if `activity` is not set.
+    let is_active = || match activity.as_ref() {
+        Some(activity) => {
+            let active = activity.take();
+            if !active {
+                debug!("no activity since last heartbeat, skipping heartbeat");
+            }
+            active
+        }
+        None => true,
+    };
+
+    // After a heartbeat has been sent, cancel immediately on request, without
+    // waiting for the next scheduled heartbeat.
+    loop {
+        select!(
+            _ = cancel.cancelled() => break,
+            // A requested heartbeat is sent whether the command is active or
+            // not, and does not delay the next scheduled one.
+            _ = requested.notified() => {
+                debug!("sending requested heartbeat");
+                delivery::send(DataType::CheckIn, config.request(&mut clock)).await;
+                last_sent = clock.instant();
+            }
+            _ = interval.tick() => {
+                if is_active() && config.is_healthy().await {
+                    delivery::send(DataType::CheckIn, config.request(&mut clock)).await;
+                    last_sent = clock.instant();
+                }
+            }
+            _ = clock_jump_interval.tick() => {
+                if let Some(ClockJump::Forward(jump)) = clock_jumps.check() {
+                    debug!("system clock jumped forward by {}s, sending heartbeat", jump.as_secs());
+                    if is_active() && config.is_healthy().await {
+                        delivery::send(DataType::CheckIn, config.request(&mut clock)).await;
+                        last_sent = clock.instant();
+                    }
+                    interval.reset();
+                }
+            }
+        );
+    }
+
+    // When using a longer interval than the default, the last heartbeat may
+    // have been sent a long time before the process exited.
+    if clock.instant() - last_sent > DEFAULT_HEARTBEAT_INTERVAL
+        && is_active()
+        && config.is_healthy().await
+    {
+        delivery::send(DataType::CheckIn, config.request(&mut clock)).await;
+    }
+}
+
+fn maybe_spawn_activity(
+    lines: Option<Receiver<String>>,
+    activity: &Arc<Activity>,
+    buffer: usize,
+    tasks: &TaskTracker,
+) -> Option<Receiver<String>> {
+    lines.map(|lines| {
+        let (sender, receiver) = channel(buffer);
+        tasks.spawn(activity_loop(activity.clone(), lines, sender));
+        receiver
+    })
+}
+
+// Observes the lines received from the command's output as activity, and
+// forwards them to the given sender.
+async fn activity_loop(
+    activity: Arc<Activity>,
+    mut lines: Receiver<String>,
+    sender: Sender<String>,
+) {
+    while let Some(line) = lines.recv().await {
+        activity.observe(&line);
+
+        // The lines may only be captured to detect activity, in which case
+        // there is no receiver for them.
+        let _ = sender.send(line).await;
+    }
+}
+
+// Detects whether the command has stalled, when it writes no output for
+// the timeout, to report it with the last lines of its output.
+struct Stall {
+    timeout: Duration,
+    error: Option<ErrorConfig>,
+    // The PID of the command, if it is killed when it stalls.
+    kill: Option<u32>,
+    lines: MessageLines,
+}
+
+impl Stall {
+    fn report(&self, tasks: &TaskTracker, clock: &mut impl Clock) {
+        warn!(
+            "command wrote no output for {:?}; it may have stalled",
+            self.timeout
+        );
+
+        if let Some(error) = self.error.as_ref() {
+            tasks.spawn(delivery::send(
+                DataType::Error,
+                error.request_from_stall(clock, self.timeout, self.lines.to_vec()),
+            ));
+        }
+
+        if let Some(id) = self.kill {
+            warn!("killing stalled command");
+
+            let pid = Pid::from_raw(id.try_into().expect("Invalid PID"));
+            if let Err(err) = kill(pid, Signal::SIGKILL) {
+                debug!("error killing child: {}", err);
+            }
+        }
+    }
+}
+
+fn spawn_stall(
+    stall: Stall,
+    stdout: Option<Receiver<String>>,
+    stderr: Option<Receiver<String>>,
+    buffer: usize,
+    tasks: &TaskTracker,
+    clock: impl Clock,
+) -> (Option<Receiver<String>>, Option<Receiver<String>>) {
+    let (stdout_sender, stdout_lines) = channel(buffer);
+    let (stderr_sender, stderr_lines) = channel(buffer);
+    let (stdout_lines, stderr_lines) = (
+        stdout.is_some().then_some(stdout_lines),
+        stderr.is_some().then_some(stderr_lines),
+    );
+
+    tasks.spawn(stall_loop(
+        stall,
+        stdout,
+        stderr,
+        stdout_sender,
+        stderr_sender,
+        clock,
+    ));
+    (stdout_lines, stderr_lines)
+}
+
+// Forwards the lines received from the command's output to the given
+// senders, reporting the command as stalled when no line is received for
+// the timeout. A stall is only reported again once the command writes more
+// output.
+async fn stall_loop(
+    mut stall: Stall,
+    mut stdout: Option<Receiver<String>>,
+    mut stderr: Option<Receiver<String>>,
+    stdout_sender: Sender<String>,
+    stderr_sender: Sender<String>,
+    mut clock: impl Clock,
+) {
+    let tasks = TaskTracker::new();
+    let deadline = clock.sleep(stall.timeout);
+    tokio::pin!(deadline);
+    let mut stalled = false;
+
+    loop {
+        select! {
+            Some(maybe_line) = maybe_recv(&mut stdout) => {
+                match maybe_line {
+                    None => {
+                        stdout = None;
+                        if stderr.is_none() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Some(line) => {
+                        stall.lines.push(line.clone());
+                        let _ = stdout_sender.send(line).await;
+                    }
+                }
+            }
+
+            Some(maybe_line) = maybe_recv(&mut stderr) => {
+                match maybe_line {
+                    None => {
+                        stderr = None;
+                        if stdout.is_none() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Some(line) => {
+                        stall.lines.push(line.clone());
+                        let _ = stderr_sender.send(line).await;
+                    }
+                }
+            }
 
-    if let Some(code) = exit_status.code() {
-        Ok(code)
-    } else {
-        match exit_status.signal() {
-            Some(signal) => Ok(128 + signal),
-            None => Err("command exited without code or signal".into()),
+            _ = &mut deadline, if !stalled => {
+                stall.report(&tasks, &mut clock);
+                stalled = true;
+                continue;
+            }
+
+            else => break
         }
+
+        stalled = false;
+        deadline.as_mut().reset(clock.instant() + stall.timeout);
     }
+
+    tasks.close();
+    tasks.wait().await;
 }
 
-type SpawnedChild = (
-    Child,
-    Option<UnboundedReceiver<String>>,
-    Option<UnboundedReceiver<String>>,
-);
+fn maybe_spawn_ready(
+    lines: Option<Receiver<String>>,
+    pattern: &Pattern,
+    ready: &CancellationToken,
+    buffer: usize,
+    tasks: &TaskTracker,
+) -> Option<Receiver<String>> {
+    lines.map(|lines| {
+        let (sender, receiver) = channel(buffer);
+        tasks.spawn(ready_loop(pattern.clone(), ready.clone(), lines, sender));
+        receiver
+    })
+}
 
-fn spawn_child(cli: &Cli, tasks: &TaskTracker) -> io::Result<SpawnedChild> {
-    let should_stdout = cli.should_pipe_stdout();
-    let should_stderr = cli.should_pipe_stderr();
+// Cancels the ready token once a line matching the pattern is received
+// from the command's output, and forwards the lines to the given sender.
+async fn ready_loop(
+    pattern: Pattern,
+    ready: CancellationToken,
+    mut lines: Receiver<String>,
+    sender: Sender<String>,
+) {
+    while let Some(line) = lines.recv().await {
+        if !ready.is_cancelled() && pattern.0.is_match(&line) {
+            debug!("command is ready");
+            ready.cancel();
+        }
 
-    let mut child = command(&cli.command, should_stdout, should_stderr).spawn()?;
+        let _ = sender.send(line).await;
+    }
+}
 
-    let stdout = if should_stdout {
-        let (sender, receiver) = unbounded_channel();
-        tasks.spawn(pipe_lines(child.stdout.take().unwrap(), stdout(), sender));
-        Some(receiver)
-    } else {
-        None
+// Waits for the command to be ready, if it is waited for. Returns whether
+// it is ready, or `false` if the cancellation token is cancelled first.
+async fn wait_until_ready(ready: Option<CancellationToken>, cancel: &CancellationToken) -> bool {
+    let Some(ready) = ready else {
+        return true;
     };
 
-    let stderr = if should_stderr {
-        let (sender, receiver) = unbounded_channel();
-        tasks.spawn(pipe_lines(child.stderr.take().unwrap(), stderr(), sender));
-        Some(receiver)
-    } else {
-        None
-    };
+    select! {
+        biased;
 
-    Ok((child, stdout, stderr))
+        _ = ready.cancelled() => true,
+        _ = cancel.cancelled() => false,
+    }
 }
 
-// Pipes lines from an asynchronous reader to a synchronous writer, returning
-// a join handle for the task piping the lines, which must not be dropped for
-// the lifetime of the process, and a channel receiver that will receive each
-// line as it is written.
-async fn pipe_lines(
-    from: impl AsyncRead + Unpin + Send + 'static,
-    mut to: impl Write + Send + 'static,
-    sender: UnboundedSender<String>,
+// Reports an error if the command does not write a line matching the
+// pattern within the timeout, unless it exits first.
+async fn ready_timeout(
+    pattern: Pattern,
+    ready: CancellationToken,
+    exited: CancellationToken,
+    timeout: Duration,
+    error: Option<ErrorConfig>,
 ) {
-    let mut from = BufReader::new(from).lines();
+    select! {
+        _ = ready.cancelled() => return,
+        _ = exited.cancelled() => return,
+        _ = SystemTimestamp.sleep(timeout) => {}
+    }
+
+    warn!(
+        "command did not write a line matching the ready pattern within {:?}",
+        timeout
+    );
+
+    if let Some(error) = error {
+        let message = format!(
+            "[Process did not write a line matching `{}` within {}s]",
+            pattern.0.as_str(),
+            timeout.as_secs()
+        );
+
+        delivery::send(
+            DataType::Error,
+            error.request_from_message(&mut SystemTimestamp, "StartTimeoutError", &message),
+        )
+        .await;
+    }
+}
+
+const METRICS_BATCH_SIZE: usize = 100;
+
+// Sends the StatsD lines received from the command's standard output as
+// metrics, in batches, and forwards any other lines to the given sender.
+async fn statsd_loop(config: MetricsConfig, mut lines: Receiver<String>, sender: Sender<String>) {
+    let mut metrics = Vec::new();
+    let mut interval = interval(Duration::from_secs(10));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let tasks = TaskTracker::new();
 
     loop {
-        match from.next_line().await {
-            Ok(Some(line)) => {
-                if let Err(err) = writeln!(to, "{}", line) {
-                    debug!("error writing line: {}", err);
-                    break;
-                }
+        if metrics.len() >= METRICS_BATCH_SIZE {
+            let request = config.request_from_metrics(std::mem::take(&mut metrics));
+            tasks.spawn(delivery::send(DataType::Metrics, request));
+            interval.reset();
+        }
 
-                if let Err(err) = sender.send(line) {
-                    debug!("error sending line: {}", err);
+        select! {
+            maybe_line = lines.recv() => {
+                let Some(line) = maybe_line else {
                     break;
                 };
+
+                match statsd::parse_line(&line) {
+                    Some(metric) => metrics.push(metric),
+                    // The lines may not be used for logs or error messages,
+                    // in which case there is no receiver for them.
+                    None => {
+                        let _ = sender.send(line).await;
+                    }
+                }
             }
-            Ok(None) => break,
-            Err(err) => {
-                debug!("error reading line: {}", err);
-                break;
+
+            _ = interval.tick() => {
+                if !metrics.is_empty() {
+                    let request = config.request_from_metrics(std::mem::take(&mut metrics));
+                    tasks.spawn(delivery::send(DataType::Metrics, request));
+                }
             }
         }
     }
-}
 
-async fn heartbeat_loop(config: HeartbeatConfig, cancel: CancellationToken) {
-    let mut interval = interval(Duration::from_secs(30));
-    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    if !metrics.is_empty() {
+        tasks.spawn(delivery::send(
+            DataType::Metrics,
+            config.request_from_metrics(metrics),
+        ));
+    }
 
-    // Ensure at least one heartbeat is sent.
-    send_request(config.request(&mut SystemTimestamp)).await;
-    interval.tick().await;
+    tasks.close();
+    tasks.wait().await;
+}
 
-    // After a heartbeat has been sent, cancel immediately on request, without
-    // waiting for the next scheduled heartbeat.
-    loop {
-        select!(
+// Like `pipe_lines`, but writes the output as soon as it is read, without
+// waiting for the end of the line, flushing it after every write. Complete
+// lines are still sent to the sender, as well as an incomplete last line.
+async fn pipe_chunks(
+    mut from: impl AsyncRead + Unpin + Send + 'static,
+    mut to: impl Write + Send + 'static,
+    sender: Sender<String>,
+    cancel: CancellationToken,
+    lines: &'static AtomicUsize,
+    on_error: PassthroughErrorAction,
+    max_line_bytes: Option<usize>,
+) {
+    let mut buffer = vec![0; READ_CHUNK_SIZE];
+    let mut splitter = LineSplitter::new(max_line_bytes);
+
+    'read: loop {
+        let read = select! {
             _ = cancel.cancelled() => break,
-            _ = interval.tick() => send_request(config.request(&mut SystemTimestamp)).await,
-        );
+            read = from.read(&mut buffer) => read,
+        };
+
+        let chunk = match read {
+            Ok(0) => break,
+            Ok(read) => &buffer[..read],
+            Err(err) => {
+                debug!("error reading output: {}", err);
+                break;
+            }
+        };
+
+        if let Err(err) = to.write_all(chunk).and_then(|_| to.flush()) {
+            if on_error == PassthroughErrorAction::Stop {
+                debug!("error writing output: {}", err);
+                break;
+            }
+
+            skip_write(err);
+        }
+
+        for line in splitter.push(chunk) {
+            if let Err(err) = sender.send(line.text).await {
+                debug!("error sending line: {}", err);
+                break 'read;
+            }
+
+            dump::increment(lines);
+        }
     }
-}
 
-const LOG_MESSAGES_BATCH_SIZE: usize = 100;
+    if let Some(line) = splitter.finish() {
+        match sender.send(line.text).await {
+            Ok(()) => dump::increment(lines),
+            Err(err) => debug!("error sending line: {}", err),
+        }
+    }
+}
 
-async fn log_loop(
+async fn log_loop<R: Receive<String>>(
     log: LogConfig,
-    mut stdout: Option<UnboundedReceiver<String>>,
-    mut stderr: Option<UnboundedReceiver<String>>,
+    mut stdout: Option<R>,
+    mut stderr: Option<R>,
+    mut stdin: Option<R>,
+    mut files: Option<Receiver<FileLine>>,
+    clock: impl Clock,
 ) {
-    if stdout.is_none() && stderr.is_none() {
+    if stdout.is_none() && stderr.is_none() && stdin.is_none() && files.is_none() {
         return;
     }
 
-    let mut timestamp = MonotonicTimestamp::new(SystemTimestamp);
+    let mut timestamp = MonotonicTimestamp::new(clock.clone());
+    let mut clock_jumps = ClockJumpDetector::new(clock.clone());
+    clock_jumps.check();
 
-    let mut messages = Vec::new();
-    let mut interval = interval(Duration::from_secs(10));
+    let mut messages = LogBuffer::new(log.batch.capacity, log.batch.capacity_bytes);
+    let mut limiter = log.rate_limit.map(RateLimiter::new);
+    let mut interval = clock.interval(log.batch.flush_interval);
     interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
     let tasks = TaskTracker::new();
 
     loop {
-        if messages.len() >= LOG_MESSAGES_BATCH_SIZE {
-            let request = log.request(std::mem::take(&mut messages));
-            tasks.spawn(send_request(request));
+        if log.batch.is_full(messages.len(), messages.bytes()) {
+            spawn_log_request(&tasks, &log, &mut timestamp, &mut messages, &mut limiter);
             interval.reset();
         }
 
+        dump::set(&STATS.pending_log_messages, messages.len());
+
         select! {
             Some(maybe_line) = maybe_recv(&mut stdout) => {
                 match maybe_line {
                     None => {
                         stdout = None;
-                        if stderr.is_none() {
+                        if stderr.is_none() && stdin.is_none() && files.is_none() {
                             break;
                         }
                     }
+                    Some(line) if !is_allowed(&mut limiter, &line, &clock) => {}
                     Some(line) => {
-                        messages.push(LogMessage::new(&log, &mut timestamp, LogSeverity::Info, line));
+                        dump::increment(&STATS.logged_stdout_lines);
+                        let severity = log.severity(&line, log.stdout_severity);
+                        messages.push(LogMessage::new(&log, &mut timestamp, severity, line));
                     }
                 }
             }
@@ -309,117 +2131,242 @@ async fn log_loop(
                 match maybe_line {
                     None => {
                         stderr = None;
-                        if stdout.is_none() {
+                        if stdout.is_none() && stdin.is_none() && files.is_none() {
                             break;
                         }
                     }
+                    Some(line) if !is_allowed(&mut limiter, &line, &clock) => {}
                     Some(line) => {
-                        messages.push(LogMessage::new(&log, &mut timestamp, LogSeverity::Error, line));
+                        dump::increment(&STATS.logged_stderr_lines);
+                        let severity = log.severity(&line, log.stderr_severity);
+                        messages.push(LogMessage::new(&log, &mut timestamp, severity, line));
                     }
                 }
             }
 
-            _ = interval.tick() => {
-                if stdout.is_none() && stderr.is_none() {
-                    break;
-                }
-
-                if !messages.is_empty() {
-                    let request = log.request(std::mem::take(&mut messages));
-                    tasks.spawn(send_request(request));
-                }
-            }
-
-            else => break
-        }
-    }
-
-    if !messages.is_empty() {
-        let request = log.request(messages);
-        tasks.spawn(send_request(request));
-    }
-
-    tasks.close();
-    tasks.wait().await;
-}
-
-const ERROR_MESSAGE_LINES: usize = 10;
-
-async fn error_message_loop(
-    sender: oneshot::Sender<VecDeque<String>>,
-    mut stdout: Option<UnboundedReceiver<String>>,
-    mut stderr: Option<UnboundedReceiver<String>>,
-) {
-    let mut lines = VecDeque::with_capacity(ERROR_MESSAGE_LINES);
-
-    loop {
-        select! {
-            Some(maybe_line) = maybe_recv(&mut stdout) => {
+            Some(maybe_line) = maybe_recv(&mut stdin) => {
                 match maybe_line {
                     None => {
-                        stdout = None;
-                        if stderr.is_none() {
+                        stdin = None;
+                        if stdout.is_none() && stderr.is_none() && files.is_none() {
                             break;
                         }
                     }
+                    Some(line) if !is_allowed(&mut limiter, &line, &clock) => {}
                     Some(line) => {
-                        if lines.len() >= ERROR_MESSAGE_LINES {
-                            lines.pop_front();
-                        }
-
-                        lines.push_back(line);
+                        messages.push(LogMessage::new(&log, &mut timestamp, LogSeverity::Debug, line));
                     }
                 }
             }
 
-            Some(maybe_line) = maybe_recv(&mut stderr) => {
+            Some(maybe_line) = maybe_recv(&mut files) => {
                 match maybe_line {
                     None => {
-                        stderr = None;
-                        if stdout.is_none() {
+                        files = None;
+                        if stdout.is_none() && stderr.is_none() && stdin.is_none() {
                             break;
                         }
                     }
+                    Some(line) if !is_allowed(&mut limiter, &line.line, &clock) => {}
                     Some(line) => {
-                        if lines.len() >= ERROR_MESSAGE_LINES {
-                            lines.pop_front();
-                        }
-
-                        lines.push_back(line);
+                        let severity = log.severity(&line.line, LogSeverity::Info);
+                        messages.push(LogMessage::from_file(&log, &mut timestamp, severity, line));
                     }
                 }
             }
 
+            _ = interval.tick() => {
+                if stdout.is_none() && stderr.is_none() && stdin.is_none() && files.is_none() {
+                    break;
+                }
+
+                if let Some(jump) = clock_jumps.check() {
+                    debug!("system clock jump detected, adjusting log timestamps: {:?}", jump);
+                    timestamp.clock_jumped(&jump);
+                }
+
+                if !messages.is_empty() || has_dropped(&limiter) {
+                    spawn_log_request(&tasks, &log, &mut timestamp, &mut messages, &mut limiter);
+                }
+            }
+
             else => break
         }
     }
 
-    if sender.send(lines).is_err() {
-        debug!("error sending error message");
+    if !messages.is_empty() || has_dropped(&limiter) {
+        spawn_log_request(&tasks, &log, &mut timestamp, &mut messages, &mut limiter);
+    }
+
+    tasks.close();
+    tasks.wait().await;
+}
+
+// Whether the line is within the log rate limit, if any.
+fn is_allowed(limiter: &mut Option<RateLimiter>, line: &str, clock: &impl Clock) -> bool {
+    match limiter {
+        Some(limiter) => limiter.allow(line, clock.instant().into_std()),
+        None => true,
     }
 }
 
-async fn forward_signals_and_wait(mut child: Child) -> io::Result<ExitStatus> {
-    use nix::sys::signal::kill;
-    use nix::unistd::Pid;
+fn has_dropped(limiter: &Option<RateLimiter>) -> bool {
+    limiter
+        .as_ref()
+        .is_some_and(|limiter| limiter.dropped() > 0)
+}
 
-    let mut signals = signal_stream()?;
+// Sends the messages in the buffer, alongside messages reporting the number
+// of lines dropped since the previous request, if any.
+fn spawn_log_request(
+    tasks: &TaskTracker,
+    log: &LogConfig,
+    timestamp: &mut impl Timestamp,
+    buffer: &mut LogBuffer,
+    limiter: &mut Option<RateLimiter>,
+) {
+    let dropped = buffer.take_dropped();
+    let (mut messages, in_flight) = buffer.take();
+
+    if dropped > 0 {
+        debug!("dropped {} log lines", dropped);
+        dump::add(&STATS.dropped_log_messages, dropped);
+        messages.push(LogMessage::dropped(log, timestamp, dropped));
+    }
+
+    if let Some(limiter) = limiter.as_mut() {
+        let dropped = limiter.take_dropped();
+
+        if dropped > 0 {
+            debug!("dropped {} log lines over the rate limit", dropped);
+            dump::add(&STATS.dropped_log_messages, dropped);
+            messages.push(LogMessage::rate_limited(
+                log,
+                timestamp,
+                dropped,
+                limiter.limit(),
+            ));
+        }
+    }
+
+    // The messages are in flight until every request is sent.
+    let in_flight = Arc::new(in_flight);
+    for request in log.requests(messages) {
+        let in_flight = in_flight.clone();
+        tasks.spawn(async move {
+            delivery::send(DataType::Log, request).await;
+            drop(in_flight);
+        });
+    }
+}
+
+enum ChildExit {
+    Exited(ExitStatus),
+    // The child process exited after a terminating signal was received.
+    Terminated(ExitStatus),
+    // The child process was stopped when a terminating signal was received,
+    // and exited after it was continued.
+    StoppedAtShutdown(ExitStatus, Signal),
+    // An upgrade was requested while the child process was still running.
+    Upgrade(ChildProcess),
+    // A state dump was requested while the child process was still running.
+    Quit(ChildProcess),
+}
+
+async fn forward_signals_and_wait(
+    mut child: ChildProcess,
+    mut upgrade_signal: Option<tokio::signal::unix::Signal>,
+    config: &WrapConfig,
+    heartbeat_request: &Notify,
+) -> io::Result<ChildExit> {
+    let kill_after = config.kill_after;
+    let mut signals = forwarding_signal_stream(config.forward_signals.keys().copied())?;
+    // Cleared once the shutdown file is found, so that it is only handled
+    // once, like a single terminating signal.
+    let mut shutdown_file = config.shutdown_file.as_deref();
+    let mut stopped_at_shutdown = None;
+    // Whether a terminating signal was received.
+    let mut terminating = false;
+    // Started when the first terminating signal is forwarded to the child.
+    let mut kill_timer = None;
 
     loop {
         select! {
             biased;
 
             status = child.wait() => {
-                return status
+                return status.map(|status| match (stopped_at_shutdown, terminating) {
+                    (Some(signal), _) => ChildExit::StoppedAtShutdown(status, signal),
+                    (None, true) => ChildExit::Terminated(status),
+                    (None, false) => ChildExit::Exited(status),
+                })
             }
 
-            Some(signal) = signals.next() => {
+            Some(()) = maybe_recv_signal(&mut upgrade_signal) => {
+                debug!("received upgrade signal");
+                return Ok(ChildExit::Upgrade(child))
+            }
+
+            Some(()) = maybe_sleep(&mut kill_timer) => {
+                kill_timer = None;
+
+                if let Some(id) = child.id() {
+                    warn!(
+                        "command did not exit {:?} after it was asked to terminate; killing it",
+                        kill_after.unwrap_or_default()
+                    );
+
+                    let pid = Pid::from_raw(id.try_into().expect("Invalid PID"));
+                    if let Err(err) = kill(pid, Signal::SIGKILL) {
+                        debug!("error killing child: {}", err);
+                    }
+                }
+            }
+
+            Some(signal) = next_signal(&mut signals, &mut shutdown_file) => {
+                if signal == Signal::SIGQUIT && config.quit_action == QuitAction::Dump {
+                    debug!("received quit signal, dumping state");
+                    return Ok(ChildExit::Quit(child))
+                }
+
+                if signal == Signal::SIGUSR2 && config.usr2_action == Usr2Action::Heartbeat {
+                    debug!("received SIGUSR2, requesting heartbeat");
+                    heartbeat_request.notify_one();
+                    continue;
+                }
+
+                terminating |= has_terminating_intent(&signal);
+
+                // A stopped child would not handle the signal until it is
+                // continued, and the wrapper would wait for it forever.
+                let stopped = has_terminating_intent(&signal) && child.is_stopped();
+
+                let Some(forwarded) = config.forwarded_signal(signal) else {
+                    debug!("not forwarding signal to child: {}", signal);
+                    continue;
+                };
+
                 if let Some(id) = child.id() {
                     let pid = Pid::from_raw(id.try_into().expect("Invalid PID"));
-                    match kill(pid, signal) {
-                        Ok(_) => trace!("forwarded signal to child: {}", signal),
+                    match kill(pid, forwarded) {
+                        Ok(_) => trace!("forwarded signal to child: {}", forwarded),
                         Err(err) => debug!("error forwarding signal to child: {}", err),
                     };
+
+                    if let (Some(duration), None) = (kill_after, kill_timer.as_ref()) {
+                        if has_terminating_intent(&signal) {
+                            kill_timer = Some(Box::pin(SystemTimestamp.sleep(duration)));
+                        }
+                    }
+
+                    if stopped {
+                        warn!("command is stopped; continuing it to terminate it");
+                        stopped_at_shutdown = Some(signal);
+
+                        if let Err(err) = kill(pid, Signal::SIGCONT) {
+                            debug!("error continuing child: {}", err);
+                        }
+                    }
                 } else {
                     debug!("cannot forward signal to child: child process has no PID");
                 }
@@ -428,39 +2375,356 @@ async fn forward_signals_and_wait(mut child: Child) -> io::Result<ExitStatus> {
     }
 }
 
-async fn send_error_exit_request(
-    error: ErrorConfig,
-    exit_status: ExitStatus,
-    receiver: oneshot::Receiver<VecDeque<String>>,
-) {
-    let lines = match receiver.await {
-        Ok(lines) => lines,
-        Err(_) => {
-            debug!("error receiving error message");
-            VecDeque::new()
+// Waits for the backoff before the command is restarted. If a terminating
+// signal is received while waiting, it is returned, and the command is not
+// restarted.
+async fn wait_before_restart(
+    backoff: Duration,
+    mut shutdown_file: Option<&Path>,
+) -> io::Result<Option<Signal>> {
+    let mut signals = signal_stream()?;
+    let sleep = SystemTimestamp.sleep(backoff);
+    tokio::pin!(sleep);
+
+    loop {
+        select! {
+            _ = &mut sleep => return Ok(None),
+
+            Some(signal) = next_signal(&mut signals, &mut shutdown_file) => {
+                if has_terminating_intent(&signal) {
+                    return Ok(Some(signal));
+                }
+
+                trace!("ignoring non-terminating signal before restart: {}", signal);
+            }
+        }
+    }
+}
+
+// How often to check whether the shutdown file exists.
+const SHUTDOWN_FILE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Returns the next signal received by the wrapper. Once the shutdown file
+// exists, if set, it is returned as a `SIGTERM` signal, for orchestrators
+// that cannot send signals to the wrapper, but can create files.
+async fn next_signal(
+    signals: &mut (impl Stream<Item = Signal> + Unpin),
+    shutdown_file: &mut Option<&Path>,
+) -> Option<Signal> {
+    let wait_for_file = async {
+        match shutdown_file {
+            Some(path) => loop {
+                if path.exists() {
+                    break Some(*path);
+                }
+                SystemTimestamp.sleep(SHUTDOWN_FILE_INTERVAL).await;
+            },
+            None => None,
         }
     };
 
-    send_request(error.request_from_exit(&mut SystemTimestamp, &exit_status, lines)).await;
+    select! {
+        signal = signals.next() => signal,
+
+        Some(path) = wait_for_file => {
+            warn!("shutdown file {} exists; stopping command", path.display());
+            *shutdown_file = None;
+            Some(Signal::SIGTERM)
+        }
+    }
+}
+
+async fn maybe_recv_signal(signal: &mut Option<tokio::signal::unix::Signal>) -> Option<()> {
+    match signal {
+        Some(signal) => signal.recv().await,
+        None => None,
+    }
+}
+
+async fn maybe_sleep(sleep: &mut Option<Pin<Box<Sleep>>>) -> Option<()> {
+    match sleep {
+        Some(sleep) => {
+            sleep.await;
+            Some(())
+        }
+        None => None,
+    }
 }
 
-fn command(argv: &[String], should_stdout: bool, should_stderr: bool) -> Command {
+fn command(config: &WrapConfig) -> Command {
+    let argv = &config.command;
     let mut command = Command::new(argv[0].clone());
     for arg in argv[1..].iter() {
         command.arg(arg);
     }
 
-    if should_stdout {
+    if config.clear_env {
+        command.env_clear();
+    } else {
+        for (key, _) in std::env::vars_os() {
+            if key.to_str().is_some_and(environment::is_wrapper_var) {
+                command.env_remove(key);
+            }
+        }
+    }
+
+    command.envs(&config.env);
+    command.envs(config.child_env());
+
+    if config.stdout {
         command.stdout(Stdio::piped());
     }
 
-    if should_stderr {
+    if config.stderr {
         command.stderr(Stdio::piped());
     }
 
+    if let Some(dir) = config.chdir.as_ref() {
+        command.current_dir(dir);
+    }
+
+    // The group and user are switched before the `pre_exec` hooks run, as
+    // switching them resets the signal set by `exit_with_parent`.
+    if let Some(gid) = config.group {
+        command.gid(gid);
+    }
+
+    if let Some(uid) = config.user {
+        command.uid(uid);
+    }
+
+    match config.stdin {
+        StdinMode::Inherit => {}
+        StdinMode::Null => {
+            command.stdin(Stdio::null());
+        }
+        StdinMode::Log => {
+            command.stdin(Stdio::piped());
+        }
+    }
+
     unsafe {
         command.pre_exec(exit::exit_with_parent);
     }
 
     command
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check_in::CheckInConfig;
+    use crate::log::LogBatch;
+    use crate::record::RecordedRequest;
+    use crate::timestamp::tests::TestClock;
+    use std::path::PathBuf;
+    use std::sync::OnceLock;
+
+    // The requests are printed instead of sent, and recorded to a directory
+    // shared by the tests in the process, so each test only reads the ones
+    // whose URL or body contains the given text.
+    fn recorded(matching: &str) -> Vec<RecordedRequest> {
+        static DIR: OnceLock<PathBuf> = OnceLock::new();
+
+        let dir = DIR.get_or_init(|| {
+            let dir = std::env::temp_dir().join(format!("{}-main-test", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            dry_run::enable();
+            record::init(dir.clone());
+            dir
+        });
+
+        record::read(dir)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(_, request)| request)
+            .filter(|request| {
+                request.url.contains(matching)
+                    || request
+                        .body
+                        .as_deref()
+                        .is_some_and(|body| body.contains(matching))
+            })
+            .collect()
+    }
+
+    fn heartbeat_config(identifier: &str, interval: Duration) -> HeartbeatConfig {
+        HeartbeatConfig {
+            check_in: CheckInConfig {
+                api_key: "some_api_key".to_string(),
+                endpoint: "https://some-endpoint.com".to_string(),
+                identifier: identifier.to_string(),
+            },
+            interval,
+            on_idle: true,
+            activity_patterns: vec![],
+            health_cmd: None,
+        }
+    }
+
+    fn log_config(group: &str) -> LogConfig {
+        LogConfig {
+            api_key: "some_api_key".to_string(),
+            endpoint: "https://some-endpoint.com".to_string(),
+            hostname: "some-hostname".to_string(),
+            group: group.to_string(),
+            group_per_run: false,
+            origin: LogOrigin::All,
+            digest: "some-digest".to_string(),
+            command: None,
+            revision: None,
+            tags: Default::default(),
+            severity_patterns: vec![],
+            stdout_severity: LogSeverity::Info,
+            stderr_severity: LogSeverity::Error,
+            batch: LogBatch::default(),
+            rate_limit: None,
+            severity_sources: vec![],
+            compress: false,
+        }
+    }
+
+    fn stall(action: &str, timeout: Duration) -> Stall {
+        Stall {
+            timeout,
+            error: Some(ErrorConfig {
+                api_key: "some_api_key".to_string(),
+                endpoint: "https://some-endpoint.com".to_string(),
+                action: action.to_string(),
+                hostname: "some-hostname".to_string(),
+                digest: "some-digest".to_string(),
+                command: None,
+                revision: None,
+                tags: Default::default(),
+                compress: false,
+            }),
+            kill: None,
+            lines: MessageLines::new(10, None),
+        }
+    }
+
+    fn clock() -> TestClock {
+        TestClock::new(Duration::from_secs(1_000_000_000))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn heartbeat_loop_interval() {
+        recorded("");
+        let cancel = CancellationToken::new();
+        let task = tokio::spawn(heartbeat_loop(
+            heartbeat_config("heartbeat-interval", Duration::from_secs(30)),
+            None,
+            Arc::new(Notify::new()),
+            cancel.clone(),
+            clock(),
+        ));
+
+        // A heartbeat is sent right away, and then once per interval.
+        tokio::time::sleep(Duration::from_secs(95)).await;
+        assert_eq!(recorded("heartbeat-interval").len(), 4);
+
+        // No final heartbeat is sent, as the last one was sent recently.
+        cancel.cancel();
+        task.await.unwrap();
+        assert_eq!(recorded("heartbeat-interval").len(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn heartbeat_loop_long_interval() {
+        recorded("");
+        let cancel = CancellationToken::new();
+        let task = tokio::spawn(heartbeat_loop(
+            heartbeat_config("heartbeat-long-interval", Duration::from_secs(600)),
+            None,
+            Arc::new(Notify::new()),
+            cancel.clone(),
+            clock(),
+        ));
+
+        tokio::time::sleep(Duration::from_secs(100)).await;
+        assert_eq!(recorded("heartbeat-long-interval").len(), 1);
+
+        // A final heartbeat is sent, as the last one was sent longer ago
+        // than the default interval.
+        cancel.cancel();
+        task.await.unwrap();
+        assert_eq!(recorded("heartbeat-long-interval").len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn log_loop_flush_interval() {
+        recorded("");
+        let (sender, receiver) = channel(LINES_BUFFER);
+        let task = tokio::spawn(log_loop(
+            log_config("log-flush-interval"),
+            Some(receiver),
+            None,
+            None,
+            None,
+            clock(),
+        ));
+
+        // The lines are sent once per flush interval, which starts when the
+        // loop does, not as they are read.
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        sender.send("first".to_string()).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(recorded("log-flush-interval").is_empty());
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        sender.send("second".to_string()).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+
+        // The remaining lines are sent when the output is closed.
+        sender.send("third".to_string()).await.unwrap();
+        drop(sender);
+        task.await.unwrap();
+
+        let bodies: Vec<String> = recorded("log-flush-interval")
+            .into_iter()
+            .filter_map(|request| request.body)
+            .collect();
+        assert_eq!(bodies.len(), 3);
+        for (body, line) in bodies.iter().zip(["first", "second", "third"]) {
+            assert!(body.contains(line), "{body}");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stall_loop_reports_once() {
+        recorded("");
+        let (sender, receiver) = channel(LINES_BUFFER);
+        let (stdout_sender, mut stdout_lines) = channel(LINES_BUFFER);
+        let (stderr_sender, _) = channel(LINES_BUFFER);
+        let task = tokio::spawn(stall_loop(
+            stall("stall-loop", Duration::from_secs(60)),
+            Some(receiver),
+            None,
+            stdout_sender,
+            stderr_sender,
+            clock(),
+        ));
+
+        sender.send("first".to_string()).await.unwrap();
+        assert_eq!(stdout_lines.recv().await.unwrap(), "first");
+        tokio::time::sleep(Duration::from_secs(59)).await;
+        assert!(recorded("stall-loop").is_empty());
+
+        // A stall is reported once, however long the command stays silent.
+        tokio::time::sleep(Duration::from_secs(300)).await;
+        let reports = recorded("stall-loop");
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].body.as_deref().unwrap().contains("first"));
+
+        // It is reported again once the command writes more output and then
+        // stalls again.
+        sender.send("second".to_string()).await.unwrap();
+        assert_eq!(stdout_lines.recv().await.unwrap(), "second");
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        assert_eq!(recorded("stall-loop").len(), 2);
+
+        drop(sender);
+        task.await.unwrap();
+        assert_eq!(recorded("stall-loop").len(), 2);
+    }
+}
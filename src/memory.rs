@@ -0,0 +1,123 @@
+// Sizes the wrapper's buffers from a single memory budget, given with
+// `--max-wrapper-memory`, so that its overhead on memory-constrained hosts
+// can be reasoned about with one option. The budget is split between the
+// buffers that grow with the command's output:
+//
+// - half of it for the log lines held until they are sent, including the
+//   ones in requests that are being sent;
+// - an eighth of it for the log lines in a single request, whose body is
+//   built and compressed while they are still held;
+// - a sixteenth of it for the last lines of output kept for error messages,
+//   of which there are up to two copies;
+// - an eighth of it for the lines passed between the stages that process
+//   the command's output, such as the ones that strip escape sequences or
+//   look for the ready pattern, which hold a number of lines each.
+//
+// The size of those lines is limited to 1/1024 of the budget, and a
+// longer line is read and sent in parts, so that the number of lines held
+// by each stage can be derived from the budget.
+//
+// The rest is left for the wrapper itself, its runtime, the lines being
+// read and processed by each stage, and the bodies of the requests that
+// are being sent.
+
+use crate::units;
+
+// Below this, the buffers would be too small to hold a typical batch of
+// log lines.
+pub const MIN_WRAPPER_MEMORY: usize = 1 << 20;
+
+// The most channels that the lines of the command's output can be passed
+// through at once: up to six for its standard output, five for its standard
+// error and two for its standard input, rounded up.
+const MAX_LINE_CHANNELS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget(usize);
+
+impl MemoryBudget {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match units::parse_size(value)? {
+            bytes if bytes < MIN_WRAPPER_MEMORY => Err(format!(
+                "invalid memory budget `{value}`; must be at least 1MiB"
+            )),
+            bytes => Ok(Self(bytes)),
+        }
+    }
+
+    // The maximum size of the log lines held in memory.
+    pub fn log_buffer_bytes(&self) -> usize {
+        self.0 / 2
+    }
+
+    // The maximum size of the log lines in a single request.
+    pub fn log_batch_bytes(&self) -> usize {
+        self.0 / 8
+    }
+
+    // The maximum size of each copy of the output kept for error messages.
+    pub fn error_bytes(&self) -> usize {
+        self.0 / 32
+    }
+
+    // The maximum size of a line of output, beyond which it is read in
+    // parts.
+    pub fn line_bytes(&self) -> usize {
+        self.0 / 1024
+    }
+
+    // How many lines each channel between the stages that process the
+    // command's output holds, so that all of them together hold at most an
+    // eighth of the budget.
+    pub fn lines_buffer(&self) -> usize {
+        (self.0 / 8 / MAX_LINE_CHANNELS / self.line_bytes()).max(1)
+    }
+}
+
+// Returns the smaller of the size given with an option and the share of the
+// budget, if any, so that the option can only lower the share.
+pub fn within_budget(
+    size: Option<usize>,
+    budget: Option<MemoryBudget>,
+    share: impl Fn(&MemoryBudget) -> usize,
+) -> Option<usize> {
+    match (size, budget.as_ref().map(share)) {
+        (Some(size), Some(share)) => Some(size.min(share)),
+        (size, share) => size.or(share),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_budget_shares() {
+        let budget = MemoryBudget::parse("64MiB").unwrap();
+        assert_eq!(budget.log_buffer_bytes(), 32 << 20);
+        assert_eq!(budget.log_batch_bytes(), 8 << 20);
+        assert_eq!(budget.error_bytes(), 2 << 20);
+        assert_eq!(budget.line_bytes(), 64 << 10);
+        assert_eq!(budget.lines_buffer(), 8);
+        assert!(budget.lines_buffer() * budget.line_bytes() * MAX_LINE_CHANNELS <= (64 << 20) / 8);
+
+        assert!(MemoryBudget::parse("1MiB").is_ok());
+        assert_eq!(
+            MemoryBudget::parse("512KB"),
+            Err("invalid memory budget `512KB`; must be at least 1MiB".to_string())
+        );
+        assert!(MemoryBudget::parse("lots").is_err());
+    }
+
+    #[test]
+    fn memory_within_budget() {
+        let budget = Some(MemoryBudget::parse("64MiB").unwrap());
+        let share = MemoryBudget::log_batch_bytes;
+
+        assert_eq!(within_budget(None, None, share), None);
+        assert_eq!(within_budget(Some(1000), None, share), Some(1000));
+        assert_eq!(within_budget(None, budget, share), Some(8 << 20));
+        assert_eq!(within_budget(Some(1000), budget, share), Some(1000));
+        assert_eq!(within_budget(Some(64 << 20), budget, share), Some(8 << 20));
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use reqwest::Body;
+use serde::{Deserialize, Serialize};
+
+use crate::client::client;
+use crate::resources::ResourceUsage;
+use crate::wrap::{default_endpoint, hostname, require, validate_endpoint};
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub api_key: String,
+    #[serde(default = "default_endpoint")]
+    pub endpoint: String,
+    pub name: String,
+    #[serde(default = "hostname")]
+    pub hostname: String,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl MetricsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        require(&self.api_key, "metrics API key")?;
+        validate_endpoint(&self.endpoint)?;
+        require(&self.name, "metrics name")
+    }
+
+    pub fn request(&self, body: impl Into<Body>) -> Result<reqwest::Request, reqwest::Error> {
+        let url = format!("{}/metrics/json", self.endpoint);
+
+        client()
+            .post(url)
+            .query(&[("api_key", &self.api_key)])
+            .header("Content-Type", "application/json")
+            .body(body)
+            .build()
+    }
+
+    // Reports how long the command ran for, as a `process_duration`
+    // distribution in milliseconds, and how it exited, as a `process_exit`
+    // counter.
+    pub fn request_from_exit(
+        &self,
+        duration: Duration,
+        exit: &ExitStatus,
+    ) -> Result<reqwest::Request, reqwest::Error> {
+        self.request(MetricsBody::from_exit(self, duration, exit))
+    }
+
+    // Reports the resource usage of the running command, as the
+    // `process_cpu_time` (in milliseconds), `process_memory_rss` (in bytes)
+    // and `process_open_fds` gauges.
+    pub fn request_from_usage(
+        &self,
+        usage: &ResourceUsage,
+    ) -> Result<reqwest::Request, reqwest::Error> {
+        self.request(MetricsBody::from_usage(self, usage))
+    }
+
+    // Reports how long after its last scheduled time the command started,
+    // as a `cron_schedule_drift` distribution in milliseconds.
+    pub fn request_from_schedule_drift(
+        &self,
+        drift: Duration,
+    ) -> Result<reqwest::Request, reqwest::Error> {
+        self.request(MetricsBody(vec![Metric {
+            name: "cron_schedule_drift".to_string(),
+            metric_type: MetricType::Distribution,
+            value: drift.as_secs_f64() * 1000.0,
+            tags: self.tags(),
+        }]))
+    }
+
+    // Reports custom metrics emitted by the command. The tags of each metric
+    // are added to the name, the hostname and the configured tags.
+    pub fn request_from_metrics(
+        &self,
+        metrics: Vec<Metric>,
+    ) -> Result<reqwest::Request, reqwest::Error> {
+        self.request(MetricsBody::from_metrics(self, metrics))
+    }
+
+    fn tags(&self) -> BTreeMap<String, String> {
+        let mut tags = self.tags.clone();
+        tags.insert("name".to_string(), self.name.clone());
+        tags.insert("hostname".to_string(), self.hostname.clone());
+        tags
+    }
+}
+
+#[derive(Serialize)]
+#[serde(transparent)]
+pub struct MetricsBody(Vec<Metric>);
+
+impl MetricsBody {
+    pub fn from_exit(config: &MetricsConfig, duration: Duration, exit: &ExitStatus) -> Self {
+        let mut tags = config.tags();
+        tags.insert("exit_kind".to_string(), exit_kind(exit).to_string());
+
+        MetricsBody(vec![
+            Metric {
+                name: "process_duration".to_string(),
+                metric_type: MetricType::Distribution,
+                value: duration.as_secs_f64() * 1000.0,
+                tags: tags.clone(),
+            },
+            Metric {
+                name: "process_exit".to_string(),
+                metric_type: MetricType::Counter,
+                value: 1.0,
+                tags,
+            },
+        ])
+    }
+
+    pub fn from_metrics(config: &MetricsConfig, metrics: Vec<Metric>) -> Self {
+        MetricsBody(
+            metrics
+                .into_iter()
+                .map(|metric| Metric {
+                    tags: config.tags().into_iter().chain(metric.tags).collect(),
+                    ..metric
+                })
+                .collect(),
+        )
+    }
+
+    pub fn from_usage(config: &MetricsConfig, usage: &ResourceUsage) -> Self {
+        let gauge = |name: &str, value: f64| Metric {
+            name: name.to_string(),
+            metric_type: MetricType::Gauge,
+            value,
+            tags: config.tags(),
+        };
+
+        MetricsBody(vec![
+            gauge("process_cpu_time", usage.cpu_time.as_secs_f64() * 1000.0),
+            gauge("process_memory_rss", usage.rss_bytes as f64),
+            gauge("process_open_fds", usage.open_fds as f64),
+        ])
+    }
+}
+
+impl From<MetricsBody> for Body {
+    fn from(body: MetricsBody) -> Self {
+        Body::from(serde_json::to_string(&body).unwrap())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metric {
+    pub name: String,
+    pub metric_type: MetricType,
+    pub value: f64,
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricType {
+    Counter,
+    Distribution,
+    Gauge,
+}
+
+fn exit_kind(exit: &ExitStatus) -> &'static str {
+    if exit.code().is_some() {
+        "code"
+    } else if exit.signal().is_some() {
+        "signal"
+    } else {
+        "unknown"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics_config() -> MetricsConfig {
+        MetricsConfig {
+            api_key: "some_api_key".to_string(),
+            endpoint: "https://some-endpoint.com".to_string(),
+            name: "some-name".to_string(),
+            hostname: "some-hostname".to_string(),
+            tags: [("team".to_string(), "backend".to_string())].into(),
+        }
+    }
+
+    #[test]
+    fn metrics_config_request_from_exit() {
+        let config = metrics_config();
+        let exit = ExitStatus::from_raw(9);
+
+        let request = config
+            .request_from_exit(Duration::from_millis(1500), &exit)
+            .unwrap();
+
+        assert_eq!(request.method().as_str(), "POST");
+        assert_eq!(
+            request.url().as_str(),
+            "https://some-endpoint.com/metrics/json?api_key=some_api_key"
+        );
+        assert_eq!(
+            request.headers().get("Content-Type").unwrap(),
+            "application/json"
+        );
+
+        let body = request.body().unwrap().as_bytes().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        let tags = serde_json::json!({
+            "name": "some-name",
+            "hostname": "some-hostname",
+            "exit_kind": "signal",
+            "team": "backend"
+        });
+
+        assert_eq!(
+            body,
+            serde_json::json!([
+                {
+                    "name": "process_duration",
+                    "metricType": "distribution",
+                    "value": 1500.0,
+                    "tags": tags
+                },
+                {
+                    "name": "process_exit",
+                    "metricType": "counter",
+                    "value": 1.0,
+                    "tags": tags
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn metrics_config_request_from_metrics() {
+        let config = metrics_config();
+        let metric = Metric {
+            name: "jobs.processed".to_string(),
+            metric_type: MetricType::Counter,
+            value: 3.0,
+            tags: [("team".to_string(), "frontend".to_string())].into(),
+        };
+
+        let request = config.request_from_metrics(vec![metric]).unwrap();
+
+        let body = request.body().unwrap().as_bytes().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+
+        assert_eq!(
+            body,
+            serde_json::json!([
+                {
+                    "name": "jobs.processed",
+                    "metricType": "counter",
+                    "value": 3.0,
+                    "tags": {
+                        "name": "some-name",
+                        "hostname": "some-hostname",
+                        "team": "frontend"
+                    }
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn metrics_config_request_from_usage() {
+        let config = metrics_config();
+        let usage = ResourceUsage {
+            cpu_time: Duration::from_millis(2500),
+            rss_bytes: 4096,
+            open_fds: 7,
+        };
+
+        let request = config.request_from_usage(&usage).unwrap();
+
+        let body = request.body().unwrap().as_bytes().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        let tags = serde_json::json!({
+            "name": "some-name",
+            "hostname": "some-hostname",
+            "team": "backend"
+        });
+
+        assert_eq!(
+            body,
+            serde_json::json!([
+                {
+                    "name": "process_cpu_time",
+                    "metricType": "gauge",
+                    "value": 2500.0,
+                    "tags": tags
+                },
+                {
+                    "name": "process_memory_rss",
+                    "metricType": "gauge",
+                    "value": 4096.0,
+                    "tags": tags
+                },
+                {
+                    "name": "process_open_fds",
+                    "metricType": "gauge",
+                    "value": 7.0,
+                    "tags": tags
+                }
+            ])
+        );
+    }
+}
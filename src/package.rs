@@ -1,2 +1,52 @@
+use std::env::consts::{ARCH, OS};
+use std::fmt::Write as _;
+
+use crate::client::system_roots_path;
+
 pub const NAME: &str = env!("CARGO_PKG_NAME");
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// The version printed by `--version`. If `verbose` is set, it is followed by
+// how the wrapper was built, and the root certificates it can trust, to
+// tell whether it can send data from a given host or container.
+pub fn version(verbose: bool) -> String {
+    let mut version = format!("{NAME} {VERSION}\n");
+
+    if !verbose {
+        return version;
+    }
+
+    let features: Vec<&str> = [("fake-endpoint", cfg!(feature = "fake-endpoint"))]
+        .into_iter()
+        .filter_map(|(feature, enabled)| enabled.then_some(feature))
+        .collect();
+
+    let system_roots = match system_roots_path() {
+        Some(path) => path.display().to_string(),
+        None => "not found".to_string(),
+    };
+
+    // Writing to a `String` cannot fail.
+    let _ = writeln!(version, "target: {}-{}", ARCH, OS);
+    let _ = writeln!(version, "tls backend: rustls");
+    let _ = writeln!(
+        version,
+        "bundled root certificates: webpki-roots (used by default)"
+    );
+    let _ = writeln!(
+        version,
+        "system root certificates: {} (used with --tls-roots system or all)",
+        system_roots
+    );
+    let _ = writeln!(
+        version,
+        "features: {}",
+        if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(", ")
+        }
+    );
+
+    version
+}
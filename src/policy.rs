@@ -0,0 +1,320 @@
+// Restricts which commands the wrapper may execute, and which of its options
+// may be used, according to a policy file. This makes it safe to allow users
+// on a shared host to run the wrapper with elevated privileges, such as with
+// a broad `sudo` rule, as the policy is enforced before the command starts.
+//
+// The policy file is only read from a fixed path, and is only trusted if it
+// is owned by root and not writable by other users. If there is no policy
+// file, every command and option is allowed.
+//
+// Commands are matched by the path of their executable, after resolving
+// symbolic links. A command given by name is looked up in the wrapper's
+// `PATH`, and is executed by the path it was found at, so that changing the
+// command's `PATH` with `--env` cannot change which executable runs.
+//
+// Every command the wrapper runs is checked, not only the wrapped command:
+// the canary commands, and the command strings given to `--shell`,
+// `--identifier-cmd`, `--health-cmd` and `--on-failure`. For a command
+// string, both the shell that runs it and the program it starts with must
+// be allowed. As a command string can run other programs, only a policy
+// that does not allow the shell prevents those from running.
+
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::cli::Cli;
+use crate::wrap::WrapConfig;
+
+pub const POLICY_PATH: &str = "/etc/appsignal-run/policy.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    // The commands that may be executed. If not set, any command that is
+    // not denied may be executed.
+    allow_commands: Option<Vec<PathBuf>>,
+    // The commands that may not be executed.
+    deny_commands: Vec<PathBuf>,
+    // The long names of the options that may not be used, such as `user`.
+    deny_options: Vec<String>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Policy {
+    // Reads the policy file at the given path, if it exists.
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(format!(
+                    "could not read policy file {}: {}",
+                    path.display(),
+                    err
+                ))
+            }
+        };
+
+        // A policy that other users can change does not restrict them.
+        if metadata.uid() != 0 || metadata.permissions().mode() & 0o022 != 0 {
+            return Err(format!(
+                "refusing to use policy file {}, as it must be owned by root and \
+                not writable by other users",
+                path.display()
+            ));
+        }
+
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("could not read policy file {}: {}", path.display(), err))?;
+
+        Self::parse(path, &contents).map(Some)
+    }
+
+    fn parse(path: &Path, contents: &str) -> Result<Self, String> {
+        let policy: Self = toml::from_str(contents)
+            .map_err(|err| format!("could not parse policy file {}: {}", path.display(), err))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            ..policy
+        })
+    }
+
+    // Checks that the given options, by their long name, are allowed.
+    pub fn check_options<'a>(
+        &self,
+        options: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), String> {
+        for option in options {
+            if self.deny_options.iter().any(|denied| denied == option) {
+                return Err(format!(
+                    "the policy in {} does not allow the --{} option",
+                    self.path.display(),
+                    option
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Checks the options given to the wrapper, and the command strings it
+    // runs through a shell, before any command is run.
+    pub fn check_cli(&self, cli: &Cli) -> Result<(), String> {
+        self.check_options(cli.given_options())?;
+
+        for (shell, command) in cli.shell_commands() {
+            self.check_shell(&shell, command)?;
+        }
+
+        Ok(())
+    }
+
+    // Checks that the command is allowed, and resolves it to the path of
+    // its executable, which the command is then executed by.
+    pub fn check_command(&self, config: &mut WrapConfig) -> Result<(), String> {
+        let chdir = config.chdir.clone();
+        self.check_argv(&mut config.command, chdir.as_deref())
+    }
+
+    // Checks that the program of the given arguments is allowed, resolving
+    // it to the path of its executable.
+    pub fn check_argv(&self, argv: &mut [String], chdir: Option<&Path>) -> Result<(), String> {
+        let program = &argv[0];
+        let resolved =
+            resolve(program, chdir).ok_or_else(|| format!("could not find command `{program}`"))?;
+
+        let executable = resolved
+            .canonicalize()
+            .map_err(|err| format!("could not find command `{}`: {}", resolved.display(), err))?;
+
+        self.check_executable(&executable)?;
+        argv[0] = resolved.display().to_string();
+
+        Ok(())
+    }
+
+    // Checks that the shell, and the program that the command string run
+    // with it starts with, are allowed. A program that cannot be found,
+    // such as a shell builtin, is left for the shell to run or reject.
+    pub fn check_shell(&self, shell: &str, command: &str) -> Result<(), String> {
+        self.check_argv(&mut [shell.to_string()], None)?;
+
+        let executable = command
+            .split_whitespace()
+            .next()
+            .and_then(|program| resolve(program, None))
+            .and_then(|resolved| resolved.canonicalize().ok());
+
+        match executable {
+            Some(executable) => self.check_executable(&executable),
+            None => Ok(()),
+        }
+    }
+
+    fn check_executable(&self, executable: &Path) -> Result<(), String> {
+        let matches = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .any(|path| path.canonicalize().is_ok_and(|path| path == executable))
+        };
+
+        let allowed = match self.allow_commands.as_ref() {
+            Some(allowed) => matches(allowed),
+            None => true,
+        };
+
+        if !allowed || matches(&self.deny_commands) {
+            return Err(format!(
+                "the policy in {} does not allow running {}",
+                self.path.display(),
+                executable.display()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// Resolves the command's program to a path, as it would be resolved when the
+// command is executed: relative to the command's working directory if it
+// contains a `/`, or in the `PATH` directories otherwise.
+fn resolve(program: &str, chdir: Option<&Path>) -> Option<PathBuf> {
+    if program.contains('/') {
+        let path = match chdir {
+            Some(dir) => dir.join(program),
+            None => PathBuf::from(program),
+        };
+
+        return Some(path);
+    }
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| {
+            path.metadata()
+                .is_ok_and(|metadata| metadata.is_file() && metadata.mode() & 0o111 != 0)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(contents: &str) -> Policy {
+        Policy::parse(Path::new("policy.toml"), contents).expect("failed to parse policy")
+    }
+
+    fn check_command(policy: &Policy, command: &str) -> Result<String, String> {
+        let mut config = WrapConfig::builder([command])
+            .build()
+            .expect("failed to build config");
+
+        policy
+            .check_command(&mut config)
+            .map(|_| config.command[0].clone())
+    }
+
+    #[test]
+    fn policy_check_command() {
+        let sh = std::fs::canonicalize("/bin/sh").unwrap();
+        let policy = policy(&format!(
+            "allow_commands = [{:?}]",
+            sh.display().to_string()
+        ));
+
+        assert_eq!(check_command(&policy, "/bin/sh"), Ok("/bin/sh".to_string()));
+        assert!(check_command(&policy, "sh").is_ok_and(|path| path.ends_with("/sh")));
+        assert_eq!(
+            check_command(&policy, "/no/such/command"),
+            Err(
+                "could not find command `/no/such/command`: No such file or directory (os error 2)"
+                    .to_string()
+            )
+        );
+
+        let denied = std::env::current_exe().unwrap();
+        assert_eq!(
+            check_command(&policy, denied.to_str().unwrap()),
+            Err(format!(
+                "the policy in policy.toml does not allow running {}",
+                denied.canonicalize().unwrap().display()
+            ))
+        );
+
+        let policy = self::policy(r#"deny_commands = ["/bin/sh"]"#);
+        assert!(check_command(&policy, "/bin/sh").is_err());
+        assert!(check_command(&policy, denied.to_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn policy_check_shell() {
+        let denied = std::env::current_exe().unwrap();
+        let denied = denied.to_str().unwrap();
+        let policy = policy(&format!("deny_commands = [{denied:?}]"));
+
+        assert_eq!(policy.check_shell("sh", "echo backup-3"), Ok(()));
+        assert_eq!(policy.check_shell("sh", "exit 1"), Ok(()));
+        assert!(policy
+            .check_shell("sh", &format!("{denied} --version"))
+            .is_err());
+        assert!(policy.check_shell(denied, "echo backup-3").is_err());
+
+        let mut argv = vec![denied.to_string(), "--version".to_string()];
+        assert!(policy.check_argv(&mut argv, None).is_err());
+    }
+
+    #[test]
+    fn policy_check_cli() {
+        use crate::package::NAME;
+        use clap::Parser;
+
+        fn cli(args: &[&str]) -> Cli {
+            Cli::try_parse_from(
+                [NAME, "some-name", "--api-key", "some-api-key"]
+                    .iter()
+                    .chain(args),
+            )
+            .expect("failed to parse CLI arguments")
+        }
+
+        let denied = std::env::current_exe().unwrap().display().to_string();
+        let policy = policy(&format!("deny_commands = [{denied:?}]"));
+        let denied = format!("{denied} --version");
+
+        assert_eq!(policy.check_cli(&cli(&["--", "true"])), Ok(()));
+
+        for args in [
+            vec!["--cron", "--identifier-cmd", &denied, "--", "true"],
+            vec!["--heartbeat", "--health-cmd", &denied, "--", "true"],
+            vec!["--on-failure", &denied, "--", "true"],
+            vec!["--shell", &denied],
+        ] {
+            assert!(policy.check_cli(&cli(&args)).is_err(), "{args:?}");
+        }
+
+        let policy = self::policy(r#"deny_commands = ["/bin/sh"]"#);
+        let cli = cli(&["--on-failure", "./diagnose.sh", "--", "true"]);
+        assert!(policy.check_cli(&cli).is_err());
+    }
+
+    #[test]
+    fn policy_check_options() {
+        let policy = policy(r#"deny_options = ["user", "env"]"#);
+
+        assert_eq!(policy.check_options(["cron", "no-log"]), Ok(()));
+        assert_eq!(
+            policy.check_options(["cron", "user"]),
+            Err("the policy in policy.toml does not allow the --user option".to_string())
+        );
+    }
+
+    #[test]
+    fn policy_parse_errors() {
+        assert!(Policy::parse(Path::new("policy.toml"), "allow_options = []").is_err());
+        assert!(Policy::parse(Path::new("policy.toml"), "deny_commands = \"/bin/sh\"").is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use std::io;
+
+use clap::ValueEnum;
+use libc::c_int;
+use serde::Deserialize;
+
+// How much to lower the scheduling priority of the wrapper's own work, such
+// as matching patterns against the command's output and compressing
+// requests, so that it does not compete with the command on a constrained
+// host. The command's priority is not changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapperPriority {
+    // A niceness of at least 10, and the lowest best-effort I/O priority.
+    Low,
+    // Only use the CPU and disk when nothing else is using them.
+    Idle,
+}
+
+const LOW_NICENESS: c_int = 10;
+
+impl WrapperPriority {
+    // Lowers the priority of every thread of the wrapper. On Linux, the
+    // priority is set per thread, and threads started later inherit the
+    // priority of the thread that starts them.
+    #[cfg(target_os = "linux")]
+    pub fn apply(self) -> io::Result<()> {
+        for entry in std::fs::read_dir("/proc/self/task")? {
+            let tid = entry?.file_name().to_str().and_then(|tid| tid.parse().ok());
+
+            if let Some(tid) = tid {
+                self.apply_to_thread(tid)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn apply_to_thread(self, tid: libc::pid_t) -> io::Result<()> {
+        // From `linux/ioprio.h`.
+        const IOPRIO_WHO_PROCESS: c_int = 1;
+        const IOPRIO_CLASS_SHIFT: c_int = 13;
+        const IOPRIO_CLASS_BE: c_int = 2;
+        const IOPRIO_CLASS_IDLE: c_int = 3;
+        const IOPRIO_BE_LOWEST: c_int = 7;
+
+        let ioprio = match self {
+            Self::Low => {
+                lower_niceness(tid as libc::id_t, LOW_NICENESS)?;
+                IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT | IOPRIO_BE_LOWEST
+            }
+            Self::Idle => {
+                let param = libc::sched_param { sched_priority: 0 };
+                check(unsafe { libc::sched_setscheduler(tid, libc::SCHED_IDLE, &param) })?;
+                IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT
+            }
+        };
+
+        let result =
+            unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, tid, ioprio) };
+        check(result as c_int)
+    }
+
+    // macOS has neither per-thread priorities nor I/O priority classes, so
+    // the niceness of the whole process is lowered.
+    #[cfg(target_os = "macos")]
+    pub fn apply(self) -> io::Result<()> {
+        let niceness = match self {
+            Self::Low => LOW_NICENESS,
+            Self::Idle => 20,
+        };
+
+        lower_niceness(0, niceness)
+    }
+}
+
+// Sets the niceness of the given thread or process, unless it is already
+// higher, as only privileged processes can lower their niceness.
+fn lower_niceness(who: libc::id_t, niceness: c_int) -> io::Result<()> {
+    use nix::errno::Errno;
+
+    // The niceness can be -1, so errors are told apart by `errno`.
+    Errno::clear();
+    let current = unsafe { libc::getpriority(libc::PRIO_PROCESS, who) };
+    if current == -1 && Errno::last_raw() != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if current >= niceness {
+        return Ok(());
+    }
+
+    check(unsafe { libc::setpriority(libc::PRIO_PROCESS, who, niceness) })
+}
+
+fn check(result: c_int) -> io::Result<()> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
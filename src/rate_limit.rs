@@ -0,0 +1,235 @@
+// Limits the rate at which lines of output are sent as logs, so that a
+// command that writes lines in a tight loop cannot use up the logging quota
+// of the AppSignal app. Lines over the limit are dropped, and a log message
+// reporting their number is sent in their place.
+//
+// The limit is given as an amount per period, such as `100/s` for a hundred
+// lines per second, or `1MB/min` for a megabyte of lines per minute. Bursts
+// of up to the full amount are allowed, as long as the average rate stays
+// below the limit.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct LogRateLimit {
+    amount: u64,
+    unit: RateUnit,
+    period: RatePeriod,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RateUnit {
+    Lines,
+    Bytes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RatePeriod {
+    Second,
+    Minute,
+    Hour,
+}
+
+impl RatePeriod {
+    fn duration(&self) -> Duration {
+        match self {
+            Self::Second => Duration::from_secs(1),
+            Self::Minute => Duration::from_secs(60),
+            Self::Hour => Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl LogRateLimit {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        Self::try_from(value.to_string())
+    }
+}
+
+impl TryFrom<String> for LogRateLimit {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || {
+            format!(
+                "invalid log rate limit `{value}`; expected a number of lines or bytes \
+                per second, minute or hour, such as `100/s` or `1MB/min`"
+            )
+        };
+
+        let (amount, period) = value.split_once('/').ok_or_else(invalid)?;
+
+        let digits = amount
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(amount.len());
+        let (number, suffix) = amount.split_at(digits);
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+
+        let (unit, multiplier) = match suffix.trim().to_ascii_lowercase().as_str() {
+            "" | "lines" => (RateUnit::Lines, 1),
+            "b" => (RateUnit::Bytes, 1),
+            "kb" => (RateUnit::Bytes, 1_000),
+            "mb" => (RateUnit::Bytes, 1_000_000),
+            "gb" => (RateUnit::Bytes, 1_000_000_000),
+            _ => return Err(invalid()),
+        };
+
+        let period = match period.trim().to_ascii_lowercase().as_str() {
+            "s" | "sec" | "second" => RatePeriod::Second,
+            "m" | "min" | "minute" => RatePeriod::Minute,
+            "h" | "hour" => RatePeriod::Hour,
+            _ => return Err(invalid()),
+        };
+
+        let amount = number.checked_mul(multiplier).ok_or_else(invalid)?;
+
+        if amount == 0 {
+            return Err(format!(
+                "invalid log rate limit `{value}`; the limit must be greater than zero"
+            ));
+        }
+
+        Ok(Self {
+            amount,
+            unit,
+            period,
+        })
+    }
+}
+
+impl fmt::Display for LogRateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.unit {
+            RateUnit::Lines => "lines",
+            RateUnit::Bytes => "bytes",
+        };
+
+        let period = match self.period {
+            RatePeriod::Second => "second",
+            RatePeriod::Minute => "minute",
+            RatePeriod::Hour => "hour",
+        };
+
+        write!(f, "{} {} per {}", self.amount, unit, period)
+    }
+}
+
+// A token bucket, which holds up to the limit's amount of tokens, and is
+// refilled at the limit's rate. Each line takes one token, or one token per
+// byte, depending on the limit's unit.
+pub struct RateLimiter {
+    limit: LogRateLimit,
+    tokens: f64,
+    updated: Instant,
+    dropped: usize,
+}
+
+impl RateLimiter {
+    pub fn new(limit: LogRateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.amount as f64,
+            updated: Instant::now(),
+            dropped: 0,
+        }
+    }
+
+    pub fn limit(&self) -> LogRateLimit {
+        self.limit
+    }
+
+    // Whether the line is within the limit at the given time. Lines that
+    // are not are counted as dropped.
+    pub fn allow(&mut self, line: &str, now: Instant) -> bool {
+        let capacity = self.limit.amount as f64;
+        let elapsed = now.saturating_duration_since(self.updated);
+        let refill = elapsed.as_secs_f64() / self.limit.period.duration().as_secs_f64();
+
+        self.tokens = (self.tokens + refill * capacity).min(capacity);
+        self.updated = now;
+
+        // A line longer than the limit is allowed once the bucket is full,
+        // so that it is not dropped forever.
+        let cost = match self.limit.unit {
+            RateUnit::Lines => 1.0,
+            RateUnit::Bytes => (line.len() as f64).min(capacity),
+        };
+
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            true
+        } else {
+            self.dropped += 1;
+            false
+        }
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    // Returns the number of lines dropped since the last call, and resets it.
+    pub fn take_dropped(&mut self) -> usize {
+        std::mem::take(&mut self.dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_rate_limit_parse() {
+        for (value, expected) in [
+            ("100/s", "100 lines per second"),
+            ("5 lines/min", "5 lines per minute"),
+            ("512B/sec", "512 bytes per second"),
+            ("1MB/min", "1000000 bytes per minute"),
+            ("2kb/hour", "2000 bytes per hour"),
+        ] {
+            assert_eq!(
+                LogRateLimit::parse(value).unwrap().to_string(),
+                expected,
+                "{value:?}"
+            );
+        }
+
+        for value in ["100", "0/s", "-1/s", "10/day", "10XB/s", "/s", "MB/s"] {
+            assert!(LogRateLimit::parse(value).is_err(), "{value:?} should fail");
+        }
+    }
+
+    #[test]
+    fn rate_limiter_allow() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(LogRateLimit::parse("2/s").unwrap());
+        limiter.updated = start;
+
+        assert!(limiter.allow("first", start));
+        assert!(limiter.allow("second", start));
+        assert!(!limiter.allow("third", start));
+        assert!(!limiter.allow("fourth", start));
+        assert_eq!(limiter.take_dropped(), 2);
+        assert_eq!(limiter.take_dropped(), 0);
+
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.allow("fifth", later));
+        assert!(!limiter.allow("sixth", later));
+
+        let mut limiter = RateLimiter::new(LogRateLimit::parse("10B/s").unwrap());
+        limiter.updated = start;
+
+        assert!(limiter.allow("123456", start));
+        assert!(!limiter.allow("123456", start));
+        assert!(limiter.allow("1234", start));
+
+        // A line longer than the limit is allowed once the bucket is full.
+        let later = start + Duration::from_secs(1);
+        assert!(limiter.allow("a line longer than ten bytes", later));
+        assert!(!limiter.allow("1", later));
+    }
+}
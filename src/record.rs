@@ -0,0 +1,465 @@
+// Records the requests sent to AppSignal, when `--record` is set, and sends
+// them again with the `replay` subcommand, so that the data a wrapper sent
+// can be shared and inspected, such as in a support request.
+//
+// Each request is written to its own JSON file in the directory, with its
+// method, URL, headers and body. The files are named after the time the
+// request was sent, so that they are replayed in the order they were sent.
+// Compressed bodies are written decompressed, so that they can be read, and
+// compressed again when replayed.
+//
+// The API key is redacted from the recorded URL, so that the files can be
+// shared. An API key to replay the requests with must be given instead. The
+// values of headers that may hold credentials, such as the `Authorization`
+// header of the spans sent to an OpenTelemetry collector, are redacted too.
+//
+// Requests recorded too long ago are not replayed, so that stale check-ins
+// and data are not sent as if they were new. The errors, logs and metrics in
+// the requests that are replayed are tagged with `replayed`, so that the
+// data sent again is not mistaken for data sent live.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use ::log::{debug, warn};
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::{Method, Request, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::client::{client, is_compressed, text_body, with_body};
+
+const REDACTED: &str = "REDACTED";
+// The parts of the names of headers whose values are redacted.
+const SENSITIVE_HEADERS: &[&str] = &["auth", "key", "token", "secret", "password", "cookie"];
+// The tag added to the errors, logs and metrics in replayed requests.
+const REPLAYED_TAG: &str = "replayed";
+
+// How long ago a request can have been recorded to be replayed.
+pub const DEFAULT_REPLAY_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+// Keeps the names of requests recorded in the same millisecond apart.
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    // When the request was sent, in RFC 3339 format.
+    pub timestamp: String,
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<String>,
+    // Whether the body was compressed when it was sent.
+    pub compressed: bool,
+}
+
+impl RecordedRequest {
+    pub fn new(request: &Request, timestamp: DateTime<Utc>) -> Self {
+        let url = with_api_key(request.url(), REDACTED);
+
+        let headers = request
+            .headers()
+            .iter()
+            .filter(|(name, _)| *name != "content-encoding")
+            .filter_map(|(name, value)| {
+                let value = match is_sensitive_header(name.as_str()) {
+                    true => REDACTED,
+                    false => value.to_str().ok()?,
+                };
+
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect();
+
+        Self {
+            timestamp: timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+            method: request.method().to_string(),
+            url: url.to_string(),
+            headers,
+            body: text_body(request),
+            compressed: is_compressed(request),
+        }
+    }
+
+    // How long before the given time the request was recorded.
+    pub fn age(&self, now: DateTime<Utc>) -> Result<Duration, String> {
+        let timestamp = DateTime::parse_from_rfc3339(&self.timestamp)
+            .map_err(|err| format!("invalid recorded request timestamp: {err}"))?;
+
+        Ok((now - timestamp.with_timezone(&Utc))
+            .to_std()
+            .unwrap_or_default())
+    }
+
+    // Adds the `replayed` tag to the errors, logs and metrics in the body.
+    // Other bodies, such as those of check-ins, which have no tags, are left
+    // as they are.
+    pub fn mark_replayed(&mut self) {
+        let Some(body) = self.body.as_deref() else {
+            return;
+        };
+
+        let path = Url::parse(&self.url)
+            .map(|url| url.path().to_string())
+            .unwrap_or_default();
+
+        let marked = if path.ends_with("/errors") {
+            mark_json(body, |error| add_tag(error, "tags"))
+        } else if path.ends_with("/logs/json") {
+            body.lines()
+                .map(|line| mark_json(line, |message| add_tag(message, "attributes")))
+                .collect::<Option<Vec<_>>>()
+                .map(|lines| lines.into_iter().map(|line| line + "\n").collect())
+        } else if path.ends_with("/metrics/json") {
+            mark_json(body, |metrics| {
+                for metric in metrics.as_array_mut().into_iter().flatten() {
+                    add_tag(metric, "tags");
+                }
+            })
+        } else {
+            None
+        };
+
+        if let Some(marked) = marked {
+            self.body = Some(marked);
+        }
+    }
+
+    // Builds the request to send it again, with the given API key in place
+    // of the redacted one. If an endpoint is given, the request is sent to
+    // it instead of the one it was recorded for.
+    pub fn request(&self, api_key: &str, endpoint: Option<&str>) -> Result<Request, String> {
+        let invalid = |err: &dyn std::fmt::Display| format!("invalid recorded request: {err}");
+
+        let url = Url::parse(&self.url).map_err(|err| invalid(&err))?;
+        let mut url = with_api_key(&url, api_key);
+
+        if let Some(endpoint) = endpoint {
+            let query = url.query().map(|query| format!("?{query}"));
+            let endpoint = endpoint.trim_end_matches('/');
+            url = Url::parse(&format!(
+                "{}{}{}",
+                endpoint,
+                url.path(),
+                query.unwrap_or_default()
+            ))
+            .map_err(|err| invalid(&err))?;
+        }
+
+        let method = Method::from_bytes(self.method.as_bytes()).map_err(|err| invalid(&err))?;
+        let mut builder = client().request(method, url);
+
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+
+        if let Some(body) = self.body.clone() {
+            builder = with_body(builder, body, self.compressed);
+        }
+
+        builder.build().map_err(|err| invalid(&err))
+    }
+}
+
+// Header names are lowercase, as they are normalized by `reqwest`.
+fn is_sensitive_header(name: &str) -> bool {
+    SENSITIVE_HEADERS.iter().any(|part| name.contains(part))
+}
+
+// Parses the JSON document, changes it and serializes it again. Returns
+// `None` if it is not valid JSON.
+fn mark_json(json: &str, mark: impl FnOnce(&mut Value)) -> Option<String> {
+    let mut value: Value = serde_json::from_str(json).ok()?;
+    mark(&mut value);
+    serde_json::to_string(&value).ok()
+}
+
+// Adds the `replayed` tag to the object's tags or attributes, under the
+// given key, creating them if they were left out because there were none.
+fn add_tag(value: &mut Value, key: &str) {
+    let Some(object) = value.as_object_mut() else {
+        return;
+    };
+
+    let tags = object
+        .entry(key)
+        .or_insert_with(|| Value::Object(Default::default()));
+
+    if let Some(tags) = tags.as_object_mut() {
+        tags.insert(REPLAYED_TAG.to_string(), Value::from("true"));
+    }
+}
+
+// Returns the URL with the value of its `api_key` parameter, if any,
+// replaced by the given one.
+fn with_api_key(url: &Url, api_key: &str) -> Url {
+    let mut url = url.clone();
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(key, value)| match key.as_ref() {
+            "api_key" => (key.into_owned(), api_key.to_string()),
+            _ => (key.into_owned(), value.into_owned()),
+        })
+        .collect();
+
+    if !query.is_empty() {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+
+    url
+}
+
+pub fn init(dir: PathBuf) {
+    let _ = DIR.set(dir);
+}
+
+// Writes the request to the recording directory, if one is set. Requests
+// that cannot be written are not recorded, but are still sent.
+pub fn record(request: &Request) {
+    let Some(dir) = DIR.get() else {
+        return;
+    };
+
+    let now = Utc::now();
+    let recorded = RecordedRequest::new(request, now);
+    let name = format!(
+        "{}-{}-{:04}.json",
+        now.format("%Y%m%dT%H%M%S%.3fZ"),
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let written = std::fs::create_dir_all(dir).and_then(|_| {
+        let contents = serde_json::to_string_pretty(&recorded)?;
+        std::fs::write(dir.join(&name), contents)
+    });
+
+    if let Err(err) = written {
+        debug!("error recording request to {}: {}", dir.display(), err);
+    }
+}
+
+// Reads the recorded requests in the directory, in the order they were
+// sent. Files that cannot be read are reported as warnings and skipped.
+pub fn read(dir: &Path) -> Result<Vec<(PathBuf, RecordedRequest)>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|err| {
+        format!(
+            "could not read recording directory {}: {}",
+            dir.display(),
+            err
+        )
+    })?;
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    paths.sort();
+
+    let requests = paths
+        .into_iter()
+        .filter_map(|path| {
+            let parsed = std::fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|contents| {
+                    serde_json::from_str(&contents).map_err(|err| err.to_string())
+                });
+
+            match parsed {
+                Ok(request) => Some((path, request)),
+                Err(err) => {
+                    warn!(
+                        "could not read recorded request {}: {}; skipping it",
+                        path.display(),
+                        err
+                    );
+                    None
+                }
+            }
+        })
+        .collect();
+
+    Ok(requests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> Request {
+        with_body(
+            client()
+                .post("https://appsignal-endpoint.net/logs/json?api_key=secret&kind=start")
+                .header("Content-Type", "application/x-ndjson"),
+            "{\"message\":\"one\"}\n".to_string(),
+            true,
+        )
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn record_redacts_api_key() {
+        let timestamp = DateTime::from_timestamp(1_000_000_000, 0).unwrap();
+        let recorded = RecordedRequest::new(&request(), timestamp);
+
+        assert_eq!(
+            recorded,
+            RecordedRequest {
+                timestamp: "2001-09-09T01:46:40.000Z".to_string(),
+                method: "POST".to_string(),
+                url: "https://appsignal-endpoint.net/logs/json?api_key=REDACTED&kind=start"
+                    .to_string(),
+                headers: BTreeMap::from([(
+                    "content-type".to_string(),
+                    "application/x-ndjson".to_string()
+                )]),
+                body: Some("{\"message\":\"one\"}\n".to_string()),
+                compressed: true,
+            }
+        );
+    }
+
+    #[test]
+    fn record_redacts_sensitive_headers() {
+        let request = client()
+            .post("http://collector:4318/v1/traces")
+            .header("Authorization", "Bearer secret")
+            .header("X-Api-Key", "secret")
+            .header("X-Scope-OrgID", "tenant")
+            .header("Content-Type", "application/json")
+            .build()
+            .unwrap();
+
+        let timestamp = DateTime::from_timestamp(1_000_000_000, 0).unwrap();
+        let recorded = RecordedRequest::new(&request, timestamp);
+
+        assert_eq!(
+            recorded.headers,
+            BTreeMap::from([
+                ("authorization".to_string(), "REDACTED".to_string()),
+                ("content-type".to_string(), "application/json".to_string()),
+                ("x-api-key".to_string(), "REDACTED".to_string()),
+                ("x-scope-orgid".to_string(), "tenant".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn record_replay_request() {
+        let timestamp = DateTime::from_timestamp(1_000_000_000, 0).unwrap();
+        let recorded = RecordedRequest::new(&request(), timestamp);
+
+        let replayed = recorded.request("other-key", None).unwrap();
+        assert_eq!(
+            replayed.url().as_str(),
+            "https://appsignal-endpoint.net/logs/json?api_key=other-key&kind=start"
+        );
+        assert!(is_compressed(&replayed));
+        assert_eq!(
+            text_body(&replayed),
+            Some("{\"message\":\"one\"}\n".to_string())
+        );
+        assert_eq!(replayed.headers()["content-type"], "application/x-ndjson");
+
+        let replayed = recorded
+            .request("other-key", Some("http://127.0.0.1:8080/"))
+            .unwrap();
+        assert_eq!(
+            replayed.url().as_str(),
+            "http://127.0.0.1:8080/logs/json?api_key=other-key&kind=start"
+        );
+    }
+
+    #[test]
+    fn record_age() {
+        let timestamp = DateTime::from_timestamp(1_000_000_000, 0).unwrap();
+        let recorded = RecordedRequest::new(&request(), timestamp);
+
+        let now = DateTime::from_timestamp(1_000_000_090, 0).unwrap();
+        assert_eq!(recorded.age(now), Ok(Duration::from_secs(90)));
+        assert_eq!(recorded.age(timestamp), Ok(Duration::ZERO));
+
+        let invalid = RecordedRequest {
+            timestamp: "yesterday".to_string(),
+            ..recorded
+        };
+        assert!(invalid.age(now).is_err());
+    }
+
+    #[test]
+    fn record_mark_replayed() {
+        let recorded = |path: &str, body: &str| RecordedRequest {
+            timestamp: "2001-09-09T01:46:40.000Z".to_string(),
+            method: "POST".to_string(),
+            url: format!("https://appsignal-endpoint.net{path}?api_key=REDACTED"),
+            headers: BTreeMap::new(),
+            body: Some(body.to_string()),
+            compressed: false,
+        };
+
+        for (path, body, expected) in [
+            (
+                "/errors",
+                r#"{"action":"job","tags":{"hostname":"host"}}"#,
+                r#"{"action":"job","tags":{"hostname":"host","replayed":"true"}}"#,
+            ),
+            (
+                "/logs/json",
+                "{\"message\":\"one\"}\n{\"attributes\":{\"a\":\"b\"}}\n",
+                "{\"attributes\":{\"replayed\":\"true\"},\"message\":\"one\"}\n\
+                 {\"attributes\":{\"a\":\"b\",\"replayed\":\"true\"}}\n",
+            ),
+            (
+                "/metrics/json",
+                r#"[{"name":"process_exit","tags":{}}]"#,
+                r#"[{"name":"process_exit","tags":{"replayed":"true"}}]"#,
+            ),
+            (
+                "/check_ins/cron",
+                r#"{"identifier":"job"}"#,
+                r#"{"identifier":"job"}"#,
+            ),
+            ("/errors", "not JSON", "not JSON"),
+        ] {
+            let mut request = recorded(path, body);
+            request.mark_replayed();
+            assert_eq!(request.body.as_deref(), Some(expected), "{path}: {body}");
+        }
+    }
+
+    #[test]
+    fn record_read_in_order() {
+        let dir = std::env::temp_dir().join(format!("{}-record-test", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for (name, seconds) in [("b.json", 2), ("a.json", 1)] {
+            let timestamp = DateTime::from_timestamp(seconds, 0).unwrap();
+            let recorded = RecordedRequest::new(&request(), timestamp);
+            std::fs::write(dir.join(name), serde_json::to_string(&recorded).unwrap()).unwrap();
+        }
+        std::fs::write(dir.join("c.json"), "not JSON").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a request").unwrap();
+
+        let requests = read(&dir).unwrap();
+        let timestamps: Vec<&str> = requests
+            .iter()
+            .map(|(_, request)| request.timestamp.as_str())
+            .collect();
+        assert_eq!(
+            timestamps,
+            vec!["1970-01-01T00:00:01.000Z", "1970-01-01T00:00:02.000Z"]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(read(&dir).is_err());
+    }
+}
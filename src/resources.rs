@@ -0,0 +1,91 @@
+// Reads the resource usage of a running process from `/proc`. This is only
+// available on Linux. Elsewhere, reading the resource usage fails, and no
+// resource usage metrics are sent.
+
+use std::io;
+use std::time::Duration;
+
+// How often the resource usage of the command is sent as metrics.
+pub const RESOURCE_USAGE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ResourceUsage {
+    // The CPU time spent by the process, in user and system mode.
+    pub cpu_time: Duration,
+    // The resident set size of the process, in bytes.
+    pub rss_bytes: u64,
+    pub open_fds: u64,
+}
+
+impl ResourceUsage {
+    pub fn read(pid: u32) -> io::Result<Self> {
+        let stat = std::fs::read_to_string(format!("/proc/{pid}/stat"))?;
+        let statm = std::fs::read_to_string(format!("/proc/{pid}/statm"))?;
+        let open_fds = std::fs::read_dir(format!("/proc/{pid}/fd"))?.count() as u64;
+
+        let (cpu_ticks, rss_pages) = parse_stat(&stat)
+            .zip(parse_statm(&statm))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid /proc contents"))?;
+
+        // SAFETY: `sysconf` has no preconditions.
+        let (ticks_per_sec, page_size) = unsafe {
+            (
+                libc::sysconf(libc::_SC_CLK_TCK),
+                libc::sysconf(libc::_SC_PAGESIZE),
+            )
+        };
+
+        Ok(ResourceUsage {
+            cpu_time: Duration::from_secs_f64(cpu_ticks as f64 / ticks_per_sec.max(1) as f64),
+            rss_bytes: rss_pages * page_size.max(0) as u64,
+            open_fds,
+        })
+    }
+}
+
+// Returns the user and system CPU time, in clock ticks, from the contents of
+// `/proc/<pid>/stat`. The command name, in the second field, may contain
+// spaces and parentheses, so the fields are read after its last `)`.
+fn parse_stat(stat: &str) -> Option<u64> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    let mut fields = fields.split_whitespace();
+
+    // After the command name, `utime` and `stime` are the 12th and 13th
+    // fields.
+    let utime: u64 = fields.nth(11)?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+
+    Some(utime + stime)
+}
+
+// Returns the resident set size, in pages, from the contents of
+// `/proc/<pid>/statm`.
+fn parse_statm(statm: &str) -> Option<u64> {
+    statm.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_proc_contents() {
+        let stat = "1234 (some (weird) cmd) S 1 1234 1234 0 -1 4194560 \
+            1500 0 0 0 250 50 0 0 20 0 1 0 12345 10000000 500";
+
+        assert_eq!(parse_stat(stat), Some(300));
+        assert_eq!(parse_stat("1234 (cmd) S 1"), None);
+
+        assert_eq!(parse_statm("2500 700 300 10 0 400 0\n"), Some(700));
+        assert_eq!(parse_statm(""), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn read_own_resource_usage() {
+        let usage = ResourceUsage::read(std::process::id()).unwrap();
+
+        assert!(usage.rss_bytes > 0);
+        assert!(usage.open_fds > 0);
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::units::parse_duration;
+
+const DEFAULT_MAX_RESTARTS: u32 = 5;
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(10);
+
+// When the command is restarted after it exits. Only failures, as decided
+// by the failure exit codes, are restarted, and only up to the given number
+// of times, waiting for the backoff duration before each restart.
+//
+// Given as `on-failure`, optionally followed by the maximum number of
+// restarts and the backoff duration, such as `on-failure:max=3,backoff=1m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct RestartPolicy {
+    pub max_restarts: u32,
+    pub backoff: Duration,
+}
+
+impl RestartPolicy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "invalid restart policy `{value}`; expected `on-failure`, \
+                optionally followed by options such as `:max=5,backoff=10s`"
+            )
+        };
+
+        let (mode, options) = match value.split_once(':') {
+            Some((mode, options)) => (mode, Some(options)),
+            None => (value, None),
+        };
+
+        if mode != "on-failure" {
+            return Err(invalid());
+        }
+
+        let mut policy = Self {
+            max_restarts: DEFAULT_MAX_RESTARTS,
+            backoff: DEFAULT_BACKOFF,
+        };
+
+        for option in options.into_iter().flat_map(|options| options.split(',')) {
+            match option.trim().split_once('=') {
+                Some(("max", max)) => {
+                    policy.max_restarts = max.trim().parse().map_err(|_| invalid())?;
+                }
+                Some(("backoff", backoff)) => {
+                    policy.backoff = parse_duration(backoff).map_err(|_| invalid())?;
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(policy)
+    }
+}
+
+impl TryFrom<String> for RestartPolicy {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_restarts: u32, backoff: u64) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts,
+            backoff: Duration::from_secs(backoff),
+        }
+    }
+
+    #[test]
+    fn parse_restart_policy() {
+        for (value, expected) in [
+            ("on-failure", policy(5, 10)),
+            ("on-failure:max=3", policy(3, 10)),
+            ("on-failure:backoff=30", policy(5, 30)),
+            ("on-failure:max=0,backoff=2m", policy(0, 120)),
+            ("on-failure:backoff=1h, max=1", policy(1, 3600)),
+            ("on-failure:backoff=1m30s", policy(5, 90)),
+        ] {
+            assert_eq!(RestartPolicy::parse(value), Ok(expected), "{value:?}");
+        }
+
+        for value in [
+            "",
+            "always",
+            "on-failure:",
+            "on-failure:max=-1",
+            "on-failure:backoff=10d",
+            "on-failure:backoff=s",
+            "on-failure:delay=10s",
+        ] {
+            assert!(
+                RestartPolicy::parse(value).is_err(),
+                "{value:?} should fail"
+            );
+        }
+    }
+}
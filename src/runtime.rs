@@ -0,0 +1,32 @@
+use std::io;
+use std::num::NonZeroUsize;
+use std::thread::available_parallelism;
+
+use ::log::debug;
+use tokio::runtime::{Builder, Runtime};
+
+// Builds the Tokio runtime used to execute and monitor the command.
+//
+// By default, a multi-threaded runtime is used, with as many worker threads
+// as CPUs are available to the wrapper. Unlike the number of CPUs in the
+// host, `available_parallelism` takes into account the CPU quota of the
+// cgroup the wrapper runs in, as well as its CPU affinity mask, so that the
+// runtime does not spawn dozens of workers in a container limited to a
+// fraction of a CPU.
+//
+// When `single_thread` is set, a current-thread runtime is used instead.
+pub fn runtime(single_thread: bool) -> io::Result<Runtime> {
+    let mut builder = if single_thread {
+        debug!("using current-thread runtime");
+        Builder::new_current_thread()
+    } else {
+        let worker_threads = available_parallelism().map_or(1, NonZeroUsize::get);
+        debug!("using multi-thread runtime with {} workers", worker_threads);
+
+        let mut builder = Builder::new_multi_thread();
+        builder.worker_threads(worker_threads);
+        builder
+    };
+
+    builder.enable_all().build()
+}
@@ -0,0 +1,148 @@
+use ::log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::state::StateDir;
+
+// Samples the errors reported for commands that fail very frequently,
+// such as commands that are run every few seconds.
+//
+// The first failure after a success is always reported. After that, only
+// one in every `rate` consecutive failures is reported, alongside the
+// number of failures that were suppressed since the last reported one.
+//
+// The number of consecutive failures is persisted in the state directory,
+// as each run of the command is a separate invocation of the wrapper.
+#[derive(Clone)]
+pub struct ErrorSampler {
+    pub rate: u64,
+    pub state_dir: StateDir,
+    pub action: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct SampleState {
+    failures: u64,
+    suppressed: u64,
+}
+
+impl SampleState {
+    fn failure(&mut self, rate: u64) -> Option<u64> {
+        self.failures += 1;
+
+        if self.failures == 1 || self.suppressed + 1 >= rate {
+            Some(std::mem::take(&mut self.suppressed))
+        } else {
+            self.suppressed += 1;
+            None
+        }
+    }
+}
+
+impl ErrorSampler {
+    // Records a failure of the command. If the error for this failure should
+    // be reported, returns the number of errors suppressed since the last
+    // reported one.
+    pub fn failure(&self) -> Option<u64> {
+        let mut state: SampleState = self.state_dir.read(&self.file_name());
+        let result = state.failure(self.rate);
+
+        self.write(&state);
+
+        if result.is_none() {
+            debug!(
+                "suppressing error report for {} ({} consecutive failures)",
+                self.action, state.failures
+            );
+        }
+
+        result
+    }
+
+    // Records a success of the command, so that the next failure is reported.
+    pub fn success(&self) {
+        let state: SampleState = self.state_dir.read(&self.file_name());
+
+        if state != SampleState::default() {
+            self.write(&SampleState::default());
+        }
+    }
+
+    fn write(&self, state: &SampleState) {
+        if let Err(err) = self.state_dir.write(&self.file_name(), state) {
+            debug!("error writing error sampling state: {}", err);
+        }
+    }
+
+    fn file_name(&self) -> String {
+        format!("error-sample-{}.json", hex::encode(&self.action))
+    }
+}
+
+// Parses an error sample rate in the form `1/N`.
+pub fn parse_rate(value: &str) -> Result<u64, String> {
+    value
+        .strip_prefix("1/")
+        .and_then(|rate| rate.parse::<u64>().ok())
+        .filter(|rate| *rate > 0)
+        .ok_or_else(|| "the sample rate must be given as 1/N, such as 1/10".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_state_failure() {
+        let mut state = SampleState::default();
+
+        let reported: Vec<Option<u64>> = (0..12).map(|_| state.failure(5)).collect();
+
+        assert_eq!(
+            reported,
+            vec![
+                Some(0),
+                None,
+                None,
+                None,
+                None,
+                Some(4),
+                None,
+                None,
+                None,
+                None,
+                Some(4),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn error_sampler_success_resets_failures() {
+        let path = std::env::temp_dir().join(format!("{}-sample-test", std::process::id()));
+        let sampler = ErrorSampler {
+            rate: 10,
+            state_dir: StateDir::new(&path),
+            action: "some-action".to_string(),
+        };
+
+        assert_eq!(sampler.failure(), Some(0));
+        assert_eq!(sampler.failure(), None);
+        assert_eq!(sampler.failure(), None);
+
+        sampler.success();
+
+        assert_eq!(sampler.failure(), Some(0));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn parse_sample_rate() {
+        assert_eq!(parse_rate("1/10"), Ok(10));
+        assert_eq!(parse_rate("1/1"), Ok(1));
+
+        for value in ["10", "1/0", "2/10", "1/", "1/ten"] {
+            assert!(parse_rate(value).is_err());
+        }
+    }
+}
@@ -0,0 +1,240 @@
+// Parses cron schedules, given as five fields separated by whitespace, such
+// as `*/15 9-17 * * mon-fri`, in order to tell when the command was last
+// expected to start.
+//
+// Each field can be `*`, a value, a range (`9-17`), any of those with a
+// step (`*/15`, `9-17/2`), or a comma-separated list of them. Months and
+// days of the week can also be given by their three-letter names. As in
+// most cron implementations, when both the day of the month and the day of
+// the week are restricted, a day matches if either of them does.
+
+use chrono::{Datelike, Duration, NaiveDateTime, Timelike};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: Field,
+    hours: Field,
+    days: Field,
+    months: Field,
+    weekdays: Field,
+}
+
+// The values that a field matches, as a bit set, and whether it was `*`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Field {
+    values: u64,
+    any: bool,
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// How far back to look for the last scheduled time. Schedules that match
+// less often than once a year, such as ones for the 29th of February, may
+// not be found.
+const MAX_LOOKBACK_DAYS: i64 = 366;
+
+impl CronSchedule {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = value.split_whitespace().collect();
+
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "invalid cron schedule `{value}`; expected five fields, such as `15 3 * * *`"
+            ));
+        };
+
+        let invalid = |field: &str| format!("invalid cron schedule `{value}`: invalid {field}");
+
+        let mut weekdays =
+            Field::parse(weekdays, 0, 7, &WEEKDAYS).ok_or_else(|| invalid("day of the week"))?;
+
+        // Both 0 and 7 are Sunday.
+        if weekdays.values & (1 << 7) != 0 {
+            weekdays.values = (weekdays.values | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: Field::parse(minutes, 0, 59, &[]).ok_or_else(|| invalid("minute"))?,
+            hours: Field::parse(hours, 0, 23, &[]).ok_or_else(|| invalid("hour"))?,
+            days: Field::parse(days, 1, 31, &[]).ok_or_else(|| invalid("day of the month"))?,
+            months: Field::parse(months, 1, 12, &MONTHS).ok_or_else(|| invalid("month"))?,
+            weekdays,
+        })
+    }
+
+    // Returns the last time, at or before the given time, at which the
+    // schedule matches.
+    pub fn last_before(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let earliest = time - Duration::days(MAX_LOOKBACK_DAYS);
+        let mut time = time.with_second(0)?.with_nanosecond(0)?;
+
+        while time >= earliest {
+            if !self.matches_day(time) {
+                // Skip to the last minute of the previous day.
+                time = time.date().and_hms_opt(0, 0, 0)? - Duration::minutes(1);
+            } else if !self.hours.contains(time.hour()) {
+                // Skip to the last minute of the previous hour.
+                time = time.with_minute(0)? - Duration::minutes(1);
+            } else if !self.minutes.contains(time.minute()) {
+                time -= Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+
+        None
+    }
+
+    // Whether the schedule matches the minute of the given time.
+    pub fn matches(&self, time: NaiveDateTime) -> bool {
+        self.matches_day(time)
+            && self.hours.contains(time.hour())
+            && self.minutes.contains(time.minute())
+    }
+
+    fn matches_day(&self, time: NaiveDateTime) -> bool {
+        if !self.months.contains(time.month()) {
+            return false;
+        }
+
+        let day = self.days.contains(time.day());
+        let weekday = self
+            .weekdays
+            .contains(time.weekday().num_days_from_sunday());
+
+        match (self.days.any, self.weekdays.any) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl Field {
+    fn parse(value: &str, min: u32, max: u32, names: &[&str]) -> Option<Self> {
+        let mut values = 0;
+
+        for part in value.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, Some(step.parse::<u32>().ok()?)),
+                None => (part, None),
+            };
+
+            let (start, end) = match range.split_once('-') {
+                _ if range == "*" => (min, max),
+                Some((start, end)) => (
+                    parse_value(start, min, names)?,
+                    parse_value(end, min, names)?,
+                ),
+                None => {
+                    let start = parse_value(range, min, names)?;
+                    // A single value with a step, such as `5/15`, is a range
+                    // up to the maximum value.
+                    (start, if step.is_some() { max } else { start })
+                }
+            };
+
+            if start < min || end > max || start > end || step == Some(0) {
+                return None;
+            }
+
+            for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+                values |= 1 << value;
+            }
+        }
+
+        Some(Self {
+            values,
+            any: value == "*",
+        })
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.values & (1 << value) != 0
+    }
+}
+
+// Parses a value, either as a number or, if the field has names for its
+// values, as a name, case-insensitively.
+fn parse_value(value: &str, min: u32, names: &[&str]) -> Option<u32> {
+    if let Ok(value) = value.parse() {
+        return Some(value);
+    }
+
+    names
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(value))
+        .map(|index| index as u32 + min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn cron_schedule_last_before() {
+        // 2024-03-13 is a Wednesday.
+        let now = time("2024-03-13 10:07:42");
+
+        for (schedule, expected) in [
+            ("* * * * *", "2024-03-13 10:07:00"),
+            ("*/15 * * * *", "2024-03-13 10:00:00"),
+            ("5/15 * * * *", "2024-03-13 10:05:00"),
+            ("30 9-17 * * *", "2024-03-13 09:30:00"),
+            ("0 3 * * *", "2024-03-13 03:00:00"),
+            ("0 12 * * *", "2024-03-12 12:00:00"),
+            ("0 0 * * mon-fri", "2024-03-13 00:00:00"),
+            ("0 0 * * SUN", "2024-03-10 00:00:00"),
+            ("0 0 * * 7", "2024-03-10 00:00:00"),
+            ("0 0 1 * *", "2024-03-01 00:00:00"),
+            ("0 0 1,15 jan,dec *", "2024-01-15 00:00:00"),
+            ("0 0 20 * mon", "2024-03-11 00:00:00"),
+        ] {
+            let schedule = CronSchedule::parse(schedule).unwrap();
+            assert_eq!(
+                schedule.last_before(now),
+                Some(time(expected)),
+                "{schedule:?}"
+            );
+        }
+
+        let schedule = CronSchedule::parse("0 0 30 2 *").unwrap();
+        assert_eq!(schedule.last_before(now), None);
+    }
+
+    #[test]
+    fn cron_schedule_matches() {
+        let schedule = CronSchedule::parse("*/15 9-17 * * mon-fri").unwrap();
+
+        // 2024-03-13 is a Wednesday, and 2024-03-16 is a Saturday.
+        assert!(schedule.matches(time("2024-03-13 09:00:00")));
+        assert!(schedule.matches(time("2024-03-13 17:45:59")));
+        assert!(!schedule.matches(time("2024-03-13 09:01:00")));
+        assert!(!schedule.matches(time("2024-03-13 18:00:00")));
+        assert!(!schedule.matches(time("2024-03-16 12:00:00")));
+    }
+
+    #[test]
+    fn cron_schedule_parse_errors() {
+        for value in [
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "10-5 * * * *",
+            "* * * foo *",
+            "@daily",
+        ] {
+            assert!(CronSchedule::parse(value).is_err(), "{value:?} should fail");
+        }
+    }
+}
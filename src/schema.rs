@@ -0,0 +1,252 @@
+// The schema of the error and log payloads sent to AppSignal, so that relays
+// that inspect the payloads on their way to AppSignal can rely on their
+// shape across releases.
+//
+// Each version of the schema is described by a JSON Schema document in the
+// `schema` directory, which the payloads are checked against in the tests
+// below. A change to the shape of either payload adds a version, instead of
+// changing an existing one. The latest version is sent by default, and an
+// earlier one can be chosen with `--payload-schema-version`, so that the
+// wrapper can be upgraded before the relays that inspect its payloads.
+//
+// The version that a request's body follows is sent in its
+// `X-AppSignal-Run-Payload-Schema` header.
+
+use std::sync::OnceLock;
+
+use crate::error::ErrorBody;
+use crate::log::LogMessage;
+use crate::ndjson;
+
+pub const HEADER: &str = "X-AppSignal-Run-Payload-Schema";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchemaVersion {
+    #[default]
+    V1,
+}
+
+impl SchemaVersion {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "1" => Ok(Self::V1),
+            _ => Err(format!(
+                "unsupported payload schema version `{value}`; supported versions are: 1"
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::V1 => "1",
+        }
+    }
+}
+
+static VERSION: OnceLock<SchemaVersion> = OnceLock::new();
+
+pub fn init(version: SchemaVersion) {
+    let _ = VERSION.set(version);
+}
+
+// The version of the schema to send payloads in.
+pub fn version() -> SchemaVersion {
+    VERSION.get().copied().unwrap_or_default()
+}
+
+pub fn error_body(body: &ErrorBody) -> serde_json::Result<String> {
+    match version() {
+        SchemaVersion::V1 => serde_json::to_string(body),
+    }
+}
+
+pub fn log_body(messages: Vec<LogMessage>) -> serde_json::Result<String> {
+    match version() {
+        SchemaVersion::V1 => ndjson::to_string(messages),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorConfig;
+    use crate::log::{LogConfig, LogSeverity};
+    use crate::timestamp::tests::timestamp;
+    use serde_json::{json, Value};
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::time::Duration;
+
+    const ERROR_V1: &str = include_str!("../schema/v1/error.json");
+    const LOG_V1: &str = include_str!("../schema/v1/log.json");
+
+    // Checks the value against the subset of JSON Schema used by the schema
+    // documents, returning the path of each part of it that does not match.
+    fn violations(value: &Value, schema: &Value, path: &str) -> Vec<String> {
+        let mut found = Vec::new();
+
+        let matches_type = match schema["type"].as_str() {
+            Some("object") => value.is_object(),
+            Some("string") => value.is_string(),
+            Some("integer") => value.is_u64() || value.is_i64(),
+            _ => true,
+        };
+        if !matches_type {
+            found.push(format!("{path}: expected {}", schema["type"]));
+        }
+
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                found.push(format!("{path}: expected {expected}"));
+            }
+        }
+
+        if let Some(allowed) = schema["enum"].as_array() {
+            if !allowed.contains(value) {
+                found.push(format!("{path}: {value} is not one of {allowed:?}"));
+            }
+        }
+
+        if let (Some(minimum), Some(number)) = (schema["minimum"].as_i64(), value.as_i64()) {
+            if number < minimum {
+                found.push(format!("{path}: {number} is less than {minimum}"));
+            }
+        }
+
+        let Some(object) = value.as_object() else {
+            return found;
+        };
+
+        for required in schema["required"].as_array().into_iter().flatten() {
+            if !object.contains_key(required.as_str().unwrap()) {
+                found.push(format!("{path}: missing {required}"));
+            }
+        }
+
+        for (key, field) in object {
+            let path = format!("{path}.{key}");
+            match (
+                schema["properties"].get(key),
+                &schema["additionalProperties"],
+            ) {
+                (Some(property), _) => found.extend(violations(field, property, &path)),
+                (None, Value::Bool(false)) => found.push(format!("{path}: not allowed")),
+                (None, Value::Object(_)) => {
+                    found.extend(violations(field, &schema["additionalProperties"], &path))
+                }
+                (None, _) => {}
+            }
+        }
+
+        found
+    }
+
+    fn assert_matches(payload: &str, schema: &str) {
+        let schema: Value = serde_json::from_str(schema).unwrap();
+        let value: Value = serde_json::from_str(payload).unwrap();
+        let violations = violations(&value, &schema, "$");
+
+        assert!(violations.is_empty(), "{payload}: {violations:?}");
+    }
+
+    fn error_config() -> ErrorConfig {
+        serde_json::from_value(json!({
+            "api_key": "some-api-key",
+            "action": "some-action",
+            "command": "some-command",
+            "revision": "some-revision",
+            "tags": {"environment": "production"},
+        }))
+        .unwrap()
+    }
+
+    fn log_config() -> LogConfig {
+        serde_json::from_value(json!({
+            "api_key": "some-api-key",
+            "group": "some-group",
+            "command": "some-command",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn schema_version_parse() {
+        assert_eq!(SchemaVersion::parse("1"), Ok(SchemaVersion::V1));
+        assert_eq!(SchemaVersion::default().as_str(), "1");
+        assert_eq!(
+            SchemaVersion::parse("2"),
+            Err("unsupported payload schema version `2`; supported versions are: 1".to_string())
+        );
+    }
+
+    #[test]
+    fn schema_error_payload_v1() {
+        let config = error_config();
+        let lines = || vec!["some output".to_string()];
+        let spawn_error = std::io::Error::from(std::io::ErrorKind::NotFound);
+
+        let bodies = [
+            ErrorBody::from_spawn(&config, &mut timestamp(), &spawn_error),
+            ErrorBody::from_exit(
+                &config,
+                &mut timestamp(),
+                &ExitStatus::from_raw(1 << 8),
+                lines(),
+            ),
+            ErrorBody::from_exit(&config, &mut timestamp(), &ExitStatus::from_raw(9), []),
+            ErrorBody::from_stall(&config, &mut timestamp(), Duration::from_secs(60), lines()),
+            ErrorBody::from_message(&config, &mut timestamp(), "SomeError", "some message"),
+        ];
+
+        for body in bodies {
+            assert_matches(&error_body(&body).unwrap(), ERROR_V1);
+        }
+    }
+
+    #[test]
+    fn schema_log_payload_v1() {
+        let config = log_config();
+        let messages = vec![
+            LogMessage::new(
+                &config,
+                &mut timestamp(),
+                LogSeverity::Critical,
+                "some message".to_string(),
+            ),
+            LogMessage::dropped(&config, &mut timestamp(), 3),
+        ];
+
+        let body = log_body(messages).unwrap();
+        assert_eq!(body.lines().count(), 2);
+
+        for line in body.lines() {
+            assert_matches(line, LOG_V1);
+        }
+    }
+
+    #[test]
+    fn schema_violations() {
+        let schema: Value = serde_json::from_str(ERROR_V1).unwrap();
+        let payload = json!({
+            "timestamp": "now",
+            "action": "some-action",
+            "namespace": "web",
+            "error": {"name": "SomeError"},
+            "tags": {"exit_code": 1},
+            "extra": true,
+        });
+
+        let mut found = violations(&payload, &schema, "$");
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                "$.error: missing \"message\"",
+                "$.extra: not allowed",
+                "$.namespace: expected \"process\"",
+                "$.tags.exit_code: expected \"string\"",
+                "$.timestamp: expected \"integer\"",
+            ]
+        );
+    }
+}
@@ -1,19 +1,12 @@
 use nix::sys::signal::Signal;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
 use std::io;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio_stream::{wrappers::SignalStream, Stream, StreamExt, StreamMap};
 
 fn nix_to_tokio(signal: &Signal) -> SignalKind {
-    match signal {
-        Signal::SIGINT => SignalKind::interrupt(),
-        Signal::SIGTERM => SignalKind::terminate(),
-        Signal::SIGHUP => SignalKind::hangup(),
-        Signal::SIGQUIT => SignalKind::quit(),
-        Signal::SIGUSR1 => SignalKind::user_defined1(),
-        Signal::SIGUSR2 => SignalKind::user_defined2(),
-        Signal::SIGWINCH => SignalKind::window_change(),
-        _ => panic!("unsupported signal: {:?}", signal),
-    }
+    SignalKind::from_raw(*signal as i32)
 }
 
 // This is a list of signals that are meaningful to forward to the child
@@ -57,18 +50,45 @@ pub fn has_terminating_intent(signal: &Signal) -> bool {
 }
 
 pub fn signal_stream() -> io::Result<impl Stream<Item = Signal>> {
+    forwarding_signal_stream([])
+}
+
+// Returns a stream of the signals in `CHILD_FORWARDABLE_SIGNALS`, as well as
+// the given signals, such as those that the wrapper is configured to forward
+// to the child process as a different signal.
+pub fn forwarding_signal_stream(
+    additional: impl IntoIterator<Item = Signal>,
+) -> io::Result<impl Stream<Item = Signal>> {
     let mut signals = StreamMap::new();
 
-    for nix_signal in &CHILD_FORWARDABLE_SIGNALS {
-        signals.insert(
-            *nix_signal,
-            SignalStream::new(signal(nix_to_tokio(nix_signal))?),
-        );
+    for nix_signal in CHILD_FORWARDABLE_SIGNALS.into_iter().chain(additional) {
+        if !signals.contains_key(&nix_signal) {
+            signals.insert(
+                nix_signal,
+                SignalStream::new(signal(nix_to_tokio(&nix_signal))?),
+            );
+        }
     }
 
     Ok(signals.map(|(signal, _)| signal))
 }
 
+// Returns whether the wrapper can handle the signal, in order to forward it
+// to the child process. Besides `SIGKILL` and `SIGSTOP`, which cannot be
+// handled, this excludes signals that report a fault in the wrapper itself,
+// or that the runtime uses to track the child process.
+fn is_forwardable(signal: Signal) -> bool {
+    !matches!(
+        signal,
+        Signal::SIGKILL
+            | Signal::SIGSTOP
+            | Signal::SIGILL
+            | Signal::SIGFPE
+            | Signal::SIGSEGV
+            | Signal::SIGCHLD
+    )
+}
+
 // A mapping of signal numbers to signal names. Uses `libc` constants to
 // correctly map non-portable signals to their names across platforms.
 // For an unknown signal, the signal number is returned as a string.
@@ -106,3 +126,72 @@ pub fn signal_name(signal: i32) -> String {
         signal => format!("{}", signal),
     }
 }
+
+// Parses a signal given by name, such as `SIGKILL` or `KILL`, or by number.
+pub fn parse_signal(value: &str) -> Result<Signal, String> {
+    let signal = match value.parse::<i32>() {
+        Ok(number) => Signal::try_from(number).ok(),
+        Err(_) => {
+            let name = value.to_ascii_uppercase();
+            let name = if name.starts_with("SIG") {
+                name
+            } else {
+                format!("SIG{name}")
+            };
+
+            name.parse::<Signal>().ok()
+        }
+    };
+
+    signal.ok_or_else(|| format!("invalid signal `{value}`; expected a name such as `SIGKILL`"))
+}
+
+// Parses a mapping of a signal received by the wrapper to the signal to
+// forward to the child process instead, such as `SIGHUP=SIGUSR1`.
+pub fn parse_signal_mapping(value: &str) -> Result<(Signal, Signal), String> {
+    let (from, to) = value
+        .split_once('=')
+        .ok_or_else(|| format!("invalid signal mapping `{value}`; expected FROM=TO"))?;
+
+    let from = parse_signal(from)?;
+    let to = parse_signal(to)?;
+
+    if !is_forwardable(from) {
+        return Err(format!("cannot forward {from}, as it cannot be handled"));
+    }
+
+    Ok((from, to))
+}
+
+// Deserializes an optional signal, given by name or by number as a string,
+// as in `parse_signal`.
+pub fn deserialize_signal<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Signal>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| parse_signal(&value).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+// Deserializes a list of signals, as in `parse_signal`.
+pub fn deserialize_signals<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Signal>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| parse_signal(value).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+// Deserializes a map of signals to the signals to forward instead, as in
+// `parse_signal_mapping`.
+pub fn deserialize_signal_map<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<Signal, Signal>, D::Error> {
+    BTreeMap::<String, String>::deserialize(deserializer)?
+        .iter()
+        .map(|(from, to)| {
+            parse_signal_mapping(&format!("{from}={to}")).map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
@@ -0,0 +1,775 @@
+// Destinations for the command's output and for how it exited, such as logs
+// and errors sent to AppSignal, or a file, the system log or a webhook given
+// with the `--sink` option.
+//
+// The lines of the command's output are passed to every sink as they are
+// read, after they have been sanitized. Once the output is closed and the
+// command has exited, its exit is passed to every sink, which returns a
+// future that completes when the sink has delivered it. If the wrapper is
+// upgraded, the command does not exit, and the sinks are dropped instead.
+
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::process::ExitStatus;
+
+use ::log::{debug, warn};
+use nix::sys::signal::Signal;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tokio::select;
+use tokio::sync::mpsc::{self, unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::task::JoinSet;
+
+use crate::audit_log::syslog;
+use crate::batch::SummaryReader;
+use crate::channel::maybe_recv;
+use crate::client::external_client;
+use crate::delivery::{self, DataType};
+use crate::error::{ErrorBody, ErrorConfig, MessageLines};
+use crate::hook::FailureHook;
+use crate::log::{LogConfig, LogMessage, LogOrigin};
+use crate::metrics::MetricsConfig;
+use crate::sample::ErrorSampler;
+use crate::signal::signal_name;
+use crate::timestamp::{SystemTimestamp, Timestamp};
+
+// The stream of the command that a line was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+    // The lines written to the command's standard input, if it is piped.
+    Stdin,
+}
+
+// How the command exited.
+#[derive(Debug)]
+pub struct Exit {
+    pub status: ExitStatus,
+    // Whether the exit status is considered a failure.
+    pub failure: bool,
+    // If the command was stopped when a terminating signal was received, the
+    // signal and the status it exited with after it was continued. The exit
+    // status is then the one for that signal.
+    pub stopped_at_shutdown: Option<(Signal, ExitStatus)>,
+}
+
+// A future that completes when a sink has delivered the command's exit.
+pub type Delivered = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+pub trait Sink: Send {
+    // Called for each line of the command's output.
+    fn line(&mut self, _stream: Stream, _line: &str) {}
+
+    // Called once the command's output is closed, with how it exited.
+    fn exit(self: Box<Self>, exit: &Exit) -> Delivered;
+}
+
+// Passes the lines of the command's output to the sinks until it is closed,
+// and then passes its exit to them, waiting for them to deliver it.
+pub async fn sink_loop(
+    mut sinks: Vec<Box<dyn Sink>>,
+    mut stdout: Option<mpsc::Receiver<String>>,
+    mut stderr: Option<mpsc::Receiver<String>>,
+    mut stdin: Option<mpsc::Receiver<String>>,
+    exit: oneshot::Receiver<Exit>,
+) {
+    loop {
+        let (stream, line) = select! {
+            Some(maybe_line) = maybe_recv(&mut stdout) => match maybe_line {
+                Some(line) => (Stream::Stdout, line),
+                None => {
+                    stdout = None;
+                    continue;
+                }
+            },
+
+            Some(maybe_line) = maybe_recv(&mut stderr) => match maybe_line {
+                Some(line) => (Stream::Stderr, line),
+                None => {
+                    stderr = None;
+                    continue;
+                }
+            },
+
+            Some(maybe_line) = maybe_recv(&mut stdin) => match maybe_line {
+                Some(line) => (Stream::Stdin, line),
+                None => {
+                    stdin = None;
+                    continue;
+                }
+            },
+
+            else => break
+        };
+
+        for sink in sinks.iter_mut() {
+            sink.line(stream, &line);
+        }
+    }
+
+    let Ok(exit) = exit.await else {
+        debug!("command did not exit; dropping sinks");
+        return;
+    };
+
+    let mut delivered = JoinSet::new();
+
+    for sink in sinks {
+        delivered.spawn(sink.exit(&exit));
+    }
+
+    while delivered.join_next().await.is_some() {}
+}
+
+// A destination given with the `--sink` option.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum SinkDestination {
+    // Appends a line of JSON to the file for each line of output, and for
+    // the exit.
+    File(PathBuf),
+    // Sends each line of output, and the exit, to the system log.
+    Syslog,
+    // Sends the exit to the URL as JSON.
+    Webhook(Url),
+}
+
+impl SinkDestination {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let expected = "expected `file:PATH`, `syslog` or `webhook:URL`";
+
+        match value.split_once(':') {
+            _ if value == "syslog" => Ok(Self::Syslog),
+            Some(("file", "")) => Err(format!("missing path in sink `{value}`; {expected}")),
+            Some(("file", path)) => Ok(Self::File(PathBuf::from(path))),
+            Some(("webhook", url)) => match Url::parse(url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(Self::Webhook(url)),
+                _ => Err(format!("invalid URL in sink `{value}`; {expected}")),
+            },
+            _ => Err(format!("invalid sink `{value}`; {expected}")),
+        }
+    }
+}
+
+impl TryFrom<String> for SinkDestination {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SinkConfig {
+    pub destination: SinkDestination,
+    pub name: String,
+    pub command: String,
+}
+
+impl SinkConfig {
+    pub fn sink(&self, digest: &str) -> Result<Box<dyn Sink>, String> {
+        let exit = ExitRecord {
+            name: self.name.clone(),
+            command: self.command.clone(),
+            digest: digest.to_string(),
+        };
+
+        Ok(match &self.destination {
+            SinkDestination::File(path) => Box::new(FileSink {
+                file: BufWriter::new(
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .map_err(|err| {
+                            format!("could not open sink file {}: {}", path.display(), err)
+                        })?,
+                ),
+                path: path.clone(),
+                exit,
+            }),
+            SinkDestination::Syslog => Box::new(SyslogSink { exit }),
+            SinkDestination::Webhook(url) => Box::new(WebhookSink {
+                url: url.clone(),
+                exit,
+            }),
+        })
+    }
+}
+
+// Sends the lines of the command's output as logs to AppSignal, by passing
+// them to the log loop through the channels returned by `LogSink::new`.
+pub struct LogSink {
+    stdout: Option<UnboundedSender<String>>,
+    stderr: Option<UnboundedSender<String>>,
+    stdin: Option<UnboundedSender<String>>,
+}
+
+type LogReceivers = (
+    Option<UnboundedReceiver<String>>,
+    Option<UnboundedReceiver<String>>,
+    Option<UnboundedReceiver<String>>,
+);
+
+impl LogSink {
+    // Returns the sink, and the receivers for the lines of each stream that
+    // are sent as logs, according to the log origin. The output streams can
+    // be read for other reasons, such as standard error for the message of
+    // an error report with `--error`, so their lines are not sent as logs
+    // unless the origin includes them, such as with `--no-log`.
+    pub fn new(origin: LogOrigin, stdin: bool) -> (Self, LogReceivers) {
+        fn channel(
+            enabled: bool,
+        ) -> (
+            Option<UnboundedSender<String>>,
+            Option<UnboundedReceiver<String>>,
+        ) {
+            if enabled {
+                let (sender, receiver) = unbounded_channel();
+                (Some(sender), Some(receiver))
+            } else {
+                (None, None)
+            }
+        }
+
+        let (stdout, stdout_lines) = channel(origin.is_out());
+        let (stderr, stderr_lines) = channel(origin.is_err());
+        let (stdin, stdin_lines) = channel(stdin);
+
+        (
+            Self {
+                stdout,
+                stderr,
+                stdin,
+            },
+            (stdout_lines, stderr_lines, stdin_lines),
+        )
+    }
+}
+
+impl Sink for LogSink {
+    fn line(&mut self, stream: Stream, line: &str) {
+        let sender = match stream {
+            Stream::Stdout => &self.stdout,
+            Stream::Stderr => &self.stderr,
+            Stream::Stdin => &self.stdin,
+        };
+
+        if let Some(sender) = sender {
+            if let Err(err) = sender.send(line.to_string()) {
+                debug!("error sending line to log loop: {}", err);
+            }
+        }
+    }
+
+    // Dropping the senders lets the log loop send the remaining logs.
+    fn exit(self: Box<Self>, _exit: &Exit) -> Delivered {
+        Box::pin(async {})
+    }
+}
+
+// Reports the command's exit as an error to AppSignal, if it failed, with
+// the last lines of its output as the error message.
+pub struct ErrorSink {
+    pub error: ErrorConfig,
+    pub sampler: Option<ErrorSampler>,
+    pub lines: MessageLines,
+    // The `--on-failure` command whose output to include in the error
+    // report for a failure, which is then sent once it has finished.
+    pub hook: Option<FailureHook>,
+    // If `--batch-summary` is set, the summary line whose values to add as
+    // tags to the error report.
+    pub batch_summary: Option<SummaryReader>,
+}
+
+impl Sink for ErrorSink {
+    fn line(&mut self, stream: Stream, line: &str) {
+        if stream != Stream::Stdin {
+            self.lines.push(line.to_string());
+        }
+
+        if let (Stream::Stdout, Some(reader)) = (stream, self.batch_summary.as_mut()) {
+            reader.line(line);
+        }
+    }
+
+    fn exit(self: Box<Self>, exit: &Exit) -> Delivered {
+        let Self {
+            mut error,
+            sampler,
+            lines,
+            hook,
+            batch_summary,
+        } = *self;
+
+        if let Some(summary) = batch_summary.and_then(SummaryReader::summary) {
+            error.tags.extend(summary.tags());
+        }
+
+        let request = if let Some((signal, status)) = exit.stopped_at_shutdown {
+            sample_error(error, &sampler).map(|error| {
+                error.request_from_stopped_at_shutdown(
+                    &mut SystemTimestamp,
+                    signal as i32,
+                    &status,
+                    lines,
+                )
+            })
+        } else if !exit.failure {
+            if let Some(sampler) = sampler.as_ref() {
+                sampler.success();
+            }
+
+            None
+        } else if let Some(hook) = hook {
+            // The report is built when the command fails, and completed
+            // once the hook has finished.
+            let status = exit.status;
+            let hook_lines = lines.with_same_limits();
+            let report = sample_error(error, &sampler).map(|error| {
+                let body = ErrorBody::from_exit(&error, &mut SystemTimestamp, &status, lines);
+                (error, body)
+            });
+
+            return Box::pin(async move {
+                let result = hook.run(&status).await;
+
+                if let Some((error, mut body)) = report {
+                    result.add_to(&mut body, hook_lines);
+                    delivery::send(DataType::Error, error.request(body)).await;
+                }
+            });
+        } else {
+            sample_error(error, &sampler)
+                .map(|error| error.request_from_exit(&mut SystemTimestamp, &exit.status, lines))
+        };
+
+        match request {
+            Some(request) => Box::pin(delivery::send(DataType::Error, request)),
+            None => Box::pin(async {}),
+        }
+    }
+}
+
+// Runs the `--on-failure` command when the command fails, if its output is
+// not included in the error report.
+pub struct HookSink {
+    pub hook: FailureHook,
+}
+
+impl Sink for HookSink {
+    fn exit(self: Box<Self>, exit: &Exit) -> Delivered {
+        if !exit.failure || exit.stopped_at_shutdown.is_some() {
+            return Box::pin(async {});
+        }
+
+        let status = exit.status;
+        Box::pin(async move {
+            self.hook.run(&status).await;
+        })
+    }
+}
+
+// Sends the values of the batch summary line as metrics once the command
+// exits, and warns if the command succeeded but reported failed items.
+pub struct BatchSink {
+    pub reader: SummaryReader,
+    pub metrics: Option<MetricsConfig>,
+    // If `--warn-on-batch-failures` is set, the log config to send the
+    // warning for a partial failure with.
+    pub warn: Option<LogConfig>,
+}
+
+impl Sink for BatchSink {
+    fn line(&mut self, stream: Stream, line: &str) {
+        if stream == Stream::Stdout {
+            self.reader.line(line);
+        }
+    }
+
+    fn exit(self: Box<Self>, exit: &Exit) -> Delivered {
+        let Self {
+            reader,
+            metrics,
+            warn,
+        } = *self;
+
+        let Some(summary) = reader.summary() else {
+            debug!("command did not write a batch summary line");
+            return Box::pin(async {});
+        };
+
+        let mut requests = Vec::new();
+
+        if let Some(metrics) = metrics {
+            requests.push((
+                DataType::Metrics,
+                metrics.request_from_metrics(summary.metrics()),
+            ));
+        }
+
+        if let Some(log) = warn.filter(|_| !exit.failure && summary.failed() > 0) {
+            warn!(
+                "command succeeded, but {} batch items failed: {}",
+                summary.failed(),
+                summary.describe()
+            );
+
+            let message = LogMessage::partial_failure(&log, &mut SystemTimestamp, &summary);
+            for request in log.requests(vec![message]) {
+                requests.push((DataType::Log, request));
+            }
+        }
+
+        let delivered: Vec<_> = requests
+            .into_iter()
+            .map(|(data_type, request)| delivery::send(data_type, request))
+            .collect();
+
+        Box::pin(async move {
+            for delivered in delivered {
+                delivered.await;
+            }
+        })
+    }
+}
+
+// Records the failure with the error sampler, if any. Returns `None` if the
+// error report for the failure should be suppressed.
+pub fn sample_error(mut error: ErrorConfig, sampler: &Option<ErrorSampler>) -> Option<ErrorConfig> {
+    let Some(sampler) = sampler else {
+        return Some(error);
+    };
+
+    let suppressed = sampler.failure()?;
+
+    if suppressed > 0 {
+        error
+            .tags
+            .insert("suppressed_errors".to_string(), suppressed.to_string());
+    }
+
+    Some(error)
+}
+
+// The fields identifying the invocation in the exit records of the file,
+// system log and webhook sinks.
+#[derive(Debug, Clone, Serialize)]
+struct ExitRecord {
+    name: String,
+    command: String,
+    digest: String,
+}
+
+impl ExitRecord {
+    fn to_json(&self, exit: &Exit) -> String {
+        #[derive(Serialize)]
+        struct Record<'a> {
+            timestamp: String,
+            #[serde(flatten)]
+            invocation: &'a ExitRecord,
+            exit_code: Option<i32>,
+            signal: Option<String>,
+            failure: bool,
+        }
+
+        serde_json::to_string(&Record {
+            timestamp: SystemTimestamp.as_rfc3339(),
+            invocation: self,
+            exit_code: exit.status.code(),
+            signal: exit.status.signal().map(signal_name),
+            failure: exit.failure,
+        })
+        .expect("failed to serialize exit record")
+    }
+}
+
+// The records are buffered, so that a line of output does not block the
+// runtime on a write to the file, and written when the buffer is full or
+// when the command exits.
+struct FileSink {
+    file: BufWriter<File>,
+    path: PathBuf,
+    exit: ExitRecord,
+}
+
+impl FileSink {
+    fn write(&mut self, line: String) {
+        if let Err(err) = writeln!(self.file, "{line}") {
+            debug!(
+                "error writing to sink file {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+impl Sink for FileSink {
+    fn line(&mut self, stream: Stream, line: &str) {
+        #[derive(Serialize)]
+        struct Record<'a> {
+            timestamp: String,
+            name: &'a str,
+            stream: Stream,
+            message: &'a str,
+        }
+
+        let record = serde_json::to_string(&Record {
+            timestamp: SystemTimestamp.as_rfc3339(),
+            name: &self.exit.name,
+            stream,
+            message: line,
+        })
+        .expect("failed to serialize line record");
+
+        self.write(record);
+    }
+
+    fn exit(mut self: Box<Self>, exit: &Exit) -> Delivered {
+        let record = self.exit.to_json(exit);
+        self.write(record);
+
+        if let Err(err) = self.file.flush() {
+            debug!(
+                "error writing to sink file {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+
+        Box::pin(async {})
+    }
+}
+
+struct SyslogSink {
+    exit: ExitRecord,
+}
+
+impl Sink for SyslogSink {
+    fn line(&mut self, stream: Stream, line: &str) {
+        let priority = match stream {
+            Stream::Stdout => libc::LOG_INFO,
+            Stream::Stderr => libc::LOG_ERR,
+            Stream::Stdin => libc::LOG_DEBUG,
+        };
+
+        syslog(
+            libc::LOG_USER | priority,
+            &format!("{}: {}", self.exit.name, line),
+        );
+    }
+
+    fn exit(self: Box<Self>, exit: &Exit) -> Delivered {
+        let priority = if exit.failure {
+            libc::LOG_ERR
+        } else {
+            libc::LOG_NOTICE
+        };
+
+        syslog(libc::LOG_USER | priority, &self.exit.to_json(exit));
+        Box::pin(async {})
+    }
+}
+
+// The webhook is not an AppSignal endpoint, so its request is sent with its
+// own client: it is not recorded, not skipped when AppSignal's endpoint is
+// failing, and does not use the TLS settings for AppSignal's endpoint.
+struct WebhookSink {
+    url: Url,
+    exit: ExitRecord,
+}
+
+impl Sink for WebhookSink {
+    fn exit(self: Box<Self>, exit: &Exit) -> Delivered {
+        let request = external_client()
+            .post(self.url.clone())
+            .header("Content-Type", "application/json")
+            .body(self.exit.to_json(exit))
+            .send();
+
+        Box::pin(async move {
+            match request.await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => debug!(
+                    "webhook sink request failed with status: {}",
+                    response.status()
+                ),
+                Err(err) => debug!("error sending exit to webhook sink: {}", err),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // A sink that records the lines and the exit passed to it.
+    struct TestSink(Arc<Mutex<Vec<String>>>);
+
+    impl Sink for TestSink {
+        fn line(&mut self, stream: Stream, line: &str) {
+            self.0.lock().unwrap().push(format!("{stream:?}: {line}"));
+        }
+
+        fn exit(self: Box<Self>, exit: &Exit) -> Delivered {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("exit: {:?}", exit.status.code()));
+            Box::pin(async {})
+        }
+    }
+
+    fn exit(code: i32) -> Exit {
+        Exit {
+            status: ExitStatus::from_raw(code << 8),
+            failure: code != 0,
+            stopped_at_shutdown: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn sink_loop_passes_lines_and_exit() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let other_records = Arc::new(Mutex::new(Vec::new()));
+
+        let (stdout, stdout_lines) = mpsc::channel(1);
+        let (stderr, stderr_lines) = mpsc::channel(1);
+        let (exit_sender, exit_receiver) = oneshot::channel();
+
+        stdout.send("one".to_string()).await.unwrap();
+        drop(stdout);
+        stderr.send("two".to_string()).await.unwrap();
+        drop(stderr);
+        exit_sender.send(exit(3)).unwrap();
+
+        sink_loop(
+            vec![
+                Box::new(TestSink(records.clone())),
+                Box::new(TestSink(other_records.clone())),
+            ],
+            Some(stdout_lines),
+            Some(stderr_lines),
+            None,
+            exit_receiver,
+        )
+        .await;
+
+        // The lines of different streams may be passed in any order, but
+        // always before the exit.
+        for records in [records, other_records] {
+            let mut records = records.lock().unwrap().clone();
+            assert_eq!(records.pop().unwrap(), "exit: Some(3)");
+            records.sort();
+            assert_eq!(records, vec!["Stderr: two", "Stdout: one"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn sink_loop_without_exit() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let (exit_sender, exit_receiver) = oneshot::channel::<Exit>();
+        drop(exit_sender);
+
+        sink_loop(
+            vec![Box::new(TestSink(records.clone()))],
+            None,
+            None,
+            None,
+            exit_receiver,
+        )
+        .await;
+
+        assert!(records.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn sink_destination_parse() {
+        assert_eq!(
+            SinkDestination::parse("file:/var/log/backup.json"),
+            Ok(SinkDestination::File(PathBuf::from("/var/log/backup.json")))
+        );
+        assert_eq!(
+            SinkDestination::parse("syslog"),
+            Ok(SinkDestination::Syslog)
+        );
+        assert_eq!(
+            SinkDestination::parse("webhook:https://example.com/hook"),
+            Ok(SinkDestination::Webhook(
+                Url::parse("https://example.com/hook").unwrap()
+            ))
+        );
+
+        for value in [
+            "",
+            "file:",
+            "webhook:not-a-url",
+            "webhook:ftp://example.com",
+            "stdout",
+        ] {
+            assert!(SinkDestination::parse(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn log_sink_only_sends_lines_from_origin() {
+        let (mut sink, (stdout, stderr, stdin)) = LogSink::new(LogOrigin::Stderr, false);
+        assert!(stdout.is_none());
+        assert!(stdin.is_none());
+
+        sink.line(Stream::Stdout, "some output");
+        sink.line(Stream::Stderr, "some error");
+
+        let mut stderr = stderr.unwrap();
+        assert_eq!(stderr.try_recv().unwrap(), "some error");
+        assert!(stderr.try_recv().is_err());
+
+        let (mut sink, receivers) = LogSink::new(LogOrigin::None, true);
+        sink.line(Stream::Stderr, "some error");
+        assert!(matches!(receivers, (None, None, Some(_))));
+    }
+
+    #[tokio::test]
+    async fn file_sink() {
+        let path = std::env::temp_dir().join(format!("{}-sink-test.json", std::process::id()));
+
+        let config = SinkConfig {
+            destination: SinkDestination::File(path.clone()),
+            name: "some-name".to_string(),
+            command: "some-command".to_string(),
+        };
+
+        let mut sink = config.sink("some-digest").unwrap();
+        sink.line(Stream::Stdout, "some line");
+        assert!(std::fs::read_to_string(&path).unwrap().is_empty());
+        sink.exit(&exit(2)).await;
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let records: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["name"], "some-name");
+        assert_eq!(records[0]["stream"], "stdout");
+        assert_eq!(records[0]["message"], "some line");
+        assert_eq!(records[1]["command"], "some-command");
+        assert_eq!(records[1]["digest"], "some-digest");
+        assert_eq!(records[1]["exit_code"], 2);
+        assert_eq!(records[1]["signal"], serde_json::Value::Null);
+        assert_eq!(records[1]["failure"], true);
+    }
+}
@@ -0,0 +1,235 @@
+// Splits the output of a stream into lines as it is read, in chunks of a
+// fixed size, instead of reading it a line at a time. This way, a read
+// returns as soon as a chunk is available, even while a very long line is
+// being written, so that the task reading it is not held up waiting for the
+// end of the line.
+//
+// Lines are decoded as UTF-8, replacing invalid sequences, and returned
+// without their line endings. A line can be limited to a maximum size, such
+// as when the wrapper's memory is limited with `--max-wrapper-memory`, in
+// which case a longer line is returned in parts, as it is read, instead of
+// being held in memory until its end is read.
+
+// The size of the chunks in which the output of a stream is read.
+pub const READ_CHUNK_SIZE: usize = 8192;
+
+#[derive(Debug, Default)]
+pub struct LineSplitter {
+    // The start of the line that is being read, until its end is read.
+    line: Vec<u8>,
+    // The maximum size of the start of a line held until its end is read.
+    max_bytes: Option<usize>,
+}
+
+// A line read from the stream, or a part of a line longer than the maximum
+// size, which is not the end of the line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub text: String,
+    pub end: bool,
+}
+
+impl LineSplitter {
+    pub fn new(max_bytes: Option<usize>) -> Self {
+        Self {
+            line: Vec::new(),
+            max_bytes,
+        }
+    }
+
+    // Adds the chunk to the line being read, returning the lines that it
+    // completes, if any, and the parts of the line that exceed the maximum
+    // size.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Line> {
+        let mut lines = Vec::new();
+
+        for part in chunk.split_inclusive(|byte| *byte == b'\n') {
+            match part.strip_suffix(b"\n") {
+                Some(end) => {
+                    self.line.extend_from_slice(end);
+                    self.strip_carriage_return();
+                    self.split_long(&mut lines);
+                    lines.push(self.take());
+                }
+                None => {
+                    self.line.extend_from_slice(part);
+                    self.split_long(&mut lines);
+                }
+            }
+        }
+
+        lines
+    }
+
+    // Returns the last line, if it was not completed when the stream was
+    // closed.
+    pub fn finish(&mut self) -> Option<Line> {
+        match self.line.is_empty() {
+            true => None,
+            false => {
+                self.strip_carriage_return();
+                Some(self.take())
+            }
+        }
+    }
+
+    // Returns the start of the line being read, if any, when reading stops
+    // before its end is read, such as when the stream is passed to another
+    // process, which reads the rest of the line.
+    pub fn flush(&mut self) -> Option<Line> {
+        match self.line.is_empty() {
+            true => None,
+            false => Some(Line {
+                end: false,
+                ..self.take()
+            }),
+        }
+    }
+
+    fn strip_carriage_return(&mut self) {
+        if self.line.ends_with(b"\r") {
+            self.line.pop();
+        }
+    }
+
+    fn take(&mut self) -> Line {
+        let line = std::mem::take(&mut self.line);
+
+        Line {
+            text: String::from_utf8_lossy(&line).into_owned(),
+            end: true,
+        }
+    }
+
+    // Splits the parts that exceed the maximum size off the start of the
+    // line being read, at the start of a UTF-8 character, so that a valid
+    // character is not split across parts.
+    fn split_long(&mut self, lines: &mut Vec<Line>) {
+        let Some(max_bytes) = self.max_bytes else {
+            return;
+        };
+
+        while self.line.len() > max_bytes {
+            let mut at = max_bytes;
+            while at > max_bytes.saturating_sub(3) && at > 0 && is_continuation(self.line[at]) {
+                at -= 1;
+            }
+
+            if at == 0 {
+                at = max_bytes;
+            }
+
+            let rest = self.line.split_off(at);
+            let part = std::mem::replace(&mut self.line, rest);
+
+            lines.push(Line {
+                text: String::from_utf8_lossy(&part).into_owned(),
+                end: false,
+            });
+        }
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(lines: Vec<Line>) -> Vec<String> {
+        lines.into_iter().map(|line| line.text).collect()
+    }
+
+    #[test]
+    fn splitter_lines_across_chunks() {
+        let mut splitter = LineSplitter::default();
+
+        assert_eq!(texts(splitter.push(b"one\ntw")), vec!["one"]);
+        assert_eq!(texts(splitter.push(b"o")), Vec::<String>::new());
+        assert_eq!(
+            texts(splitter.push(b"\r\nthree\n\nfour")),
+            vec!["two", "three", ""]
+        );
+        assert_eq!(
+            splitter.finish().map(|line| line.text).as_deref(),
+            Some("four")
+        );
+        assert_eq!(splitter.finish(), None);
+
+        assert_eq!(texts(splitter.push(b"\xffbad\n")), vec!["\u{fffd}bad"]);
+        assert_eq!(splitter.finish(), None);
+    }
+
+    #[test]
+    fn splitter_flush() {
+        let mut splitter = LineSplitter::default();
+
+        assert!(splitter.push(b"start-\r").is_empty());
+        assert_eq!(
+            splitter.flush(),
+            Some(Line {
+                text: "start-\r".to_string(),
+                end: false
+            })
+        );
+        assert_eq!(splitter.flush(), None);
+        assert_eq!(splitter.finish(), None);
+    }
+
+    #[test]
+    fn splitter_long_line() {
+        let mut splitter = LineSplitter::default();
+        let chunk = vec![b'a'; READ_CHUNK_SIZE];
+
+        for _ in 0..100 {
+            assert!(splitter.push(&chunk).is_empty());
+        }
+
+        let lines = splitter.push(b"\n");
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].text.len(), READ_CHUNK_SIZE * 100);
+        assert!(lines[0].end);
+    }
+
+    #[test]
+    fn splitter_max_bytes() {
+        let mut splitter = LineSplitter::new(Some(READ_CHUNK_SIZE * 2));
+        let chunk = vec![b'a'; READ_CHUNK_SIZE];
+
+        // A large input without a newline is returned in parts as it is
+        // read, and never held in full.
+        let mut parts = Vec::new();
+        for _ in 0..100 {
+            parts.extend(splitter.push(&chunk));
+            assert!(splitter.line.len() <= READ_CHUNK_SIZE * 2);
+        }
+
+        assert_eq!(parts.len(), 49);
+        assert!(parts
+            .iter()
+            .all(|part| !part.end && part.text.len() == READ_CHUNK_SIZE * 2));
+
+        let last = splitter.push(b"\r\n");
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].text.len(), READ_CHUNK_SIZE * 2);
+        assert!(last[0].end);
+
+        // Parts are split at the start of a UTF-8 character.
+        let mut splitter = LineSplitter::new(Some(4));
+        assert_eq!(
+            splitter.push("aaé€b\n".as_bytes()),
+            vec![
+                Line {
+                    text: "aaé".to_string(),
+                    end: false
+                },
+                Line {
+                    text: "€b".to_string(),
+                    end: true
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::io;
+use std::path::PathBuf;
+
+use ::log::debug;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::package::NAME;
+
+// A directory in which the wrapper persists state across invocations, such
+// as the number of consecutive failures of a command.
+//
+// Each piece of state is stored as a JSON file in the directory. State is
+// best-effort: if it cannot be read, the default value is used instead.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct StateDir(PathBuf);
+
+impl StateDir {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self(path.into())
+    }
+
+    pub fn read<T: DeserializeOwned + Default>(&self, name: &str) -> T {
+        let path = self.0.join(name);
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    debug!("error reading state file {}: {}", path.display(), err);
+                }
+                return T::default();
+            }
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|err| {
+            debug!("error parsing state file {}: {}", path.display(), err);
+            T::default()
+        })
+    }
+
+    // Writes the state to a temporary file, which is then renamed, so that
+    // concurrent invocations never read a partially written file.
+    pub fn write<T: Serialize>(&self, name: &str, value: &T) -> io::Result<()> {
+        std::fs::create_dir_all(&self.0)?;
+
+        let path = self.0.join(name);
+        let temporary = self.0.join(format!(".{}.{}", name, std::process::id()));
+
+        std::fs::write(&temporary, serde_json::to_string(value)?)?;
+        std::fs::rename(&temporary, &path)
+    }
+}
+
+impl Default for StateDir {
+    // Uses `$XDG_STATE_HOME/appsignal-run`, falling back to
+    // `~/.local/state/appsignal-run`, or to a directory in the temporary
+    // directory if the home directory is not known.
+    fn default() -> Self {
+        let base = std::env::var_os("XDG_STATE_HOME")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .filter(|path| !path.is_empty())
+                    .map(|home| PathBuf::from(home).join(".local").join("state"))
+            })
+            .unwrap_or_else(std::env::temp_dir);
+
+        Self(base.join(NAME))
+    }
+}
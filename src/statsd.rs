@@ -0,0 +1,98 @@
+// Parses metrics written by the command to its standard output as StatsD
+// lines, such as `jobs.processed:1|c|#queue:default`, so that they can be
+// sent to AppSignal as custom metrics.
+//
+// The supported metric types are counters (`c`), gauges (`g`), and timers,
+// histograms and distributions (`ms`, `h` and `d`), which are all sent as
+// distributions. A sample rate (`@0.5`) is only applied to counters.
+
+use std::collections::BTreeMap;
+
+use crate::metrics::{Metric, MetricType};
+
+pub fn parse_line(line: &str) -> Option<Metric> {
+    let mut sections = line.trim().split('|');
+
+    let (name, value) = sections.next()?.rsplit_once(':')?;
+    let mut value: f64 = value.parse().ok()?;
+
+    if name.is_empty() || !value.is_finite() {
+        return None;
+    }
+
+    let metric_type = match sections.next()? {
+        "c" => MetricType::Counter,
+        "g" => MetricType::Gauge,
+        "ms" | "h" | "d" => MetricType::Distribution,
+        _ => return None,
+    };
+
+    let mut tags = BTreeMap::new();
+
+    for section in sections {
+        if let Some(rate) = section.strip_prefix('@') {
+            let rate: f64 = rate.parse().ok().filter(|rate| *rate > 0.0)?;
+
+            if let MetricType::Counter = metric_type {
+                value /= rate;
+            }
+        } else if let Some(pairs) = section.strip_prefix('#') {
+            for pair in pairs.split(',').filter(|pair| !pair.is_empty()) {
+                let (key, value) = pair.split_once(':').unwrap_or((pair, "true"));
+                tags.insert(key.to_string(), value.to_string());
+            }
+        } else {
+            return None;
+        }
+    }
+
+    Some(Metric {
+        name: name.to_string(),
+        metric_type,
+        value,
+        tags,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_statsd_lines() {
+        let metric = parse_line("jobs.processed:1|c|@0.5|#queue:default,retry").unwrap();
+
+        assert_eq!(metric.name, "jobs.processed");
+        assert_eq!(metric.metric_type, MetricType::Counter);
+        assert_eq!(metric.value, 2.0);
+        assert_eq!(
+            metric.tags,
+            BTreeMap::from([
+                ("queue".to_string(), "default".to_string()),
+                ("retry".to_string(), "true".to_string()),
+            ])
+        );
+
+        let metric = parse_line("queue.size:-3.5|g").unwrap();
+        assert_eq!(metric.metric_type, MetricType::Gauge);
+        assert_eq!(metric.value, -3.5);
+
+        let metric = parse_line("request.time:320|ms|@0.1").unwrap();
+        assert_eq!(metric.metric_type, MetricType::Distribution);
+        assert_eq!(metric.value, 320.0);
+
+        for line in [
+            "",
+            "Processed 3 jobs",
+            "at 12:30 | done",
+            "jobs.processed:1",
+            "jobs.processed:one|c",
+            ":1|c",
+            "jobs.processed:1|s",
+            "jobs.processed:1|c|@0",
+            "jobs.processed:1|c|queue:default",
+        ] {
+            assert!(parse_line(line).is_none(), "{line:?} should not parse");
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// Records the latency of the requests sent to AppSignal, grouped by the
+// endpoint they were sent to, so that a summary of them can be written when
+// the wrapper exits. This makes the impact of a slow endpoint on short
+// commands measurable.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::package::NAME;
+
+static REQUESTS: Mutex<BTreeMap<String, RequestStats>> = Mutex::new(BTreeMap::new());
+static LEAKS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static FAILURES: Mutex<BTreeMap<Failure, usize>> = Mutex::new(BTreeMap::new());
+
+// The reasons for which requests to AppSignal fail. Failures are counted by
+// their reason, so that each reason is reported once when the wrapper
+// exits, instead of once for every request, which would flood the emails
+// that cron sends with the wrapper's output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Failure {
+    Connect,
+    Timeout,
+    Status(u16),
+    Skipped,
+    Other,
+}
+
+impl Failure {
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Connect => "could not connect".to_string(),
+            Self::Timeout => "timed out".to_string(),
+            Self::Status(status) => format!("status {status}"),
+            Self::Skipped => "skipped, as the endpoint is failing".to_string(),
+            Self::Other => "could not send".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RequestStats {
+    latencies: Vec<Duration>,
+    failed: usize,
+    skipped: usize,
+}
+
+impl RequestStats {
+    fn render(&self) -> String {
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+
+        let mut line = format!("{} requests", latencies.len());
+
+        if self.failed > 0 {
+            let _ = write!(line, " ({} failed)", self.failed);
+        }
+
+        if self.skipped > 0 {
+            let _ = write!(line, ", {} skipped", self.skipped);
+        }
+
+        if let Some(max) = latencies.last() {
+            let _ = write!(
+                line,
+                ", p50 {}ms, p95 {}ms, max {}ms",
+                percentile(&latencies, 50).as_millis(),
+                percentile(&latencies, 95).as_millis(),
+                max.as_millis()
+            );
+        }
+
+        line
+    }
+}
+
+// Returns the nearest-rank percentile of the given sorted latencies.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn with_endpoint(endpoint: &str, f: impl FnOnce(&mut RequestStats)) {
+    let mut requests = REQUESTS.lock().unwrap_or_else(|err| err.into_inner());
+    f(requests.entry(endpoint.to_string()).or_default());
+}
+
+// Records a request that was sent, whether or not it succeeded.
+pub fn request(endpoint: &str, latency: Duration, success: bool) {
+    with_endpoint(endpoint, |stats| {
+        stats.latencies.push(latency);
+
+        if !success {
+            stats.failed += 1;
+        }
+    });
+}
+
+// Records a request that was not sent, as its endpoint is failing.
+pub fn skipped(endpoint: &str) {
+    with_endpoint(endpoint, |stats| stats.skipped += 1);
+}
+
+// Records a request that failed, for the given reason.
+pub fn failure(failure: Failure) {
+    let mut failures = FAILURES.lock().unwrap_or_else(|err| err.into_inner());
+    *failures.entry(failure).or_default() += 1;
+}
+
+// Returns a line describing the failed requests for each reason they failed
+// for, if any.
+pub fn failures() -> Vec<String> {
+    let failures = FAILURES.lock().unwrap_or_else(|err| err.into_inner());
+    render_failures(&failures)
+}
+
+fn render_failures(failures: &BTreeMap<Failure, usize>) -> Vec<String> {
+    failures
+        .iter()
+        .map(|(failure, count)| {
+            let requests = if *count == 1 { "request" } else { "requests" };
+            format!(
+                "{} {} to AppSignal failed ({})",
+                count,
+                requests,
+                failure.describe()
+            )
+        })
+        .collect()
+}
+
+// Records a resource that was not released when the wrapper exited.
+pub fn leak(description: String) {
+    let mut leaks = LEAKS.lock().unwrap_or_else(|err| err.into_inner());
+    leaks.push(description);
+}
+
+pub fn render() -> String {
+    let requests = REQUESTS.lock().unwrap_or_else(|err| err.into_inner());
+    let mut summary = format!("{} request summary\n", NAME);
+
+    if requests.is_empty() {
+        summary.push_str("  no requests sent\n");
+    }
+
+    for (endpoint, stats) in requests.iter() {
+        let _ = writeln!(summary, "  {}: {}", endpoint, stats.render());
+    }
+
+    let leaks = LEAKS.lock().unwrap_or_else(|err| err.into_inner());
+
+    for leak in leaks.iter() {
+        let _ = writeln!(summary, "  not released on exit: {}", leak);
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_stats_render() {
+        let stats = RequestStats {
+            latencies: (1..=20).rev().map(Duration::from_millis).collect(),
+            failed: 2,
+            skipped: 1,
+        };
+
+        assert_eq!(
+            stats.render(),
+            "20 requests (2 failed), 1 skipped, p50 10ms, p95 19ms, max 20ms"
+        );
+
+        let stats = RequestStats {
+            skipped: 3,
+            ..Default::default()
+        };
+
+        assert_eq!(stats.render(), "0 requests, 3 skipped");
+    }
+
+    #[test]
+    fn failures_render() {
+        assert!(render_failures(&BTreeMap::new()).is_empty());
+
+        let failures = BTreeMap::from([
+            (Failure::Skipped, 4),
+            (Failure::Connect, 3),
+            (Failure::Status(503), 1),
+        ]);
+
+        assert_eq!(
+            render_failures(&failures),
+            vec![
+                "3 requests to AppSignal failed (could not connect)",
+                "1 request to AppSignal failed (status 503)",
+                "4 requests to AppSignal failed (skipped, as the endpoint is failing)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,339 @@
+// Tails the files given with `--log-file`, for commands that write their
+// logs to files instead of to their standard output or standard error. Only
+// lines written after the wrapper starts are read. When a file is rotated,
+// by renaming it or truncating it, the lines written to the new file are
+// read from its start.
+//
+// The files are read in fixed-size chunks, in a blocking task, and each line
+// is sent as soon as it is read, so that a file with many new lines, or a
+// long line, is not held in memory at once. A line longer than the maximum
+// size, if any, is sent in parts.
+
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::debug;
+use serde::Deserialize;
+use tokio::select;
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+
+use crate::splitter::{LineSplitter, READ_CHUNK_SIZE};
+use crate::timestamp::Clock;
+
+// How often to check the files for new lines.
+const TAIL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct LogFile {
+    pub path: PathBuf,
+    // The log group to send the file's lines to, instead of the one for the
+    // command's output.
+    pub group: Option<String>,
+}
+
+impl LogFile {
+    // Parses a file given as `PATH` or `PATH:GROUP`, such as
+    // `/var/log/app.log:app`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (path, group) = match value.rsplit_once(':') {
+            Some((path, group)) if !group.is_empty() && !group.contains('/') => {
+                (path, Some(group.to_string()))
+            }
+            _ => (value, None),
+        };
+
+        if path.is_empty() {
+            return Err(format!(
+                "invalid log file `{value}`; expected `PATH` or `PATH:GROUP`"
+            ));
+        }
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            group,
+        })
+    }
+}
+
+impl TryFrom<String> for LogFile {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+// A line read from one of the tailed files.
+pub struct FileLine {
+    pub file: Arc<LogFile>,
+    pub line: String,
+}
+
+// Sends the lines written to the file until the cancellation token is
+// cancelled, after which the lines written up to then are sent. The file is
+// opened when this is called, so that the lines written after it returns,
+// such as by the command once it is started, are read.
+pub fn tail(
+    file: Arc<LogFile>,
+    sender: Sender<FileLine>,
+    clock: impl Clock,
+    cancel: CancellationToken,
+    max_line_bytes: Option<usize>,
+) -> impl Future<Output = ()> {
+    let mut tailer = Tailer::new(file.path.clone(), max_line_bytes);
+
+    async move {
+        loop {
+            let cancelled = select! {
+                _ = cancel.cancelled() => true,
+                _ = clock.sleep(TAIL_INTERVAL) => false,
+            };
+
+            let (file, sender) = (file.clone(), sender.clone());
+            let polled = tokio::task::spawn_blocking(move || {
+                let open = tailer.poll(cancelled, |line| {
+                    let line = FileLine {
+                        file: file.clone(),
+                        line,
+                    };
+
+                    sender.blocking_send(line).is_ok()
+                });
+
+                (tailer, open)
+            })
+            .await;
+
+            match polled {
+                Ok((polled, true)) if !cancelled => tailer = polled,
+                _ => return,
+            }
+        }
+    }
+}
+
+struct Tailer {
+    path: PathBuf,
+    // The open file, alongside its inode, if it exists.
+    file: Option<(File, u64)>,
+    // Whether a file that is opened is read from its start. This is the
+    // case for every file but the one that exists when the wrapper starts.
+    from_start: bool,
+    // The start of the last line, if it is not complete yet.
+    splitter: LineSplitter,
+}
+
+impl Tailer {
+    fn new(path: PathBuf, max_line_bytes: Option<usize>) -> Self {
+        let mut tailer = Self {
+            path,
+            file: None,
+            from_start: false,
+            splitter: LineSplitter::new(max_line_bytes),
+        };
+
+        tailer.open();
+        tailer.from_start = true;
+        tailer
+    }
+
+    fn open(&mut self) {
+        let opened = File::open(&self.path).and_then(|mut file| {
+            let inode = file.metadata()?.ino();
+            if !self.from_start {
+                file.seek(SeekFrom::End(0))?;
+            }
+            Ok((file, inode))
+        });
+
+        match opened {
+            Ok(file) => self.file = Some(file),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => debug!("could not open log file {}: {}", self.path.display(), err),
+        }
+    }
+
+    // Passes the complete lines written since the last poll to `send`, as
+    // they are read. If `flush` is set, the last line is passed even if it
+    // is not complete. Returns `false` if `send` does, once it does.
+    fn poll(&mut self, flush: bool, mut send: impl FnMut(String) -> bool) -> bool {
+        if self.file.is_none() {
+            self.open();
+        }
+
+        let Some((file, inode)) = self.file.as_mut() else {
+            return true;
+        };
+        let inode = *inode;
+
+        // A file that is shorter than the position read up to was truncated.
+        let position = file.stream_position().unwrap_or(0);
+        if file
+            .metadata()
+            .is_ok_and(|metadata| metadata.len() < position)
+        {
+            debug!("log file {} was truncated", self.path.display());
+            let _ = file.seek(SeekFrom::Start(0));
+            // The start of the last line was truncated with the rest.
+            self.splitter.flush();
+        }
+
+        let mut buffer = vec![0; READ_CHUNK_SIZE];
+        loop {
+            let read = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    debug!("could not read log file {}: {}", self.path.display(), err);
+                    break;
+                }
+            };
+
+            for line in self.splitter.push(&buffer[..read]) {
+                if !send(line.text) {
+                    return false;
+                }
+            }
+        }
+
+        // Once the file is renamed or removed, and it was read to its end,
+        // the file at its path, if any, is opened from its start.
+        let rotated = match std::fs::metadata(&self.path) {
+            Ok(metadata) => metadata.ino() != inode,
+            Err(_) => true,
+        };
+
+        if rotated {
+            debug!("log file {} was rotated", self.path.display());
+            self.file = None;
+        }
+
+        if !rotated && !flush {
+            return true;
+        }
+
+        match self.splitter.finish() {
+            Some(line) => send(line.text),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    #[test]
+    fn parse_log_file() {
+        assert_eq!(
+            LogFile::parse("/var/log/app.log"),
+            Ok(LogFile {
+                path: PathBuf::from("/var/log/app.log"),
+                group: None,
+            })
+        );
+        assert_eq!(
+            LogFile::parse("/var/log/app.log:app"),
+            Ok(LogFile {
+                path: PathBuf::from("/var/log/app.log"),
+                group: Some("app".to_string()),
+            })
+        );
+        assert_eq!(
+            LogFile::parse("./logs:2024/app.log"),
+            Ok(LogFile {
+                path: PathBuf::from("./logs:2024/app.log"),
+                group: None,
+            })
+        );
+        assert!(LogFile::parse("").is_err());
+        assert!(LogFile::parse(":app").is_err());
+    }
+
+    fn poll(tailer: &mut Tailer, flush: bool) -> Vec<String> {
+        let mut lines = Vec::new();
+        assert!(tailer.poll(flush, |line| {
+            lines.push(line);
+            true
+        }));
+        lines
+    }
+
+    #[test]
+    fn tailer_rotation() {
+        let dir = std::env::temp_dir().join(format!("{}-tail-test", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        let append = |contents: &str| {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .unwrap();
+            file.write_all(contents.as_bytes()).unwrap();
+        };
+
+        // Lines written before the wrapper starts are not read.
+        append("before start\n");
+        let mut tailer = Tailer::new(path.clone(), None);
+        assert!(poll(&mut tailer, false).is_empty());
+
+        append("first\nsec");
+        assert_eq!(poll(&mut tailer, false), ["first"]);
+        append("ond\r\n");
+        assert_eq!(poll(&mut tailer, false), ["second"]);
+
+        // The file is renamed, and a new one is created in its place.
+        append("before rename\n");
+        std::fs::rename(&path, dir.join("app.log.1")).unwrap();
+        append("after rename\n");
+        assert_eq!(poll(&mut tailer, false), ["before rename"]);
+        assert_eq!(poll(&mut tailer, false), ["after rename"]);
+
+        // The file is truncated.
+        std::fs::write(&path, "").unwrap();
+        assert!(poll(&mut tailer, false).is_empty());
+        append("after truncate\n");
+        assert_eq!(poll(&mut tailer, false), ["after truncate"]);
+
+        append("incomplete");
+        assert!(poll(&mut tailer, false).is_empty());
+        assert_eq!(poll(&mut tailer, true), ["incomplete"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tailer_long_line() {
+        let dir = std::env::temp_dir().join(format!("{}-tail-long-test", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        std::fs::write(&path, "").unwrap();
+
+        let mut tailer = Tailer::new(path.clone(), Some(READ_CHUNK_SIZE));
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&vec![b'a'; READ_CHUNK_SIZE * 10]).unwrap();
+
+        // A long line without a newline is sent in parts as it is read, and
+        // reading stops once a part cannot be sent.
+        let mut parts = 0;
+        assert!(!tailer.poll(false, |part| {
+            assert_eq!(part.len(), READ_CHUNK_SIZE);
+            parts += 1;
+            parts < 3
+        }));
+        assert_eq!(parts, 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 use chrono::{DateTime, SecondsFormat};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::{Interval, Sleep};
 
 #[derive(Clone, Copy)]
 pub struct SystemTimestamp;
@@ -12,6 +13,8 @@ impl Timestamp for SystemTimestamp {
     }
 }
 
+impl Clock for SystemTimestamp {}
+
 const MONOTONIC_GAP: Duration = Duration::from_millis(1);
 
 // This works around an issue with the logging feature, where timestamps only
@@ -23,14 +26,37 @@ const MONOTONIC_GAP: Duration = Duration::from_millis(1);
 // timestamps returned between two successive calls are at least one
 // millisecond apart. This means, however, that the timestamps may not
 // accurately reflect the times at which the logs were written.
+//
+// When the system clock is set back by a large amount, such as by an NTP
+// time step, the timestamps would be stuck one millisecond apart until the
+// system clock catches up. To avoid this, the amount by which the clock
+// was set back is added as an offset to the source time, which is then
+// gradually reduced, slewing the timestamps back to the source time.
 pub struct MonotonicTimestamp<T: Timestamp> {
     last: Option<Duration>,
+    last_source: Option<Duration>,
+    offset: Duration,
     source: T,
 }
 
+// While an offset is being slewed, it is reduced by a tenth of the elapsed
+// source time, so that the timestamps advance at nine tenths of its speed.
+const SLEW_RATIO: u32 = 10;
+
 impl<T: Timestamp> MonotonicTimestamp<T> {
     pub fn new(source: T) -> Self {
-        Self { last: None, source }
+        Self {
+            last: None,
+            last_source: None,
+            offset: Duration::ZERO,
+            source,
+        }
+    }
+
+    pub fn clock_jumped(&mut self, jump: &ClockJump) {
+        if let ClockJump::Backward(jump) = jump {
+            self.offset += *jump;
+        }
     }
 
     #[cfg(test)]
@@ -41,7 +67,17 @@ impl<T: Timestamp> MonotonicTimestamp<T> {
 
 impl<T: Timestamp> Timestamp for MonotonicTimestamp<T> {
     fn now(&mut self) -> Duration {
-        let now = self.source.now();
+        let source = self.source.now();
+
+        if let Some(elapsed) = self
+            .last_source
+            .and_then(|last_source| source.checked_sub(last_source))
+        {
+            self.offset = self.offset.saturating_sub(elapsed / SLEW_RATIO);
+        }
+
+        self.last_source = Some(source);
+        let now = source + self.offset;
 
         self.last = Some(match self.last {
             Some(last) => match now.checked_sub(last) {
@@ -55,6 +91,89 @@ impl<T: Timestamp> Timestamp for MonotonicTimestamp<T> {
     }
 }
 
+// Detects jumps in the system clock, by comparing the time elapsed on the
+// system clock with the time elapsed on the monotonic clock between two
+// successive checks.
+//
+// The monotonic clock does not advance while the system is suspended, so
+// a resume from sleep is detected as a forward jump of the system clock.
+// Adjustments of the system clock, such as an NTP time step, are detected
+// as either a forward or a backward jump.
+pub struct ClockJumpDetector<T: Timestamp> {
+    last: Option<(Duration, Instant)>,
+    source: T,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClockJump {
+    Forward(Duration),
+    Backward(Duration),
+}
+
+// Differences below this threshold are not considered jumps, as they may
+// be caused by the time it takes to perform the check itself.
+const CLOCK_JUMP_THRESHOLD: Duration = Duration::from_secs(5);
+
+impl<T: Timestamp> ClockJumpDetector<T> {
+    pub fn new(source: T) -> Self {
+        Self { last: None, source }
+    }
+
+    fn check_at(&mut self, instant: Instant) -> Option<ClockJump> {
+        let now = self.source.now();
+        let last = self.last.replace((now, instant));
+
+        let (last_now, last_instant) = last?;
+        let monotonic_elapsed = instant.saturating_duration_since(last_instant);
+
+        match now.checked_sub(last_now) {
+            Some(elapsed) => match elapsed.checked_sub(monotonic_elapsed) {
+                Some(jump) if jump > CLOCK_JUMP_THRESHOLD => Some(ClockJump::Forward(jump)),
+                _ => None,
+            },
+            None => {
+                let jump = last_now - now + monotonic_elapsed;
+                (jump > CLOCK_JUMP_THRESHOLD).then_some(ClockJump::Backward(jump))
+            }
+        }
+    }
+}
+
+impl<T: Clock> ClockJumpDetector<T> {
+    pub fn check(&mut self) -> Option<ClockJump> {
+        let instant = self.source.instant().into_std();
+        self.check_at(instant)
+    }
+}
+
+// Timestamps before the start of 2020 are assumed to come from a system
+// clock that was never set, such as on a device without a battery-backed
+// clock that boots with its clock set to 1970 until it is synchronised.
+const SANE_EPOCH: Duration = Duration::from_secs(1_577_836_800);
+
+// How often to check whether the system clock was set while waiting for it.
+const SANE_CLOCK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn is_sane(timestamp: &mut impl Timestamp) -> bool {
+    timestamp.now() >= SANE_EPOCH
+}
+
+// Waits for the system clock to be set to a sane time, for at most the
+// given timeout. Returns whether it was set in time.
+pub async fn wait_for_sane_clock(mut clock: impl Clock, timeout: Duration) -> bool {
+    let deadline = clock.instant() + timeout;
+
+    while !is_sane(&mut clock) {
+        if clock.instant() >= deadline {
+            return false;
+        }
+
+        clock.sleep(SANE_CLOCK_INTERVAL).await;
+    }
+
+    true
+}
+
 pub trait Timestamp {
     fn now(&mut self) -> Duration;
 
@@ -72,9 +191,35 @@ pub trait Timestamp {
     }
 }
 
+// A clock that provides both the system time, as a timestamp, and the
+// monotonic time that intervals and sleeps are measured against.
+//
+// Tasks that wait on the clock take it as an argument, so that they can be
+// tested with Tokio's time paused, in which case the monotonic time only
+// advances when the test advances it, or when every task is waiting on it.
+// The system time of the `TestClock` used in tests advances along with it.
+pub trait Clock: Timestamp + Clone + Send + 'static {
+    fn instant(&self) -> tokio::time::Instant {
+        tokio::time::Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        tokio::time::sleep(duration)
+    }
+
+    fn interval(&self, period: Duration) -> Interval {
+        self.interval_at(self.instant(), period)
+    }
+
+    fn interval_at(&self, start: tokio::time::Instant, period: Duration) -> Interval {
+        tokio::time::interval_at(start, period)
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use std::sync::{Arc, Mutex};
 
     pub struct TestTimestamp(Duration);
 
@@ -84,6 +229,40 @@ pub mod tests {
         }
     }
 
+    // A clock whose system time starts at the given time and advances along
+    // with Tokio's monotonic time, which can be paused and advanced by tests.
+    // The system time can also be made to jump, which affects every clone.
+    #[derive(Clone)]
+    pub struct TestClock {
+        start: tokio::time::Instant,
+        system: Arc<Mutex<Duration>>,
+    }
+
+    impl TestClock {
+        pub fn new(system: Duration) -> Self {
+            Self {
+                start: tokio::time::Instant::now(),
+                system: Arc::new(Mutex::new(system)),
+            }
+        }
+
+        pub fn jump_forward(&self, duration: Duration) {
+            *self.system.lock().unwrap() += duration;
+        }
+
+        pub fn jump_backward(&self, duration: Duration) {
+            *self.system.lock().unwrap() -= duration;
+        }
+    }
+
+    impl Timestamp for TestClock {
+        fn now(&mut self) -> Duration {
+            *self.system.lock().unwrap() + self.start.elapsed()
+        }
+    }
+
+    impl Clock for TestClock {}
+
     pub fn timestamp() -> TestTimestamp {
         TestTimestamp(Duration::from_secs(1_000_000_000))
     }
@@ -91,6 +270,96 @@ pub mod tests {
     pub const EXPECTED_SECS: u64 = 1_000_000_000;
     pub const EXPECTED_RFC3339: &str = "2001-09-09T01:46:40.000Z";
 
+    #[test]
+    fn clock_jump_detector() {
+        let start = Instant::now();
+        let mut detector = ClockJumpDetector::new(TestTimestamp(Duration::from_secs(1_000)));
+
+        // The first check has nothing to compare against.
+        assert_eq!(detector.check_at(start), None);
+
+        // Both clocks advance by the same amount.
+        detector.source = TestTimestamp(Duration::from_secs(1_010));
+        assert_eq!(detector.check_at(start + Duration::from_secs(10)), None);
+
+        // The system clock advances while the monotonic clock does not,
+        // such as when the system is suspended.
+        detector.source = TestTimestamp(Duration::from_secs(4_610));
+        assert_eq!(
+            detector.check_at(start + Duration::from_secs(20)),
+            Some(ClockJump::Forward(Duration::from_secs(3_590)))
+        );
+
+        // The system clock is set back.
+        detector.source = TestTimestamp(Duration::from_secs(4_520));
+        assert_eq!(
+            detector.check_at(start + Duration::from_secs(30)),
+            Some(ClockJump::Backward(Duration::from_secs(100)))
+        );
+
+        // Small differences are not considered jumps.
+        detector.source = TestTimestamp(Duration::from_secs(4_532));
+        assert_eq!(detector.check_at(start + Duration::from_secs(40)), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clock_jump_detector_with_test_clock() {
+        let clock = TestClock::new(Duration::from_secs(1_000));
+        let mut detector = ClockJumpDetector::new(clock.clone());
+        let mut interval = clock.interval(Duration::from_secs(5));
+
+        interval.tick().await;
+        assert_eq!(detector.check(), None);
+
+        interval.tick().await;
+        assert_eq!(clock.clone().as_secs(), 1_005);
+        assert_eq!(detector.check(), None);
+
+        clock.jump_forward(Duration::from_secs(3_600));
+        interval.tick().await;
+        assert_eq!(clock.clone().as_secs(), 4_610);
+        assert_eq!(
+            detector.check(),
+            Some(ClockJump::Forward(Duration::from_secs(3_600)))
+        );
+
+        clock.jump_backward(Duration::from_secs(100));
+        clock.sleep(Duration::from_secs(5)).await;
+        assert_eq!(
+            detector.check(),
+            Some(ClockJump::Backward(Duration::from_secs(100)))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_sane_clock_until_set() {
+        let clock = TestClock::new(Duration::from_secs(1_000));
+        assert!(!is_sane(&mut clock.clone()));
+
+        let setter = clock.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            setter.jump_forward(SANE_EPOCH);
+        });
+
+        let start = clock.instant();
+        assert!(wait_for_sane_clock(clock.clone(), Duration::from_secs(60)).await);
+        assert!(clock.instant() - start < Duration::from_secs(12));
+        assert!(is_sane(&mut clock.clone()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_sane_clock_times_out() {
+        let clock = TestClock::new(Duration::from_secs(1_000));
+
+        let start = clock.instant();
+        assert!(!wait_for_sane_clock(clock.clone(), Duration::from_secs(5)).await);
+        assert!(clock.instant() - start <= Duration::from_secs(6));
+
+        let clock = TestClock::new(SANE_EPOCH);
+        assert!(wait_for_sane_clock(clock, Duration::from_secs(5)).await);
+    }
+
     #[test]
     fn monotonic_timestamp() {
         // If the source time stays the same between calls,
@@ -133,4 +402,30 @@ pub mod tests {
         monotonic.swap(TestTimestamp(Duration::from_millis(1_510)));
         assert_eq!(monotonic.now().as_millis(), 1_510);
     }
+
+    #[test]
+    fn monotonic_timestamp_clock_jumped_backward() {
+        let mut monotonic = MonotonicTimestamp::new(TestTimestamp(Duration::from_secs(1_000)));
+        assert_eq!(monotonic.now().as_secs(), 1_000);
+
+        // The system clock is set back by 100 seconds.
+        monotonic.swap(TestTimestamp(Duration::from_secs(900)));
+        monotonic.clock_jumped(&ClockJump::Backward(Duration::from_secs(100)));
+
+        // The timestamps continue from where they were before the jump...
+        assert_eq!(monotonic.now().as_secs(), 1_000);
+
+        // ... and advance at a slower pace than the source time, until
+        // the offset is fully slewed.
+        monotonic.swap(TestTimestamp(Duration::from_secs(950)));
+        assert_eq!(monotonic.now().as_secs(), 1_045);
+
+        monotonic.swap(TestTimestamp(Duration::from_secs(1_900)));
+        assert_eq!(monotonic.now().as_secs(), 1_900);
+
+        // Forward jumps are not slewed.
+        monotonic.clock_jumped(&ClockJump::Forward(Duration::from_secs(100)));
+        monotonic.swap(TestTimestamp(Duration::from_secs(2_000)));
+        assert_eq!(monotonic.now().as_secs(), 2_000);
+    }
 }
@@ -0,0 +1,396 @@
+// Sends a span covering the command's execution to an OpenTelemetry
+// collector given with `--otlp-endpoint`, so that wrapped jobs appear in a
+// tracing view alongside the services they interact with.
+//
+// The span is sent once the command exits, using the OTLP/HTTP protocol
+// with a JSON body, to the `/v1/traces` path of the endpoint. It has the
+// same attributes as the tags of the errors sent to AppSignal, including
+// the digest of the invocation, and how the command exited. Its status is
+// an error if the exit is considered a failure.
+
+use std::collections::BTreeMap;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+use crate::client::client;
+use crate::error::{exit_context, exit_tags};
+use crate::package::{NAME, VERSION};
+use crate::wrap::{hostname, random_digest, require, validate_endpoint};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TraceConfig {
+    pub endpoint: String,
+    // The headers to send with the request, such as those that authenticate
+    // it with the collector.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub name: String,
+    #[serde(default = "hostname")]
+    pub hostname: String,
+    #[serde(default = "random_digest")]
+    pub digest: String,
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub revision: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    // The IDs of the trace and of the span that covers the command's
+    // execution, as lowercase hexadecimal.
+    #[serde(default = "random_trace_id")]
+    pub trace_id: String,
+    #[serde(default = "random_digest")]
+    pub span_id: String,
+}
+
+pub fn random_trace_id() -> String {
+    use hex::encode;
+    use rand::random;
+
+    encode(random::<[u8; 16]>())
+}
+
+// The IDs of the trace and of the span of an invocation, which the command
+// can parent its own spans to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub span_id: String,
+}
+
+impl TraceContext {
+    // The context of a span that is not sent to a collector, so that the
+    // command's own spans still share a trace.
+    pub fn random() -> Self {
+        Self {
+            trace_id: random_trace_id(),
+            span_id: random_digest(),
+        }
+    }
+
+    // The context as a W3C Trace Context `traceparent` header, with the
+    // span marked as sampled.
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace_id, self.span_id)
+    }
+}
+
+impl TraceConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        validate_endpoint(&self.endpoint)?;
+        require(&self.name, "trace name")?;
+
+        for (name, value) in &self.headers {
+            if HeaderName::try_from(name).is_err() || HeaderValue::try_from(value).is_err() {
+                return Err(format!("invalid OTLP header: {name}"));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn context(&self) -> TraceContext {
+        TraceContext {
+            trace_id: self.trace_id.clone(),
+            span_id: self.span_id.clone(),
+        }
+    }
+
+    pub fn request(&self, body: TraceBody) -> Result<reqwest::Request, reqwest::Error> {
+        let url = format!("{}/v1/traces", self.endpoint.trim_end_matches('/'));
+        let mut builder = client()
+            .post(url)
+            .header("Content-Type", "application/json");
+
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+
+        builder
+            .body(serde_json::to_string(&body).expect("failed to serialize span"))
+            .build()
+    }
+
+    // Reports the command's execution as a span that ended at the given
+    // time, since the Unix epoch, after running for the given duration.
+    pub fn request_from_exit(
+        &self,
+        end: Duration,
+        duration: Duration,
+        exit: &ExitStatus,
+        failure: bool,
+    ) -> Result<reqwest::Request, reqwest::Error> {
+        self.request(TraceBody::from_exit(self, end, duration, exit, failure))
+    }
+
+    fn attributes(&self, exit: &ExitStatus) -> Vec<KeyValue> {
+        let mut tags = self.tags.clone();
+        tags.insert(format!("{}-digest", NAME), self.digest.clone());
+
+        if let Some(command) = self.command.as_ref() {
+            tags.insert("command".to_string(), command.clone());
+        }
+
+        if let Some(revision) = self.revision.as_ref() {
+            tags.insert("revision".to_string(), revision.clone());
+        }
+
+        tags.extend(exit_tags(exit));
+
+        tags.into_iter()
+            .map(|(key, value)| KeyValue::string(key, value))
+            .collect()
+    }
+}
+
+// The body of an OTLP/HTTP export request for a single span.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceBody {
+    resource_spans: Vec<ResourceSpans>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceSpans {
+    resource: Resource,
+    scope_spans: Vec<ScopeSpans>,
+}
+
+#[derive(Debug, Serialize)]
+struct Resource {
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Debug, Serialize)]
+struct ScopeSpans {
+    scope: Scope,
+    spans: Vec<Span>,
+}
+
+#[derive(Debug, Serialize)]
+struct Scope {
+    name: String,
+    version: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Span {
+    trace_id: String,
+    span_id: String,
+    name: String,
+    kind: u8,
+    // The timestamps are 64-bit integers, which are encoded as strings in
+    // OTLP's JSON encoding.
+    start_time_unix_nano: String,
+    end_time_unix_nano: String,
+    attributes: Vec<KeyValue>,
+    status: Status,
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    code: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct KeyValue {
+    key: String,
+    value: AnyValue,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum AnyValue {
+    StringValue(String),
+}
+
+impl KeyValue {
+    fn string(key: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            value: AnyValue::StringValue(value.into()),
+        }
+    }
+}
+
+// The span kind for an operation that is not a client or a server.
+const SPAN_KIND_INTERNAL: u8 = 1;
+const STATUS_CODE_OK: u8 = 1;
+const STATUS_CODE_ERROR: u8 = 2;
+
+impl TraceBody {
+    pub fn from_exit(
+        config: &TraceConfig,
+        end: Duration,
+        duration: Duration,
+        exit: &ExitStatus,
+        failure: bool,
+    ) -> Self {
+        let start = end.saturating_sub(duration);
+
+        let status = match failure {
+            true => Status {
+                code: STATUS_CODE_ERROR,
+                message: Some(format!("process exited with {}", exit_context(exit).1)),
+            },
+            false => Status {
+                code: STATUS_CODE_OK,
+                message: None,
+            },
+        };
+
+        TraceBody {
+            resource_spans: vec![ResourceSpans {
+                resource: Resource {
+                    attributes: vec![
+                        KeyValue::string("service.name", config.name.clone()),
+                        KeyValue::string("host.name", config.hostname.clone()),
+                    ],
+                },
+                scope_spans: vec![ScopeSpans {
+                    scope: Scope {
+                        name: NAME.to_string(),
+                        version: VERSION.to_string(),
+                    },
+                    spans: vec![Span {
+                        trace_id: config.trace_id.clone(),
+                        span_id: config.span_id.clone(),
+                        name: config.name.clone(),
+                        kind: SPAN_KIND_INTERNAL,
+                        start_time_unix_nano: start.as_nanos().to_string(),
+                        end_time_unix_nano: end.as_nanos().to_string(),
+                        attributes: config.attributes(exit),
+                        status,
+                    }],
+                }],
+            }],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::os::unix::process::ExitStatusExt;
+
+    fn trace_config() -> TraceConfig {
+        TraceConfig {
+            endpoint: "http://collector:4318/".to_string(),
+            headers: BTreeMap::from([("x-token".to_string(), "secret".to_string())]),
+            name: "some-name".to_string(),
+            hostname: "some-hostname".to_string(),
+            digest: "some-digest".to_string(),
+            command: Some("some-command".to_string()),
+            revision: None,
+            tags: BTreeMap::from([("team".to_string(), "backend".to_string())]),
+            trace_id: "0af7651916cd43dd8448eb211c80319c".to_string(),
+            span_id: "b7ad6b7169203331".to_string(),
+        }
+    }
+
+    #[test]
+    fn trace_random_ids() {
+        let trace_id = random_trace_id();
+        assert_eq!(trace_id.len(), 32);
+        assert!(trace_id.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn trace_context_traceparent() {
+        assert_eq!(
+            trace_config().context().traceparent(),
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+        );
+
+        let context = TraceContext::random();
+        assert_eq!(context.traceparent().len(), 55);
+        assert_ne!(context, TraceContext::random());
+    }
+
+    #[test]
+    fn trace_request_from_exit() {
+        let request = trace_config()
+            .request_from_exit(
+                Duration::from_secs(100),
+                Duration::from_millis(1500),
+                &ExitStatus::from_raw(1 << 8),
+                true,
+            )
+            .expect("failed to create request");
+
+        assert_eq!(request.url().as_str(), "http://collector:4318/v1/traces");
+        assert_eq!(request.headers()["x-token"], "secret");
+
+        let body: Value =
+            serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        let resource_spans = &body["resourceSpans"][0];
+
+        assert_eq!(
+            resource_spans["resource"]["attributes"],
+            json!([
+                {"key": "service.name", "value": {"stringValue": "some-name"}},
+                {"key": "host.name", "value": {"stringValue": "some-hostname"}},
+            ])
+        );
+
+        let span = &resource_spans["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(span["spanId"], "b7ad6b7169203331");
+        assert_eq!(span["name"], "some-name");
+        assert_eq!(span["startTimeUnixNano"], "98500000000");
+        assert_eq!(span["endTimeUnixNano"], "100000000000");
+        assert_eq!(
+            span["status"],
+            json!({"code": 2, "message": "process exited with code 1"})
+        );
+
+        let attributes: BTreeMap<&str, &str> = span["attributes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|attribute| {
+                (
+                    attribute["key"].as_str().unwrap(),
+                    attribute["value"]["stringValue"].as_str().unwrap(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            attributes,
+            BTreeMap::from([
+                ("appsignal-run-digest", "some-digest"),
+                ("command", "some-command"),
+                ("exit_code", "1"),
+                ("exit_kind", "code"),
+                ("team", "backend"),
+            ])
+        );
+    }
+
+    #[test]
+    fn trace_status_ok() {
+        let body = TraceBody::from_exit(
+            &trace_config(),
+            Duration::from_secs(100),
+            Duration::from_secs(1),
+            &ExitStatus::from_raw(0),
+            false,
+        );
+
+        let body = serde_json::to_value(body).unwrap();
+        assert_eq!(
+            body["resourceSpans"][0]["scopeSpans"][0]["spans"][0]["status"],
+            json!({"code": 1})
+        );
+    }
+}
@@ -0,0 +1,209 @@
+// Parses the durations and sizes given to command-line options and in
+// configuration files, such as `30s`, `5m`, `1h30m` or `10MB`.
+//
+// A number without a unit is a number of seconds or bytes, as options took
+// those before units were accepted.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+const DURATION_EXAMPLES: &str = "a number of seconds, or a duration such as `30s`, `5m` or `1h30m`";
+const SIZE_EXAMPLES: &str = "a number of bytes, or a size such as `512KB`, `10MB` or `1GiB`";
+
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration `{value}`; expected {DURATION_EXAMPLES}");
+
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut duration = Duration::ZERO;
+    let mut rest = value;
+
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    while !rest.is_empty() {
+        let (number, after) = split_number(rest).ok_or_else(invalid)?;
+        let unit_len = after
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(after.len());
+        let (unit, after) = after.split_at(unit_len);
+
+        let part = match unit.to_ascii_lowercase().as_str() {
+            "ms" => Duration::from_millis(number),
+            "s" => Duration::from_secs(number),
+            "m" | "min" => Duration::from_secs(number.checked_mul(60).ok_or_else(invalid)?),
+            "h" => Duration::from_secs(number.checked_mul(60 * 60).ok_or_else(invalid)?),
+            _ => return Err(invalid()),
+        };
+
+        duration = duration.checked_add(part).ok_or_else(invalid)?;
+        rest = after;
+    }
+
+    Ok(duration)
+}
+
+// Parses a duration that must be greater than zero, such as a timeout or
+// an interval.
+pub fn parse_positive_duration(value: &str) -> Result<Duration, String> {
+    match parse_duration(value)? {
+        duration if duration.is_zero() => Err(format!(
+            "invalid duration `{value}`; must be greater than zero"
+        )),
+        duration => Ok(duration),
+    }
+}
+
+pub fn parse_size(value: &str) -> Result<usize, String> {
+    let invalid = || format!("invalid size `{value}`; expected {SIZE_EXAMPLES}");
+
+    let (number, unit) = split_number(value.trim()).ok_or_else(invalid)?;
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .and_then(|size| usize::try_from(size).ok())
+        .ok_or_else(invalid)
+}
+
+// Parses a size that must be greater than zero, such as a limit.
+pub fn parse_positive_size(value: &str) -> Result<usize, String> {
+    match parse_size(value)? {
+        0 => Err(format!("invalid size `{value}`; must be greater than zero")),
+        size => Ok(size),
+    }
+}
+
+// Splits the number at the start of the value from the rest of it.
+fn split_number(value: &str) -> Option<(u64, &str)> {
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, rest) = value.split_at(digits);
+    Some((number.parse().ok()?, rest))
+}
+
+// Deserializes a duration given either as a number of seconds or as a
+// string with units, such as `interval = 60` or `interval = "1m"`.
+pub fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Seconds(u64),
+        Duration(String),
+    }
+
+    match Value::deserialize(deserializer)? {
+        Value::Seconds(seconds) => Ok(Duration::from_secs(seconds)),
+        Value::Duration(value) => parse_duration(&value).map_err(serde::de::Error::custom),
+    }
+}
+
+pub fn deserialize_optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    #[derive(Deserialize)]
+    struct Wrapper(#[serde(deserialize_with = "deserialize_duration")] Duration);
+
+    Option::<Wrapper>::deserialize(deserializer).map(|wrapper| wrapper.map(|Wrapper(d)| d))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_durations() {
+        for (value, expected) in [
+            ("0", Duration::ZERO),
+            ("30", Duration::from_secs(30)),
+            ("30s", Duration::from_secs(30)),
+            ("5m", Duration::from_secs(5 * 60)),
+            ("5min", Duration::from_secs(5 * 60)),
+            ("2h", Duration::from_secs(2 * 60 * 60)),
+            ("1h30m", Duration::from_secs(90 * 60)),
+            ("1m30s", Duration::from_secs(90)),
+            ("1500ms", Duration::from_millis(1500)),
+            (" 10S ", Duration::from_secs(10)),
+        ] {
+            assert_eq!(parse_duration(value), Ok(expected), "{value:?}");
+        }
+
+        for value in ["", "s", "-1", "1.5s", "10d", "1h 30m", "m5"] {
+            assert_eq!(
+                parse_duration(value),
+                Err(format!(
+                    "invalid duration `{value}`; expected {DURATION_EXAMPLES}"
+                )),
+                "{value:?}"
+            );
+        }
+
+        assert!(parse_positive_duration("0s").is_err());
+        assert_eq!(parse_positive_duration("1"), Ok(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn parse_sizes() {
+        for (value, expected) in [
+            ("0", 0),
+            ("512", 512),
+            ("512B", 512),
+            ("10KB", 10_000),
+            ("10kb", 10_000),
+            ("10MB", 10_000_000),
+            ("1GB", 1_000_000_000),
+            ("4KiB", 4096),
+            ("1MiB", 1_048_576),
+        ] {
+            assert_eq!(parse_size(value), Ok(expected), "{value:?}");
+        }
+
+        for value in ["", "MB", "-1", "1.5MB", "10TB"] {
+            assert_eq!(
+                parse_size(value),
+                Err(format!("invalid size `{value}`; expected {SIZE_EXAMPLES}")),
+                "{value:?}"
+            );
+        }
+
+        assert!(parse_positive_size("0KB").is_err());
+    }
+
+    #[test]
+    fn deserialize_durations() {
+        #[derive(Deserialize)]
+        struct Config {
+            #[serde(deserialize_with = "deserialize_duration")]
+            interval: Duration,
+            #[serde(default, deserialize_with = "deserialize_optional_duration")]
+            timeout: Option<Duration>,
+        }
+
+        let config: Config = toml::from_str("interval = 60").unwrap();
+        assert_eq!(config.interval, Duration::from_secs(60));
+        assert_eq!(config.timeout, None);
+
+        let config: Config = toml::from_str("interval = \"1m30s\"\ntimeout = \"5m\"").unwrap();
+        assert_eq!(config.interval, Duration::from_secs(90));
+        assert_eq!(config.timeout, Some(Duration::from_secs(300)));
+
+        assert!(toml::from_str::<Config>("interval = \"soon\"").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+// Zero-downtime upgrades of the wrapper process.
+//
+// A wrapper started with `--pid-file` writes its PID to that file. Running
+// `appsignal-run upgrade --pid-file PATH` sends `UPGRADE_SIGNAL` to that
+// PID, causing the running wrapper to stop reading from the child process'
+// pipes, flush its pending logs, and re-execute itself -- picking up the
+// binary that is now installed at the same path -- with the same arguments.
+//
+// Since `execve` keeps the same PID, the child process remains a child of
+// the re-executed wrapper. The read ends of the child process' pipes are
+// inherited by the new wrapper process, and their file descriptors, along
+// with the child process' PID and the digest of the invocation, are passed
+// to it in the `UPGRADE_STATE_ENV` environment variable.
+
+use std::ffi::OsString;
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use ::log::debug;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::cli::UpgradeArgs;
+
+// Neither `SIGUSR1` nor `SIGUSR2` can be used to request an upgrade, as
+// they are forwarded to the child process. `SIGALRM` is not forwarded to
+// the child process, and it is not otherwise used by the wrapper.
+const UPGRADE_SIGNAL: Signal = Signal::SIGALRM;
+
+const UPGRADE_STATE_ENV: &str = "APPSIGNAL_RUN_UPGRADE_STATE";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpgradeState {
+    pub pid: u32,
+    pub stdout: Option<RawFd>,
+    pub stderr: Option<RawFd>,
+    pub digest: String,
+}
+
+impl UpgradeState {
+    // Reads the state passed by the wrapper process that was running before
+    // the upgrade, if any, removing it from the environment so that it is
+    // not inherited by other processes.
+    //
+    // This must be called before any threads are spawned.
+    pub fn inherited() -> Option<Self> {
+        let value = std::env::var(UPGRADE_STATE_ENV).ok()?;
+        std::env::remove_var(UPGRADE_STATE_ENV);
+
+        match serde_json::from_str(&value) {
+            Ok(state) => Some(state),
+            Err(err) => {
+                debug!("error parsing upgrade state: {}", err);
+                None
+            }
+        }
+    }
+
+    pub fn stdout(&self) -> Option<OwnedFd> {
+        self.stdout.map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    pub fn stderr(&self) -> Option<OwnedFd> {
+        self.stderr.map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    // Re-executes the wrapper with the same arguments it was started with,
+    // passing this state to the new process. On success, this function does
+    // not return.
+    pub fn exec(&self) -> io::Error {
+        let mut args = std::env::args_os();
+        // The path at which the wrapper was executed, rather than the path
+        // returned by `std::env::current_exe`, is used, so that the binary
+        // that is now installed at that path is executed.
+        let program = args
+            .next()
+            .unwrap_or_else(|| OsString::from(crate::package::NAME));
+
+        let state = match serde_json::to_string(self) {
+            Ok(state) => state,
+            Err(err) => return err.into(),
+        };
+
+        Command::new(program)
+            .args(args)
+            .env(UPGRADE_STATE_ENV, state)
+            .exec()
+    }
+}
+
+// Duplicates the file descriptor for the read end of one of the child
+// process' pipes, so that it can be inherited by the re-executed wrapper.
+// Unlike the file descriptors created by the standard library, the
+// duplicated file descriptor does not have the `FD_CLOEXEC` flag set.
+pub fn inheritable(fd: Option<RawFd>) -> io::Result<Option<RawFd>> {
+    let Some(fd) = fd else {
+        return Ok(None);
+    };
+
+    match unsafe { libc::dup(fd) } {
+        -1 => Err(io::Error::last_os_error()),
+        fd => Ok(Some(fd)),
+    }
+}
+
+pub fn signal_stream() -> io::Result<tokio::signal::unix::Signal> {
+    signal(SignalKind::alarm())
+}
+
+// Requests the wrapper whose PID is written in the given PID file to
+// re-execute itself.
+pub fn request(args: &UpgradeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(&args.pid_file)
+        .map_err(|err| format!("could not read {}: {}", args.pid_file.display(), err))?;
+
+    let pid: i32 = contents
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid PID in {}", args.pid_file.display()))?;
+
+    kill(Pid::from_raw(pid), UPGRADE_SIGNAL)
+        .map_err(|err| format!("could not signal wrapper with PID {}: {}", pid, err))?;
+
+    Ok(())
+}
+
+// A file containing the PID of the wrapper process, which is removed when
+// dropped. When the wrapper is re-executed, it is not dropped, and the
+// file is overwritten by the new wrapper process, whose PID is the same.
+pub struct PidFile(PathBuf);
+
+impl PidFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        std::fs::write(path, format!("{}\n", std::process::id()))?;
+        Ok(Self(path.to_path_buf()))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.0) {
+            debug!("error removing PID file: {}", err);
+        }
+    }
+}
@@ -0,0 +1,1430 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use nix::sys::signal::Signal;
+use serde::Deserialize;
+
+use crate::check_in::{CronConfig, HeartbeatConfig, Usr2Action};
+use crate::correlate::Correlate;
+use crate::dump::QuitAction;
+use crate::environment;
+use crate::error::{ErrorConfig, DEFAULT_MESSAGE_LINES};
+use crate::failure::{ExitCodes, FailureCodes};
+use crate::hook::{FailureHook, DEFAULT_ON_FAILURE_TIMEOUT};
+use crate::log::{LogConfig, Pattern};
+use crate::metrics::MetricsConfig;
+use crate::priority::WrapperPriority;
+use crate::redact::Redactor;
+use crate::restart::RestartPolicy;
+use crate::sample::ErrorSampler;
+use crate::signal::{
+    deserialize_signal, deserialize_signal_map, deserialize_signals, has_terminating_intent,
+};
+use crate::sink::SinkConfig;
+use crate::state::StateDir;
+use crate::tail::LogFile;
+use crate::trace::{TraceConfig, TraceContext};
+use crate::units::deserialize_optional_duration;
+
+pub const DEFAULT_ENDPOINT: &str = "https://appsignal-endpoint.net";
+
+pub fn default_endpoint() -> String {
+    DEFAULT_ENDPOINT.to_string()
+}
+
+pub fn default_compress() -> bool {
+    true
+}
+
+pub fn hostname() -> String {
+    use nix::unistd::gethostname;
+
+    gethostname()
+        .ok()
+        .and_then(|hostname| OsString::into_string(hostname).ok())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// Resolves the user and group to run the command as, each given by name or
+// by ID, to their IDs. Unless a group is given, the user's primary group is
+// used.
+fn user_and_group(
+    user: Option<&str>,
+    group: Option<&str>,
+) -> Result<(Option<u32>, Option<u32>), String> {
+    use nix::unistd::{Gid, Group, Uid, User};
+
+    let user = match user {
+        Some(name) => {
+            let found = match name.parse() {
+                Ok(uid) => User::from_uid(Uid::from_raw(uid)),
+                Err(_) => User::from_name(name),
+            };
+
+            match found {
+                Ok(Some(user)) => Some(user),
+                Ok(None) => return Err(format!("unknown user `{name}`")),
+                Err(err) => return Err(format!("could not look up user `{name}`: {err}")),
+            }
+        }
+        None => None,
+    };
+
+    let gid = match group {
+        Some(name) => {
+            let found = match name.parse() {
+                Ok(gid) => Group::from_gid(Gid::from_raw(gid)),
+                Err(_) => Group::from_name(name),
+            };
+
+            match found {
+                Ok(Some(group)) => Some(group.gid),
+                Ok(None) => return Err(format!("unknown group `{name}`")),
+                Err(err) => return Err(format!("could not look up group `{name}`: {err}")),
+            }
+        }
+        None => user.as_ref().map(|user| user.gid),
+    };
+
+    Ok((user.map(|user| user.uid.as_raw()), gid.map(Gid::as_raw)))
+}
+
+pub fn random_digest() -> String {
+    use hex::encode;
+    use rand::random;
+
+    encode(random::<[u8; 8]>())
+}
+
+// What the command's standard input is connected to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StdinMode {
+    // The wrapper's standard input, which the command inherits.
+    #[default]
+    Inherit,
+    // `/dev/null`, so that the command reads nothing from it.
+    Null,
+    // A pipe, to which the wrapper writes the lines it reads from its own
+    // standard input, sending them as logs.
+    Log,
+}
+
+// What to do when the command's output cannot be written to the wrapper's
+// output, such as when it is a closed pipe or a full disk.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PassthroughErrorAction {
+    // Skip the output that cannot be written, and keep reading it, so that
+    // it is still sent as logs and used for error messages.
+    #[default]
+    Continue,
+    // Stop reading the output.
+    Stop,
+}
+
+// The configuration for an invocation of the wrapper, independent of the
+// command-line interface that it was parsed from.
+//
+// A `WrapConfig` can only be obtained through `WrapConfigBuilder::build`,
+// either directly or by deserializing it, so that every configuration is
+// validated in the same way.
+#[derive(Deserialize)]
+#[serde(try_from = "WrapConfigBuilder")]
+pub struct WrapConfig {
+    pub command: Vec<String>,
+    pub digest: String,
+    pub log: Option<LogConfig>,
+    pub error: Option<ErrorConfig>,
+    pub metrics: Option<MetricsConfig>,
+    // If set, StatsD lines in the command's standard output are sent as
+    // metrics using this configuration, instead of as logs.
+    pub statsd: Option<MetricsConfig>,
+    // If set, a span covering the command's execution is sent to an
+    // OpenTelemetry collector using this configuration.
+    pub trace: Option<TraceConfig>,
+    pub cron: Option<CronConfig>,
+    pub heartbeat: Option<HeartbeatConfig>,
+    pub error_sample: Option<u64>,
+    // The maximum number of lines, and optionally bytes, of the command's
+    // output to include in the error message.
+    pub error_lines: usize,
+    pub error_bytes: Option<usize>,
+    pub failure_codes: FailureCodes,
+    pub success_codes: Option<ExitCodes>,
+    pub state_dir: StateDir,
+    pub pid_file: Option<PathBuf>,
+    pub quit_action: QuitAction,
+    pub usr2_action: Usr2Action,
+    // The signal to forward to the command, instead of the terminating
+    // signal that the wrapper received. If not set, the received signal is
+    // forwarded.
+    pub stop_signal: Option<Signal>,
+    // Signals to forward to the command as a different signal. These take
+    // precedence over the stop signal.
+    pub forward_signals: BTreeMap<Signal, Signal>,
+    // Signals that are not forwarded to the command. The wrapper still
+    // handles them, so that they do not terminate it.
+    pub no_forward: Vec<Signal>,
+    pub dump_file: Option<PathBuf>,
+    // A file whose existence asks the wrapper to stop the command, as if it
+    // received `SIGTERM`.
+    pub shutdown_file: Option<PathBuf>,
+    pub correlate: Option<Correlate>,
+    // The trace context of the invocation, exposed to the command with
+    // `--correlate tracecontext`. It is the context of the span sent with
+    // `--otlp-endpoint`, if it is set.
+    pub trace_context: TraceContext,
+    // How long to keep reading the command's output after it exits, while
+    // processes it left behind keep its standard output or standard error
+    // open. If not set, the output is read until it is closed.
+    pub linger: Option<Duration>,
+    // How long to wait for the command to exit after a terminating signal is
+    // forwarded to it, before killing it. If not set, the command is never
+    // killed.
+    pub kill_after: Option<Duration>,
+    // When to restart the command after it exits. If not set, the command
+    // is never restarted.
+    pub restart: Option<RestartPolicy>,
+    // How long the command can write no output before it is reported as
+    // stalled, and whether it is then killed. If not set, stalls are not
+    // detected.
+    pub stall_timeout: Option<Duration>,
+    pub stall_kill: bool,
+    // The command to run each time the command fails. If not set, no
+    // command is run.
+    pub on_failure: Option<FailureHook>,
+    // The pattern of the line that the command writes once it is ready,
+    // before which the cron start check-in and the first heartbeat are not
+    // sent, and how long to wait for it before reporting an error. If not
+    // set, the command is ready as soon as it starts.
+    pub ready_pattern: Option<Pattern>,
+    pub ready_timeout: Option<Duration>,
+    // How long to wait for requests to AppSignal to complete after the
+    // command exits. If not set, every request is waited for.
+    pub flush_timeout: Option<Duration>,
+    // Whether to write the command's output as soon as it is read, instead
+    // of a line at a time.
+    pub unbuffered: bool,
+    pub on_passthrough_error: PassthroughErrorAction,
+    // The maximum size of the line being read from each output stream,
+    // beyond which it is read in parts. If not set, lines can be of any
+    // size.
+    pub max_line_bytes: Option<usize>,
+    // How many lines each stage that processes the command's output holds,
+    // before the stage that sends lines to it waits for it. If not set, a
+    // default number of lines is held.
+    pub lines_buffer: Option<usize>,
+    // Whether to write a summary of the requests sent to AppSignal when the
+    // wrapper exits.
+    pub summary: bool,
+    // Whether to read a batch summary line from the command's standard
+    // output, and whether to warn when it reports failed items but the
+    // command succeeded.
+    pub batch_summary: bool,
+    pub warn_on_batch_failures: bool,
+    // Patterns to redact from the command's output before it is sent as logs
+    // or as part of the error message.
+    pub redact: Redactor,
+    // Whether to strip ANSI escape sequences from the command's output
+    // before it is sent as logs or as part of the error message.
+    pub strip_ansi: bool,
+    // Whether to capture the command's standard output and standard error,
+    // to send them as logs or to use them as part of the error message.
+    pub stdout: bool,
+    pub stderr: bool,
+    pub stdin: StdinMode,
+    // The IDs of the user and group to run the command as. If not set, the
+    // command runs as the wrapper's user and group.
+    pub user: Option<u32>,
+    pub group: Option<u32>,
+    // The working directory to run the command in. If not set, the command
+    // runs in the wrapper's working directory.
+    pub chdir: Option<PathBuf>,
+    // The environment variables to set for the command, from the files and
+    // the variables given, and whether to clear the rest of the wrapper's
+    // environment instead of only the variables that configure the wrapper.
+    pub env: BTreeMap<String, String>,
+    pub clear_env: bool,
+    // Destinations for the command's output and exit, in addition to the
+    // logs and errors sent to AppSignal.
+    pub sinks: Vec<SinkConfig>,
+    pub single_thread: bool,
+    // How much to lower the wrapper's own scheduling priority once the
+    // command is started. If not set, it is not changed.
+    pub wrapper_priority: Option<WrapperPriority>,
+    // How long to wait for the system clock to be set before starting the
+    // command, if it is set to a time that is obviously wrong.
+    pub wait_for_clock: Option<Duration>,
+    // Files to send the lines written to as logs, alongside the command's
+    // output.
+    pub log_files: Vec<LogFile>,
+}
+
+impl WrapConfig {
+    pub fn builder<S: Into<String>>(command: impl IntoIterator<Item = S>) -> WrapConfigBuilder {
+        WrapConfigBuilder {
+            command: command.into_iter().map(Into::into).collect(),
+            ..Default::default()
+        }
+    }
+
+    // Uses the given digest for cron check-ins, logs and errors.
+    pub fn set_digest(&mut self, digest: String) {
+        if let Some(log) = self.log.as_mut() {
+            log.digest = digest.clone();
+        }
+
+        if let Some(error) = self.error.as_mut() {
+            error.digest = digest.clone();
+        }
+
+        if let Some(cron) = self.cron.as_mut() {
+            cron.digest = digest.clone();
+        }
+
+        if let Some(trace) = self.trace.as_mut() {
+            trace.digest = digest.clone();
+        }
+
+        self.digest = digest;
+    }
+
+    // Whether to send a cron finish check-in for the given exit status. This
+    // is independent from whether the exit is reported as an error.
+    pub fn is_cron_success(&self, exit: &ExitStatus) -> bool {
+        match (self.success_codes.as_ref(), exit.code()) {
+            (Some(codes), Some(code)) => code == 0 || codes.contains(code),
+            _ => !self.failure_codes.is_failure(exit),
+        }
+    }
+
+    // The signal to forward to the command when the wrapper receives the
+    // given signal, if it is forwarded.
+    pub fn forwarded_signal(&self, signal: Signal) -> Option<Signal> {
+        if self.no_forward.contains(&signal) {
+            return None;
+        }
+
+        match (self.forward_signals.get(&signal), self.stop_signal) {
+            (Some(forwarded), _) => Some(*forwarded),
+            (None, Some(stop_signal)) if has_terminating_intent(&signal) => Some(stop_signal),
+            (None, _) => Some(signal),
+        }
+    }
+
+    // The environment variables to set for the command, in addition to the
+    // ones inherited from the wrapper.
+    pub fn child_env(&self) -> Vec<(&'static str, String)> {
+        self.correlate
+            .map(|correlate| correlate.env(&self.digest, &self.trace_context))
+            .unwrap_or_default()
+    }
+
+    pub fn error_sampler(&self) -> Option<ErrorSampler> {
+        match (self.error.as_ref(), self.error_sample) {
+            (Some(error), Some(rate)) => Some(ErrorSampler {
+                rate,
+                state_dir: self.state_dir.clone(),
+                action: error.action.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WrapConfigBuilder {
+    command: Vec<String>,
+    digest: Option<String>,
+    log: Option<LogConfig>,
+    error: Option<ErrorConfig>,
+    metrics: Option<MetricsConfig>,
+    statsd: Option<MetricsConfig>,
+    trace: Option<TraceConfig>,
+    cron: Option<CronConfig>,
+    heartbeat: Option<HeartbeatConfig>,
+    error_sample: Option<u64>,
+    error_lines: Option<usize>,
+    error_bytes: Option<usize>,
+    failure_codes: FailureCodes,
+    success_codes: Option<ExitCodes>,
+    state_dir: Option<StateDir>,
+    pid_file: Option<PathBuf>,
+    quit_action: QuitAction,
+    usr2_action: Usr2Action,
+    #[serde(deserialize_with = "deserialize_signal")]
+    stop_signal: Option<Signal>,
+    #[serde(deserialize_with = "deserialize_signal_map")]
+    forward_signals: BTreeMap<Signal, Signal>,
+    #[serde(deserialize_with = "deserialize_signals")]
+    no_forward: Vec<Signal>,
+    dump_file: Option<PathBuf>,
+    shutdown_file: Option<PathBuf>,
+    correlate: Option<Correlate>,
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    linger: Option<Duration>,
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    kill_after: Option<Duration>,
+    restart: Option<RestartPolicy>,
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    stall_timeout: Option<Duration>,
+    stall_kill: bool,
+    on_failure: Option<String>,
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    on_failure_timeout: Option<Duration>,
+    on_failure_report: bool,
+    ready_pattern: Option<Pattern>,
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    ready_timeout: Option<Duration>,
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    flush_timeout: Option<Duration>,
+    unbuffered: bool,
+    on_passthrough_error: PassthroughErrorAction,
+    max_line_bytes: Option<usize>,
+    lines_buffer: Option<usize>,
+    summary: bool,
+    batch_summary: bool,
+    warn_on_batch_failures: bool,
+    redact: Redactor,
+    strip_ansi: Option<bool>,
+    stdout: Option<bool>,
+    stderr: Option<bool>,
+    stdin: StdinMode,
+    user: Option<String>,
+    group: Option<String>,
+    chdir: Option<PathBuf>,
+    env: BTreeMap<String, String>,
+    env_files: Vec<PathBuf>,
+    clear_env: bool,
+    sinks: Vec<SinkConfig>,
+    single_thread: bool,
+    wrapper_priority: Option<WrapperPriority>,
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    wait_for_clock: Option<Duration>,
+    log_files: Vec<LogFile>,
+}
+
+impl WrapConfigBuilder {
+    // The digest used for cron check-ins, logs and errors. If not set, a
+    // random digest is used. Overrides the digest of the given configs.
+    pub fn digest(mut self, digest: impl Into<Option<String>>) -> Self {
+        self.digest = digest.into();
+        self
+    }
+
+    pub fn log(mut self, log: impl Into<Option<LogConfig>>) -> Self {
+        self.log = log.into();
+        self
+    }
+
+    pub fn error(mut self, error: impl Into<Option<ErrorConfig>>) -> Self {
+        self.error = error.into();
+        self
+    }
+
+    pub fn metrics(mut self, metrics: impl Into<Option<MetricsConfig>>) -> Self {
+        self.metrics = metrics.into();
+        self
+    }
+
+    pub fn statsd(mut self, statsd: impl Into<Option<MetricsConfig>>) -> Self {
+        self.statsd = statsd.into();
+        self
+    }
+
+    pub fn trace(mut self, trace: impl Into<Option<TraceConfig>>) -> Self {
+        self.trace = trace.into();
+        self
+    }
+
+    pub fn cron(mut self, cron: impl Into<Option<CronConfig>>) -> Self {
+        self.cron = cron.into();
+        self
+    }
+
+    pub fn heartbeat(mut self, heartbeat: impl Into<Option<HeartbeatConfig>>) -> Self {
+        self.heartbeat = heartbeat.into();
+        self
+    }
+
+    pub fn error_sample(mut self, rate: impl Into<Option<u64>>) -> Self {
+        self.error_sample = rate.into();
+        self
+    }
+
+    // The maximum number of lines of output in the error message. Defaults
+    // to 10 lines.
+    pub fn error_lines(mut self, lines: impl Into<Option<usize>>) -> Self {
+        self.error_lines = lines.into();
+        self
+    }
+
+    // The maximum size, in bytes, of the output in the error message.
+    pub fn error_bytes(mut self, bytes: impl Into<Option<usize>>) -> Self {
+        self.error_bytes = bytes.into();
+        self
+    }
+
+    // Which exit codes of the command are considered failures. Defaults to
+    // any non-zero exit code.
+    pub fn failure_codes(mut self, failure_codes: FailureCodes) -> Self {
+        self.failure_codes = failure_codes;
+        self
+    }
+
+    // Exit codes, other than zero, for which a cron finish check-in is sent.
+    // Defaults to the exit codes that are not considered failures.
+    pub fn success_codes(mut self, success_codes: impl Into<Option<ExitCodes>>) -> Self {
+        self.success_codes = success_codes.into();
+        self
+    }
+
+    pub fn state_dir(mut self, state_dir: impl Into<Option<StateDir>>) -> Self {
+        self.state_dir = state_dir.into();
+        self
+    }
+
+    pub fn pid_file(mut self, pid_file: impl Into<Option<PathBuf>>) -> Self {
+        self.pid_file = pid_file.into();
+        self
+    }
+
+    // What to do when the wrapper receives `SIGQUIT`. Defaults to forwarding
+    // it to the command.
+    pub fn quit_action(mut self, quit_action: QuitAction) -> Self {
+        self.quit_action = quit_action;
+        self
+    }
+
+    // What to do when the wrapper receives `SIGUSR2`. Defaults to forwarding
+    // it to the command.
+    pub fn usr2_action(mut self, usr2_action: Usr2Action) -> Self {
+        self.usr2_action = usr2_action;
+        self
+    }
+
+    // The signal to forward to the command when the wrapper receives a
+    // terminating signal, such as `SIGQUIT` for commands that shut down
+    // gracefully on it.
+    pub fn stop_signal(mut self, stop_signal: impl Into<Option<Signal>>) -> Self {
+        self.stop_signal = stop_signal.into();
+        self
+    }
+
+    // Signals to forward to the command as a different signal, such as
+    // `SIGHUP` as `SIGUSR1` for commands that reload their configuration on
+    // the latter.
+    pub fn forward_signals(
+        mut self,
+        forward_signals: impl IntoIterator<Item = (Signal, Signal)>,
+    ) -> Self {
+        self.forward_signals.extend(forward_signals);
+        self
+    }
+
+    // Signals that are not forwarded to the command.
+    pub fn no_forward(mut self, no_forward: Vec<Signal>) -> Self {
+        self.no_forward = no_forward;
+        self
+    }
+
+    // A file to write the state dump to, alongside standard error.
+    pub fn dump_file(mut self, dump_file: impl Into<Option<PathBuf>>) -> Self {
+        self.dump_file = dump_file.into();
+        self
+    }
+
+    // A file to stop the command when it exists, as if the wrapper received
+    // `SIGTERM`.
+    pub fn shutdown_file(mut self, shutdown_file: impl Into<Option<PathBuf>>) -> Self {
+        self.shutdown_file = shutdown_file.into();
+        self
+    }
+
+    // Exposes the digest of the invocation to the command, so that an
+    // integration running inside it can correlate its data with the
+    // wrapper's.
+    pub fn correlate(mut self, correlate: impl Into<Option<Correlate>>) -> Self {
+        self.correlate = correlate.into();
+        self
+    }
+
+    // How long to keep reading the command's output after it
+    // exits. If not set, the output is read until it is closed.
+    pub fn linger(mut self, linger: impl Into<Option<Duration>>) -> Self {
+        self.linger = linger.into();
+        self
+    }
+
+    // How long to wait for the command to exit after a
+    // terminating signal is forwarded to it, before killing it with
+    // `SIGKILL`. If not set, the command is never killed.
+    pub fn kill_after(mut self, kill_after: impl Into<Option<Duration>>) -> Self {
+        self.kill_after = kill_after.into();
+        self
+    }
+
+    // When to restart the command after it fails, and how many times. If
+    // not set, the command is never restarted.
+    pub fn restart(mut self, restart: impl Into<Option<RestartPolicy>>) -> Self {
+        self.restart = restart.into();
+        self
+    }
+
+    // How long the command can write no output before it is
+    // reported as stalled. If not set, stalls are not detected.
+    pub fn stall_timeout(mut self, stall_timeout: impl Into<Option<Duration>>) -> Self {
+        self.stall_timeout = stall_timeout.into();
+        self
+    }
+
+    // Whether to kill the command with `SIGKILL` when it stalls. Defaults
+    // to `false`.
+    pub fn stall_kill(mut self, stall_kill: bool) -> Self {
+        self.stall_kill = stall_kill;
+        self
+    }
+
+    // The command to run, through the shell, each time the command fails.
+    pub fn on_failure(mut self, on_failure: impl Into<Option<String>>) -> Self {
+        self.on_failure = on_failure.into();
+        self
+    }
+
+    // How long to wait for the `on_failure` command to finish before
+    // killing it. Defaults to 30 seconds.
+    pub fn on_failure_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.on_failure_timeout = timeout.into();
+        self
+    }
+
+    // Whether to include the output of the `on_failure` command in the
+    // error report for the failure. Defaults to `false`.
+    pub fn on_failure_report(mut self, on_failure_report: bool) -> Self {
+        self.on_failure_report = on_failure_report;
+        self
+    }
+
+    // The pattern of the line that the command writes once it is ready. The
+    // cron start check-in and the first heartbeat are only sent after it.
+    pub fn ready_pattern(mut self, pattern: impl Into<Option<Pattern>>) -> Self {
+        self.ready_pattern = pattern.into();
+        self
+    }
+
+    // How long to wait for the command to be ready before
+    // reporting an error. If not set, the command is waited for forever.
+    pub fn ready_timeout(mut self, ready_timeout: impl Into<Option<Duration>>) -> Self {
+        self.ready_timeout = ready_timeout.into();
+        self
+    }
+
+    // How long to wait for requests to AppSignal to complete
+    // after the command exits. If not set, every request is waited for.
+    pub fn flush_timeout(mut self, flush_timeout: impl Into<Option<Duration>>) -> Self {
+        self.flush_timeout = flush_timeout.into();
+        self
+    }
+
+    // Whether to write the command's output as soon as it is read, instead
+    // of a line at a time. Defaults to `false`.
+    pub fn unbuffered(mut self, unbuffered: bool) -> Self {
+        self.unbuffered = unbuffered;
+        self
+    }
+
+    // What to do when the command's output cannot be written to the
+    // wrapper's output. Defaults to skipping the output that cannot be
+    // written.
+    pub fn on_passthrough_error(mut self, on_passthrough_error: PassthroughErrorAction) -> Self {
+        self.on_passthrough_error = on_passthrough_error;
+        self
+    }
+
+    // The maximum size of the line being read from each output stream,
+    // beyond which it is read in parts. Defaults to no maximum.
+    pub fn max_line_bytes(mut self, max_line_bytes: impl Into<Option<usize>>) -> Self {
+        self.max_line_bytes = max_line_bytes.into();
+        self
+    }
+
+    // How many lines each stage that processes the command's output holds,
+    // before the stage that sends lines to it waits for it.
+    pub fn lines_buffer(mut self, lines_buffer: impl Into<Option<usize>>) -> Self {
+        self.lines_buffer = lines_buffer.into();
+        self
+    }
+
+    // Whether to write a summary of the requests sent to AppSignal, with
+    // their latency, when the wrapper exits. Defaults to `false`.
+    pub fn summary(mut self, summary: bool) -> Self {
+        self.summary = summary;
+        self
+    }
+
+    // Whether to read a batch summary line, such as
+    // `appsignal_summary: processed=1000 failed=3`, from the command's
+    // standard output. Defaults to `false`.
+    pub fn batch_summary(mut self, batch_summary: bool) -> Self {
+        self.batch_summary = batch_summary;
+        self
+    }
+
+    // Whether to send a warning log line when the batch summary line reports
+    // failed items, but the command succeeded. Defaults to `false`.
+    pub fn warn_on_batch_failures(mut self, warn_on_batch_failures: bool) -> Self {
+        self.warn_on_batch_failures = warn_on_batch_failures;
+        self
+    }
+
+    // Patterns whose matches are replaced with `[REDACTED]` in the lines of
+    // output sent to AppSignal. Defaults to no patterns.
+    pub fn redact(mut self, redact: Redactor) -> Self {
+        self.redact = redact;
+        self
+    }
+
+    // Whether to strip ANSI escape sequences, such as colours, from the lines
+    // of output sent to AppSignal. Defaults to `true`.
+    pub fn strip_ansi(mut self, strip_ansi: bool) -> Self {
+        self.strip_ansi = Some(strip_ansi);
+        self
+    }
+
+    // Whether to capture the command's standard output. Defaults to `true`.
+    pub fn stdout(mut self, stdout: bool) -> Self {
+        self.stdout = Some(stdout);
+        self
+    }
+
+    // Whether to capture the command's standard error. Defaults to `true`.
+    pub fn stderr(mut self, stderr: bool) -> Self {
+        self.stderr = Some(stderr);
+        self
+    }
+
+    // What to connect the command's standard input to. Defaults to the
+    // wrapper's standard input.
+    pub fn stdin(mut self, stdin: StdinMode) -> Self {
+        self.stdin = stdin;
+        self
+    }
+
+    // The user to run the command as, by name or by ID. Unless a group is
+    // set, the command also runs as the user's primary group.
+    pub fn user(mut self, user: impl Into<Option<String>>) -> Self {
+        self.user = user.into();
+        self
+    }
+
+    // The group to run the command as, by name or by ID.
+    pub fn group(mut self, group: impl Into<Option<String>>) -> Self {
+        self.group = group.into();
+        self
+    }
+
+    // The working directory to run the command in.
+    pub fn chdir(mut self, chdir: impl Into<Option<PathBuf>>) -> Self {
+        self.chdir = chdir.into();
+        self
+    }
+
+    // Environment variables to set for the command. These override the ones
+    // in the environment files.
+    pub fn env(mut self, env: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.env.extend(env);
+        self
+    }
+
+    // Files with environment variables to set for the command, read when
+    // the config is built. Later files override earlier ones.
+    pub fn env_files(mut self, env_files: Vec<PathBuf>) -> Self {
+        self.env_files = env_files;
+        self
+    }
+
+    // Whether to start the command with an empty environment, instead of
+    // the wrapper's. Defaults to `false`.
+    pub fn clear_env(mut self, clear_env: bool) -> Self {
+        self.clear_env = clear_env;
+        self
+    }
+
+    // Destinations for the command's output and exit, such as a file.
+    pub fn sinks(mut self, sinks: Vec<SinkConfig>) -> Self {
+        self.sinks = sinks;
+        self
+    }
+
+    // Whether to use a current-thread runtime. Defaults to `false`.
+    pub fn single_thread(mut self, single_thread: bool) -> Self {
+        self.single_thread = single_thread;
+        self
+    }
+
+    // How much to lower the wrapper's own scheduling priority, below the
+    // command's, once the command is started.
+    pub fn wrapper_priority(mut self, priority: impl Into<Option<WrapperPriority>>) -> Self {
+        self.wrapper_priority = priority.into();
+        self
+    }
+
+    // How long to wait for the system clock to be set before
+    // starting the command, if it is obviously wrong. If not set, the
+    // command is started right away.
+    pub fn wait_for_clock(mut self, wait_for_clock: impl Into<Option<Duration>>) -> Self {
+        self.wait_for_clock = wait_for_clock.into();
+        self
+    }
+
+    // Files to tail, sending the lines written to them as logs.
+    pub fn log_files(mut self, log_files: Vec<LogFile>) -> Self {
+        self.log_files = log_files;
+        self
+    }
+
+    pub fn build(self) -> Result<WrapConfig, String> {
+        if self.command.is_empty() {
+            return Err("no command to execute".to_string());
+        }
+
+        let stdout = self.stdout.unwrap_or(true);
+        let stderr = self.stderr.unwrap_or(true);
+
+        if let Some(log) = self.log.as_ref() {
+            log.validate()?;
+
+            if log.origin.is_out() && !stdout {
+                return Err("cannot send standard output as logs without capturing it".to_string());
+            }
+
+            if log.origin.is_err() && !stderr {
+                return Err("cannot send standard error as logs without capturing it".to_string());
+            }
+        }
+
+        if let Some(error) = self.error.as_ref() {
+            error.validate()?;
+        }
+
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.validate()?;
+        }
+
+        if let Some(trace) = self.trace.as_ref() {
+            trace.validate()?;
+        }
+
+        if let Some(statsd) = self.statsd.as_ref() {
+            statsd.validate()?;
+
+            if !stdout {
+                return Err(
+                    "cannot read StatsD lines from standard output without capturing it"
+                        .to_string(),
+                );
+            }
+        }
+
+        if let Some(cron) = self.cron.as_ref() {
+            cron.validate()?;
+        }
+
+        if let Some(heartbeat) = self.heartbeat.as_ref() {
+            heartbeat.validate()?;
+
+            if !heartbeat.on_idle && !stdout && !stderr {
+                return Err(
+                    "cannot detect activity for heartbeats without capturing the output"
+                        .to_string(),
+                );
+            }
+        }
+
+        if self.usr2_action == Usr2Action::Heartbeat && self.heartbeat.is_none() {
+            return Err(
+                "cannot send heartbeats on SIGUSR2 without heartbeat check-ins".to_string(),
+            );
+        }
+
+        if self.success_codes.is_some() && self.cron.is_none() {
+            return Err("cannot set success exit codes without cron check-ins".to_string());
+        }
+
+        if self.cron.is_some() && self.heartbeat.is_some() {
+            return Err("cannot send both cron and heartbeat check-ins".to_string());
+        }
+
+        match self.error_sample {
+            Some(0) => return Err("the error sample rate must be greater than zero".to_string()),
+            Some(_) if self.error.is_none() => {
+                return Err("cannot sample errors when errors are not sent".to_string())
+            }
+            _ => {}
+        }
+
+        if let Some(signal) = self
+            .no_forward
+            .iter()
+            .find(|signal| self.forward_signals.contains_key(signal))
+        {
+            return Err(format!(
+                "cannot both forward {signal} as a different signal and not forward it"
+            ));
+        }
+
+        match self.stall_timeout {
+            Some(timeout) if timeout.is_zero() => {
+                return Err("the stall timeout must be greater than zero".to_string())
+            }
+            Some(_) if !stdout && !stderr => {
+                return Err("cannot detect stalls without capturing the output".to_string())
+            }
+            None if self.stall_kill => {
+                return Err("cannot kill the command on stalls without a stall timeout".to_string())
+            }
+            _ => {}
+        }
+
+        match (self.on_failure.as_ref(), self.on_failure_timeout) {
+            (Some(_), Some(timeout)) if timeout.is_zero() => {
+                return Err("the on-failure command timeout must be greater than zero".to_string())
+            }
+            (None, Some(_)) => {
+                return Err("cannot time out the on-failure command without one".to_string())
+            }
+            _ => {}
+        }
+
+        if self.on_failure_report {
+            if self.on_failure.is_none() {
+                return Err("cannot report the on-failure command's output without one".to_string());
+            }
+
+            if self.error.is_none() {
+                return Err(
+                    "cannot report the on-failure command's output when errors are not sent"
+                        .to_string(),
+                );
+            }
+        }
+
+        if self.batch_summary && !stdout {
+            return Err(
+                "cannot read the batch summary line without capturing standard output".to_string(),
+            );
+        }
+
+        if self.warn_on_batch_failures {
+            if !self.batch_summary {
+                return Err(
+                    "cannot warn about batch failures without reading the batch summary line"
+                        .to_string(),
+                );
+            }
+
+            if self.log.is_none() {
+                return Err("cannot warn about batch failures when logs are not sent".to_string());
+            }
+        }
+
+        if self.ready_pattern.is_some() && !stdout && !stderr {
+            return Err("cannot detect readiness without capturing the output".to_string());
+        }
+
+        match self.ready_timeout {
+            Some(timeout) if timeout.is_zero() => {
+                return Err("the ready timeout must be greater than zero".to_string())
+            }
+            Some(_) if self.ready_pattern.is_none() => {
+                return Err(
+                    "cannot time out waiting for readiness without a ready pattern".to_string(),
+                )
+            }
+            _ => {}
+        }
+
+        // A restarted command would inherit the wrapper's lowered priority,
+        // which cannot be raised again without privileges.
+        if self.restart.is_some() && self.wrapper_priority.is_some() {
+            return Err(
+                "cannot lower the wrapper's priority when restarting the command".to_string(),
+            );
+        }
+
+        if self.restart.is_some() && self.stdin == StdinMode::Log {
+            return Err(
+                "cannot restart the command when its standard input is sent as logs".to_string(),
+            );
+        }
+
+        if !self.log_files.is_empty() && self.log.is_none() {
+            return Err("cannot tail log files without sending logs".to_string());
+        }
+
+        let (user, group) = user_and_group(self.user.as_deref(), self.group.as_deref())?;
+
+        if let Some(dir) = self.chdir.as_ref() {
+            if !dir.is_dir() {
+                return Err(format!(
+                    "cannot run the command in {}, as it is not a directory",
+                    dir.display()
+                ));
+            }
+        }
+
+        let mut env = BTreeMap::new();
+
+        for path in &self.env_files {
+            env.extend(environment::read_file(path)?);
+        }
+
+        env.extend(self.env);
+
+        let trace_context = self
+            .trace
+            .as_ref()
+            .map(TraceConfig::context)
+            .unwrap_or_else(TraceContext::random);
+
+        let mut config = WrapConfig {
+            command: self.command,
+            digest: String::new(),
+            log: self.log,
+            error: self.error,
+            metrics: self.metrics,
+            statsd: self.statsd,
+            trace: self.trace,
+            cron: self.cron,
+            heartbeat: self.heartbeat,
+            error_sample: self.error_sample,
+            error_lines: self.error_lines.unwrap_or(DEFAULT_MESSAGE_LINES),
+            error_bytes: self.error_bytes,
+            failure_codes: self.failure_codes,
+            success_codes: self.success_codes,
+            state_dir: self.state_dir.unwrap_or_default(),
+            pid_file: self.pid_file,
+            quit_action: self.quit_action,
+            usr2_action: self.usr2_action,
+            stop_signal: self.stop_signal,
+            forward_signals: self.forward_signals,
+            no_forward: self.no_forward,
+            dump_file: self.dump_file,
+            shutdown_file: self.shutdown_file,
+            correlate: self.correlate,
+            trace_context,
+            linger: self.linger,
+            kill_after: self.kill_after,
+            restart: self.restart,
+            stall_timeout: self.stall_timeout,
+            stall_kill: self.stall_kill,
+            on_failure: self.on_failure.map(|command| FailureHook {
+                command,
+                timeout: self
+                    .on_failure_timeout
+                    .unwrap_or(DEFAULT_ON_FAILURE_TIMEOUT),
+                report: self.on_failure_report,
+            }),
+            ready_pattern: self.ready_pattern,
+            ready_timeout: self.ready_timeout,
+            flush_timeout: self.flush_timeout,
+            unbuffered: self.unbuffered,
+            on_passthrough_error: self.on_passthrough_error,
+            max_line_bytes: self.max_line_bytes,
+            lines_buffer: self.lines_buffer,
+            summary: self.summary,
+            batch_summary: self.batch_summary,
+            warn_on_batch_failures: self.warn_on_batch_failures,
+            redact: self.redact,
+            strip_ansi: self.strip_ansi.unwrap_or(true),
+            stdout,
+            stderr,
+            stdin: self.stdin,
+            user,
+            group,
+            chdir: self.chdir,
+            env,
+            clear_env: self.clear_env,
+            sinks: self.sinks,
+            single_thread: self.single_thread,
+            wrapper_priority: self.wrapper_priority,
+            wait_for_clock: self.wait_for_clock,
+            log_files: self.log_files,
+        };
+
+        config.set_digest(self.digest.unwrap_or_else(random_digest));
+
+        Ok(config)
+    }
+}
+
+impl TryFrom<WrapConfigBuilder> for WrapConfig {
+    type Error = String;
+
+    fn try_from(builder: WrapConfigBuilder) -> Result<Self, Self::Error> {
+        builder.build()
+    }
+}
+
+// Validates that a required configuration value is not empty.
+pub fn require(value: &str, name: &str) -> Result<(), String> {
+    if value.trim().is_empty() {
+        return Err(format!("the {name} must not be empty"));
+    }
+
+    Ok(())
+}
+
+pub fn validate_endpoint(endpoint: &str) -> Result<(), String> {
+    match reqwest::Url::parse(endpoint) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+        _ => Err(format!("invalid endpoint: {endpoint}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check_in::CheckInConfig;
+    use crate::log::{LogBatch, LogOrigin, LogSeverity};
+
+    fn log_config() -> LogConfig {
+        LogConfig {
+            api_key: "some-api-key".to_string(),
+            endpoint: DEFAULT_ENDPOINT.to_string(),
+            hostname: "some-hostname".to_string(),
+            group: "some-group".to_string(),
+            group_per_run: false,
+            origin: LogOrigin::All,
+            digest: "some-log-digest".to_string(),
+            command: None,
+            revision: None,
+            tags: Default::default(),
+            severity_patterns: vec![],
+            stdout_severity: LogSeverity::Info,
+            stderr_severity: LogSeverity::Error,
+            batch: LogBatch::default(),
+            rate_limit: None,
+            severity_sources: vec![],
+            compress: true,
+        }
+    }
+
+    fn cron_config() -> CronConfig {
+        CronConfig {
+            check_in: CheckInConfig {
+                api_key: "some-api-key".to_string(),
+                endpoint: DEFAULT_ENDPOINT.to_string(),
+                identifier: "some-identifier".to_string(),
+            },
+            digest: "some-cron-digest".to_string(),
+            schedule: None,
+        }
+    }
+
+    #[test]
+    fn random_digest() {
+        let digest = super::random_digest();
+        assert!(digest.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(digest.len(), 16);
+    }
+
+    #[test]
+    fn wrap_config_builder() {
+        let config = WrapConfig::builder(["true"])
+            .digest("some-digest".to_string())
+            .log(log_config())
+            .cron(cron_config())
+            .build()
+            .expect("failed to build config");
+
+        assert_eq!(config.command, vec!["true"]);
+        assert_eq!(config.digest, "some-digest");
+        assert_eq!(config.log.unwrap().digest, "some-digest");
+        assert_eq!(config.cron.unwrap().digest, "some-digest");
+        assert!(config.error.is_none());
+        assert!(config.stdout);
+        assert!(config.stderr);
+        assert_eq!(config.stdin, StdinMode::Inherit);
+        assert_eq!(config.user, None);
+        assert_eq!(config.group, None);
+    }
+
+    #[test]
+    fn wrap_config_builder_user_and_group() {
+        for (user, group, expected) in [
+            (Some("root"), None, (Some(0), Some(0))),
+            (Some("0"), None, (Some(0), Some(0))),
+            (None, Some("root"), (None, Some(0))),
+            (Some("root"), Some("0"), (Some(0), Some(0))),
+        ] {
+            let config = WrapConfig::builder(["true"])
+                .user(user.map(String::from))
+                .group(group.map(String::from))
+                .build()
+                .expect("failed to build config");
+
+            assert_eq!((config.user, config.group), expected, "{user:?} {group:?}");
+        }
+
+        for (user, group, error) in [
+            (Some("no-such-user"), None, "unknown user `no-such-user`"),
+            (None, Some("no-such-group"), "unknown group `no-such-group`"),
+        ] {
+            let result = WrapConfig::builder(["true"])
+                .user(user.map(String::from))
+                .group(group.map(String::from))
+                .build();
+
+            assert_eq!(result.err(), Some(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn wrap_config_builder_errors() {
+        for (builder, error) in [
+            (
+                WrapConfig::builder(Vec::<String>::new()),
+                "no command to execute",
+            ),
+            (
+                WrapConfig::builder(["true"]).log(LogConfig {
+                    api_key: "".to_string(),
+                    ..log_config()
+                }),
+                "the log API key must not be empty",
+            ),
+            (
+                WrapConfig::builder(["true"]).log(LogConfig {
+                    endpoint: "not-a-url".to_string(),
+                    ..log_config()
+                }),
+                "invalid endpoint: not-a-url",
+            ),
+            (
+                WrapConfig::builder(["true"])
+                    .log(log_config())
+                    .stdout(false),
+                "cannot send standard output as logs without capturing it",
+            ),
+            (
+                WrapConfig::builder(["true"]).error_sample(10),
+                "cannot sample errors when errors are not sent",
+            ),
+            (
+                WrapConfig::builder(["true"]).chdir(PathBuf::from("/nonexistent")),
+                "cannot run the command in /nonexistent, as it is not a directory",
+            ),
+            (
+                WrapConfig::builder(["true"])
+                    .statsd(MetricsConfig {
+                        api_key: "some-api-key".to_string(),
+                        endpoint: DEFAULT_ENDPOINT.to_string(),
+                        name: "some-name".to_string(),
+                        hostname: "some-hostname".to_string(),
+                        tags: Default::default(),
+                    })
+                    .stdout(false),
+                "cannot read StatsD lines from standard output without capturing it",
+            ),
+            (
+                WrapConfig::builder(["true"])
+                    .heartbeat(HeartbeatConfig {
+                        check_in: CheckInConfig {
+                            api_key: "some-api-key".to_string(),
+                            endpoint: DEFAULT_ENDPOINT.to_string(),
+                            identifier: "some-identifier".to_string(),
+                        },
+                        interval: Duration::from_secs(30),
+                        on_idle: false,
+                        activity_patterns: vec![],
+                        health_cmd: None,
+                    })
+                    .stdout(false)
+                    .stderr(false),
+                "cannot detect activity for heartbeats without capturing the output",
+            ),
+        ] {
+            assert_eq!(builder.build().err(), Some(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn wrap_config_is_cron_success() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let exit_code = |code: i32| ExitStatus::from_raw(code << 8);
+
+        for (success_codes, failure_codes, expected) in [
+            (None, FailureCodes::NonZero, [true, false, false]),
+            (Some("3"), FailureCodes::NonZero, [true, false, true]),
+            (
+                None,
+                FailureCodes::Except(ExitCodes::parse("1").unwrap()),
+                [true, true, false],
+            ),
+            (
+                Some("3"),
+                FailureCodes::Except(ExitCodes::parse("1").unwrap()),
+                [true, false, true],
+            ),
+        ] {
+            let config = WrapConfig::builder(["true"])
+                .cron(cron_config())
+                .success_codes(success_codes.map(|codes| ExitCodes::parse(codes).unwrap()))
+                .failure_codes(failure_codes)
+                .build()
+                .expect("failed to build config");
+
+            let actual = [0, 1, 3].map(|code| config.is_cron_success(&exit_code(code)));
+
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn wrap_config_child_env() {
+        let config = WrapConfig::builder(["true"])
+            .digest("some-digest".to_string())
+            .build()
+            .unwrap();
+        assert!(config.child_env().is_empty());
+
+        let config = WrapConfig::builder(["true"])
+            .digest("some-digest".to_string())
+            .correlate(Correlate::Appsignal)
+            .build()
+            .unwrap();
+        assert_eq!(
+            config.child_env(),
+            vec![
+                ("APPSIGNAL_RUN_DIGEST", "some-digest".to_string()),
+                (
+                    "APPSIGNAL_RUN_DIGEST_TAG",
+                    "appsignal-run-digest".to_string()
+                )
+            ]
+        );
+
+        let trace = TraceConfig {
+            endpoint: "http://localhost:4318".to_string(),
+            headers: BTreeMap::new(),
+            name: "some-name".to_string(),
+            hostname: "some-hostname".to_string(),
+            digest: "some-digest".to_string(),
+            command: None,
+            revision: None,
+            tags: BTreeMap::new(),
+            trace_id: "0af7651916cd43dd8448eb211c80319c".to_string(),
+            span_id: "b7ad6b7169203331".to_string(),
+        };
+        let config = WrapConfig::builder(["true"])
+            .digest("some-digest".to_string())
+            .trace(trace)
+            .correlate(Correlate::Tracecontext)
+            .build()
+            .unwrap();
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        assert_eq!(
+            config.child_env()[2..],
+            [
+                ("TRACEPARENT", traceparent.to_string()),
+                ("APPSIGNAL_RUN_TRACEPARENT", traceparent.to_string())
+            ]
+        );
+
+        let config = WrapConfig::builder(["true"])
+            .correlate(Correlate::Tracecontext)
+            .build()
+            .unwrap();
+        assert_eq!(config.child_env()[2].1, config.trace_context.traceparent());
+    }
+
+    #[test]
+    fn wrap_config_forwarded_signal() {
+        let config = WrapConfig::builder(["true"])
+            .stop_signal(Signal::SIGQUIT)
+            .forward_signals([
+                (Signal::SIGHUP, Signal::SIGUSR1),
+                (Signal::SIGINT, Signal::SIGTERM),
+            ])
+            .no_forward(vec![Signal::SIGWINCH])
+            .build()
+            .expect("failed to build config");
+
+        assert_eq!(
+            config.forwarded_signal(Signal::SIGHUP),
+            Some(Signal::SIGUSR1)
+        );
+        assert_eq!(
+            config.forwarded_signal(Signal::SIGINT),
+            Some(Signal::SIGTERM)
+        );
+        assert_eq!(
+            config.forwarded_signal(Signal::SIGTERM),
+            Some(Signal::SIGQUIT)
+        );
+        assert_eq!(
+            config.forwarded_signal(Signal::SIGUSR2),
+            Some(Signal::SIGUSR2)
+        );
+        assert_eq!(config.forwarded_signal(Signal::SIGWINCH), None);
+
+        let error = WrapConfig::builder(["true"])
+            .forward_signals([(Signal::SIGHUP, Signal::SIGUSR1)])
+            .no_forward(vec![Signal::SIGHUP])
+            .build()
+            .err()
+            .expect("expected build to fail");
+        assert_eq!(
+            error,
+            "cannot both forward SIGHUP as a different signal and not forward it"
+        );
+    }
+
+    #[test]
+    fn wrap_config_deserialize() {
+        let config: WrapConfig = serde_json::from_str(
+            r#"{
+                "command": ["sleep", "10"],
+                "log": {"api_key": "some-api-key", "group": "some-group", "origin": "stderr"},
+                "heartbeat": {
+                    "check_in": {"api_key": "some-api-key", "identifier": "some-identifier"},
+                    "interval": 60
+                },
+                "stdout": false,
+                "stdin": "null",
+                "stop_signal": "SIGQUIT",
+                "forward_signals": {"SIGHUP": "SIGUSR1"},
+                "no_forward": ["SIGWINCH"]
+            }"#,
+        )
+        .expect("failed to deserialize config");
+
+        let log = config.log.expect("expected log config");
+        assert_eq!(log.origin, LogOrigin::Stderr);
+        assert_eq!(log.endpoint, DEFAULT_ENDPOINT);
+        assert_eq!(log.digest, config.digest);
+        assert_eq!(config.stdin, StdinMode::Null);
+        assert_eq!(config.stop_signal, Some(Signal::SIGQUIT));
+        assert_eq!(
+            config.forward_signals,
+            BTreeMap::from([(Signal::SIGHUP, Signal::SIGUSR1)])
+        );
+        assert_eq!(config.no_forward, vec![Signal::SIGWINCH]);
+
+        let heartbeat = config.heartbeat.expect("expected heartbeat config");
+        assert_eq!(heartbeat.interval, std::time::Duration::from_secs(60));
+
+        let error = serde_json::from_str::<WrapConfig>(r#"{"command": []}"#)
+            .err()
+            .expect("expected deserialization to fail");
+        assert_eq!(error.to_string(), "no command to execute");
+    }
+}
@@ -0,0 +1,53 @@
+// Runs the wrapper with a standard input that is never closed, such as a
+// pipe whose writer is still running, to check that a terminating signal
+// still makes it exit.
+
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+fn spawn(args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_appsignal-run"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to spawn the wrapper")
+}
+
+// Sends SIGTERM to the wrapper once it has started reading, and waits for
+// it to exit, returning its exit code, or `None` if it has not exited in
+// time.
+fn terminate(mut child: Child) -> Option<i32> {
+    sleep(Duration::from_millis(500));
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM).unwrap();
+
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_secs(5) {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status.code();
+        }
+
+        sleep(Duration::from_millis(50));
+    }
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    None
+}
+
+#[test]
+fn stdin_pipe_exits_on_sigterm() {
+    let child = spawn(&["some-name", "--stdin", "--api-key", "k", "--dry-run"]);
+    assert_eq!(terminate(child), Some(128 + 15));
+}
+
+#[test]
+fn stdin_log_exits_on_sigterm() {
+    let child = spawn(&["log", "some-group", "--api-key", "k", "--dry-run"]);
+    assert_eq!(terminate(child), Some(128 + 15));
+}
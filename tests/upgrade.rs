@@ -0,0 +1,43 @@
+// Upgrades a running wrapper, which re-executes itself and inherits the
+// command and its pipes, while the command is writing a line.
+
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+#[test]
+fn upgrade_keeps_partial_line() {
+    let bin = env!("CARGO_BIN_EXE_appsignal-run");
+    let pid_file = std::env::temp_dir().join(format!("{}-upgrade-test.pid", std::process::id()));
+
+    let wrapper = Command::new(bin)
+        .args(["some-name", "--api-key", "k", "--dry-run", "--pid-file"])
+        .arg(&pid_file)
+        .args(["--", "sh", "-c", "printf start-; sleep 2; echo end"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn the wrapper");
+
+    sleep(Duration::from_secs(1));
+
+    let status = Command::new(bin)
+        .args(["upgrade", "--pid-file"])
+        .arg(&pid_file)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = wrapper.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(!pid_file.exists());
+
+    // The start of the line, read before the upgrade, is written once,
+    // followed by its end, read after the upgrade.
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "start-end\n");
+
+    // Both parts are sent as logs, by each of the wrapper processes.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(r#""message": "start-""#), "{stderr}");
+    assert!(stderr.contains(r#""message": "end""#), "{stderr}");
+}